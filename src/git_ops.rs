@@ -1,54 +1,238 @@
+use crate::git_temp_worktree::CwdGuard;
 use crate::tui::{render_message, App};
+use once_cell::sync::{Lazy, OnceCell};
 use ratatui::style::Color;
 use ratatui::{backend::Backend, Terminal};
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
-use std::process::Command;
+use std::process::{Command, Output};
+use std::sync::Mutex;
 
-const MAX_DIFF_BYTES: usize = 200 * 1024; // 200 KiB
+pub const DEFAULT_MAX_DIFF_BYTES: usize = 200 * 1024; // 200 KiB
+/// Smallest accepted `--max-diff-bytes`; less leaves the model nothing to work with.
+pub const MIN_MAX_DIFF_BYTES: usize = 4 * 1024;
+
+/// Parse a `--max-diff-bytes` / `GH_AUTOPR_MAX_DIFF_BYTES` value.
+pub fn parse_max_diff_bytes(value: &str) -> Result<usize, String> {
+    let bytes: usize = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a byte count, got \"{}\"", value))?;
+    if bytes < MIN_MAX_DIFF_BYTES {
+        return Err(format!(
+            "must be at least {} bytes, got {}",
+            MIN_MAX_DIFF_BYTES, bytes
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Run `program args`, unless `app.dry_run` is set and the command would change
+/// the repository or GitHub. Such commands are logged as `WOULD RUN: ...` and
+/// report success with empty output, so the rest of the flow keeps going.
+pub fn run_command(app: &mut App, program: &str, args: &[&str]) -> std::io::Result<Output> {
+    if app.dry_run && !is_read_only_command(program, args) {
+        app.add_log("INFO", format!("WOULD RUN: {} {}", program, args.join(" ")));
+        return Ok(Output {
+            status: success_status(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        });
+    }
+    Command::new(program).args(args).output()
+}
+
+/// Whether a git/gh/glab invocation only inspects state and is safe under `--dry-run`.
+pub fn is_read_only_command(program: &str, args: &[&str]) -> bool {
+    match (program, args) {
+        ("git", ["branch", "-vv", ..]) | ("git", ["worktree", "list", ..]) => true,
+        ("git", ["config", "--get", ..]) | ("git", ["config", "user.name"]) => true,
+        ("git", ["remote", "get-url", ..]) => true,
+        ("git" | "gh" | "glab", ["--version"]) => true,
+        ("git", [sub, ..]) => matches!(
+            *sub,
+            "diff"
+                | "rev-parse"
+                | "log"
+                | "status"
+                | "merge-base"
+                | "symbolic-ref"
+                | "for-each-ref"
+                | "ls-remote"
+                | "rev-list"
+                | "show"
+                | "write-tree"
+        ),
+        ("gh", [group, action, ..]) => matches!(
+            (*group, *action),
+            ("pr", "list")
+                | ("pr", "view")
+                | ("pr", "checks")
+                | ("issue", "list")
+                | ("repo", "view")
+                | ("auth", "status")
+        ),
+        ("glab", [group, action, ..]) => matches!(
+            (*group, *action),
+            ("mr", "list") | ("mr", "view") | ("issue", "list")
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn success_status() -> std::process::ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    std::process::ExitStatus::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success_status() -> std::process::ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    std::process::ExitStatus::from_raw(0)
+}
+
+/// How to get git working, for `ensure_git_available`.
+pub const GIT_INSTALL_HINT: &str =
+    "Install git (https://git-scm.com/downloads) and make sure it is on PATH.";
+
+/// How to get gh working, for `ensure_gh_available`.
+pub const GH_INSTALL_HINT: &str = "gh-autopr opens PRs with the GitHub CLI: install it from \
+     https://cli.github.com, make sure it is on PATH, and sign in with `gh auth login`.";
+
+/// Fail with `hint` when `program --version` can't be run, instead of the bare
+/// "No such file or directory" the first real invocation would give.
+pub fn ensure_command_available(
+    app: &mut App,
+    program: &str,
+    hint: &str,
+) -> Result<(), Box<dyn Error>> {
+    let problem = match run_command(app, program, &["--version"]) {
+        Ok(output) if output.status.success() => return Ok(()),
+        Ok(output) => format!(
+            "`{} --version` failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            format!("{} is not installed (not found on PATH)", program)
+        }
+        Err(e) => format!("{} could not be run: {}", program, e),
+    };
+    let msg = format!("{}. {}", problem, hint);
+    app.add_error(msg.clone());
+    Err(msg.into())
+}
+
+pub fn ensure_git_available(app: &mut App) -> Result<(), Box<dyn Error>> {
+    ensure_command_available(app, "git", GIT_INSTALL_HINT)
+}
+
+pub fn ensure_gh_available(app: &mut App) -> Result<(), Box<dyn Error>> {
+    ensure_command_available(app, "gh", GH_INSTALL_HINT)
+}
+
+/// Fail before anything is committed or pushed when gh is installed but not
+/// logged in, which would otherwise only show up as gh's stderr at PR time.
+pub fn ensure_gh_authenticated(app: &mut App) -> Result<(), Box<dyn Error>> {
+    let output = run_command(app, "gh", &["auth", "status"])?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut msg = "GitHub CLI is not authenticated; run `gh auth login`".to_string();
+    let details = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if !details.is_empty() {
+        msg.push_str(&format!(
+            " ({})",
+            details.lines().next().unwrap_or_default()
+        ));
+    }
+    app.add_error(msg.clone());
+    Err(msg.into())
+}
 
 pub fn git_ensure_in_repo(app: &mut App) -> Result<(), Box<dyn Error>> {
-    let output = Command::new("git")
-        .args(["rev-parse", "--is-inside-work-tree"])
-        .output()?;
+    let output = run_command(app, "git", &["rev-parse", "--is-inside-work-tree"])?;
 
     if !output.status.success() {
         app.add_log("ERROR", "Not in a git repository.");
-        std::process::exit(1);
+        return Err("Not in a git repository".into());
     }
 
     Ok(())
 }
 
+/// Fail on an unborn branch (a fresh `git init`), where `HEAD` doesn't resolve
+/// and every later diff against it would fail with a less helpful error.
+pub fn git_ensure_has_commits(app: &mut App) -> Result<(), Box<dyn Error>> {
+    let output = run_command(app, "git", &["rev-parse", "--verify", "--quiet", "HEAD"])?;
+    if !output.status.success() {
+        let msg = "repository has no commits yet; make an initial commit first";
+        app.add_error(msg);
+        return Err(msg.into());
+    }
+    Ok(())
+}
+
+/// Fail on a detached HEAD, unless `branch_from_detached` names a branch to
+/// create at the current commit. Returns the branch the run continues on.
 pub fn git_ensure_not_detached_head<B: Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
     branch_name: &String,
-) -> Result<(), Box<dyn Error>>
+    branch_from_detached: Option<&str>,
+) -> Result<String, Box<dyn Error>>
 where
     <B as Backend>::Error: 'static,
 {
     if branch_name == "HEAD" {
+        if let Some(new_branch) = branch_from_detached {
+            return git_branch_from_detached_head(app, new_branch);
+        }
         app.add_log(
             "ERROR",
-            "Detached HEAD state detected. Please check out a branch.",
+            "Detached HEAD state detected. Please check out a branch, or pass --branch-from-detached <name>.",
         );
         render_message(
             terminal,
             "Error",
-            "Detached HEAD state detected. Please check out a branch.",
+            "Detached HEAD state detected. Please check out a branch, or pass --branch-from-detached <name>.",
             Color::Red,
         )?;
         return Err("Detached HEAD state detected".into());
     }
-    Ok(())
+    Ok(branch_name.clone())
+}
+
+/// Create `new_branch` at the detached HEAD and switch to it.
+pub fn git_branch_from_detached_head(
+    app: &mut App,
+    new_branch: &str,
+) -> Result<String, Box<dyn Error>> {
+    if !crate::gpt_ops::is_valid_git_branch_name(new_branch) {
+        let e = format!("Invalid branch name for --branch-from-detached: \"{new_branch}\"");
+        app.add_error(e.clone());
+        return Err(e.into());
+    }
+    let output = run_command(app, "git", &["switch", "-c", new_branch])?;
+    if !output.status.success() {
+        let e = format!(
+            "Could not create branch \"{new_branch}\" from detached HEAD: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        app.add_error(e.clone());
+        return Err(e.into());
+    }
+    app.add_log(
+        "INFO",
+        format!("HEAD was detached; created and switched to branch \"{new_branch}\""),
+    );
+    Ok(new_branch.to_string())
 }
 
 pub fn git_cd_to_repo_root(app: &mut App) -> Result<(), Box<dyn Error>> {
-    let output = Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .output()?;
+    let output = run_command(app, "git", &["rev-parse", "--show-toplevel"])?;
     if output.status.success() {
         let repo_root = String::from_utf8(output.stdout)?.trim().to_string();
         std::env::set_current_dir(&repo_root)?;
@@ -64,21 +248,308 @@ pub fn git_cd_to_repo_root(app: &mut App) -> Result<(), Box<dyn Error>> {
 
 /// Return either
 /// 1. the diff of staged/index changes against `merge_base` (or `HEAD`), or
-/// 2. if nothing is staged, the diff of **working-tree** changes against `merge_base`.
+/// 2. if nothing is staged, the diff of **working-tree** changes against `merge_base`
+///    (empty instead with `app.staged_only`).
 ///
-/// The result is truncated to `MAX_DIFF_BYTES` **on a character boundary**
+/// The result is truncated to `app.max_diff_bytes` **on a character boundary**
 /// to keep it AI-friendly.
 pub fn git_diff_uncommitted(app: &mut App, current_branch: &str) -> Result<String, Box<dyn Error>> {
-    let pathspec = ["--", ".", ":!*.lock"]; // exclude *.lock anywhere
+    let pathspec = build_diff_pathspec(app);
+    let pathspec: Vec<&str> = pathspec.iter().map(String::as_str).collect();
 
     // 1. staged changes first
     if let Some(diff) = git_run_diff(app, true, current_branch, &pathspec)? {
-        return Ok(truncate_utf8(&diff, MAX_DIFF_BYTES));
+        return Ok(model_diff(app, diff));
+    }
+
+    if app.staged_only {
+        app.add_log(
+            "INFO",
+            "--staged-only: nothing is staged, ignoring working-tree changes",
+        );
+        return Ok(String::new());
     }
 
     // 2. otherwise fall back to working-tree changes
     let diff = git_run_diff(app, false, current_branch, &pathspec)?.unwrap_or_default(); // may be empty
-    Ok(truncate_utf8(&diff, MAX_DIFF_BYTES))
+    Ok(model_diff(app, diff))
+}
+
+/// `--include-unpushed`: the uncommitted changes together with the commits on
+/// `branch` that its upstream doesn't have yet, diffed against the merge base
+/// of the two, so the model sees the whole change. `None` when `branch` has no
+/// upstream or nothing unpushed; callers then use [`git_diff_uncommitted`].
+pub fn git_diff_uncommitted_and_unpushed(
+    app: &mut App,
+    branch: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let upstream = format!("{}@{{upstream}}", branch);
+    let Ok(base) = git_output(Command::new("git").args(["merge-base", &upstream, branch])) else {
+        app.add_log(
+            "INFO",
+            format!(
+                "--include-unpushed: {} has no upstream, describing the uncommitted changes only",
+                branch
+            ),
+        );
+        return Ok(None);
+    };
+    let unpushed: usize = git_output(Command::new("git").args([
+        "rev-list",
+        "--count",
+        &format!("{}..{}", base, branch),
+    ]))?
+    .parse()?;
+    if unpushed == 0 {
+        return Ok(None);
+    }
+    app.add_log(
+        "INFO",
+        format!(
+            "--include-unpushed: describing {} unpushed commit(s) together with the uncommitted changes",
+            unpushed
+        ),
+    );
+    // Staged or working-tree changes as `git_diff_uncommitted` would pick them;
+    // against `base` the index always differs, by the unpushed commits
+    let pathspec = build_diff_pathspec(app);
+    let pathspec: Vec<&str> = pathspec.iter().map(String::as_str).collect();
+    let staged = app.staged_only || git_run_diff(app, true, branch, &pathspec)?.is_some();
+    let diff = git_run_diff(app, staged, &base, &pathspec)?.unwrap_or_default();
+    Ok(Some(model_diff(app, diff)))
+}
+
+/// Repo-root file of gitignore-style patterns to keep out of the diffs sent to the AI
+pub const DIFF_IGNORE_FILE: &str = ".gh-autopr-ignore";
+
+/// `-- <pathspec>` for diffs: the `--path` restrictions, or the whole tree,
+/// always excluding `*.lock` files anywhere and the patterns in `DIFF_IGNORE_FILE`.
+pub fn build_diff_pathspec(app: &App) -> Vec<String> {
+    let mut pathspec = vec!["--".to_string()];
+    if app.paths.is_empty() {
+        pathspec.push(".".to_string());
+    } else {
+        pathspec.extend(app.paths.iter().cloned());
+    }
+    pathspec.push(":!*.lock".to_string());
+    if let Ok(patterns) = fs_err::read_to_string(DIFF_IGNORE_FILE) {
+        pathspec.extend(ignore_file_pathspec(&patterns));
+    }
+    pathspec
+}
+
+/// Exclude pathspecs for the gitignore-style `patterns`: a pattern without an
+/// inner `/` matches at any depth, a leading `/` anchors it to the root, and
+/// every pattern also covers what is below a matching directory. Blank lines,
+/// `#` comments and `!` negations (which pathspecs can't express) are skipped.
+pub fn ignore_file_pathspec(patterns: &str) -> Vec<String> {
+    let mut pathspec = Vec::new();
+    for line in patterns.lines().map(str::trim_end) {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let pattern = line.trim_end_matches('/');
+        let glob = match pattern.strip_prefix('/') {
+            Some(anchored) => anchored.to_string(),
+            None if pattern.contains('/') => pattern.to_string(),
+            None => format!("**/{}", pattern),
+        };
+        pathspec.push(format!(":(exclude,glob){}", glob));
+        if !glob.ends_with("/**") {
+            pathspec.push(format!(":(exclude,glob){}/**", glob));
+        }
+    }
+    pathspec
+}
+
+/// Paths that `git diff --numstat -z` reports as binary (`-` for both counts).
+pub fn binary_paths_from_numstat(numstat: &str) -> Vec<String> {
+    numstat
+        .split('\0')
+        .filter_map(|record| record.strip_prefix("-\t-\t"))
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `args` (a `git diff` command line ending in a pathspec) plus excludes for
+/// the files that diff would only report as "Binary files differ".
+fn without_binary_files(app: &mut App, mut args: Vec<String>) -> Vec<String> {
+    let mut numstat = args.clone();
+    numstat.splice(1..1, ["--numstat", "-z", "--no-renames"].map(String::from));
+    let numstat: Vec<&str> = numstat.iter().map(String::as_str).collect();
+    let binary = match run_command(app, "git", &numstat) {
+        Ok(out) if out.status.success() => {
+            binary_paths_from_numstat(&String::from_utf8_lossy(&out.stdout))
+        }
+        _ => return args,
+    };
+    if !binary.is_empty() {
+        app.add_log(
+            "INFO",
+            format!("Leaving {} binary file(s) out of the diff", binary.len()),
+        );
+    }
+    args.extend(
+        binary
+            .iter()
+            .map(|path| format!(":(exclude,literal){}", path)),
+    );
+    args
+}
+
+/// `-- <pathspec>` matching everything outside the `--path` restrictions.
+fn outside_paths_pathspec(paths: &[String]) -> Vec<String> {
+    let mut pathspec = vec!["--".to_string(), ".".to_string()];
+    pathspec.extend(paths.iter().map(|p| format!(":(exclude){}", p)));
+    pathspec
+}
+
+/// Validate `--path` pathspecs (relative to the current directory) and make them
+/// relative to the repo root, where all later git commands run. A pathspec must
+/// match at least one tracked or untracked file.
+pub fn resolve_path_restrictions(paths: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let prefix = git_output(Command::new("git").args(["rev-parse", "--show-prefix"]))?;
+
+    paths
+        .iter()
+        .map(|path| {
+            let matches = git_output(Command::new("git").args([
+                "ls-files",
+                "--cached",
+                "--others",
+                "--exclude-standard",
+                "--",
+                path,
+            ]))?;
+            if matches.is_empty() {
+                return Err(format!("--path {} matches no files", path).into());
+            }
+            Ok(format!("{}{}", prefix, path))
+        })
+        .collect()
+}
+
+/// Tokens that are secrets whatever they are assigned to: AWS access key IDs,
+/// OpenAI-style `sk-` keys, GitHub tokens and PEM private key headers. Masked
+/// in diffs here and in logs by `crate::tui::redact`.
+pub(crate) static SECRET_TOKEN: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(
+        r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b|\bsk-[A-Za-z0-9_-]{16,}|\b(?:gh[pousr]_[A-Za-z0-9]{30,}|github_pat_[A-Za-z0-9_]{30,})|-----BEGIN [A-Z ]*PRIVATE KEY-----",
+    )
+    .expect("valid secret token regex")
+});
+
+/// `NAME = value` and `name: "value"` where the name ends like a secret's.
+static SECRET_ASSIGNMENT: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(
+        r#"(?i)\b[a-z0-9_.-]*(?:token|secret|password|passwd|api_?key)["']?\s*[:=]\s*["']?([^\s"',;]+)"#,
+    )
+    .expect("valid secret assignment regex")
+});
+
+/// Shannon entropy of `value` in bits per character.
+fn entropy(value: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in value.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = value.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Whether the value of a secret-looking assignment is a literal worth
+/// hiding, rather than a reference like `os.environ["X"]` or `${X}` or a word.
+fn looks_like_secret_value(value: &str) -> bool {
+    const MIN_CHARS: usize = 8;
+    const MIN_BITS_PER_CHAR: f64 = 3.0;
+    value.chars().count() >= MIN_CHARS
+        && !value.contains(['(', ')', '$', '{', '}', '[', ']', '<', '>'])
+        && entropy(value) >= MIN_BITS_PER_CHAR
+}
+
+/// Mask likely secrets in a diff with `<redacted>`: known token formats, and
+/// high-entropy values assigned to `*_TOKEN`, `*_SECRET`, `PASSWORD` and the
+/// like. Returns the scrubbed diff and how many lines were masked.
+pub fn scrub_diff(diff: &str) -> (String, usize) {
+    let mut masked_lines = 0;
+    let lines: Vec<String> = diff
+        .split('\n')
+        .map(|line| {
+            let mut scrubbed = SECRET_TOKEN.replace_all(line, "<redacted>").into_owned();
+            let values: Vec<_> = SECRET_ASSIGNMENT
+                .captures_iter(&scrubbed)
+                .filter_map(|caps| caps.get(1))
+                .filter(|value| looks_like_secret_value(value.as_str()))
+                .map(|value| value.range())
+                .collect();
+            for range in values.into_iter().rev() {
+                scrubbed.replace_range(range, "<redacted>");
+            }
+            if scrubbed != line {
+                masked_lines += 1;
+            }
+            scrubbed
+        })
+        .collect();
+    (lines.join("\n"), masked_lines)
+}
+
+/// Prepare a diff for the model: scrub secrets (unless `--no-scrub`), then truncate.
+fn model_diff(app: &mut App, diff: String) -> String {
+    let diff = if app.scrub_secrets {
+        let (scrubbed, masked) = scrub_diff(&diff);
+        if masked > 0 {
+            app.add_log(
+                "WARN",
+                format!(
+                    "Masked likely secrets on {} diff line(s) before sending them to the AI (--no-scrub sends them as is)",
+                    masked
+                ),
+            );
+        }
+        scrubbed
+    } else {
+        diff
+    };
+    truncate_diff(app, diff)
+}
+
+/// Cap a diff at `app.max_diff_bytes`, warning when context is lost. With
+/// `--summarize-large` it is kept whole, to be summarized per file instead.
+fn truncate_diff(app: &mut App, diff: String) -> String {
+    if diff.len() <= app.max_diff_bytes {
+        return diff;
+    }
+    if app.summarize_large {
+        app.add_log(
+            "INFO",
+            format!(
+                "Diff is {} bytes, over {}; it will be summarized per file (--summarize-large)",
+                diff.len(),
+                app.max_diff_bytes
+            ),
+        );
+        return diff;
+    }
+    app.add_log(
+        "WARN",
+        format!(
+            "Diff is {} bytes, truncated to {} (raise with --max-diff-bytes)",
+            diff.len(),
+            app.max_diff_bytes
+        ),
+    );
+    truncate_utf8(&diff, app.max_diff_bytes)
 }
 
 /// Helper: run `git diff`, returning `Ok(Some(diff))` if diff is non-empty.
@@ -88,14 +559,16 @@ fn git_run_diff(
     base: &str,
     pathspec: &[&str],
 ) -> Result<Option<String>, Box<dyn Error>> {
-    let mut args = vec!["diff"];
+    let mut args = vec!["diff".to_string()];
     if staged {
-        args.push("--staged"); // alias for `--cached`
+        args.push("--staged".to_string()); // alias for `--cached`
     }
-    args.push(base);
-    args.extend_from_slice(pathspec);
+    args.push(base.to_string());
+    args.extend(pathspec.iter().map(|spec| spec.to_string()));
+    let args = without_binary_files(app, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
-    let out = Command::new("git").args(&args).output()?;
+    let out = run_command(app, "git", &args)?;
     if !out.status.success() {
         app.add_error(String::from_utf8_lossy(&out.stderr).to_string());
         return Err("git diff failed".into());
@@ -110,89 +583,574 @@ pub fn truncate_utf8(s: &str, max_bytes: usize) -> String {
     if s.len() <= max_bytes {
         return s.to_owned();
     }
-    let mut end = max_bytes;
-    while !s.is_char_boundary(end) {
-        end -= 1;
-    } // back up to char boundary
+    // Back up to the nearest char boundary; 0 always is one, so no underflow
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0);
     s[..end].to_owned()
 }
 
-/// Get diff between the current branch and its parent/base branch.
+/// Drop unchanged context lines from a unified diff, keeping only file headers,
+/// hunk headers (`@@`) and `+`/`-` lines. Roughly halves the token count of
+/// context-heavy diffs at some comprehension cost for the model.
+pub fn strip_diff_context(diff: &str) -> String {
+    diff.lines()
+        .filter(|line| !line.starts_with(' '))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drop individual file diffs larger than `max_bytes`, leaving a one-line note
+/// in their place. Returns the filtered diff and the paths that were omitted.
+pub fn drop_oversized_file_diffs(diff: &str, max_bytes: usize) -> (String, Vec<String>) {
+    let mut kept = String::with_capacity(diff.len().min(max_bytes * 4));
+    let mut omitted = Vec::new();
+
+    for chunk in split_file_diffs(diff) {
+        if chunk.len() <= max_bytes {
+            kept.push_str(chunk);
+            continue;
+        }
+        let path = chunk
+            .lines()
+            .next()
+            .and_then(|header| header.rsplit_once(" b/"))
+            .map(|(_, path)| path.to_string())
+            .unwrap_or_else(|| "<unknown>".to_string());
+        kept.push_str(&format!(
+            "# Omitted {} (diff is {} bytes, over the {} byte per-file limit)\n",
+            path,
+            chunk.len(),
+            max_bytes
+        ));
+        omitted.push(path);
+    }
+
+    (kept, omitted)
+}
+
+/// Size of a unified diff: files touched and lines added plus removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffSize {
+    pub files: usize,
+    pub changed_lines: usize,
+}
+
+pub fn diff_size(diff: &str) -> DiffSize {
+    let mut size = DiffSize {
+        files: 0,
+        changed_lines: 0,
+    };
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            size.files += 1;
+        } else if (line.starts_with('+') && !line.starts_with("+++"))
+            || (line.starts_with('-') && !line.starts_with("---"))
+        {
+            size.changed_lines += 1;
+        }
+    }
+    size
+}
+
+/// Split a unified diff into per-file chunks on `diff --git` boundaries.
+/// Any preamble before the first header is returned as its own chunk.
+fn split_file_diffs(diff: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = diff
+        .match_indices("diff --git ")
+        .map(|(i, _)| i)
+        .filter(|&i| i == 0 || diff.as_bytes()[i - 1] == b'\n')
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts.push(diff.len());
+    starts
+        .windows(2)
+        .map(|w| &diff[w[0]..w[1]])
+        .filter(|chunk| !chunk.is_empty())
+        .collect()
+}
+
+/// Split a unified diff into `(path, file diff)` pairs, one per file, in diff
+/// order. The path is the one after the change (the new name of a rename);
+/// text before the first `diff --git` header is left out.
+pub fn split_diff_by_file(diff: &str) -> Vec<(String, String)> {
+    split_file_diffs(diff)
+        .into_iter()
+        .filter_map(|chunk| {
+            let header = chunk.lines().next()?.strip_prefix("diff --git ")?;
+            let path = chunk
+                .lines()
+                .find_map(|line| {
+                    line.strip_prefix("rename to ")
+                        .or_else(|| line.strip_prefix("+++ b/"))
+                })
+                .or_else(|| header.rsplit_once(" b/").map(|(_, path)| path))
+                .unwrap_or(header);
+            Some((path.to_string(), chunk.to_string()))
+        })
+        .collect()
+}
+
+/// Get diff between the current branch and its parent/base branch, taking the
+/// base from `<remote>/<base>` when that is fresher (see [`resolve_base_ref`]).
 pub fn git_diff_between_branches(
     app: &mut App,
     base_branch: &str,
     current_branch: &str,
 ) -> Result<String, Box<dyn Error>> {
+    let base_ref = resolve_base_ref(&app.remote, base_branch);
+    if base_ref == base_branch {
+        app.add_log(
+            "INFO",
+            format!(
+                "Comparing {} against base branch: {}",
+                current_branch, base_branch
+            ),
+        );
+    } else {
+        app.add_log(
+            "INFO",
+            format!(
+                "Comparing {} against base branch: {} (local {} is missing or behind it)",
+                current_branch, base_ref, base_branch
+            ),
+        );
+    }
+
+    git_run_range_diff(app, &format!("{}...{}", base_ref, current_branch))
+}
+
+/// `<remote>/<base>` when the local `base` branch is missing or strictly
+/// behind it, else `base` unchanged. A local branch that is ahead or has
+/// diverged is the user's own work and is kept; so is any `base` the remote
+/// doesn't have, such as a SHA or `HEAD~1`.
+pub fn resolve_base_ref(remote: &str, base: &str) -> String {
+    let remote_ref = format!("{}/{}", remote, base);
+    if !remote_ref_exists(remote, base).unwrap_or(false) {
+        return base.to_string();
+    }
+    if !local_branch_exists(base).unwrap_or(true) {
+        return remote_ref;
+    }
+    let local = format!("refs/heads/{}", base);
+    let behind = git_is_ancestor(&local, &remote_ref).unwrap_or(false)
+        && !git_is_ancestor(&remote_ref, &local).unwrap_or(true);
+    if behind {
+        remote_ref
+    } else {
+        base.to_string()
+    }
+}
+
+/// Get diff of exactly what `current_branch` introduced since it forked from
+/// `base_branch`, using `git merge-base --fork-point`. Unlike the three-dot diff,
+/// this consults the base's reflog and so copes with bases that were rewritten
+/// after the branch was created. Falls back to [`git_diff_between_branches`]
+/// when no fork point can be determined.
+pub fn git_diff_since_fork_point(
+    app: &mut App,
+    base_branch: &str,
+    current_branch: &str,
+) -> Result<String, Box<dyn Error>> {
+    let output = run_command(
+        app,
+        "git",
+        &["merge-base", "--fork-point", base_branch, current_branch],
+    )?;
+    let fork_point = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if !output.status.success() || fork_point.is_empty() {
+        app.add_log(
+            "INFO",
+            format!(
+                "No fork point found between {} and {}, using merge base",
+                base_branch, current_branch
+            ),
+        );
+        return git_diff_between_branches(app, base_branch, current_branch);
+    }
+
     app.add_log(
         "INFO",
         format!(
-            "Comparing {} against base branch: {}",
-            current_branch, base_branch
+            "Comparing {} against fork point {} of {}",
+            current_branch,
+            &fork_point[..fork_point.len().min(12)],
+            base_branch
         ),
     );
+    git_run_range_diff(app, &format!("{}..{}", fork_point, current_branch))
+}
+
+/// Helper: run `git diff <range>` over the default pathspec.
+fn git_run_range_diff(app: &mut App, range: &str) -> Result<String, Box<dyn Error>> {
+    let mut args = vec!["diff".to_string(), range.to_string()];
+    args.extend(build_diff_pathspec(app));
+    let args = without_binary_files(app, args);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run_command(app, "git", &args)?;
+
+    if !output.status.success() {
+        app.add_error(String::from_utf8_lossy(&output.stderr).to_string());
+        return Err(format!(
+            "Failed to get diff between branches: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+
+    let diff = String::from_utf8(output.stdout)?.trim().to_string();
+    Ok(model_diff(app, diff))
+}
+
+/// A single commit's message, split into subject line and body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitMessage {
+    pub subject: String,
+    pub body: String,
+}
+
+/// List the commits on `current_branch` that are not on `base_branch`, oldest first.
+pub fn git_branch_commits(
+    app: &mut App,
+    base_branch: &str,
+    current_branch: &str,
+) -> Result<Vec<CommitMessage>, Box<dyn Error>> {
+    // %x1f separates subject from body, %x1e terminates each commit
+    let output = run_command(
+        app,
+        "git",
+        &[
+            "log",
+            "--reverse",
+            "--format=%s%x1f%b%x1e",
+            &format!("{}..{}", base_branch, current_branch),
+        ],
+    )?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).to_string();
+        app.add_error(err.clone());
+        return Err(format!("Failed to list branch commits: {}", err).into());
+    }
+
+    let commits: Vec<CommitMessage> = String::from_utf8(output.stdout)?
+        .split('\x1e')
+        .filter_map(|record| {
+            let (subject, body) = record.trim_start_matches('\n').split_once('\x1f')?;
+            Some(CommitMessage {
+                subject: subject.trim().to_string(),
+                body: without_generated_trailer(body.trim()).to_string(),
+            })
+        })
+        .collect();
+
+    app.add_log(
+        "INFO",
+        format!("Found {} commits on {}", commits.len(), current_branch),
+    );
+    Ok(commits)
+}
+
+/// Render commits as a Markdown PR body: one bullet per commit subject, with
+/// the commit body indented beneath it.
+pub fn format_commits_as_pr_body(commits: &[CommitMessage]) -> String {
+    let mut body = String::from("### Commits\n");
+    for commit in commits {
+        body.push_str(&format!("- {}\n", commit.subject));
+        for line in commit.body.lines() {
+            if line.trim().is_empty() {
+                body.push('\n');
+            } else {
+                body.push_str(&format!("  {}\n", line));
+            }
+        }
+    }
+    body.trim_end().to_string()
+}
+
+/// `git diff --stat` of `base_branch...current_branch`: the branch's changes since it forked.
+pub fn git_diff_stat(
+    app: &mut App,
+    base_branch: &str,
+    current_branch: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut args = vec![
+        "diff".to_string(),
+        "--stat".to_string(),
+        format!("{}...{}", base_branch, current_branch),
+    ];
+    args.extend(build_diff_pathspec(app));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run_command(app, "git", &args)?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).to_string();
+        app.add_error(err.clone());
+        return Err(format!("Failed to compute diffstat: {}", err).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+const DIFFSTAT_OPEN: &str = "<details><summary>Diffstat</summary>";
+const DIFFSTAT_CLOSE: &str = "</details>";
+
+/// Put `stat` into a collapsed `<details>` block at the end of `body`. An
+/// existing diffstat block is regenerated in place instead of duplicated.
+pub fn with_diffstat_block(body: &str, stat: &str) -> String {
+    let block = format!(
+        "{}\n\n```\n{}\n```\n\n{}",
+        DIFFSTAT_OPEN, stat, DIFFSTAT_CLOSE
+    );
+
+    if let Some(start) = body.find(DIFFSTAT_OPEN) {
+        if let Some(len) = body[start..].find(DIFFSTAT_CLOSE) {
+            let end = start + len + DIFFSTAT_CLOSE.len();
+            return format!("{}{}{}", &body[..start], block, &body[end..]);
+        }
+    }
+
+    let body = body.trim_end();
+    if body.is_empty() {
+        block
+    } else {
+        format!("{}\n\n{}", body, block)
+    }
+}
+
+/// Append `items` to `body` as a GitHub task list. Items already present in the
+/// body (checked or not) are skipped, so re-applying a checklist never duplicates it.
+pub fn append_checklist(body: &str, items: &[String]) -> String {
+    let missing: Vec<&String> = items
+        .iter()
+        .filter(|item| {
+            !body.lines().any(|line| {
+                let line = line.trim();
+                line == format!("- [ ] {}", item)
+                    || line.eq_ignore_ascii_case(&format!("- [x] {}", item))
+            })
+        })
+        .collect();
+    if missing.is_empty() {
+        return body.to_string();
+    }
+
+    let mut result = body.trim_end().to_string();
+    if !result.is_empty() {
+        result.push_str("\n\n");
+    }
+    let list: Vec<String> = missing
+        .iter()
+        .map(|item| format!("- [ ] {}", item))
+        .collect();
+    result.push_str(&list.join("\n"));
+    result
+}
+
+/// PR title and body in the style of `gh pr create --fill`: the first commit's
+/// subject becomes the title and the commit bodies, joined, the description.
+/// Returns `None` when there are no commits.
+pub fn fill_pr_from_commits(commits: &[CommitMessage]) -> Option<(String, Option<String>)> {
+    let title = commits.first()?.subject.clone();
+    let bodies: Vec<&str> = commits
+        .iter()
+        .map(|c| c.body.as_str())
+        .filter(|b| !b.is_empty())
+        .collect();
+    let body = (!bodies.is_empty()).then(|| bodies.join("\n\n"));
+    Some((title, body))
+}
+
+/// The remote to fetch from and push to when `--remote` is not given: the
+/// remote the current branch tracks, else `origin`, else the only remote.
+/// Falls back to `origin` (and so the usual git errors) when none applies.
+pub fn git_default_remote(app: &mut App) -> Result<String, Box<dyn Error>> {
+    let tracked = git_output(Command::new("git").args(["symbolic-ref", "--quiet", "HEAD"]))
+        .and_then(|head| {
+            git_output(Command::new("git").args([
+                "for-each-ref",
+                "--format=%(upstream:remotename)",
+                &head,
+            ]))
+        })
+        .ok()
+        .filter(|remote| !remote.is_empty() && remote != ".");
+    let remotes = git_output(Command::new("git").arg("remote"))?;
+    let remotes: Vec<&str> = remotes.lines().collect();
+    let remote = match tracked {
+        Some(remote) => remote,
+        None if remotes.contains(&"origin") => "origin".to_string(),
+        None if remotes.len() == 1 => remotes[0].to_string(),
+        None => "origin".to_string(),
+    };
+    if remote != "origin" {
+        app.add_log("INFO", format!("Using remote: {}", remote));
+    }
+    Ok(remote)
+}
+
+/// Main branch found by the first successful `git_main_branch` of this process
+static MAIN_BRANCH_CACHE: OnceCell<Mutex<Option<String>>> = OnceCell::new();
+
+/// Determine the repository's main branch from `<remote>/HEAD` (`app.remote`).
+/// The result is remembered for the rest of the process, so the `set-head
+/// --auto` below, which contacts the remote, runs at most once.
+///
+/// `origin/HEAD` is a locally cached symref, so after a default-branch rename
+/// on the remote it can point at a branch that no longer exists. The detected
+/// branch is therefore verified; a stale value triggers `set-head --auto`, and
+/// if that does not help, the well-known names `main` and `master` are tried.
+pub fn git_main_branch(app: &mut App) -> Result<String, Box<dyn Error>> {
+    let cache = MAIN_BRANCH_CACHE.get_or_init(|| Mutex::new(None));
+    let mut cache = cache.lock().unwrap();
+    if let Some(branch) = cache.as_ref() {
+        app.add_log("INFO", format!("Using cached main branch: {}", branch));
+        return Ok(branch.clone());
+    }
+    let branch = detect_main_branch(app)?;
+    *cache = Some(branch.clone());
+    Ok(branch)
+}
+
+/// Forget the main branch remembered by `git_main_branch`, for tests that
+/// switch between repositories.
+#[allow(dead_code)] // only called by tests
+pub fn reset_main_branch_cache() {
+    if let Some(cache) = MAIN_BRANCH_CACHE.get() {
+        *cache.lock().unwrap() = None;
+    }
+}
+
+fn detect_main_branch(app: &mut App) -> Result<String, Box<dyn Error>> {
+    let remote = app.remote.clone();
+    let stale = match read_remote_head(&remote)? {
+        Some(branch) if remote_ref_exists(&remote, &branch)? => {
+            app.add_log("INFO", format!("Determined main branch: {}", branch));
+            return Ok(branch);
+        }
+        Some(stale) => {
+            app.add_log(
+                "WARN",
+                format!(
+                    "{}/HEAD points at {}/{}, which no longer exists; re-detecting",
+                    remote, remote, stale
+                ),
+            );
+            Some(stale)
+        }
+        None => {
+            app.add_log("INFO", format!("Setting {} HEAD automatically...", remote));
+            None
+        }
+    };
+
+    let output = run_command(app, "git", &["remote", "set-head", &remote, "--auto"])?;
+    let set_head_err = if output.status.success() {
+        if let Some(branch) = read_remote_head(&remote)? {
+            if remote_ref_exists(&remote, &branch)? {
+                if let Some(stale) = stale {
+                    app.add_log(
+                        "WARN",
+                        format!("Corrected stale {}/HEAD: {} -> {}", remote, stale, branch),
+                    );
+                }
+                app.add_log("INFO", format!("Determined main branch: {}", branch));
+                return Ok(branch);
+            }
+        }
+        format!("{}/HEAD still does not point at an existing branch", remote)
+    } else {
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+
+    for fallback in ["main", "master"] {
+        if remote_ref_exists(&remote, fallback)? {
+            app.add_log(
+                "WARN",
+                format!(
+                    "Could not determine main branch from {}/HEAD, falling back to {}",
+                    remote, fallback
+                ),
+            );
+            return Ok(fallback.to_string());
+        }
+    }
+
+    app.add_error(set_head_err.clone());
+    Err(format!("Failed to determine main branch: {}", set_head_err).into())
+}
 
+/// Branch `<remote>/HEAD` points at (without the `<remote>/` prefix), if set.
+/// Uses `symbolic-ref` so a dangling `<remote>/HEAD` is still readable.
+fn read_remote_head(remote: &str) -> Result<Option<String>, Box<dyn Error>> {
     let output = Command::new("git")
         .args([
-            "diff",
-            &format!("{}...{}", base_branch, current_branch),
-            "--",
-            ".",
-            ":!*.lock",
+            "symbolic-ref",
+            "--quiet",
+            "--short",
+            &format!("refs/remotes/{}/HEAD", remote),
         ])
         .output()?;
-
     if !output.status.success() {
-        app.add_error(String::from_utf8_lossy(&output.stderr).to_string());
-        return Err(format!(
-            "Failed to get diff between branches: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )
-        .into());
+        return Ok(None);
     }
-
-    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    let branch = String::from_utf8(output.stdout)?;
+    let branch = branch
+        .trim()
+        .trim_start_matches(&format!("{}/", remote))
+        .to_string();
+    Ok(if branch.is_empty() {
+        None
+    } else {
+        Some(branch)
+    })
 }
 
-pub fn git_main_branch(app: &mut App) -> Result<String, Box<dyn Error>> {
-    let mut main_branch_output = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "origin/HEAD"])
-        .output()?;
-
-    if !main_branch_output.status.success() {
-        app.add_log("INFO", "Setting origin HEAD automatically...");
-        let output = Command::new("git")
-            .args(["remote", "set-head", "origin", "--auto"])
-            .output()?;
-
-        if !output.status.success() {
-            let err = String::from_utf8_lossy(&output.stderr).to_string();
-            app.add_error(err.clone());
-            return Err(format!("Failed to set origin HEAD: {}", err).into());
-        }
-
-        main_branch_output = Command::new("git")
-            .args(["rev-parse", "--abbrev-ref", "origin/HEAD"])
-            .output()?;
+/// Whether the remote-tracking ref `<remote>/<branch>` exists locally.
+fn remote_ref_exists(remote: &str, branch: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(Command::new("git")
+        .args([
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/remotes/{}/{}", remote, branch),
+        ])
+        .output()?
+        .status
+        .success())
+}
 
-        if !main_branch_output.status.success() {
-            let err = String::from_utf8_lossy(&main_branch_output.stderr).to_string();
-            app.add_error(err.clone());
-            return Err(format!("Failed to determine main branch: {}", err).into());
-        }
-    }
+/// The commit `rev` (a SHA, tag, branch, `HEAD~3`, ...) names, for `--since-commit`.
+pub fn git_verify_commit(rev: &str) -> Result<String, Box<dyn Error>> {
+    git_output(Command::new("git").args([
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{}^{{commit}}", rev),
+    ]))
+    .map_err(|_| format!("--since-commit {}: not a commit in this repository", rev).into())
+}
 
-    let branch = String::from_utf8(main_branch_output.stdout)?
-        .trim()
-        .trim_start_matches("origin/")
-        .to_string();
-    app.add_log("INFO", format!("Determined main branch: {}", branch));
-    Ok(branch)
+/// Whether `refs/heads/<branch>` exists.
+pub fn local_branch_exists(branch: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(Command::new("git")
+        .args([
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{}", branch),
+        ])
+        .output()?
+        .status
+        .success())
 }
 
 pub fn git_current_branch(app: &mut App) -> Result<String, Box<dyn Error>> {
-    let output = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "HEAD"])
-        .output()?;
+    let output = run_command(app, "git", &["rev-parse", "--abbrev-ref", "HEAD"])?;
 
     if !output.status.success() {
         let err = String::from_utf8_lossy(&output.stderr).to_string();
@@ -226,37 +1184,67 @@ pub fn discover_parent_branch(
     }
 
     // 1. explicit upstream, if configured
-    if let Some(up) = upstream_of(child)? {
+    if let Some(up) = upstream_of(&app.remote, child)? {
         if up != child {
             app.add_log("INFO", format!("Found upstream branch: {}", up));
             return Ok(up);
         }
     }
 
-    // 2. fall back to "nearest ancestor" among local branches
-    let local_branches = for_each_local_ref()?;
+    // 2. fall back to "nearest ancestor" among local branches. A fixed number of
+    //    git calls, however many branches there are: ancestors come from one
+    //    for-each-ref, distances from one walk of `child`'s history that stops
+    //    at the branches, so it only reads the commits `child` is ahead by.
+    //    Only the branches that walk ends on when `child` merged several are
+    //    counted on their own.
     let mut best: Option<(String, usize)> = None; // (branch, distance)
 
-    for cand in &local_branches {
-        if cand == child {
-            continue;
-        }
-
-        // Skip candidates that are not ancestors of `child`.
-        let is_ancestor = Command::new("git")
-            .args(["merge-base", "--is-ancestor", cand, child])
-            .status()?
-            .success();
-
-        if !is_ancestor {
-            continue;
-        }
+    let ancestors = local_ancestor_refs(child)?;
+    if !ancestors.is_empty() {
+        let child_tip = git_output(Command::new("git").args([
+            "rev-parse",
+            "--verify",
+            &format!("{child}^{{commit}}"),
+        ]))?;
+        let mut frontier: Option<Frontier> = None;
+
+        for (cand, tip) in &ancestors {
+            if cand == child {
+                continue;
+            }
 
-        // Distance = #commits child is ahead of cand.
-        let dist = commit_distance(cand, child)?;
-        match best {
-            Some((_, d)) if d <= dist => {} // keep closer branch
-            _ => best = Some((cand.clone(), dist)),
+            // Distance = #commits child is ahead of cand.
+            let dist = if *tip == child_tip {
+                0
+            } else {
+                let frontier = match frontier {
+                    Some(ref frontier) => frontier,
+                    None => {
+                        let tips = ancestors.iter().map(|(_, tip)| tip.as_str());
+                        frontier.insert(Frontier::of(&child_tip, tips)?)
+                    }
+                };
+                if !frontier.reached.contains(tip) {
+                    continue; // an ancestor of a branch that is closer
+                }
+                if frontier.reached.len() == 1 {
+                    frontier.ahead
+                } else {
+                    git_output(Command::new("git").args([
+                        "rev-list",
+                        "--count",
+                        &format!("{tip}..{child_tip}"),
+                    ]))?
+                    .parse()?
+                }
+            };
+            match best {
+                Some((_, d)) if d <= dist => {} // keep closer branch
+                _ => best = Some((cand.clone(), dist)),
+            }
+            if dist == 0 {
+                break; // nothing can be closer
+            }
         }
     }
 
@@ -272,8 +1260,9 @@ pub fn discover_parent_branch(
 
 /* ─────────────────────────── helpers ─────────────────────────────────────── */
 
-// Upstream branch, if any (e.g. "origin/main" or "branch_a")
-fn upstream_of(branch: &str) -> Result<Option<String>, Box<dyn Error>> {
+// Upstream branch without the `remote/` prefix, if any (e.g. "main" for
+// "origin/main", or "branch_a")
+fn upstream_of(remote: &str, branch: &str) -> Result<Option<String>, Box<dyn Error>> {
     let spec = format!("{branch}@{{upstream}}");
     let out = Command::new("git")
         .args(["rev-parse", "--abbrev-ref", &spec])
@@ -282,7 +1271,7 @@ fn upstream_of(branch: &str) -> Result<Option<String>, Box<dyn Error>> {
     if out.status.success() {
         let up = String::from_utf8_lossy(&out.stdout)
             .trim()
-            .trim_start_matches("origin/")
+            .trim_start_matches(&format!("{}/", remote))
             .to_owned();
         Ok(if up.is_empty() { None } else { Some(up) })
     } else {
@@ -290,35 +1279,69 @@ fn upstream_of(branch: &str) -> Result<Option<String>, Box<dyn Error>> {
     }
 }
 
-// List local branch names (short form, no remotes)
-fn for_each_local_ref() -> Result<Vec<String>, Box<dyn Error>> {
+// Local branches (short name, tip) that are ancestors of `child`, in refname
+// order; none if git can't resolve `child`
+fn local_ancestor_refs(child: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
     let out = Command::new("git")
-        .args(["for-each-ref", "--format=%(refname:short)", "refs/heads/"])
+        .args([
+            "for-each-ref",
+            &format!("--merged={child}"),
+            "--format=%(refname:short) %(objectname)",
+            "refs/heads/",
+        ])
         .output()?;
 
     if !out.status.success() {
-        return Err("git for-each-ref failed".into());
+        return Ok(Vec::new());
     }
     Ok(String::from_utf8(out.stdout)?
         .lines()
-        .map(|s| s.to_owned())
+        .filter_map(|line| line.rsplit_once(' '))
+        .map(|(name, tip)| (name.to_owned(), tip.to_owned()))
         .collect())
 }
 
-// Count commits reachable from `to` and not from `from`
-fn commit_distance(from: &str, to: &str) -> Result<usize, Box<dyn Error>> {
-    let range = format!("{from}..{to}");
-    let out = Command::new("git")
-        .args(["rev-list", "--count", &range])
-        .output()?;
+// Where `tip`'s history meets the branches, read with a single
+// `git rev-list --parents <tip> --not <branch tips>`, so only the commits `tip`
+// is ahead by are read. A branch whose tip is not reached is an ancestor of one
+// that is, so it is never the nearest; when only one tip is reached, the
+// commits read are exactly how far `tip` is ahead of it.
+struct Frontier {
+    // Commits `tip` has and none of the branches do
+    ahead: usize,
+    // Branch tips those commits have as parents
+    reached: std::collections::HashSet<String>,
+}
 
-    if !out.status.success() {
-        return Err("git rev-list failed".into());
+impl Frontier {
+    fn of<'a>(
+        tip: &str,
+        branch_tips: impl Iterator<Item = &'a str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let branch_tips: std::collections::HashSet<&str> = branch_tips
+            .filter(|branch_tip| *branch_tip != tip)
+            .collect();
+        let mut cmd = Command::new("git");
+        cmd.args(["rev-list", "--parents", tip, "--not"])
+            .args(&branch_tips);
+        let out = git_output(&mut cmd)?;
+
+        let mut ahead = 0;
+        let mut reached = std::collections::HashSet::new();
+        for line in out.lines() {
+            ahead += 1;
+            let parents = line.split_whitespace().skip(1);
+            reached.extend(
+                parents
+                    .filter(|id| branch_tips.contains(id))
+                    .map(str::to_owned),
+            );
+        }
+        Ok(Self { ahead, reached })
     }
-    Ok(String::from_utf8_lossy(&out.stdout).trim().parse()?)
 }
 
-/// Fetch/pull latest changes from origin.
+/// Fetch/pull latest changes from `app.remote`.
 /// When on main branch: just fetch (don't pull - we'll work in temp worktree).
 /// When on feature branch: fetch the main branch for comparison.
 pub fn git_fetch_main(
@@ -326,23 +1349,26 @@ pub fn git_fetch_main(
     current_branch: &str,
     main_branch: &str,
 ) -> Result<(), Box<dyn Error>> {
+    let remote = app.remote.clone();
     if current_branch == main_branch {
         // Just fetch, don't pull - temp worktree handles dirty state
-        let output = Command::new("git").args(["fetch", "origin"]).output()?;
+        let output = run_command(app, "git", &["fetch", &remote])?;
         if !output.status.success() {
             let err = String::from_utf8_lossy(&output.stderr).to_string();
             app.add_error(err.clone());
-            return Err(format!("Failed to fetch from origin: {}", err).into());
+            return Err(format!("Failed to fetch from {}: {}", remote, err).into());
         }
-        app.add_log("INFO", "Fetched latest changes from origin");
+        app.add_log("INFO", format!("Fetched latest changes from {}", remote));
     } else {
-        let output = Command::new("git")
-            .args([
+        let output = run_command(
+            app,
+            "git",
+            &[
                 "fetch",
-                "origin",
+                &remote,
                 &format!("{}:{}", main_branch, main_branch),
-            ])
-            .output()?;
+            ],
+        )?;
         if !output.status.success() {
             let err = String::from_utf8_lossy(&output.stderr).to_string();
             app.add_error(err.clone());
@@ -354,15 +1380,61 @@ pub fn git_fetch_main(
     Ok(())
 }
 
+/// Permission for `git_checkout_new_branch` to reset an existing branch
+/// instead of picking a suffixed name.
+#[derive(Debug, Clone, Copy)]
+pub struct ForceReset<'a> {
+    /// Why the branch is reset, for the log and the refusal
+    pub reason: &'a str,
+    /// Also reset a branch with commits the new start point lacks, dropping them
+    pub discard_commits: bool,
+}
+
+/// Create `branch_name` at `current_branch` and check it out. An existing
+/// branch of that name is kept and a suffixed name used, unless `force_reset`
+/// allows resetting it.
 pub fn git_checkout_new_branch(
     app: &mut App,
     branch_name: &str,
     current_branch: &str,
-    force_reset: bool,
+    force_reset: Option<ForceReset>,
 ) -> Result<String, Box<dyn Error>> {
     let mut candidate = branch_name.to_owned();
 
-    if !force_reset {
+    if let Some(ForceReset {
+        reason,
+        discard_commits,
+    }) = force_reset
+    {
+        let exists = Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("refs/heads/{}", candidate))
+            .output()?
+            .status
+            .success();
+        if exists && !git_is_ancestor(&candidate, current_branch)? {
+            if !discard_commits {
+                let e = format!(
+                    "Refusing to reset branch \"{candidate}\" to \"{current_branch}\" ({reason}): \
+                     it has commits that \"{current_branch}\" doesn't, which would be lost"
+                );
+                app.add_error(e.clone());
+                return Err(e.into());
+            }
+            app.add_log(
+                "WARN",
+                format!(
+                    "Resetting branch \"{candidate}\" to \"{current_branch}\" ({reason}), \
+                     dropping its own commits"
+                ),
+            );
+        } else if exists {
+            app.add_log(
+                "INFO",
+                format!("Resetting branch \"{candidate}\" to \"{current_branch}\" ({reason})"),
+            );
+        }
+    } else {
         // If the branch already exists locally, pick a suffixed name instead of failing.
         const MAX_SUFFIX: u32 = 20;
         for suffix in 0..=MAX_SUFFIX {
@@ -390,10 +1462,20 @@ pub fn git_checkout_new_branch(
         }
     }
 
+    // `feat/foo` cannot coexist with a `feat` branch: one ref would be a file,
+    // the other a directory of the same name. git's own error is cryptic.
+    if let Some(existing) = colliding_branch(&candidate)? {
+        let e = format!(
+            "Cannot create branch \"{candidate}\": it collides with existing branch \"{existing}\" \
+             (a branch name cannot also be a directory of other branches). \
+             Rename or delete \"{existing}\", or pick another name."
+        );
+        app.add_error(e.clone());
+        return Err(e.into());
+    }
+
     // Create or reset branch to current_branch's tip
-    let output = Command::new("git")
-        .args(["checkout", "-B", &candidate, current_branch])
-        .output()?;
+    let output = run_command(app, "git", &["checkout", "-B", &candidate, current_branch])?;
 
     if !output.status.success() {
         let err = String::from_utf8_lossy(&output.stderr).to_string();
@@ -408,19 +1490,154 @@ pub fn git_checkout_new_branch(
 
     Ok(candidate)
 }
+
+/// Whether `ancestor` is `descendant` or one of its ancestors.
+fn git_is_ancestor(ancestor: &str, descendant: &str) -> Result<bool, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["merge-base", "--is-ancestor", ancestor, descendant])
+        .output()?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(format!(
+            "git merge-base --is-ancestor {} {} failed: {}",
+            ancestor,
+            descendant,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into()),
+    }
+}
+
+/// A local branch that `branch` cannot coexist with: one of its parent
+/// directories (`feat` for `feat/foo`), or one nested below it (`feat/foo`
+/// for `feat`).
+fn colliding_branch(branch: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let parts: Vec<&str> = branch.split('/').collect();
+    for depth in 1..parts.len() {
+        let parent = parts[..depth].join("/");
+        if local_branch_exists(&parent)? {
+            return Ok(Some(parent));
+        }
+    }
+    let nested = git_output(Command::new("git").args([
+        "for-each-ref",
+        "--count=1",
+        "--format=%(refname:short)",
+        &format!("refs/heads/{}/", branch),
+    ]))?;
+    Ok((!nested.is_empty()).then_some(nested))
+}
+
+/// The configured `user.name`, if any.
+pub fn git_user_name() -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// The configured `user.email`, if any.
+pub fn git_user_email() -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "user.email"])
+        .output()
+        .ok()?;
+    let email = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !email.is_empty()).then_some(email)
+}
+
+/// Whether `author` has git's `Name <email>` shape, as accepted by `git commit --author`.
+pub fn is_valid_commit_author(author: &str) -> bool {
+    let Some((name, rest)) = author.trim().split_once('<') else {
+        return false;
+    };
+    let Some(email) = rest.strip_suffix('>') else {
+        return false;
+    };
+    !name.trim().is_empty()
+        && !email.contains(['<', '>'])
+        && email
+            .split_once('@')
+            .is_some_and(|(user, host)| !user.is_empty() && !host.is_empty())
+}
+
+/// Trailer on the commits gh-autopr makes, so `--amend` only rewrites its own.
+pub const GENERATED_TRAILER: &str = "Generated-by: gh-autopr";
+
+/// Commit message for a title and optional details, ending in a trailer
+/// block of `trailers` (such as `Signed-off-by: ...`) and `GENERATED_TRAILER`.
+/// Runs of blank lines in the details are collapsed, and lines of the details
+/// that repeat one of `trailers` move into the trailer block.
+pub fn commit_message(
+    commit_title: &str,
+    commit_details: &Option<String>,
+    trailers: &[String],
+) -> String {
+    let mut block: Vec<&str> = Vec::new();
+    for trailer in trailers.iter().map(|t| t.trim()) {
+        if !block.contains(&trailer) {
+            block.push(trailer);
+        }
+    }
+    let mut details = String::new();
+    let mut previous_blank = true;
+    for line in commit_details.as_deref().unwrap_or_default().lines() {
+        let line = line.trim_end();
+        if block.contains(&line.trim()) || (line.is_empty() && previous_blank) {
+            continue;
+        }
+        previous_blank = line.is_empty();
+        details.push_str(line);
+        details.push('\n');
+    }
+
+    let mut message = commit_title.trim().to_string();
+    let details = details.trim();
+    if !details.is_empty() {
+        message.push_str(&format!("\n\n{}", details));
+    }
+    block.push(GENERATED_TRAILER);
+    message.push_str(&format!("\n\n{}", block.join("\n")));
+    message
+}
+
+/// Whether the trailer block (last paragraph) of a commit message has `GENERATED_TRAILER`.
+pub fn is_generated_commit(message: &str) -> bool {
+    let trailers = message.trim_end().rsplit("\n\n").next().unwrap_or_default();
+    message.contains("\n\n")
+        && trailers
+            .lines()
+            .any(|line| line.trim() == GENERATED_TRAILER)
+}
+
+/// A commit body without its `GENERATED_TRAILER` line, for PR bodies built from commits.
+fn without_generated_trailer(body: &str) -> &str {
+    match body.trim_end().strip_suffix(GENERATED_TRAILER) {
+        Some(rest) if rest.is_empty() || rest.ends_with('\n') => rest.trim_end(),
+        _ => body,
+    }
+}
+
+/// Commit the staged changes with `commit_message`; `trailers` go in its
+/// trailer block. `author` (`Name <email>`) overrides git's configured
+/// identity for the commit's author; the committer stays the configured user.
 pub fn git_commit_staged_changes(
     app: &mut App,
     commit_title: &str,
     commit_details: &Option<String>,
+    author: Option<&str>,
+    trailers: &[String],
 ) -> Result<(), Box<dyn Error>> {
-    let mut commit_message = commit_title.trim().to_string();
-    if let Some(details) = commit_details {
-        commit_message.push_str(&format!("\n\n{}", details.trim()));
-    }
+    let commit_message = commit_message(commit_title, commit_details, trailers);
 
-    let output = Command::new("git")
-        .args(["commit", "-m", &commit_message])
-        .output()?;
+    let mut args = vec!["commit", "-m", &commit_message];
+    if let Some(author) = author {
+        args.extend(["--author", author]);
+    }
+    let output = run_command(app, "git", &args)?;
     if !output.status.success() {
         let err = String::from_utf8_lossy(&output.stderr).to_string();
         app.add_error(err.clone());
@@ -439,45 +1656,478 @@ pub fn git_has_staged_changes() -> Result<bool, Box<dyn Error>> {
     Ok(!output.status.success())
 }
 
+/// Number of files with staged and with unstaged (including untracked) changes.
+/// A file changed in both the index and the worktree counts once in each.
+pub fn git_changed_file_counts() -> Result<(usize, usize), Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=all"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to read git status: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(count_changed_files(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Every file with staged, unstaged or untracked changes (within `--path`), for `--stack`.
+pub fn git_uncommitted_paths(app: &mut App) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(paths_from_porcelain_z(&git_status_z(app)?))
+}
+
+/// The uncommitted renames (within `--path`) as `(new, old)` paths, for `--stack`.
+pub fn git_uncommitted_renames(app: &mut App) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    Ok(renames_from_porcelain_z(&git_status_z(app)?))
+}
+
+/// `git status --porcelain -z` of the changes within `--path`, untracked files included.
+fn git_status_z(app: &mut App) -> Result<String, Box<dyn Error>> {
+    let mut args = vec!["status", "--porcelain", "-z", "--untracked-files=all", "--"];
+    args.extend(app.paths.iter().map(String::as_str));
+    let output = Command::new("git").args(&args).output()?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        app.add_error(err.clone());
+        return Err(format!("Failed to read git status: {}", err).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The paths in `git status --porcelain -z` output; a rename yields both its
+/// new and its old path, as committing it needs both.
+pub fn paths_from_porcelain_z(porcelain: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut entries = porcelain.split('\0').filter(|e| !e.is_empty());
+    while let Some(entry) = entries.next() {
+        let Some(path) = entry.get(3..) else {
+            continue;
+        };
+        paths.push(path.to_string());
+        if entry.starts_with(['R', 'C']) {
+            if let Some(original) = entries.next() {
+                paths.push(original.to_string());
+            }
+        }
+    }
+    paths
+}
+
+/// The renames in `git status --porcelain -z` output, as `(new, old)` paths.
+pub fn renames_from_porcelain_z(porcelain: &str) -> Vec<(String, String)> {
+    let mut renames = Vec::new();
+    let mut entries = porcelain.split('\0').filter(|e| !e.is_empty());
+    while let Some(entry) = entries.next() {
+        if !entry.starts_with(['R', 'C']) {
+            continue;
+        }
+        if let (Some(path), Some(original)) = (entry.get(3..), entries.next()) {
+            if entry.starts_with('R') {
+                renames.push((path.to_string(), original.to_string()));
+            }
+        }
+    }
+    renames
+}
+
+/// Commit exactly `paths` (their staged and unstaged changes, deletions and
+/// untracked files), leaving every other change uncommitted.
+pub fn git_commit_paths(
+    app: &mut App,
+    paths: &[String],
+    commit_title: &str,
+    commit_details: &Option<String>,
+    author: Option<&str>,
+    trailers: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut add = vec!["add", "-A", "--"];
+    add.extend(paths.iter().map(String::as_str));
+    for args in [vec!["reset", "-q"], add] {
+        let output = run_command(app, "git", &args)?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(err.clone());
+            return Err(format!("Failed to stage {}: {}", paths.join(" "), err).into());
+        }
+    }
+    git_commit_staged_changes(app, commit_title, commit_details, author, trailers)
+}
+
+fn count_changed_files(porcelain: &str) -> (usize, usize) {
+    porcelain
+        .lines()
+        .fold((0, 0), |(staged, unstaged), line| match line.as_bytes() {
+            [b'?', b'?', ..] => (staged, unstaged + 1),
+            [x, y, ..] => (
+                staged + usize::from(*x != b' '),
+                unstaged + usize::from(*y != b' '),
+            ),
+            _ => (staged, unstaged),
+        })
+}
+
 pub fn git_stage_and_commit(
     app: &mut App,
     commit_title: &str,
     commit_details: &Option<String>,
+    author: Option<&str>,
+    trailers: &[String],
+) -> Result<(), Box<dyn Error>> {
+    git_stage_changes(app)?;
+    git_commit_staged_changes(app, commit_title, commit_details, author, trailers)?;
+    app.add_log("INFO", "Committed changes successfully");
+
+    Ok(())
+}
+
+/// Why `--amend` must not rewrite the last commit on top of `base_branch`, if
+/// anything: it is not on the branch at all, or (unless `force`) it was not
+/// made by gh-autopr.
+pub fn git_amend_blocker(base_branch: &str, force: bool) -> Result<Option<String>, Box<dyn Error>> {
+    let own_commits = git_output(Command::new("git").args([
+        "rev-list",
+        "--count",
+        &format!("{}..HEAD", base_branch),
+    ]))?;
+    if own_commits == "0" {
+        return Ok(Some(format!(
+            "the branch has no commits of its own on top of {}",
+            base_branch
+        )));
+    }
+    let message = git_output(Command::new("git").args(["log", "-1", "--format=%B"]))?;
+    if !force && !is_generated_commit(&message) {
+        return Ok(Some(format!(
+            "the last commit has no \"{}\" trailer, so it may not be gh-autopr's \
+             (--force-amend amends anyway)",
+            GENERATED_TRAILER
+        )));
+    }
+    Ok(None)
+}
+
+/// Fold the uncommitted changes into the last commit and reword it (`--amend`).
+/// The commit keeps its author.
+pub fn git_amend_last_commit(
+    app: &mut App,
+    commit_title: &str,
+    commit_details: &Option<String>,
+    trailers: &[String],
 ) -> Result<(), Box<dyn Error>> {
+    git_stage_changes(app)?;
+    let message = commit_message(commit_title, commit_details, trailers);
+    let output = run_command(app, "git", &["commit", "--amend", "-m", &message])?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).to_string();
+        app.add_error(err.clone());
+        return Err(format!("Failed to amend commit: {}", err).into());
+    }
+    app.add_log("INFO", "Amended the last commit");
+    Ok(())
+}
+
+/// Stage what `git_stage_and_commit` commits: everything (or only `app.paths`),
+/// unless something is staged already. With `app.staged_only` nothing is
+/// added, and an empty index is an error.
+fn git_stage_changes(app: &mut App) -> Result<(), Box<dyn Error>> {
+    if !app.paths.is_empty() {
+        // Staged changes outside --path stay out of the commit
+        let mut args = vec!["reset".to_string(), "-q".to_string()];
+        args.extend(outside_paths_pathspec(&app.paths));
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = run_command(app, "git", &args)?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(err.clone());
+            return Err(format!("Failed to unstage changes outside --path: {}", err).into());
+        }
+    }
+
     if git_has_staged_changes()? {
         app.add_log("INFO", "Changes already staged, skipping git add");
+    } else if app.staged_only {
+        let e = "--staged-only: nothing is staged to commit; stage changes with git add first";
+        app.add_error(e);
+        return Err(e.into());
     } else {
-        let output = Command::new("git").args(["add", "."]).output()?;
+        let targets = if app.paths.is_empty() {
+            vec![".".to_string()]
+        } else {
+            app.paths.clone()
+        };
+        let mut args = vec!["add", "--"];
+        args.extend(targets.iter().map(String::as_str));
+        let output = run_command(app, "git", &args)?;
         if output.status.success() {
-            app.add_log("INFO", "Staged all changes");
+            app.add_log(
+                "INFO",
+                format!("Staged all changes in {}", targets.join(" ")),
+            );
         } else {
             let err = String::from_utf8_lossy(&output.stderr).to_string();
             app.add_error(err.clone());
             return Err(format!("Failed to stage changes: {}", err).into());
         }
     }
+    Ok(())
+}
+
+/// Rebase the current branch onto the latest `base_branch`: `<remote>/<base>`
+/// after a fetch when the remote has it, else the local branch. A conflicting
+/// rebase is aborted so the branch is left exactly as it was.
+pub fn git_rebase_onto_base(app: &mut App, base_branch: &str) -> Result<(), Box<dyn Error>> {
+    let remote = app.remote.clone();
+    let fetch = run_command(app, "git", &["fetch", &remote, base_branch])?;
+    let onto = if fetch.status.success() && remote_ref_exists(&remote, base_branch)? {
+        format!("{}/{}", remote, base_branch)
+    } else {
+        app.add_log(
+            "WARN",
+            format!(
+                "{}/{} not available, rebasing onto local {}",
+                remote, base_branch, base_branch
+            ),
+        );
+        base_branch.to_string()
+    };
+
+    app.add_log("INFO", format!("Rebasing onto {}", onto));
+    let output = run_command(app, "git", &["rebase", &onto])?;
+    if !output.status.success() {
+        let _ = run_command(app, "git", &["rebase", "--abort"]);
+        let err = format!(
+            "Rebase onto {} hit conflicts and was aborted; the branch is unchanged. \
+             Rebase manually and resolve the conflicts, or re-run without --rebase-onto-base.\n{}",
+            onto,
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+        app.add_error(err.clone());
+        return Err(format!("Rebase onto {} failed", onto).into());
+    }
+
+    app.add_log("SUCCESS", format!("Rebased onto {}", onto));
+    Ok(())
+}
+
+/// The commits `--squash` folds into one: `merge-base(HEAD, base)..HEAD`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SquashRange {
+    pub merge_base: String,
+    /// Commits in the range
+    pub commits: usize,
+    /// How many of them are already reachable from a `<remote>/*` ref
+    pub on_remote: usize,
+}
+
+pub fn git_squash_range(base_branch: &str, remote: &str) -> Result<SquashRange, Box<dyn Error>> {
+    let merge_base = git_output(Command::new("git").args(["merge-base", "HEAD", base_branch]))?;
+    let range = format!("{}..HEAD", merge_base);
+    let count = |extra: &[&str]| -> Result<usize, Box<dyn Error>> {
+        let mut cmd = Command::new("git");
+        cmd.args(["rev-list", "--count", &range]).args(extra);
+        Ok(git_output(&mut cmd)?.parse()?)
+    };
+    let commits = count(&[])?;
+    let unpushed = count(&["--not", &format!("--remotes={}", remote)])?;
+    Ok(SquashRange {
+        merge_base,
+        commits,
+        on_remote: commits - unpushed,
+    })
+}
+
+/// `--squash`: fold the branch's local commits into the pending changes, so the
+/// normal stage-and-commit flow turns the whole branch into one commit. HEAD is
+/// detached first, so the user's branch keeps its commits. Only runs inside the
+/// temp worktree, and refuses when any commit is already on `app.remote`. Returns the
+/// number of commits squashed.
+pub fn git_squash_onto_merge_base(
+    app: &mut App,
+    base_branch: &str,
+) -> Result<usize, Box<dyn Error>> {
+    if !crate::git_temp_worktree::is_in_temp_worktree() {
+        return Err("--squash only runs inside the temp worktree".into());
+    }
+    let range = git_squash_range(base_branch, &app.remote)?;
+    if range.commits == 0 {
+        app.add_log("INFO", "--squash: no local commits to squash");
+        return Ok(0);
+    }
+    if range.on_remote > 0 {
+        let err = format!(
+            "--squash refused: {} of the {} commits since {} are already on {}. \
+             Squashing them would rewrite pushed history; re-run without --squash.",
+            range.on_remote, range.commits, base_branch, app.remote
+        );
+        app.add_error(err.clone());
+        return Err("Commits to squash are already pushed".into());
+    }
+
+    // Without staged changes everything pending goes into the commit, so keep
+    // the unstaged changes in it too once the branch's commits are staged
+    let had_staged = git_has_staged_changes()?;
+    for args in [
+        vec!["checkout", "--detach"],
+        vec!["reset", "--soft", &range.merge_base],
+    ] {
+        let output = run_command(app, "git", &args)?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(err.clone());
+            return Err(format!("--squash failed at git {}: {}", args.join(" "), err).into());
+        }
+    }
+    if !had_staged {
+        let output = run_command(app, "git", &["add", "-A"])?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr).to_string();
+            app.add_error(err.clone());
+            return Err(format!("Failed to stage changes: {}", err).into());
+        }
+    }
+
+    app.add_log(
+        "INFO",
+        format!(
+            "Squashing {} local commit(s) since {} into one",
+            range.commits, base_branch
+        ),
+    );
+    Ok(range.commits)
+}
+
+/// Tree that `git_stage_and_commit` would commit: the index when something is
+/// staged, else every change including untracked files. The real index is
+/// left untouched.
+pub fn git_pending_commit_tree() -> Result<String, Box<dyn Error>> {
+    if git_has_staged_changes()? {
+        return git_output(Command::new("git").arg("write-tree"));
+    }
 
-    git_commit_staged_changes(app, commit_title, commit_details)?;
-    app.add_log("INFO", "Committed changes successfully");
+    // Scratch index next to the real one (per-worktree), removed afterwards
+    let scratch = git_output(Command::new("git").args([
+        "rev-parse",
+        "--path-format=absolute",
+        "--git-path",
+        "gh-autopr-pending-index",
+    ]))?;
+    let with_index = |args: &[&str]| {
+        let mut cmd = Command::new("git");
+        cmd.args(args).env("GIT_INDEX_FILE", &scratch);
+        cmd
+    };
+    let tree = git_output(&mut with_index(&["read-tree", "HEAD"]))
+        .and_then(|_| git_output(&mut with_index(&["add", "-A"])))
+        .and_then(|_| git_output(&mut with_index(&["write-tree"])));
+    let _ = fs_err::remove_file(&scratch);
+    tree
+}
+
+/// A local branch whose tip is a single commit on top of HEAD with exactly the
+/// pending changes, i.e. an earlier run already committed them.
+pub fn find_branch_with_pending_changes(app: &mut App) -> Result<Option<String>, Box<dyn Error>> {
+    let head = git_output(Command::new("git").args(["rev-parse", "HEAD"]))?;
+    let tree = git_pending_commit_tree()?;
+
+    let refs = git_output(Command::new("git").args([
+        "for-each-ref",
+        "--format=%(refname:short) %(tree) %(parent)",
+        "refs/heads/",
+    ]))?;
+    let found = refs.lines().find_map(|line| {
+        let mut fields = line.split(' ');
+        let (name, branch_tree, parent) = (fields.next()?, fields.next()?, fields.next()?);
+        // Exactly one parent, and it is HEAD
+        (branch_tree == tree && parent == head && fields.next().is_none()).then(|| name.to_string())
+    });
+
+    if let Some(branch) = &found {
+        app.add_log(
+            "INFO",
+            format!("Changes already committed on {}, skipping commit", branch),
+        );
+    }
+    Ok(found)
+}
 
+/// Switch to `branch` found by `find_branch_with_pending_changes`. Its tip holds
+/// exactly the working-tree changes, so forcing the checkout loses nothing.
+pub fn git_switch_to_committed_branch(app: &mut App, branch: &str) -> Result<(), Box<dyn Error>> {
+    let output = run_command(app, "git", &["checkout", "--force", branch])?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr).to_string();
+        app.add_error(format!("Failed to checkout {}: {}", branch, err));
+        return Err(format!("Failed to checkout branch: {}", err).into());
+    }
     Ok(())
 }
 
-/// Push the current branch to origin. Returns the branch name that was actually
+/// Run a read-only git command and return its trimmed stdout.
+fn git_output(cmd: &mut Command) -> Result<String, Box<dyn Error>> {
+    let out = cmd.output()?;
+    if !out.status.success() {
+        return Err(format!(
+            "git command failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(out.stdout)?.trim().to_string())
+}
+
+/// Push the current branch to `app.remote`. Returns the branch name that was actually
 /// pushed — this may differ from `branch_name` if a remote conflict forced a
 /// rename (e.g. `release/v0.2.0` → `release/v0.2.0-2`).
-pub fn git_push_branch(app: &mut App, branch_name: &str) -> Result<String, Box<dyn Error>> {
+/// `force_with_lease` is needed after rewriting history, e.g. `--rebase-onto-base`.
+/// A push rejected as non-fast-forward is retried with `--force-with-lease`
+/// under `app.force_push`, and otherwise fails with advice; plain `--force` is never used.
+pub fn git_push_branch(
+    app: &mut App,
+    branch_name: &str,
+    mut force_with_lease: bool,
+) -> Result<String, Box<dyn Error>> {
     const MAX_RETRIES: u32 = 10;
 
     let mut current_name = branch_name.to_owned();
 
     for attempt in 0..=MAX_RETRIES {
-        let push_result = try_push(app, &current_name)?;
+        let mut push_result = try_push(app, &current_name, force_with_lease)?;
+        if let Err(err) = &push_result {
+            if !force_with_lease && is_non_fast_forward(err) {
+                if !app.force_push {
+                    let e = non_fast_forward_error(&current_name, &app.remote);
+                    app.add_error(format!("{}\n{}", e, err.trim()));
+                    return Err(e.into());
+                }
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "Push of '{}' rejected as non-fast-forward, retrying with --force-with-lease",
+                        current_name
+                    ),
+                );
+                force_with_lease = true;
+                push_result = try_push(app, &current_name, force_with_lease)?;
+            }
+        }
 
         if push_result.is_ok() {
             return Ok(current_name);
         }
         let err = push_result.unwrap_err();
+        if err.contains("stale info") {
+            let e = format!(
+                "Push of '{}' rejected: {}/{} has commits that were never fetched here, \
+                 so --force-with-lease refused to overwrite them. Fetch and integrate them \
+                 (e.g. `git pull --rebase {} {}`), then re-run.",
+                current_name, app.remote, current_name, app.remote, current_name
+            );
+            app.add_error(format!("{}\n{}", e, err.trim()));
+            return Err(e.into());
+        }
 
         // Only retry on remote-conflict errors
         let is_conflict = err.contains("remote rejected")
@@ -500,9 +2150,7 @@ pub fn git_push_branch(app: &mut App, branch_name: &str) -> Result<String, Box<d
         );
 
         // Rename the local branch
-        let rename = Command::new("git")
-            .args(["branch", "-m", &current_name, &next_name])
-            .output()?;
+        let rename = run_command(app, "git", &["branch", "-m", &current_name, &next_name])?;
 
         if !rename.status.success() {
             let rename_err = String::from_utf8_lossy(&rename.stderr).to_string();
@@ -516,27 +2164,57 @@ pub fn git_push_branch(app: &mut App, branch_name: &str) -> Result<String, Box<d
     unreachable!()
 }
 
+/// Whether a failed push's stderr says the remote branch moved on without us.
+pub fn is_non_fast_forward(stderr: &str) -> bool {
+    stderr.contains("(non-fast-forward)")
+        || stderr.contains("(fetch first)")
+        || stderr.contains("the tip of your current branch is behind")
+}
+
+/// What to do about a push of `branch` rejected as non-fast-forward.
+pub fn non_fast_forward_error(branch: &str, remote: &str) -> String {
+    format!(
+        "Push of '{branch}' rejected: {remote}/{branch} has diverged from the local branch \
+         (someone else pushed to it, or its history was rewritten here). \
+         Pull their commits with `git pull --rebase {remote} {branch}`, or re-run with \
+         --force-push to overwrite {remote}/{branch} with --force-with-lease."
+    )
+}
+
 /// Attempt a single push. Returns `Ok(Ok(()))` on success, or `Ok(Err(stderr))`
 /// on failure (so the caller can inspect the error without `?` short-circuiting).
-fn try_push(app: &mut App, branch_name: &str) -> Result<Result<(), String>, Box<dyn Error>> {
-    let check_upstream = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", &format!("{branch_name}@{{u}}")])
-        .output()?;
+fn try_push(
+    app: &mut App,
+    branch_name: &str,
+    force_with_lease: bool,
+) -> Result<Result<(), String>, Box<dyn Error>> {
+    let check_upstream = run_command(
+        app,
+        "git",
+        &["rev-parse", "--abbrev-ref", &format!("{branch_name}@{{u}}")],
+    )?;
 
     let has_upstream = check_upstream.status.success();
+    let remote = app.remote.clone();
     let mut push_args = vec!["push"];
+    if force_with_lease {
+        push_args.push("--force-with-lease");
+    }
 
     if !has_upstream {
-        push_args.extend(["--set-upstream", "origin", branch_name]);
+        push_args.extend(["--set-upstream", &remote, branch_name]);
         app.add_log("INFO", "Setting up upstream tracking branch");
     } else {
-        push_args.extend(["origin", branch_name]);
+        push_args.extend([&remote, branch_name]);
     }
 
-    let output = Command::new("git").args(&push_args).output()?;
+    let output = run_command(app, "git", &push_args)?;
 
     if output.status.success() {
-        app.add_log("INFO", format!("Pushed branch {} to origin", branch_name));
+        app.add_log(
+            "INFO",
+            format!("Pushed branch {} to {}", branch_name, remote),
+        );
         return Ok(Ok(()));
     }
 
@@ -549,6 +2227,103 @@ fn next_branch_name(original: &str, suffix: u32) -> String {
     format!("{}-iter-{}", original, suffix)
 }
 
+/// The pull request that was created or updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    pub number: u64,
+    pub url: String,
+}
+
+/// Find the PR URL that `gh pr create`/`gh pr edit` print on stdout
+/// (`https://github.com/<owner>/<repo>/pull/<n>`) and take the number from it.
+pub fn parse_pr_url(stdout: &str) -> Option<PullRequest> {
+    stdout.lines().rev().find_map(|line| {
+        let url = line.trim();
+        let (_, tail) = url.rsplit_once("/pull/")?;
+        let number = tail.trim_end_matches('/').parse().ok()?;
+        url.starts_with("http").then(|| PullRequest {
+            number,
+            url: url.to_string(),
+        })
+    })
+}
+
+/// `gh pr create` refuses when the head has nothing new relative to the base;
+/// recognised separately so it is not mistaken for an auth or network failure.
+fn is_no_commits_between_error(stderr: &str) -> bool {
+    stderr.to_ascii_lowercase().contains("no commits between")
+}
+
+/// Optional settings for `create_or_update_pull_request`.
+#[derive(Debug, Clone, Default)]
+pub struct PullRequestOptions {
+    /// Create as ready for review (false = draft)
+    pub ready: bool,
+    /// On update, also set an existing PR's draft state to match `ready`
+    /// (`--ready`/`--draft` given explicitly)
+    pub sync_draft: bool,
+    /// Logins to request reviews from
+    pub reviewers: Vec<String>,
+    /// Logins to assign; `@me` when empty
+    pub assignees: Vec<String>,
+    /// Labels to apply
+    pub labels: Vec<String>,
+    /// `owner/repo` the head branch was pushed to, when it differs from the
+    /// repository the PR is opened in (a fork)
+    pub head_repo: Option<String>,
+    /// `owner/repo` to open the PR in; gh's default repository when unset
+    pub base_repo: Option<String>,
+}
+
+impl PullRequestOptions {
+    fn assignees(&self) -> Vec<&str> {
+        if self.assignees.is_empty() {
+            vec!["@me"]
+        } else {
+            self.assignees.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Owner of the fork the head branch is pushed to, if any.
+    fn head_owner(&self) -> Option<&str> {
+        let repo = self.head_repo.as_deref()?;
+        Some(repo.split('/').next().unwrap_or(repo))
+    }
+
+    /// How gh refers to the head branch: `owner:branch` for a fork.
+    fn head_selector(&self, branch: &str) -> String {
+        match self.head_owner() {
+            Some(owner) => format!("{}:{}", owner, branch),
+            None => branch.to_string(),
+        }
+    }
+
+    /// `gh pr ready` (with `--undo` for a draft) to bring an existing PR's draft
+    /// state in line with `ready`; `None` unless `sync_draft`.
+    fn ready_toggle_args<'a>(&'a self, head: &'a str) -> Option<Vec<&'a str>> {
+        if !self.sync_draft {
+            return None;
+        }
+        let mut args = vec!["pr", "ready"];
+        if self.head_repo.is_some() {
+            args.push(head);
+        }
+        if !self.ready {
+            args.push("--undo");
+        }
+        args.extend(self.repo_args());
+        Some(args)
+    }
+
+    /// `--repo <base_repo>`, when set, for every gh pr invocation.
+    fn repo_args(&self) -> Vec<&str> {
+        match &self.base_repo {
+            Some(repo) => vec!["--repo", repo.as_str()],
+            None => Vec::new(),
+        }
+    }
+}
+
 /// Creates or updates a pull request.
 ///
 /// # Arguments
@@ -556,18 +2331,18 @@ fn next_branch_name(original: &str, suffix: u32) -> String {
 /// * `title` - PR title
 /// * `body` - PR description
 /// * `update_pr` - Whether to update existing PR instead of creating new one
-/// * `ready` - Whether to create as ready for review (false = draft)
 /// * `base_branch` - The target (base) branch for the PR
 /// * `current_branch` - The source (head) branch for the PR
+/// * `options` - Draft state, reviewers, assignees, labels and fork repos
 pub fn create_or_update_pull_request(
     app: &mut App,
     title: &str,
     body: &str,
     update_pr: bool,
-    ready: bool,
     base_branch: &str,
     current_branch: &str,
-) -> Result<(), Box<dyn Error>> {
+    options: &PullRequestOptions,
+) -> Result<PullRequest, Box<dyn Error>> {
     app.add_log(
         "INFO",
         format!(
@@ -581,31 +2356,66 @@ pub fn create_or_update_pull_request(
             base_branch
         ),
     );
+    let head = options.head_selector(current_branch);
+    if options.head_repo.is_some() {
+        app.add_log(
+            "INFO",
+            format!(
+                "Cross-repository PR: head {} into {}",
+                head,
+                options
+                    .base_repo
+                    .as_deref()
+                    .unwrap_or("gh's default repository")
+            ),
+        );
+    }
 
-    // Check for existing PR
-    let check_output = Command::new("gh")
-        .args(["pr", "list", "--state", "open", "--head", current_branch])
-        .output()?;
-
-    let s = String::from_utf8(check_output.stdout)?.trim().to_string();
-    let pr_exists = check_output.status.success()
-        && !(s.is_empty() || s.starts_with("no pull requests match your search"));
+    // Check for existing PR. `--head` takes a bare branch name here, which
+    // other forks' PRs may share, so a fork's own PR is told by its owner.
+    let mut list_args = vec![
+        "pr",
+        "list",
+        "--state",
+        "open",
+        "--head",
+        current_branch,
+        "--json",
+        "number,headRepositoryOwner",
+    ];
+    list_args.extend(options.repo_args());
+    let check_output = run_command(app, "gh", &list_args)?;
+
+    let open_prs: Vec<serde_json::Value> = if check_output.status.success() {
+        serde_json::from_slice(&check_output.stdout).unwrap_or_default()
+    } else {
+        vec![]
+    };
+    let pr_exists = open_prs.iter().any(|pr| {
+        options
+            .head_owner()
+            .is_none_or(|owner| pr["headRepositoryOwner"]["login"] == owner)
+    });
 
     let should_update = update_pr && pr_exists;
+    let pr_output = if should_update {
+        let mut args = vec!["pr", "edit"];
+        if options.head_repo.is_some() {
+            args.push(head.as_str());
+        }
+        args.extend(["--title", title, "--body", body]);
+        args.extend(options.repo_args());
+        for assignee in options.assignees() {
+            args.extend(["--add-assignee", assignee]);
+        }
+        for reviewer in &options.reviewers {
+            args.extend(["--add-reviewer", reviewer.as_str()]);
+        }
+        for label in &options.labels {
+            args.extend(["--add-label", label.as_str()]);
+        }
 
-    if should_update {
-        let args = vec![
-            "pr",
-            "edit",
-            "--title",
-            title,
-            "--body",
-            body,
-            "--add-assignee",
-            "@me",
-        ];
-
-        let update_output = Command::new("gh").args(&args).output()?;
+        let update_output = run_command(app, "gh", &args)?;
 
         if !update_output.status.success() {
             app.add_error(String::from_utf8_lossy(&update_output.stderr).to_string());
@@ -615,7 +2425,36 @@ pub fn create_or_update_pull_request(
             )
             .into());
         }
-        app.add_log("SUCCESS", "Pull request updated successfully");
+        if !app.dry_run {
+            app.add_log("SUCCESS", "Pull request updated successfully");
+        }
+        if let Some(args) = options.ready_toggle_args(&head) {
+            let ready_output = run_command(app, "gh", &args)?;
+            if !ready_output.status.success() {
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "Could not mark the PR as {}: {}",
+                        if options.ready {
+                            "ready for review"
+                        } else {
+                            "draft"
+                        },
+                        String::from_utf8_lossy(&ready_output.stderr).trim()
+                    ),
+                );
+            } else if !app.dry_run {
+                app.add_log(
+                    "INFO",
+                    if options.ready {
+                        "Marked the PR ready for review"
+                    } else {
+                        "Converted the PR to draft"
+                    },
+                );
+            }
+        }
+        update_output.stdout
     } else if update_pr {
         app.add_error("No existing PR found to update".to_string());
         return Err("No existing PR found to update".into());
@@ -627,41 +2466,83 @@ pub fn create_or_update_pull_request(
             title,
             "--body",
             body,
-            "--assignee",
-            "@me",
             "--head",
-            current_branch,
+            head.as_str(),
             "--base",
             base_branch,
         ];
+        args.extend(options.repo_args());
+        for assignee in options.assignees() {
+            args.extend(["--assignee", assignee]);
+        }
+        for reviewer in &options.reviewers {
+            args.extend(["--reviewer", reviewer.as_str()]);
+        }
+        for label in &options.labels {
+            args.extend(["--label", label.as_str()]);
+        }
 
-        if !ready {
+        if !options.ready {
             args.push("--draft");
         }
 
-        let create_output = Command::new("gh").args(&args).output()?;
+        let create_output = run_command(app, "gh", &args)?;
 
         if !create_output.status.success() {
-            app.add_error(String::from_utf8_lossy(&create_output.stderr).to_string());
+            let stderr = String::from_utf8_lossy(&create_output.stderr);
+            if is_no_commits_between_error(&stderr) {
+                let msg = format!(
+                    "Branch {} has no commits that are not already on {}, so there is nothing to open a PR for. \
+                     If {} is not the branch you meant to target, the base was likely inferred wrongly \
+                     (check the \"Base:\" line in the logs).",
+                    current_branch, base_branch, base_branch
+                );
+                app.add_error(msg.clone());
+                return Err(msg.into());
+            }
+            app.add_error(stderr.to_string());
             return Err(format!(
                 "Failed to create pull request: {}",
                 String::from_utf8_lossy(&create_output.stderr)
             )
             .into());
         }
-        app.add_log("SUCCESS", "Pull request created successfully");
+        if !app.dry_run {
+            app.add_log("SUCCESS", "Pull request created successfully");
+        }
+        create_output.stdout
+    };
+
+    if app.dry_run {
+        return Ok(PullRequest {
+            number: 0,
+            url: "(dry run)".to_string(),
+        });
     }
 
-    // Get and log the PR URL
-    let url_output = Command::new("gh")
-        .args(["pr", "view", "--json", "url", "--jq", ".url"])
-        .output()?;
-    if url_output.status.success() {
-        if let Ok(url) = String::from_utf8(url_output.stdout) {
-            app.add_log("INFO", format!("Pull request URL: {}", url.trim()));
+    // gh prints the PR URL; only ask again (for this head branch) if it didn't
+    let pr = match parse_pr_url(&String::from_utf8_lossy(&pr_output)) {
+        Some(pr) => pr,
+        None => {
+            let mut view_args = vec!["pr", "view", head.as_str(), "--json", "url", "--jq", ".url"];
+            view_args.extend(options.repo_args());
+            let view_output = run_command(app, "gh", &view_args)?;
+            match parse_pr_url(&String::from_utf8_lossy(&view_output.stdout)) {
+                Some(pr) => pr,
+                None => {
+                    let err = format!("Could not determine the PR URL for {}", current_branch);
+                    app.add_error(err.clone());
+                    return Err(err.into());
+                }
+            }
         }
-    }
-    Ok(())
+    };
+
+    app.add_log(
+        "INFO",
+        format!("Pull request #{} URL: {}", pr.number, pr.url),
+    );
+    Ok(pr)
 }
 
 use std::path::PathBuf;
@@ -678,12 +2559,14 @@ pub fn update_original_worktree_to_pr_branch(
     original_root: &PathBuf,
     had_staged_changes: bool,
 ) -> Result<(), Box<dyn Error>> {
-    std::env::set_current_dir(original_root)?;
+    let _cwd = CwdGuard::change_to(original_root)?;
 
-    if had_staged_changes {
+    if !app.paths.is_empty() {
+        update_original_worktree_outside_paths(app, pr_branch)?;
+    } else if had_staged_changes {
         // Only staged changes went to PR. Keep unstaged changes.
         // 1. Save unstaged changes (working tree vs index), including binary files
-        let unstaged_diff = Command::new("git").args(["diff", "--binary"]).output()?;
+        let unstaged_diff = run_command(app, "git", &["diff", "--binary"])?;
         if !unstaged_diff.status.success() {
             app.add_log(
                 "WARN",
@@ -696,9 +2579,7 @@ pub fn update_original_worktree_to_pr_branch(
         let unstaged_patch = unstaged_diff.stdout;
 
         // 2. Hard reset to discard staged changes
-        let reset_output = Command::new("git")
-            .args(["reset", "--hard", "HEAD"])
-            .output()?;
+        let reset_output = run_command(app, "git", &["reset", "--hard", "HEAD"])?;
         if !reset_output.status.success() {
             app.add_log(
                 "WARN",
@@ -713,29 +2594,10 @@ pub fn update_original_worktree_to_pr_branch(
         checkout_pr_branch(app, pr_branch)?;
 
         // 4. Re-apply unstaged changes
-        if !unstaged_patch.is_empty() {
-            let mut child = Command::new("git")
-                .args(["apply", "--3way", "-"])
-                .stdin(std::process::Stdio::piped())
-                .spawn()?;
-            if let Some(stdin) = child.stdin.as_mut() {
-                let _ = stdin.write_all(&unstaged_patch);
-            }
-            let status = child.wait()?;
-            if status.success() {
-                app.add_log("INFO", "Restored unstaged changes");
-            } else {
-                app.add_log(
-                    "WARN",
-                    "Some unstaged changes could not be restored cleanly",
-                );
-            }
-        }
+        reapply_patch(app, &unstaged_patch, "unstaged changes")?;
     } else {
         // All changes (unstaged + untracked) went to PR. Discard everything.
-        let reset_output = Command::new("git")
-            .args(["reset", "--hard", "HEAD"])
-            .output()?;
+        let reset_output = run_command(app, "git", &["reset", "--hard", "HEAD"])?;
         if !reset_output.status.success() {
             app.add_log(
                 "WARN",
@@ -747,7 +2609,7 @@ pub fn update_original_worktree_to_pr_branch(
         }
 
         // Remove untracked files (they were committed to PR)
-        let clean_output = Command::new("git").args(["clean", "-fd"]).output()?;
+        let clean_output = run_command(app, "git", &["clean", "-fd"])?;
         if !clean_output.status.success() {
             app.add_log(
                 "WARN",
@@ -766,6 +2628,149 @@ pub fn update_original_worktree_to_pr_branch(
     Ok(())
 }
 
+/// `--path` variant of the cleanup: only changes under the paths went to the PR.
+/// Tracked changes outside them are carried over to the PR branch, and untracked
+/// files outside them are never touched.
+fn update_original_worktree_outside_paths(
+    app: &mut App,
+    pr_branch: &str,
+) -> Result<(), Box<dyn Error>> {
+    let paths = app.paths.clone();
+    let diff = |app: &mut App, extra: &[&str], pathspec: Vec<String>| {
+        let mut args: Vec<&str> = vec!["diff", "--binary"];
+        args.extend_from_slice(extra);
+        args.extend(pathspec.iter().map(String::as_str));
+        run_command(app, "git", &args)
+    };
+
+    // 1. Save changes outside the paths, and the unstaged part inside them when
+    //    only their staged part went to the PR
+    let outside_patch = diff(app, &["HEAD"], outside_paths_pathspec(&paths))?.stdout;
+    let mut in_paths = vec!["--".to_string()];
+    in_paths.extend(paths.iter().cloned());
+    let staged_in_paths = !diff(app, &["--cached", "--quiet"], in_paths.clone())?
+        .status
+        .success();
+    let unstaged_patch = if staged_in_paths {
+        diff(app, &[], in_paths.clone())?.stdout
+    } else {
+        Vec::new()
+    };
+
+    // 2. Discard everything that went to the PR
+    let reset_output = run_command(app, "git", &["reset", "--hard", "HEAD"])?;
+    if !reset_output.status.success() {
+        app.add_log(
+            "WARN",
+            format!(
+                "Failed to reset: {}",
+                String::from_utf8_lossy(&reset_output.stderr)
+            ),
+        );
+    }
+    if !staged_in_paths {
+        let mut args = vec!["clean", "-fd"];
+        args.extend(in_paths.iter().map(String::as_str));
+        let clean_output = run_command(app, "git", &args)?;
+        if !clean_output.status.success() {
+            app.add_log(
+                "WARN",
+                format!(
+                    "Failed to clean untracked files: {}",
+                    String::from_utf8_lossy(&clean_output.stderr)
+                ),
+            );
+        }
+    }
+
+    // 3. Checkout PR branch and bring the kept changes back
+    checkout_pr_branch(app, pr_branch)?;
+    // Try both even if the first conflicts, so neither patch is silently dropped
+    let outside = reapply_patch(app, &outside_patch, "changes outside --path");
+    let unstaged = reapply_patch(app, &unstaged_patch, "unstaged changes");
+    outside.and(unstaged)
+}
+
+/// Apply a patch saved from the original worktree with `git apply --3way`.
+///
+/// If it does not apply cleanly the patch is saved under the git dir, the
+/// conflicted paths are reported via `add_error`, and an error is returned so the
+/// run does not report success over half-restored changes.
+fn reapply_patch(app: &mut App, patch: &[u8], what: &str) -> Result<(), Box<dyn Error>> {
+    if patch.is_empty() {
+        return Ok(());
+    }
+    if app.dry_run {
+        app.add_log("INFO", "WOULD RUN: git apply --3way -");
+        return Ok(());
+    }
+    let mut child = Command::new("git")
+        .args(["apply", "--3way", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        let _ = stdin.write_all(patch);
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        app.add_log("INFO", format!("Restored {}", what));
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let conflicted = conflicted_paths(&stderr);
+    let slug: Vec<&str> = what
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect();
+    let saved = git_output(Command::new("git").args(["rev-parse", "--git-dir"]))
+        .ok()
+        .map(|dir| PathBuf::from(dir).join(format!("gh-autopr-unapplied-{}.patch", slug.join("-"))))
+        .filter(|path| fs_err::write(path, patch).is_ok());
+
+    let mut message = format!("Could not restore {} cleanly", what);
+    if conflicted.is_empty() {
+        message.push_str(&format!(":\n{}", stderr.trim()));
+    } else {
+        message.push_str(&format!("; conflicts in:\n  {}", conflicted.join("\n  ")));
+        message.push_str("\nResolve the conflict markers in these files, then `git add` them.");
+    }
+    if let Some(path) = &saved {
+        message.push_str(&format!(
+            "\nThe full patch was saved to {}; re-apply it with `git apply --3way <file>` if needed.",
+            path.display()
+        ));
+    }
+    app.add_error(message);
+    Err(format!("Conflicts restoring {}", what).into())
+}
+
+/// Paths left unmerged in the index, plus any `git apply` named as not applying.
+fn conflicted_paths(apply_stderr: &str) -> Vec<String> {
+    let mut paths: Vec<String> =
+        git_output(Command::new("git").args(["diff", "--name-only", "--diff-filter=U"]))
+            .map(|out| out.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+    for line in apply_stderr.lines() {
+        let failed = line
+            .strip_prefix("error: patch failed: ")
+            .and_then(|rest| rest.rsplit_once(':'))
+            .map(|(path, _line)| path)
+            .or_else(|| {
+                line.strip_prefix("error: ")
+                    .and_then(|rest| rest.strip_suffix(": patch does not apply"))
+            });
+        if let Some(path) = failed {
+            if !paths.iter().any(|p| p == path) {
+                paths.push(path.to_string());
+            }
+        }
+    }
+    paths
+}
+
 /// Helper to checkout the PR branch (fetch from remote if needed)
 fn checkout_pr_branch(app: &mut App, pr_branch: &str) -> Result<(), Box<dyn Error>> {
     // Check if branch exists locally
@@ -775,7 +2780,7 @@ fn checkout_pr_branch(app: &mut App, pr_branch: &str) -> Result<(), Box<dyn Erro
         .success();
 
     if branch_exists {
-        let output = Command::new("git").args(["checkout", pr_branch]).output()?;
+        let output = run_command(app, "git", &["checkout", pr_branch])?;
         if !output.status.success() {
             let err = String::from_utf8_lossy(&output.stderr);
             app.add_error(format!("Failed to checkout {}: {}", pr_branch, err));
@@ -783,11 +2788,14 @@ fn checkout_pr_branch(app: &mut App, pr_branch: &str) -> Result<(), Box<dyn Erro
         }
     } else {
         // Fetch and checkout from remote
-        let _ = Command::new("git")
-            .args(["fetch", "origin", &format!("{}:{}", pr_branch, pr_branch)])
-            .output();
+        let remote = app.remote.clone();
+        let _ = run_command(
+            app,
+            "git",
+            &["fetch", &remote, &format!("{}:{}", pr_branch, pr_branch)],
+        );
 
-        let output = Command::new("git").args(["checkout", pr_branch]).output()?;
+        let output = run_command(app, "git", &["checkout", pr_branch])?;
         if !output.status.success() {
             let err = String::from_utf8_lossy(&output.stderr);
             app.add_error(format!("Failed to checkout {}: {}", pr_branch, err));
@@ -798,22 +2806,51 @@ fn checkout_pr_branch(app: &mut App, pr_branch: &str) -> Result<(), Box<dyn Erro
     Ok(())
 }
 
+/// A merged PR, as far as branch pruning needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedPr {
+    pub number: u64,
+    pub title: String,
+    /// Commit the PR head pointed at when it was merged
+    pub head_oid: String,
+}
+
+/// Whether local `branch` has commits that `head_oid` does not, i.e. work added
+/// after the PR was merged. Errs when that can't be told, e.g. `head_oid` is
+/// empty or was never fetched.
+pub fn branch_has_commits_beyond(branch: &str, head_oid: &str) -> Result<bool, String> {
+    if head_oid.is_empty() {
+        return Err("the PR has no head commit".to_string());
+    }
+    let out = Command::new("git")
+        .args(["merge-base", "--is-ancestor", branch, head_oid])
+        .output()
+        .map_err(|e| e.to_string())?;
+    match out.status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err(String::from_utf8_lossy(&out.stderr).trim().to_string()),
+    }
+}
+
 /// Get merged PRs and their associated branches
 pub fn get_merged_prs_and_branches(
     app: &mut App,
-) -> Result<HashMap<String, String>, Box<dyn Error>> {
+) -> Result<HashMap<String, MergedPr>, Box<dyn Error>> {
     app.add_log("INFO", "Fetching merged PRs...");
 
-    let output = Command::new("gh")
-        .args([
+    let output = run_command(
+        app,
+        "gh",
+        &[
             "pr",
             "list",
             "--state",
             "merged",
             "--json",
-            "headRefName,number,title",
-        ])
-        .output()?;
+            "headRefName,headRefOid,number,title",
+        ],
+    )?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -827,7 +2864,14 @@ pub fn get_merged_prs_and_branches(
     let mut merged_branches = HashMap::new();
     for pr in prs {
         if let (Some(branch), Some(number)) = (pr["headRefName"].as_str(), pr["number"].as_u64()) {
-            merged_branches.insert(branch.to_string(), format!("PR #{}", number));
+            merged_branches.insert(
+                branch.to_string(),
+                MergedPr {
+                    number,
+                    title: pr["title"].as_str().unwrap_or_default().to_string(),
+                    head_oid: pr["headRefOid"].as_str().unwrap_or_default().to_string(),
+                },
+            );
         }
     }
 
@@ -844,7 +2888,15 @@ pub fn get_local_branches_with_remotes(
 ) -> Result<HashMap<String, Option<String>>, Box<dyn Error>> {
     app.add_log("INFO", "Getting local branches...");
 
-    let output = Command::new("git").args(["branch", "-vv"]).output()?;
+    let output = run_command(
+        app,
+        "git",
+        &[
+            "for-each-ref",
+            "--format=%(refname:short) %(upstream:short)",
+            "refs/heads/",
+        ],
+    )?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -852,60 +2904,34 @@ pub fn get_local_branches_with_remotes(
         return Err("Failed to get local branches".into());
     }
 
-    let branch_output = String::from_utf8(output.stdout)?;
-    let mut branches = HashMap::new();
-
-    for line in branch_output.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-
-        // Parse git branch -vv output format
-        // Example: "* main                 1234567 [origin/main] Latest commit"
-        // Example: "  feature-branch       abcdef1 [origin/feature-branch: ahead 1] Add feature"
-
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 3 {
-            continue;
-        }
-
-        let branch_name = if parts[0] == "*" {
-            parts[1] // Current branch, name is in second position
-        } else {
-            parts[0] // Non-current branch, name is in first position
-        };
-
-        // Skip if this is the current branch indicator
-        if branch_name == "*" {
-            continue;
-        }
-
-        // Look for remote tracking branch in brackets
-        let remote_branch = if let Some(bracket_start) = line.find('[') {
-            if let Some(bracket_end) = line.find(']') {
-                let remote_info = &line[bracket_start + 1..bracket_end];
-                // Extract just the remote branch name (before any : or other info)
-                let remote_branch = remote_info.split(':').next().unwrap_or(remote_info).trim();
-                Some(remote_branch.to_string())
-            } else {
-                None
-            }
-        } else {
-            None
-        };
-
-        branches.insert(branch_name.to_string(), remote_branch);
-    }
-
+    let branches = parse_branch_upstreams(&String::from_utf8(output.stdout)?);
     app.add_log("INFO", format!("Found {} local branches", branches.len()));
     Ok(branches)
 }
 
-/// Check if a remote branch exists
-pub fn remote_branch_exists(_app: &mut App, remote_branch: &str) -> Result<bool, Box<dyn Error>> {
+/// Branch names and upstreams from `git for-each-ref --format='%(refname:short)
+/// %(upstream:short)'`. Neither can contain a space; a branch without an
+/// upstream ends in the separator.
+pub fn parse_branch_upstreams(output: &str) -> HashMap<String, Option<String>> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, upstream) = line.split_once(' ').unwrap_or((line, ""));
+            let upstream = upstream.trim();
+            (!name.is_empty()).then(|| {
+                (
+                    name.to_string(),
+                    (!upstream.is_empty()).then(|| upstream.to_string()),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Check if a branch exists on `app.remote`
+pub fn remote_branch_exists(app: &mut App, remote_branch: &str) -> Result<bool, Box<dyn Error>> {
     let output = Command::new("git")
-        .args(["ls-remote", "--exit-code", "origin", remote_branch])
+        .args(["ls-remote", "--exit-code", &app.remote, remote_branch])
         .output()?;
 
     Ok(output.status.success())
@@ -915,9 +2941,7 @@ pub fn remote_branch_exists(_app: &mut App, remote_branch: &str) -> Result<bool,
 pub fn delete_local_branch(app: &mut App, branch_name: &str) -> Result<(), Box<dyn Error>> {
     app.add_log("INFO", format!("Deleting local branch: {}", branch_name));
 
-    let output = Command::new("git")
-        .args(["branch", "-D", branch_name])
-        .output()?;
+    let output = run_command(app, "git", &["branch", "-D", branch_name])?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -932,8 +2956,21 @@ pub fn delete_local_branch(app: &mut App, branch_name: &str) -> Result<(), Box<d
     Ok(())
 }
 
-/// Main function to prune merged branches
-pub fn prune_merged_branches(app: &mut App) -> Result<(), Box<dyn Error>> {
+/// A local branch selected for deletion by branch pruning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrunableBranch {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Find local branches that are safe to prune: branches of merged PRs, and
+/// branches whose remote tracking branch no longer exists and whose commits
+/// are all in main. `force` drops the last condition, so branches whose remote
+/// was deleted unmerged (or squash-merged without a PR gh knows) go too.
+pub fn find_prunable_branches(
+    app: &mut App,
+    force: bool,
+) -> Result<Vec<PrunableBranch>, Box<dyn Error>> {
     app.add_log("INFO", "Starting branch pruning process...");
 
     // Get merged PRs and their branches
@@ -948,57 +2985,78 @@ pub fn prune_merged_branches(app: &mut App) -> Result<(), Box<dyn Error>> {
     // Get main branch to avoid deleting it
     let main_branch = git_main_branch(app).unwrap_or_else(|_| "main".to_string());
 
-    let mut deleted_count = 0;
-    let mut skipped_count = 0;
+    let mut prunable = Vec::new();
 
     for (local_branch, remote_branch_opt) in local_branches {
         // Skip current branch
         if local_branch == current_branch {
             app.add_log("INFO", format!("Skipping current branch: {}", local_branch));
-            skipped_count += 1;
             continue;
         }
 
         // Skip main branch
         if local_branch == main_branch {
             app.add_log("INFO", format!("Skipping main branch: {}", local_branch));
-            skipped_count += 1;
             continue;
         }
 
         // Check if this branch corresponds to a merged PR
-        if merged_prs.contains_key(&local_branch) {
-            // Branch was merged via PR, safe to delete
-            match delete_local_branch(app, &local_branch) {
-                Ok(_) => {
-                    let pr_info = merged_prs.get(&local_branch).unwrap();
-                    app.add_log("SUCCESS", format!("Deleted {} ({})", local_branch, pr_info));
-                    deleted_count += 1;
+        if let Some(pr) = merged_prs.get(&local_branch) {
+            // Same name as a merged PR's head, but work added since then must not be lost
+            match branch_has_commits_beyond(&local_branch, &pr.head_oid) {
+                Ok(false) => {}
+                Ok(true) => {
+                    app.add_log(
+                        "WARN",
+                        format!(
+                            "Keeping {}: it has commits that are not in merged PR #{}",
+                            local_branch, pr.number
+                        ),
+                    );
+                    continue;
                 }
                 Err(e) => {
-                    app.add_error(format!("Failed to delete {}: {}", local_branch, e));
+                    app.add_log(
+                        "WARN",
+                        format!(
+                            "Keeping {}: could not compare it with the head of merged PR #{}: {}",
+                            local_branch, pr.number, e
+                        ),
+                    );
+                    continue;
                 }
             }
+            prunable.push(PrunableBranch {
+                name: local_branch,
+                reason: format!("PR #{} merged: {}", pr.number, pr.title),
+            });
         } else if let Some(remote_branch) = remote_branch_opt {
             // Check if remote branch still exists
             match remote_branch_exists(app, &remote_branch) {
+                Ok(false) if !force && !branch_merged_into(app, &local_branch, &main_branch) => {
+                    // The remote may have been deleted without merging; keep the local work
+                    app.add_log(
+                        "WARN",
+                        format!(
+                            "Keeping {}: {} no longer exists, but the branch has commits \
+                             that are not in {} (--force-prune deletes it anyway)",
+                            local_branch, remote_branch, main_branch
+                        ),
+                    );
+                }
                 Ok(false) => {
                     // Remote branch doesn't exist, likely merged and deleted
                     app.add_log(
                         "INFO",
                         format!(
-                            "Remote branch {} no longer exists, deleting local branch {}",
+                            "Remote branch {} no longer exists, marking local branch {} for deletion",
                             remote_branch, local_branch
                         ),
                     );
-                    match delete_local_branch(app, &local_branch) {
-                        Ok(_) => {
-                            deleted_count += 1;
-                        }
-                        Err(e) => {
-                            app.add_error(format!("Failed to delete {}: {}", local_branch, e));
-                        }
-                    }
+                    prunable.push(PrunableBranch {
+                        name: local_branch,
+                        reason: format!("{} no longer exists", remote_branch),
+                    });
                 }
                 Ok(true) => {
                     app.add_log(
@@ -1008,14 +3066,12 @@ pub fn prune_merged_branches(app: &mut App) -> Result<(), Box<dyn Error>> {
                             remote_branch, local_branch
                         ),
                     );
-                    skipped_count += 1;
                 }
                 Err(e) => {
                     app.add_error(format!(
                         "Failed to check remote branch {}: {}",
                         remote_branch, e
                     ));
-                    skipped_count += 1;
                 }
             }
         } else {
@@ -1024,18 +3080,85 @@ pub fn prune_merged_branches(app: &mut App) -> Result<(), Box<dyn Error>> {
                 "INFO",
                 format!("No remote tracking branch for {}, skipping", local_branch),
             );
-            skipped_count += 1;
+        }
+    }
+
+    Ok(prunable)
+}
+
+/// Whether all of `branch`'s commits are in `main_branch`, locally or on `app.remote`.
+fn branch_merged_into(app: &App, branch: &str, main_branch: &str) -> bool {
+    [
+        main_branch.to_string(),
+        format!("{}/{}", app.remote, main_branch),
+    ]
+    .iter()
+    .any(|main| git_is_ancestor(branch, main).unwrap_or(false))
+}
+
+/// Delete the given branches, returning how many were deleted
+pub fn delete_prunable_branches(app: &mut App, branches: &[PrunableBranch]) -> usize {
+    let mut deleted_count = 0;
+    for branch in branches {
+        match delete_local_branch(app, &branch.name) {
+            Ok(_) => {
+                app.add_log(
+                    "SUCCESS",
+                    format!("Deleted {} ({})", branch.name, branch.reason),
+                );
+                deleted_count += 1;
+            }
+            Err(e) => {
+                app.add_error(format!("Failed to delete {}: {}", branch.name, e));
+            }
         }
     }
 
     app.add_log(
         "SUCCESS",
         format!(
-            "Branch pruning completed: {} deleted, {} skipped",
-            deleted_count, skipped_count
+            "Branch pruning completed: {} deleted, {} failed",
+            deleted_count,
+            branches.len() - deleted_count
         ),
     );
-    Ok(())
+    deleted_count
+}
+
+/// Describe what `update_original_worktree_to_pr_branch` will discard in the
+/// current worktree: staged paths only, or every modified and untracked path
+/// (only those under `paths` when restricted with `--path`).
+pub fn pending_worktree_discards(
+    had_staged_changes: bool,
+    paths: &[String],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let output = if !paths.is_empty() {
+        Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=all", "--"])
+            .args(paths)
+            .output()?
+    } else if had_staged_changes {
+        Command::new("git")
+            .args(["diff", "--cached", "--name-only"])
+            .output()?
+    } else {
+        Command::new("git")
+            .args(["status", "--porcelain", "--untracked-files=all"])
+            .output()?
+    };
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to list worktree changes: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect())
 }
 
 #[cfg(test)]
@@ -1,7 +1,192 @@
+use crate::git_ops::run_command;
+use crate::tui::App;
 use std::error::Error;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// RAII guard that restores the process working directory when dropped,
+/// including during panic unwinding, so the process is never left inside a
+/// directory that is about to be deleted.
+pub struct CwdGuard {
+    previous: PathBuf,
+}
+
+impl CwdGuard {
+    /// Remember the current directory, then change into `path`.
+    pub fn change_to<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let previous = std::env::current_dir()?;
+        std::env::set_current_dir(path)?;
+        Ok(Self { previous })
+    }
+}
+
+impl Drop for CwdGuard {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.previous);
+    }
+}
+
+/// Directory-name prefix of every worktree created by `TempWorktree::enter`.
+const TEMP_WORKTREE_PREFIX: &str = "autopr-wt-";
+
+/// Scratch files gh-autopr writes under the git dir and must remove again.
+const SCRATCH_FILES: &[&str] = &["gh-autopr-pending-index"];
+
+fn is_temp_worktree_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(TEMP_WORKTREE_PREFIX))
+}
+
+/// Whether the current directory is inside a temp worktree.
+pub fn is_in_temp_worktree() -> bool {
+    Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .is_some_and(|out| {
+            is_temp_worktree_path(Path::new(String::from_utf8_lossy(&out.stdout).trim()))
+        })
+}
+
+/// Temp worktrees untouched for this long were left behind by a run that was
+/// killed before `TempWorktree`'s `Drop` could remove them.
+pub const STALE_WORKTREE_AGE: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Remove temp worktrees older than `STALE_WORKTREE_AGE`: the registered ones
+/// (`git worktree remove --force`) and any directories left in the git dir.
+/// Returns the paths removed; a younger one may belong to a run still going.
+pub fn cleanup_stale_worktrees(app: &mut App) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let out = run_command(app, "git", &["worktree", "list", "--porcelain"])?;
+    let mut candidates: Vec<PathBuf> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix("worktree "))
+        .map(PathBuf::from)
+        .filter(|path| is_temp_worktree_path(path))
+        .collect();
+    let registered = candidates.len();
+
+    let out = run_command(
+        app,
+        "git",
+        &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+    )?;
+    let git_dir = PathBuf::from(String::from_utf8_lossy(&out.stdout).trim());
+    for entry in fs_err::read_dir(&git_dir)? {
+        let path = entry?.path();
+        if is_temp_worktree_path(&path) && path.is_dir() && !candidates.contains(&path) {
+            candidates.push(path);
+        }
+    }
+
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for (i, path) in candidates.into_iter().enumerate() {
+        let age = fs_err::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default());
+        // A registered worktree whose directory is gone is stale too
+        let stale = match age {
+            Ok(age) => age >= STALE_WORKTREE_AGE,
+            Err(_) => i < registered,
+        };
+        if !stale {
+            continue;
+        }
+        let display = path.display().to_string();
+        if i < registered {
+            run_command(app, "git", &["worktree", "remove", "--force", &display])?;
+        }
+        if app.dry_run {
+            app.add_log(
+                "INFO",
+                format!("Would remove stale temp worktree {}", display),
+            );
+        } else {
+            let _ = fs_err::remove_dir_all(&path);
+            app.add_log("INFO", format!("Removed stale temp worktree {}", display));
+        }
+        removed.push(path);
+    }
+    if !removed.is_empty() {
+        run_command(app, "git", &["worktree", "prune"])?;
+    }
+    Ok(removed)
+}
+
+/// Post-run self-check (`--verify-clean-exit`): report anything a finished run
+/// left behind. Each leftover is one message; an empty list means clean.
+pub fn verify_clean_exit(orig_root: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut leftovers = Vec::new();
+
+    // 1. cwd is back at the original root
+    let cwd = std::env::current_dir()?;
+    if cwd.canonicalize()? != orig_root.canonicalize()? {
+        leftovers.push(format!(
+            "Working directory is {}, expected original root {}",
+            cwd.display(),
+            orig_root.display()
+        ));
+    }
+    if is_in_temp_worktree() {
+        leftovers.push("Process is still inside a temp worktree".to_string());
+    }
+
+    // 2. no temp worktree is still registered
+    let out = Command::new("git")
+        .args(["worktree", "list", "--porcelain"])
+        .current_dir(orig_root)
+        .output()?;
+    for line in String::from_utf8_lossy(&out.stdout).lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            if is_temp_worktree_path(Path::new(path)) {
+                leftovers.push(format!("Temp worktree still registered: {}", path));
+            }
+        }
+    }
+
+    // 3. no temp worktree directories or scratch files remain in the git dir
+    let git_dir = String::from_utf8(
+        Command::new("git")
+            .args(["rev-parse", "--path-format=absolute", "--git-common-dir"])
+            .current_dir(orig_root)
+            .output()?
+            .stdout,
+    )?;
+    for entry in fs_err::read_dir(git_dir.trim())? {
+        let path = entry?.path();
+        let is_scratch = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| SCRATCH_FILES.contains(&name));
+        if is_temp_worktree_path(&path) {
+            leftovers.push(format!(
+                "Temp worktree directory left behind: {}",
+                path.display()
+            ));
+        } else if is_scratch {
+            leftovers.push(format!("Scratch file left behind: {}", path.display()));
+        }
+    }
+
+    Ok(leftovers)
+}
+
+/// Copy an untracked file into the temp worktree as git would see it: symlinks
+/// are recreated rather than followed, and permissions (the executable bit)
+/// are kept.
+fn copy_untracked(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = fs_err::symlink_metadata(from)?;
+    #[cfg(unix)]
+    if metadata.file_type().is_symlink() {
+        return std::os::unix::fs::symlink(fs_err::read_link(from)?, to);
+    }
+    fs_err::copy(from, to)?;
+    fs_err::set_permissions(to, metadata.permissions())
+}
 
 /// RAII guard for the temp worktree
 pub struct TempWorktree {
@@ -12,12 +197,15 @@ pub struct TempWorktree {
     /// This determines cleanup behavior: if true, only staged changes went to PR,
     /// so unstaged changes should be preserved in the original worktree.
     had_staged_changes: bool,
+    /// Restores the caller's cwd after `Drop` has removed the worktree.
+    _cwd: CwdGuard,
 }
 
 impl TempWorktree {
     /// Enter a detached worktree that lives in `.git/autopr-wt-<timestamp>`.
     /// Captures all dirty state (staged, unstaged, untracked) and replays it in the temp worktree.
-    pub fn enter() -> Result<Self, Box<dyn Error>> {
+    /// A branch that only exists on `remote` is checked out tracking it.
+    pub fn enter(remote: &str) -> Result<Self, Box<dyn Error>> {
         // 1. Capture original location and branch
         let orig_root = PathBuf::from(
             String::from_utf8(
@@ -80,7 +268,7 @@ impl TempWorktree {
         let ts = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_millis();
-        let path = git_dir.join(format!("autopr-wt-{}", ts));
+        let path = git_dir.join(format!("{}{}", TEMP_WORKTREE_PREFIX, ts));
 
         // 3. create detached worktree at HEAD ------------------------------------
         let out = Command::new("git")
@@ -95,7 +283,17 @@ impl TempWorktree {
         }
 
         // 4. hop into that directory --------------------------------------------
-        std::env::set_current_dir(&path)?;
+        //    From here on the guard owns cleanup: any early `?` return drops it,
+        //    which removes the worktree and restores the original cwd.
+        let worktree = Self {
+            _cwd: CwdGuard::change_to(&path)?,
+            path,
+            orig_root,
+            orig_branch,
+            had_staged_changes,
+        };
+        let orig_root = &worktree.orig_root;
+        let orig_branch = &worktree.orig_branch;
 
         // 5. make the worktree point at the **same branch name** the user had.
         //    Use --force so this works even if that branch is already active
@@ -105,12 +303,7 @@ impl TempWorktree {
         //    ② otherwise try to track the remote branch       ───────────────────
         //    ③ as last resort create an *orphan* local branch ───────────────────
         let mut ok = Command::new("git")
-            .args([
-                "switch",
-                "--force",
-                "--ignore-other-worktrees",
-                &orig_branch,
-            ]) // <-- --force here
+            .args(["switch", "--force", "--ignore-other-worktrees", orig_branch]) // <-- --force here
             .status()?
             .success();
 
@@ -122,9 +315,9 @@ impl TempWorktree {
                     "--force", // allow switching to a branch that already exists
                     "--ignore-other-worktrees",
                     "-c",
-                    &orig_branch,
+                    orig_branch,
                     "--track",
-                    &format!("origin/{}", orig_branch),
+                    &format!("{}/{}", remote, orig_branch),
                 ])
                 .status()?
                 .success();
@@ -138,7 +331,7 @@ impl TempWorktree {
                     "--force",
                     "--ignore-other-worktrees",
                     "-c",
-                    &orig_branch,
+                    orig_branch,
                 ])
                 .output()?;
             if !out.status.success() {
@@ -152,10 +345,13 @@ impl TempWorktree {
 
         // ── 6. replay the dirty state inside the temp work-tree ────────────────
 
-        // 6a. staged patch → index only
+        // 6a. staged patch → index *and* working tree, so the working tree then
+        //     matches the original index: the unstaged patch was taken against
+        //     that, and only applies cleanly to files with both kinds of hunks
+        //     on top of the staged content
         if !staged_patch.is_empty() {
             let mut child = Command::new("git")
-                .args(["apply", "--cached", "-"])
+                .args(["apply", "--index", "-"])
                 .stdin(std::process::Stdio::piped())
                 .spawn()?;
             child.stdin.as_mut().unwrap().write_all(&staged_patch)?;
@@ -164,7 +360,7 @@ impl TempWorktree {
             }
         }
 
-        // 6b. unstaged patch → working tree
+        // 6b. unstaged patch → working tree, on top of the staged content
         if !unstaged_patch.is_empty() {
             let mut child = Command::new("git")
                 .args(["apply", "-"])
@@ -184,7 +380,7 @@ impl TempWorktree {
 
                 // Check if source file exists before trying to copy
                 // (files might have been renamed/moved and no longer exist at original path)
-                if !from.exists() {
+                if fs_err::symlink_metadata(&from).is_err() {
                     eprintln!(
                         "Warning: Skipping untracked file copy - source doesn't exist: {}",
                         from.display()
@@ -203,7 +399,7 @@ impl TempWorktree {
                     }
                 }
 
-                if let Err(e) = fs_err::copy(&from, to) {
+                if let Err(e) = copy_untracked(&from, Path::new(to)) {
                     eprintln!(
                         "Warning: Failed to copy untracked file from {} to {}: {}",
                         from.display(),
@@ -216,12 +412,7 @@ impl TempWorktree {
         }
 
         // All done – temp work-tree now has *exact* dirty state.
-        Ok(Self {
-            path,
-            orig_root,
-            orig_branch,
-            had_staged_changes,
-        })
+        Ok(worktree)
     }
 
     /// Get the original worktree root path
@@ -36,3 +36,36 @@ fn test_temp_worktree_struct_fields() {
         }
     }
 }
+
+#[test]
+#[serial_test::serial]
+fn test_cwd_guard_restores_directory_on_drop() {
+    let original = std::env::current_dir().unwrap();
+    let target = tempfile::TempDir::new().unwrap();
+
+    {
+        let _guard = super::CwdGuard::change_to(target.path()).unwrap();
+        assert_eq!(
+            std::env::current_dir().unwrap().canonicalize().unwrap(),
+            target.path().canonicalize().unwrap()
+        );
+    }
+
+    assert_eq!(std::env::current_dir().unwrap(), original);
+}
+
+#[test]
+#[serial_test::serial]
+fn test_cwd_guard_restores_directory_on_panic() {
+    let original = std::env::current_dir().unwrap();
+    let target = tempfile::TempDir::new().unwrap();
+    let target_path = target.path().to_path_buf();
+
+    let result = std::panic::catch_unwind(|| {
+        let _guard = super::CwdGuard::change_to(&target_path).unwrap();
+        panic!("simulated failure while inside the temp directory");
+    });
+
+    assert!(result.is_err());
+    assert_eq!(std::env::current_dir().unwrap(), original);
+}
@@ -1,11 +1,20 @@
+use crate::git_ops::run_command;
 use crate::App;
 use once_cell::sync::OnceCell;
 use std::error::Error;
+use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 static ISSUES_CACHE: OnceCell<Mutex<Option<String>>> = OnceCell::new();
 
+/// How long issues saved by an earlier run are reused (`ai.issues_cache_ttl`)
+pub const DEFAULT_ISSUES_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// Issues of every repo sharing this `.git`, as `{key: {fetched_at, issues}}`
+const ISSUES_CACHE_FILE: &str = "gh-autopr-issues-cache.json";
+
 // Example GitHub issues JSON output:
 /*
 [
@@ -35,6 +44,17 @@ pub fn github_list_issues(app: &mut App) -> Result<String, Box<dyn Error>> {
         return Ok(cached_data.clone());
     }
 
+    // Then issues saved by a recent run, unless --refresh-issues
+    let disk_cache = issues_cache_location(app);
+    if let Some((path, key)) = disk_cache.as_ref().filter(|_| !app.refresh_issues) {
+        let saved = fs_err::read_to_string(path).unwrap_or_default();
+        if let Some(issues) = cached_issues(&saved, key, app.issues_cache_ttl, unix_now()) {
+            app.add_log("INFO", "Using GitHub issues cached by a recent run");
+            *cache = Some(issues.clone());
+            return Ok(issues);
+        }
+    }
+
     // Cache miss - fetch from GitHub
     let output = Command::new("gh")
         .args(["issue", "list", "--json", "number,title,labels,body"])
@@ -47,6 +67,7 @@ pub fn github_list_issues(app: &mut App) -> Result<String, Box<dyn Error>> {
             app.add_log("WARN", "Repository has issues disabled");
             let empty_list = "[]".to_string();
             *cache = Some(empty_list.clone());
+            save_cached_issues(disk_cache, &empty_list);
             return Ok(empty_list);
         }
         app.add_error(stderr);
@@ -58,6 +79,204 @@ pub fn github_list_issues(app: &mut App) -> Result<String, Box<dyn Error>> {
 
     // Update cache
     *cache = Some(json_str.clone());
+    save_cached_issues(disk_cache, &json_str);
 
     Ok(json_str)
 }
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The on-disk issues cache and this repo's key in it: the URL of `app.remote`,
+/// else the worktree root. `None` outside a repo or with a zero TTL.
+fn issues_cache_location(app: &App) -> Option<(PathBuf, String)> {
+    if app.issues_cache_ttl.is_zero() {
+        return None;
+    }
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+    };
+    let git_dir = git(&["rev-parse", "--path-format=absolute", "--git-common-dir"])?;
+    let key = git(&["remote", "get-url", &app.remote])
+        .or_else(|| git(&["rev-parse", "--show-toplevel"]))?;
+    Some((PathBuf::from(git_dir).join(ISSUES_CACHE_FILE), key))
+}
+
+/// Issues saved under `key` in the cache file contents `saved`, if fetched
+/// less than `ttl` before `now` (seconds since the epoch).
+pub fn cached_issues(saved: &str, key: &str, ttl: Duration, now: u64) -> Option<String> {
+    let saved: serde_json::Value = serde_json::from_str(saved).ok()?;
+    let entry = saved.get(key)?;
+    let fetched_at = entry["fetched_at"].as_u64()?;
+    if now.saturating_sub(fetched_at) >= ttl.as_secs() {
+        return None;
+    }
+    entry["issues"].as_str().map(str::to_string)
+}
+
+/// The cache file contents `saved` with `issues` stored under `key` as fetched at `now`.
+pub fn with_cached_issues(saved: &str, key: &str, issues: &str, now: u64) -> String {
+    let mut saved = serde_json::from_str::<serde_json::Value>(saved)
+        .ok()
+        .filter(serde_json::Value::is_object)
+        .unwrap_or_else(|| serde_json::json!({}));
+    saved[key] = serde_json::json!({"fetched_at": now, "issues": issues});
+    saved.to_string()
+}
+
+/// Rewrite this repo's entry in the on-disk cache; a failed write only costs the next run a `gh` call.
+fn save_cached_issues(location: Option<(PathBuf, String)>, issues: &str) {
+    if let Some((path, key)) = location {
+        let saved = fs_err::read_to_string(&path).unwrap_or_default();
+        let _ = fs_err::write(&path, with_cached_issues(&saved, &key, issues, unix_now()));
+    }
+}
+
+/// A label defined in the repository, as listed by `gh label list`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct RepoLabel {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The repository's labels, for `--auto-label`.
+pub fn github_list_labels(app: &mut App) -> Result<Vec<RepoLabel>, Box<dyn Error>> {
+    let output = Command::new("gh")
+        .args([
+            "label",
+            "list",
+            "--json",
+            "name,description",
+            "--limit",
+            "200",
+        ])
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        app.add_error(stderr.clone());
+        return Err(format!("Failed to list labels: {}", stderr.trim()).into());
+    }
+
+    let labels: Vec<RepoLabel> = serde_json::from_slice(&output.stdout)?;
+    app.add_log("INFO", format!("Found {} repository labels", labels.len()));
+    Ok(labels)
+}
+
+/// Where a PR from a fork goes: the head branch lives in `head_repo`, the PR
+/// is opened in `base_repo` (both `owner/repo`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkTarget {
+    pub head_repo: String,
+    pub base_repo: String,
+}
+
+/// The fork relationship in `gh repo view --json nameWithOwner,parent` output;
+/// `None` for a repository that is not a fork.
+pub fn fork_target_from_repo_view(json: &str) -> Option<ForkTarget> {
+    let view: serde_json::Value = serde_json::from_str(json).ok()?;
+    let parent = view.get("parent").filter(|p| !p.is_null())?;
+    Some(ForkTarget {
+        head_repo: view["nameWithOwner"].as_str()?.to_string(),
+        base_repo: format!(
+            "{}/{}",
+            parent["owner"]["login"].as_str()?,
+            parent["name"].as_str()?
+        ),
+    })
+}
+
+/// Whether `app.remote`, where the branch is pushed, is a fork on GitHub, and
+/// if so of which repository. Errors when gh can't tell (not a GitHub remote,
+/// no access), in which case the caller can't know the base repo either.
+pub fn github_fork_target(app: &mut App) -> Result<Option<ForkTarget>, Box<dyn Error>> {
+    let url = run_command(app, "git", &["remote", "get-url", &app.remote.clone()])?;
+    let url = String::from_utf8_lossy(&url.stdout).trim().to_string();
+    if url.is_empty() {
+        return Err(format!("remote {} has no URL", app.remote).into());
+    }
+    let output = run_command(
+        app,
+        "gh",
+        &["repo", "view", &url, "--json", "nameWithOwner,parent"],
+    )?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+    Ok(fork_target_from_repo_view(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// One CI check on a PR, as reported by `gh pr checks --json name,bucket`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct PrCheck {
+    pub name: String,
+    /// gh's state bucket: "pass", "fail", "pending", "skipping" or "cancel"
+    pub bucket: String,
+}
+
+/// Where a PR's checks stand as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksOutcome {
+    NoChecks,
+    Pending,
+    Passed,
+    Failed,
+}
+
+impl ChecksOutcome {
+    /// Any failure or cancellation fails the lot; otherwise pending until every check settles.
+    pub fn from_checks(checks: &[PrCheck]) -> Self {
+        if checks.is_empty() {
+            ChecksOutcome::NoChecks
+        } else if checks
+            .iter()
+            .any(|c| c.bucket == "fail" || c.bucket == "cancel")
+        {
+            ChecksOutcome::Failed
+        } else if checks.iter().any(|c| c.bucket == "pending") {
+            ChecksOutcome::Pending
+        } else {
+            ChecksOutcome::Passed
+        }
+    }
+}
+
+/// Current CI checks for `pr_number`. A PR without any checks yields an empty list.
+pub fn github_pr_checks(app: &mut App, pr_number: u64) -> Result<Vec<PrCheck>, Box<dyn Error>> {
+    let output = Command::new("gh")
+        .args([
+            "pr",
+            "checks",
+            &pr_number.to_string(),
+            "--json",
+            "name,bucket",
+        ])
+        .output()?;
+
+    // gh exits non-zero while checks are pending or failing, so parse stdout first
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if let Ok(checks) = serde_json::from_str::<Vec<PrCheck>>(&stdout) {
+        return Ok(checks);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if stderr.contains("no checks reported") {
+        return Ok(vec![]);
+    }
+    app.add_error(stderr.clone());
+    Err(format!("Failed to read PR checks: {}", stderr.trim()).into())
+}
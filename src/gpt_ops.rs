@@ -1,37 +1,310 @@
 use crate::config::AppConfig;
-use crate::tui::App;
+use crate::github_ops::RepoLabel;
+use crate::template_ops::ensure_template_sections;
+use crate::tui::{App, LogSink};
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
 use serde::Deserialize;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-/// Retries an async operation up to MAX_RETRIES times with exponential backoff.
-/// Initial delay: 1s, then 2s, then 4s (for 3 total attempts).
-async fn retry_with_backoff<F, T, E>(mut operation: F) -> Result<T, E>
-where
-    F: FnMut() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, E>> + Send>>,
-    E: std::fmt::Display,
-{
-    const MAX_RETRIES: u32 = 3;
+/// A failed AI API attempt, and whether trying again could help.
+#[derive(Debug)]
+struct ApiError {
+    message: String,
+    retryable: bool,
+}
+
+impl ApiError {
+    /// Network failures (connect, timeout) are worth retrying.
+    fn network(message: String) -> Self {
+        Self {
+            message,
+            retryable: true,
+        }
+    }
+
+    /// Only rate limits (429) and server errors (5xx) are transient; auth and
+    /// malformed-request errors fail the same way every time.
+    fn http(status: reqwest::StatusCode, message: String) -> Self {
+        Self {
+            message,
+            retryable: is_retryable_status(status),
+        }
+    }
+
+    fn fatal(message: String) -> Self {
+        Self {
+            message,
+            retryable: false,
+        }
+    }
+
+    /// An `error` event in a stream. Overload and server errors (such as
+    /// Anthropic's `overloaded_error` mid-stream) are as transient as a 429/5xx.
+    fn stream(error: &serde_json::Value) -> Self {
+        let kind = error["type"].as_str().unwrap_or_default();
+        Self {
+            message: format!(
+                "AI stream error: {}",
+                error["message"].as_str().unwrap_or(kind)
+            ),
+            retryable: matches!(
+                kind,
+                "overloaded_error" | "api_error" | "rate_limit_error" | "server_error"
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Delay before retry number `attempt` (1-based): 1s, 2s, 4s, ... plus up to
+/// 25% jitter so parallel runs don't retry in lockstep.
+fn backoff_delay(attempt: u32, jitter_seed: u64) -> Duration {
     const INITIAL_DELAY_MS: u64 = 1000;
+    let base_ms = INITIAL_DELAY_MS.saturating_mul(1 << (attempt - 1).min(16));
+    Duration::from_millis(base_ms + jitter_seed % (base_ms / 4 + 1))
+}
 
-    for attempt in 1..=MAX_RETRIES {
+/// Retries an async operation up to `max_attempts` times with exponential
+/// backoff, as long as the error is retryable. Each retry is logged.
+async fn retry_with_backoff<F, T>(
+    app: &mut App<'_>,
+    max_attempts: u32,
+    mut operation: F,
+) -> Result<T, ApiError>
+where
+    F: FnMut() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T, ApiError>> + Send>>,
+{
+    let max_attempts = max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
         match operation().await {
             Ok(result) => return Ok(result),
+            Err(err) if !err.retryable || attempt >= max_attempts => return Err(err),
             Err(err) => {
-                if attempt == MAX_RETRIES {
-                    return Err(err);
-                }
-
-                let delay_ms = INITIAL_DELAY_MS * 2u64.pow(attempt - 1);
-                eprintln!(
-                    "AI API call attempt {}/{} failed: {}. Retrying in {}ms...",
-                    attempt, MAX_RETRIES, err, delay_ms
+                let jitter_seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.subsec_nanos() as u64)
+                    .unwrap_or(0);
+                let delay = backoff_delay(attempt, jitter_seed);
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "AI API call attempt {}/{} failed: {}. Retrying in {}ms...",
+                        attempt,
+                        max_attempts,
+                        err,
+                        delay.as_millis()
+                    ),
                 );
-                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// ─── Streaming ────────────────────────────────────────────────────────────────
+
+/// Text streamed so far by the current model call (see `App::streaming_preview`).
+type StreamPreview = Arc<Mutex<String>>;
+
+/// Whether an error reply says the endpoint cannot stream, as some
+/// OpenAI-compatible servers do for `"stream": true`. Plain 400/422s (e.g. a
+/// rejected temperature) only count if they mention streaming.
+fn rejects_streaming(status: reqwest::StatusCode, body: &str) -> bool {
+    match status.as_u16() {
+        405 | 415 | 501 => true,
+        400 | 422 => body.to_ascii_lowercase().contains("stream"),
+        _ => false,
+    }
+}
+
+/// A model reply: HTTP status, the request body that produced it, the raw
+/// response body, and for a streamed reply the text assembled from it.
+struct Reply {
+    status: reqwest::StatusCode,
+    request: serde_json::Value,
+    raw: String,
+    streamed: Option<String>,
+}
+
+/// Send `body` with `"stream": true` and read the reply (see `read_response`).
+/// When the endpoint says it cannot stream, send it again as a blocking request.
+/// Each attempt starts from an empty `preview`, not the text of a failed one.
+async fn send_preferring_stream(
+    request: reqwest::RequestBuilder,
+    mut body: serde_json::Value,
+    url: &str,
+    attempt: u32,
+    provider: &str,
+    preview: &StreamPreview,
+    delta: fn(&serde_json::Value) -> Option<&str>,
+) -> Result<Reply, ApiError> {
+    if let Ok(mut preview) = preview.lock() {
+        preview.clear();
+    }
+    body["stream"] = serde_json::json!(true);
+    let send = |request: reqwest::RequestBuilder, body: serde_json::Value| async move {
+        match request.json(&body).send().await {
+            Ok(resp) => {
+                let status = resp.status();
+                let (raw, streamed) = read_response(resp, preview, delta).await?;
+                Ok(Reply {
+                    status,
+                    request: body,
+                    raw,
+                    streamed,
+                })
+            }
+            Err(e) => {
+                let err = format!("{} HTTP error: {}", provider, e);
+                record_exchange(serde_json::json!({
+                    "attempt": attempt, "url": url, "request": body, "error": err
+                }));
+                Err(ApiError::network(err))
+            }
+        }
+    };
+    let retry_request = request.try_clone();
+    let reply = send(request, body).await?;
+    match retry_request {
+        Some(retry_request) if rejects_streaming(reply.status, &reply.raw) => {
+            record_exchange(serde_json::json!({
+                "attempt": attempt,
+                "url": url,
+                "request": reply.request,
+                "status": reply.status.as_u16(),
+                "response": reply.raw,
+            }));
+            let mut body = reply.request;
+            if let Some(fields) = body.as_object_mut() {
+                fields.remove("stream");
+            }
+            send(retry_request, body).await
+        }
+        _ => Ok(reply),
+    }
+}
+
+/// Read a response body. Server-sent events are assembled from the text that
+/// `delta` extracts from each event, appended to `preview` as it arrives;
+/// returns the raw body and, for a stream, the assembled text. A stream that
+/// ends without its closing event (OpenAI's `[DONE]`, Anthropic's
+/// `message_stop`) was cut off, and is retried. Other bodies (errors, or an
+/// endpoint that ignored `"stream": true`) are read whole.
+async fn read_response(
+    mut resp: reqwest::Response,
+    preview: &StreamPreview,
+    delta: fn(&serde_json::Value) -> Option<&str>,
+) -> Result<(String, Option<String>), ApiError> {
+    let is_event_stream = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    if !is_event_stream || !resp.status().is_success() {
+        let raw = resp
+            .text()
+            .await
+            .unwrap_or_else(|_| "<failed to read body>".to_string());
+        return Ok((raw, None));
+    }
+
+    let mut raw = Vec::new();
+    let mut text = String::new();
+    let mut consumed = 0;
+    let mut closed = false;
+    loop {
+        let chunk = resp
+            .chunk()
+            .await
+            .map_err(|e| ApiError::network(format!("AI stream interrupted: {}", e)))?;
+        let done = chunk.is_none();
+        raw.extend_from_slice(chunk.as_deref().unwrap_or_default());
+        // Events are line-based; a partial last line waits for the next chunk
+        let complete = if done {
+            raw.len()
+        } else {
+            raw.iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(consumed, |i| i + 1)
+        };
+        for line in String::from_utf8_lossy(&raw[consumed..complete]).lines() {
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                closed = true;
+                continue;
+            }
+            let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else {
+                continue;
+            };
+            if event["error"].is_object() {
+                return Err(ApiError::stream(&event["error"]));
+            }
+            if event["type"] == "message_stop" {
+                closed = true;
+            }
+            if let Some(piece) = delta(&event) {
+                text.push_str(piece);
+                if let Ok(mut preview) = preview.lock() {
+                    preview.push_str(piece);
+                }
             }
         }
+        consumed = complete;
+        if done {
+            break;
+        }
+    }
+    if !closed {
+        return Err(ApiError::network("AI stream ended early".to_string()));
+    }
+    Ok((String::from_utf8_lossy(&raw).to_string(), Some(text)))
+}
+
+/// Text of an OpenAI `chat.completion.chunk` event.
+fn openai_delta(event: &serde_json::Value) -> Option<&str> {
+    event["choices"][0]["delta"]["content"].as_str()
+}
+
+/// Text of an Anthropic `content_block_delta` event.
+fn anthropic_delta(event: &serde_json::Value) -> Option<&str> {
+    (event["type"] == "content_block_delta")
+        .then(|| event["delta"]["text"].as_str())
+        .flatten()
+}
+
+// ─── Request dump (`--dump-request`) ──────────────────────────────────────────
+
+/// Every model exchange made by this process, in order: one entry per HTTP
+/// attempt (so retries show up individually) plus any JSON repair steps.
+static EXCHANGES: Lazy<Mutex<Vec<serde_json::Value>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+fn record_exchange(entry: serde_json::Value) {
+    if let Ok(mut exchanges) = EXCHANGES.lock() {
+        exchanges.push(entry);
     }
+}
 
-    unreachable!("Loop should have returned or exhausted retries")
+/// Write all exchanges recorded so far to `path`, with the API key redacted.
+fn write_exchange_dump(path: &std::path::Path, api_key: Option<&str>) -> std::io::Result<()> {
+    let exchanges = EXCHANGES.lock().map(|e| e.clone()).unwrap_or_default();
+    let dump = serde_json::to_string_pretty(&exchanges)?;
+    let secrets: Vec<String> = api_key.map(str::to_string).into_iter().collect();
+    fs_err::write(path, crate::tui::redact(&dump, &secrets))
 }
 
 // ─── Anthropic response types ─────────────────────────────────────────────────
@@ -52,18 +325,126 @@ struct AnthropicContentBlock {
 
 /// Call the configured AI provider and return the raw text response.
 async fn call_ai_api(
+    app: &mut App<'_>,
     config: &AppConfig,
     system_message: &str,
     user_message: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
+    // A misbehaving proxy may echo the key back in an error body
+    if let Some(key) = config.ai.api_key.as_deref() {
+        app.add_secret(key);
+    }
+    if let Some(summary) = sampling_summary(config) {
+        app.add_log("INFO", format!("AI sampling: {}", summary));
+    }
+    check_estimated_cost(app, config, system_message, user_message)?;
     match config.provider() {
-        "anthropic" => call_anthropic(config, system_message, user_message).await,
-        _ => call_openai(config, system_message, user_message).await,
+        "anthropic" => call_anthropic(app, config, system_message, user_message).await,
+        _ => call_openai(app, config, system_message, user_message).await,
+    }
+}
+
+/// Rough token count: about four characters per token for English and code.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// USD per million input tokens by model-name prefix (first match wins), at
+/// list prices when written. Only for the pre-request estimate.
+const INPUT_PRICES_PER_MTOK: [(&str, f64); 10] = [
+    ("gpt-4o-mini", 0.15),
+    ("gpt-4o", 2.50),
+    ("gpt-4.1-mini", 0.40),
+    ("gpt-4.1", 2.00),
+    ("o4-mini", 1.10),
+    ("claude-3-5-haiku", 0.80),
+    ("claude-haiku", 1.00),
+    ("claude-sonnet", 3.00),
+    ("claude-opus-4-1", 15.00),
+    ("claude-opus", 5.00),
+];
+
+/// Estimated USD cost of sending `tokens` input tokens to `model`, if priced.
+pub fn estimate_input_cost(model: &str, tokens: usize) -> Option<f64> {
+    INPUT_PRICES_PER_MTOK
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, per_mtok)| tokens as f64 * per_mtok / 1_000_000.0)
+}
+
+/// Log the estimated size and cost of a request, and refuse it when the cost
+/// is over `max_cost`. A model without a known price cannot be checked.
+fn check_estimated_cost(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    system_message: &str,
+    user_message: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tokens = estimate_tokens(system_message) + estimate_tokens(user_message);
+    let model = config.model();
+    let cost = estimate_input_cost(model, tokens);
+    app.add_log(
+        "INFO",
+        match cost {
+            Some(cost) => format!(
+                "Estimated prompt: ~{} tokens, ~${:.4} for {}",
+                tokens, cost, model
+            ),
+            None => format!(
+                "Estimated prompt: ~{} tokens (no price known for {})",
+                tokens, model
+            ),
+        },
+    );
+    match (config.ai.max_cost, cost) {
+        (Some(max_cost), Some(cost)) if cost > max_cost => {
+            let err = format!(
+                "Estimated cost ${:.4} exceeds --max-cost ${:.4}; shrink the diff (--path, --max-diff-bytes) or raise the limit",
+                cost, max_cost
+            );
+            app.add_error(err.clone());
+            Err(err.into())
+        }
+        (Some(_), None) => {
+            app.add_log(
+                "WARN",
+                format!("--max-cost not enforced: no price known for {}", model),
+            );
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Effective `--temperature`/`--max-tokens` overrides, if any were set.
+fn sampling_summary(config: &AppConfig) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(t) = config.temperature() {
+        parts.push(format!("temperature={}", t));
+    }
+    if let Some(n) = config.max_tokens() {
+        parts.push(format!("max_tokens={}", n));
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// Appended to 400/422 errors when sampling overrides were sent: some models
+/// (e.g. reasoning models, or Anthropic above temperature 1.0) reject them, and
+/// such errors are not retried.
+fn sampling_rejection_hint(sampling: Option<&str>, status: reqwest::StatusCode) -> String {
+    match sampling {
+        Some(summary) if matches!(status.as_u16(), 400 | 422) => format!(
+            "\nThe request set {}; if the model rejects these, unset --temperature/--max-tokens \
+             (GH_AUTOPR_TEMPERATURE/GH_AUTOPR_MAX_TOKENS) to use the provider defaults.",
+            summary
+        ),
+        _ => String::new(),
     }
 }
 
 /// Call the Anthropic Messages API directly via HTTP.
 async fn call_anthropic(
+    app: &mut App<'_>,
     config: &AppConfig,
     system_message: &str,
     user_message: &str,
@@ -85,55 +466,98 @@ async fn call_anthropic(
     let model = config.model().to_string();
     let system = system_message.to_string();
     let user = user_message.to_string();
-
-    let response_text = retry_with_backoff(|| {
+    let temperature = config.temperature();
+    let sampling = sampling_summary(config);
+    let max_tokens = config.max_tokens().unwrap_or(2048);
+    let preview = app.streaming_preview.clone();
+
+    let mut attempt = 0;
+    let response_text = retry_with_backoff(app, config.max_attempts(), || {
+        attempt += 1;
+        let attempt = attempt;
         let model = model.clone();
         let system = system.clone();
         let user = user.clone();
         let api_key = api_key.clone();
         let url = url.clone();
+        let sampling = sampling.clone();
+        let preview = preview.clone();
         Box::pin(async move {
-            let body = serde_json::json!({
+            let mut body = serde_json::json!({
                 "model": model,
-                "max_tokens": 2048,
+                "max_tokens": max_tokens,
                 "system": system,
                 "messages": [{"role": "user", "content": user}]
             });
+            if let Some(t) = temperature {
+                body["temperature"] = serde_json::json!(t);
+            }
 
             let client = reqwest::Client::new();
-            let resp = client
+            let request = client
                 .post(&url)
                 .header("x-api-key", &api_key)
-                .header("anthropic-version", "2023-06-01") // stable API version
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| format!("Anthropic HTTP error: {}", e))?;
+                .header("anthropic-version", "2023-06-01"); // stable API version
+            let Reply {
+                status,
+                request: body,
+                raw: text,
+                streamed,
+            } = send_preferring_stream(
+                request,
+                body,
+                &url,
+                attempt,
+                "Anthropic",
+                &preview,
+                anthropic_delta,
+            )
+            .await?;
+            record_exchange(serde_json::json!({
+                "attempt": attempt,
+                "url": url,
+                "request": body,
+                "status": status.as_u16(),
+                "response": text,
+            }));
 
-            if !resp.status().is_success() {
-                let status = resp.status();
-                let text = resp.text().await.unwrap_or_default();
-                return Err(format!(
-                    "Anthropic API error: URL={}, status={}, body={}",
-                    url, status, text
+            if !status.is_success() {
+                return Err(ApiError::http(
+                    status,
+                    format!(
+                        "Anthropic API error: URL={}, status={}, body={}{}",
+                        url,
+                        status,
+                        text,
+                        sampling_rejection_hint(sampling.as_deref(), status)
+                    ),
                 ));
             }
 
-            let parsed: AnthropicResponse = resp
-                .json()
-                .await
-                .map_err(|e| format!("Anthropic response parse error: {}", e))?;
+            if let Some(streamed) = streamed {
+                if streamed.is_empty() {
+                    return Err(ApiError::fatal(
+                        "Anthropic API returned no text content".to_string(),
+                    ));
+                }
+                return Ok(streamed);
+            }
+
+            let parsed: AnthropicResponse = serde_json::from_str(&text)
+                .map_err(|e| ApiError::fatal(format!("Anthropic response parse error: {}", e)))?;
 
             parsed
                 .content
                 .into_iter()
                 .find(|b| b.block_type == "text")
                 .and_then(|b| b.text)
-                .ok_or_else(|| "Anthropic API returned no text content".to_string())
+                .ok_or_else(|| {
+                    ApiError::fatal("Anthropic API returned no text content".to_string())
+                })
         })
     })
     .await
-    .map_err(|e| -> Box<dyn std::error::Error> { e.into() })?;
+    .map_err(|e| -> Box<dyn std::error::Error> { e.message.into() })?;
 
     Ok(response_text)
 }
@@ -159,6 +583,77 @@ struct OpenAiMessage {
     content: Option<String>,
 }
 
+/// Where an OpenAI API request goes and how it authenticates.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpenAiEndpoint {
+    pub url: String,
+    pub auth_header: &'static str,
+    pub auth_value: String,
+}
+
+/// The endpoint for `path` ("chat/completions" or "models").
+///
+/// Plain OpenAI (and compatible servers) get `<base_url>/<path>` with a bearer
+/// token. Azure OpenAI (`AppConfig::openai_is_azure`) sends chat requests to the
+/// deployment named by the model, or by a deployment URL given as `base_url`,
+/// with the key in an `api-key` header and the required `api-version`.
+pub fn openai_endpoint(
+    config: &AppConfig,
+    api_key: &str,
+    path: &str,
+) -> Result<OpenAiEndpoint, String> {
+    if !config.openai_is_azure() {
+        let base_url = config
+            .ai
+            .base_url
+            .as_deref()
+            .unwrap_or("https://api.openai.com/v1");
+        return Ok(OpenAiEndpoint {
+            url: format!("{}/{}", base_url.trim_end_matches('/'), path),
+            auth_header: "Authorization",
+            auth_value: format!("Bearer {}", api_key),
+        });
+    }
+
+    let base_url = config.ai.base_url.as_deref().ok_or(
+        "Azure OpenAI needs base_url (OPENAI_BASE_URL), e.g. https://<resource>.openai.azure.com",
+    )?;
+    let api_version = config
+        .ai
+        .api_version
+        .as_deref()
+        .filter(|v| !v.trim().is_empty())
+        .ok_or("Azure OpenAI needs an API version: set OPENAI_API_VERSION or `api_version` in the config file")?;
+    let base_url = base_url.trim_end_matches('/');
+    let (resource, deployment) = match base_url.split_once("/deployments/") {
+        Some((resource, rest)) => (resource, rest.split('/').next().unwrap_or(rest)),
+        None => (base_url, config.model()),
+    };
+    let resource = resource.trim_end_matches("/openai");
+    let url = if path == "models" {
+        format!("{}/openai/models", resource)
+    } else {
+        format!("{}/openai/deployments/{}/{}", resource, deployment, path)
+    };
+    Ok(OpenAiEndpoint {
+        url: format!("{}?api-version={}", url, api_version),
+        auth_header: "api-key",
+        auth_value: api_key.to_string(),
+    })
+}
+
+// ─── Model listing types (shared by OpenAI and Anthropic `/models`) ───────────
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
 /// Call any OpenAI-compatible chat completions endpoint via direct HTTP.
 ///
 /// Using `reqwest` directly means:
@@ -166,6 +661,7 @@ struct OpenAiMessage {
 /// - Unknown fields from non-standard providers (e.g. `reasoning_content`)
 ///   are silently ignored rather than causing a parse failure.
 async fn call_openai(
+    app: &mut App<'_>,
     config: &AppConfig,
     system_message: &str,
     user_message: &str,
@@ -177,53 +673,98 @@ async fn call_openai(
         .ok_or("OpenAI API key not set. Set `api_key` in ~/.config/gh-autopr/config.toml or OPENAI_KEY env var.")?
         .to_string();
 
-    let base_url = config
-        .ai
-        .base_url
-        .as_deref()
-        .unwrap_or("https://api.openai.com/v1")
-        .to_string();
-    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+    let OpenAiEndpoint {
+        url,
+        auth_header,
+        auth_value,
+    } = openai_endpoint(config, &api_key, "chat/completions")?;
     let model = config.model().to_string();
     let system = system_message.to_string();
     let user = user_message.to_string();
-
-    let response_text = retry_with_backoff(|| {
+    let temperature = config.temperature();
+    let sampling = sampling_summary(config);
+    let max_tokens = config.max_tokens();
+    let preview = app.streaming_preview.clone();
+
+    let mut attempt = 0;
+    let response_text = retry_with_backoff(app, config.max_attempts(), || {
+        attempt += 1;
+        let attempt = attempt;
         let model = model.clone();
         let system = system.clone();
         let user = user.clone();
-        let api_key = api_key.clone();
+        let auth_value = auth_value.clone();
         let url = url.clone();
+        let sampling = sampling.clone();
+        let preview = preview.clone();
         Box::pin(async move {
-            let body = serde_json::json!({
+            let mut body = serde_json::json!({
                 "model": model,
                 "messages": [
                     {"role": "system", "content": system},
                     {"role": "user",   "content": user},
                 ]
             });
+            if let Some(t) = temperature {
+                body["temperature"] = serde_json::json!(t);
+            }
+            if let Some(n) = max_tokens {
+                body["max_tokens"] = serde_json::json!(n);
+            }
 
             let client = reqwest::Client::new();
-            let resp = client
-                .post(&url)
-                .header("Authorization", format!("Bearer {}", api_key))
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| format!("OpenAI HTTP error: {}", e))?;
-
-            let status = resp.status();
-            let raw = resp
-                .text()
-                .await
-                .unwrap_or_else(|_| "<failed to read body>".to_string());
+            let request = client.post(&url).header(auth_header, auth_value);
+            let Reply {
+                status,
+                request: body,
+                raw,
+                streamed,
+            } = send_preferring_stream(
+                request,
+                body,
+                &url,
+                attempt,
+                "OpenAI",
+                &preview,
+                openai_delta,
+            )
+            .await?;
+            record_exchange(serde_json::json!({
+                "attempt": attempt,
+                "url": url,
+                "request": body,
+                "status": status.as_u16(),
+                "response": raw,
+            }));
 
             if !status.is_success() {
-                return Err(format!("OpenAI API error HTTP {}: {}", status, raw));
+                return Err(ApiError::http(
+                    status,
+                    format!(
+                        "OpenAI API error HTTP {}: {}{}",
+                        status,
+                        raw,
+                        sampling_rejection_hint(sampling.as_deref(), status)
+                    ),
+                ));
+            }
+
+            if let Some(streamed) = streamed {
+                if streamed.is_empty() {
+                    return Err(ApiError::fatal(format!(
+                        "OpenAI API streamed no content.\nRaw body: {}",
+                        raw
+                    )));
+                }
+                return Ok(streamed);
             }
 
-            let parsed: OpenAiResponse = serde_json::from_str(&raw)
-                .map_err(|e| format!("OpenAI response parse error: {}\nRaw body: {}", e, raw))?;
+            let parsed: OpenAiResponse = serde_json::from_str(&raw).map_err(|e| {
+                ApiError::fatal(format!(
+                    "OpenAI response parse error: {}\nRaw body: {}",
+                    e, raw
+                ))
+            })?;
 
             parsed
                 .choices
@@ -231,15 +772,15 @@ async fn call_openai(
                 .next()
                 .and_then(|c| c.message.content)
                 .ok_or_else(|| {
-                    format!(
+                    ApiError::fatal(format!(
                         "OpenAI API returned no choices or empty content.\nRaw body: {}",
                         raw
-                    )
+                    ))
                 })
         })
     })
     .await
-    .map_err(|e| -> Box<dyn std::error::Error> { e.into() })?;
+    .map_err(|e| -> Box<dyn std::error::Error> { e.message.into() })?;
 
     Ok(response_text)
 }
@@ -251,6 +792,7 @@ async fn call_openai(
 /// Common issues:
 /// - Trailing commas before closing braces/brackets
 /// - Bare strings in objects (missing key)
+/// - Output cut off by the token limit: an unterminated string, missing `}`/`]`
 fn try_repair_json(json: &str) -> Option<String> {
     let mut repaired = String::with_capacity(json.len() + 50);
     let mut chars = json.chars().peekable();
@@ -306,125 +848,1349 @@ fn try_repair_json(json: &str) -> Option<String> {
         return Some(aggressive);
     }
 
-    None
+    [repaired, aggressive]
+        .iter()
+        .filter_map(|candidate| close_truncated_json(candidate))
+        .find(|closed| serde_json::from_str::<serde_json::Value>(closed).is_ok())
 }
 
-/// Validates if a string is a valid git branch name
-fn is_valid_git_branch_name(name: &str) -> bool {
-    if name.is_empty() || name == "-" {
-        return false;
+/// Finish JSON that stops early: close a dangling string, give a dangling key
+/// a `null` value, drop a trailing comma, then append the `}`/`]` still open,
+/// innermost first. `None` if it isn't an object or array, or a closer doesn't
+/// match what is open.
+fn close_truncated_json(json: &str) -> Option<String> {
+    let json = json.trim();
+    if !json.starts_with(['{', '[']) {
+        return None;
+    }
+    // Open containers; for objects, whether a value (not a key) comes next
+    let mut stack: Vec<(char, bool)> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_is_key = false;
+    let mut key_awaits_colon = false;
+    for c in json.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    key_awaits_colon = string_is_key;
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                string_is_key = matches!(stack.last(), Some(('{', false)));
+            }
+            '{' | '[' => stack.push((c, false)),
+            '}' | ']' => {
+                let (open, _) = stack.pop()?;
+                if (open, c) != ('{', '}') && (open, c) != ('[', ']') {
+                    return None;
+                }
+            }
+            ':' => {
+                key_awaits_colon = false;
+                if let Some((_, expects_value)) = stack.last_mut() {
+                    *expects_value = true;
+                }
+            }
+            ',' => {
+                if let Some(('{', expects_value)) = stack.last_mut() {
+                    *expects_value = false;
+                }
+            }
+            _ => {}
+        }
     }
 
-    if name.starts_with('.') || name.ends_with('.') {
-        return false;
+    let mut closed = json.to_string();
+    if in_string {
+        // A cut escape sequence would swallow the closing quote
+        if let Some(start) = closed.rfind('\\') {
+            let tail = &closed[start + 1..];
+            let partial_unicode = tail.starts_with('u')
+                && tail.len() < 5
+                && tail[1..].chars().all(|h| h.is_ascii_hexdigit());
+            if escaped || partial_unicode {
+                closed.truncate(start);
+            }
+        }
+        closed.push('"');
+        key_awaits_colon = string_is_key;
+    }
+    let trimmed = closed.trim_end().trim_end_matches(',').trim_end().len();
+    closed.truncate(trimmed);
+    if key_awaits_colon {
+        closed.push_str(": null");
+    } else if closed.ends_with(':') {
+        closed.push_str(" null");
+    }
+    for (open, _) in stack.iter().rev() {
+        closed.push(if *open == '{' { '}' } else { ']' });
     }
+    Some(closed)
+}
 
-    if name.contains("..") {
-        return false;
+/// Fit the `gh issue list --json` array into `max_bytes` by dropping whole issues
+/// from the end, so the model always sees valid JSON. Input that is not a JSON
+/// array falls back to a plain UTF-8-safe cut.
+fn truncate_issues_json(issues_json: &str, max_bytes: usize) -> String {
+    if issues_json.len() <= max_bytes {
+        return issues_json.to_string();
     }
 
-    for c in name.chars() {
-        match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '/' | '.' => continue,
-            _ => return false,
+    let Ok(issues) = serde_json::from_str::<Vec<serde_json::Value>>(issues_json) else {
+        return crate::git_ops::truncate_utf8(issues_json, max_bytes);
+    };
+
+    let mut kept = Vec::new();
+    let mut size = 2; // "[]"
+    for issue in issues {
+        let entry_len = issue.to_string().len() + usize::from(!kept.is_empty()); // comma
+        if size + entry_len > max_bytes {
+            break;
         }
+        size += entry_len;
+        kept.push(issue);
     }
-
-    true
+    serde_json::Value::Array(kept).to_string()
 }
 
-// ─── Public API ───────────────────────────────────────────────────────────────
-
-pub async fn gpt_generate_branch_name_and_commit_description(
-    app: &mut App<'_>,
-    config: &AppConfig,
-    diff_context: String,
-    issues_json: Option<String>,
-    what_arg: Option<String>,
-    why_arg: Option<String>,
-    bigger_picture_arg: Option<String>,
-) -> Result<(String, String, Option<String>), Box<dyn std::error::Error>> {
-    const MAX_ISSUES_LEN: usize = 16 * 1024;
+/// Words too common in code and prose to say anything about relevance.
+const ISSUE_STOPWORDS: [&str; 24] = [
+    "self", "true", "false", "none", "some", "string", "return", "struct", "impl", "with", "from",
+    "this", "that", "when", "into", "should", "would", "have", "test", "tests", "value", "error",
+    "result", "could",
+];
+
+/// Lowercase words of at least 4 characters, identifiers also split at `_`.
+fn relevance_words(text: &str) -> std::collections::HashSet<String> {
+    let mut words = std::collections::HashSet::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let word = word.to_lowercase();
+        for part in std::iter::once(word.as_str()).chain(word.split('_')) {
+            if part.chars().count() >= 4 && !ISSUE_STOPWORDS.contains(&part) {
+                words.insert(part.to_string());
+            }
+        }
+    }
+    words
+}
 
-    let mut system_message = String::from(
-        r#"You prepare concise GitHub Pull Requests.
+/// The open issues ranked by how much they talk about what the diff touches,
+/// most related first, then fitted into `max_bytes` like `truncate_issues_json`.
+///
+/// Keywords are the changed file paths (weighted double) and the identifiers
+/// on changed lines. Each issue scores the keywords in its title three times
+/// and those in its body once; ties keep `gh`'s order (newest first).
+pub fn select_relevant_issues(diff: &str, issues_json: &str, max_bytes: usize) -> String {
+    let Ok(issues) = serde_json::from_str::<Vec<serde_json::Value>>(issues_json) else {
+        return truncate_issues_json(issues_json, max_bytes);
+    };
 
-OUTPUT
-Return valid JSON with EXACTLY these keys: "branch_name", "commit_title", "commit_details".
-IMPORTANT: All values must be strings (or null for commit_details). Do NOT use nested objects or arrays.
+    let mut weights: std::collections::HashMap<String, usize> = Default::default();
+    for line in diff.lines() {
+        if let Some(path) = diff_header_path(line) {
+            for word in path.into_iter().flat_map(relevance_words) {
+                weights.insert(word, 2);
+            }
+        } else if line.starts_with('+') || line.starts_with('-') {
+            for word in relevance_words(&line[1..]) {
+                weights.entry(word).or_insert(1);
+            }
+        }
+    }
+    let score = |text: &serde_json::Value| -> usize {
+        relevance_words(text.as_str().unwrap_or_default())
+            .iter()
+            .filter_map(|word| weights.get(word))
+            .sum()
+    };
 
-BRANCH NAME (strict)
-- Allowed: letters, digits, hyphen (-), underscore (_), dot (.), up to ONE forward slash (/).
-- Not allowed: spaces, parentheses, colons, other symbols.
-- No leading/trailing dot. No consecutive dots. Max one "/".
-Examples OK: feat/worktree-update, fix-memory-leak, release/v1.0.0
-Examples NO: feat(worktree): update, fix memory leak, .hidden, branch., branch..name
+    let mut ranked: Vec<(usize, serde_json::Value)> = issues
+        .into_iter()
+        .map(|issue| (3 * score(&issue["title"]) + score(&issue["body"]), issue))
+        .collect();
+    ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let ranked: Vec<serde_json::Value> = ranked.into_iter().map(|(_, issue)| issue).collect();
+    truncate_issues_json(&serde_json::Value::Array(ranked).to_string(), max_bytes)
+}
 
-COMMIT TITLE (Conventional Commits)
-- Format: <type>(<scope>)!?: <imperative summary>
-- Use "!" if breaking.
-- ≤ 72 chars. Action verbs. No fluff. Only claims supported by the diff.
+/// The first `n` issues of a JSON array; other input is returned as is.
+fn first_issues(issues_json: &str, n: usize) -> String {
+    match serde_json::from_str::<Vec<serde_json::Value>>(issues_json) {
+        Ok(mut issues) => {
+            issues.truncate(n);
+            serde_json::Value::Array(issues).to_string()
+        }
+        Err(_) => issues_json.to_string(),
+    }
+}
 
-COMMIT DETAILS (a single Markdown string, NOT a nested object)
-- Value must be a single string containing Markdown, or null. Never an object or array.
-- If the PR is truly tiny AND no issue refs: set "commit_details" to null.
-- Otherwise write ≤ 120 words total AND ≤ 8 lines. Prefer bullets. No code blocks.
-- Include ONLY sections that add high value; Exclude those with low and medium value. Section order:
-  - ### Motivation (≤ 1 bullet in the section)
-  - ### Solution (1-3 bullets)
-  - ### Impact (include ONLY if breaking; note migration in ≤ 1 bullet)
-  - ### Details (0-3 non-obvious bullets; skip routine refactors)
-  - ### Meta — single line if needed: "updated tests accordingly" or "updated comments".
-- Do NOT restate obvious diffs. Do NOT claim perf/security/UX benefits unless explicit in the diff.
-- Focus on MAJOR change(s). Minor changes only if they are the main point.
+/// Longest branch name accepted: `refs/heads/<name>` must stay a usable path
+/// on common filesystems (255-byte file names, minus room for `.lock`).
+const MAX_BRANCH_NAME_BYTES: usize = 244;
 
-GITHUB ISSUES (append at end)
-- If relevant: add exactly one line: "Relates to #X[, #Y]" or "Closes #X[, #Y]".
-- Only when truly connected. If multiple, comma-separate numbers.
-- If the PR is tiny BUT has relevant issues, include ONLY this line (do not use null).
+/// Extra requests for a valid branch name before giving up.
+const MAX_BRANCH_NAME_RETRIES: usize = 2;
 
-STYLE
-- Crisp, professional, fun-but-sparing. No filler ("this PR", "in order to", etc.).
-"#,
-    );
+/// Validates if a string is a valid git branch name
+pub fn is_valid_git_branch_name(name: &str) -> bool {
+    branch_name_problem(name).is_none()
+}
 
-    if let Some(what) = what_arg.clone() {
-        system_message.push_str(&format!("\n\nUser provided 'what': {}", what));
+/// Why `name` is not an acceptable branch name, phrased for the model.
+fn branch_name_problem(name: &str) -> Option<&'static str> {
+    if name.is_empty() || name == "-" {
+        return Some("it is empty");
     }
-    if let Some(why) = why_arg.clone() {
-        system_message.push_str(&format!("\n\nUser provided 'why': {}", why));
+    if name.len() > MAX_BRANCH_NAME_BYTES {
+        return Some("it is longer than 244 bytes");
     }
-    if let Some(bigger_picture) = bigger_picture_arg.clone() {
-        system_message.push_str(&format!(
-            "\n\nUser provided 'bigger picture': {}",
-            bigger_picture
-        ));
+
+    // git reserves `*.lock` for its ref lock files, in every path component
+    if name.split('/').any(|part| part.ends_with(".lock")) {
+        return Some("a path component ends in .lock");
+    }
+    if name.contains("@{") {
+        return Some("it contains @{");
     }
 
-    let user_message = format!(
+    if name.starts_with('.') || name.ends_with('.') {
+        return Some("it starts or ends with a dot");
+    }
+
+    if name.contains("..") {
+        return Some("it contains ..");
+    }
+
+    if !name
+        .chars()
+        .all(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '/' | '.'))
+    {
+        return Some("it contains characters other than letters, digits, -, _, / and .");
+    }
+
+    None
+}
+
+/// Best-effort repair of a model-proposed branch name (`--auto-fix-branch`):
+/// every disallowed character (spaces, colons, parentheses, ...) becomes a
+/// hyphen, hyphen runs collapse, dot runs collapse to one, and leading or
+/// trailing dots, hyphens and slashes are trimmed.
+/// `feat(worktree): update` -> `feat-worktree-update`. The result may still be
+/// invalid (e.g. empty), so callers must validate it.
+pub fn sanitize_branch_name(raw: &str) -> String {
+    let mut name = String::with_capacity(raw.len());
+    for c in raw.trim().chars() {
+        let c = match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '/' | '.' => c,
+            _ => '-',
+        };
+        let collapses = matches!(c, '-' | '.') && name.ends_with(c);
+        if !collapses {
+            name.push(c);
+        }
+    }
+    name.trim_matches(|c| matches!(c, '.' | '-' | '/'))
+        .to_string()
+}
+
+/// Extract the Conventional Commits type from a title: `feat(scope)!: x` -> `feat`.
+pub fn parse_conventional_type(title: &str) -> Option<String> {
+    let (head, _) = title.split_once(':')?;
+    let head = head.trim().trim_end_matches('!');
+    let kind = head.split_once('(').map_or(head, |(kind, _)| kind);
+    let kind = kind.trim().to_ascii_lowercase();
+    (!kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphabetic())).then_some(kind)
+}
+
+/// Prefix `branch_name` with the commit type from `commit_title` (`feat/...`).
+/// Branch names allow a single slash, so an existing prefix is replaced.
+pub fn type_prefixed_branch(branch_name: &str, commit_title: &str) -> String {
+    match parse_conventional_type(commit_title) {
+        Some(kind) => {
+            let name = branch_name
+                .split_once('/')
+                .map_or(branch_name, |(_, rest)| rest);
+            format!("{}/{}", kind, name)
+        }
+        None => branch_name.to_string(),
+    }
+}
+
+/// Directories that hold the code rather than name a part of it.
+const SOURCE_ROOTS: [&str; 4] = ["src", "lib", "crates", "packages"];
+
+/// The path a unified diff's `--- a/` or `+++ b/` file header names:
+/// `Some(None)` for the `/dev/null` side of an added or deleted file, and
+/// `None` when `line` is not a file header.
+fn diff_header_path(line: &str) -> Option<Option<&str>> {
+    let path = line
+        .strip_prefix("+++ ")
+        .or_else(|| line.strip_prefix("--- "))?;
+    if path == "/dev/null" {
+        return Some(None);
+    }
+    path.strip_prefix("b/")
+        .or_else(|| path.strip_prefix("a/"))
+        .map(Some)
+}
+
+/// Files a unified diff touches, from its `--- a/` and `+++ b/` lines.
+pub fn diff_changed_paths(diff: &str) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    for path in diff.lines().filter_map(diff_header_path).flatten() {
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.to_string());
+        }
+    }
+    paths
+}
+
+/// A Conventional Commits scope for the changed files: the innermost directory
+/// they all share (`src/git_ops/*` -> `git_ops`), else the module they all
+/// belong to (`src/git_ops.rs` with `src/git_ops/tests.rs` -> `git_ops`).
+/// `None` when they only share a source root like `src`, or nothing.
+pub fn infer_scope(changed_paths: &[String]) -> Option<String> {
+    fn common<'a>(lists: &[Vec<&'a str>]) -> Vec<&'a str> {
+        let mut prefix = lists.first().cloned().unwrap_or_default();
+        for list in lists {
+            let shared = prefix.iter().zip(list).take_while(|(a, b)| a == b).count();
+            prefix.truncate(shared);
+        }
+        prefix
+    }
+    let scope_of = |prefix: Vec<&str>| {
+        prefix
+            .last()
+            .filter(|last| !SOURCE_ROOTS.contains(last))
+            .map(|last| last.to_ascii_lowercase())
+    };
+
+    let dirs: Vec<Vec<&str>> = changed_paths
+        .iter()
+        .map(|path| {
+            let mut parts: Vec<&str> = path.split('/').collect();
+            parts.pop();
+            parts
+        })
+        .collect();
+    if let Some(scope) = scope_of(common(&dirs)) {
+        return Some(scope);
+    }
+    let modules: Vec<Vec<&str>> = changed_paths
+        .iter()
+        .zip(dirs)
+        .map(|(path, mut parts)| {
+            let file = path.rsplit('/').next().unwrap_or(path);
+            parts.push(file.split('.').next().unwrap_or(file));
+            parts
+        })
+        .collect();
+    scope_of(common(&modules)).filter(|scope| !scope.is_empty() && scope != "mod")
+}
+
+/// `title` with its Conventional Commits scope set to `scope`:
+/// `fix(ui)!: x` -> `fix(git_ops)!: x`, `fix: x` -> `fix(git_ops): x`.
+/// Titles without a type are returned unchanged.
+pub fn with_scope(title: &str, scope: &str) -> String {
+    if parse_conventional_type(title).is_none() {
+        return title.to_string();
+    }
+    let (head, rest) = title.split_once(':').unwrap_or((title, ""));
+    let breaking = if head.trim_end().ends_with('!') {
+        "!"
+    } else {
+        ""
+    };
+    let kind = head.trim().trim_end_matches('!');
+    let kind = kind.split_once('(').map_or(kind, |(kind, _)| kind).trim();
+    format!("{}({}){}:{}", kind, scope, breaking, rest)
+}
+
+/// Default `--ticket-pattern`: Jira-style keys such as `ABC-123`.
+pub const DEFAULT_TICKET_PATTERN: &str = r"[A-Z]+-\d+";
+
+/// The first ticket matching `pattern` in `branch`: `feature/ABC-123-x` -> `ABC-123`.
+pub fn ticket_from_branch(branch: &str, pattern: &regex::Regex) -> Option<String> {
+    pattern.find(branch).map(|m| m.as_str().to_string())
+}
+
+/// Prefix `title` with `[ticket]`, unless it already mentions the ticket.
+pub fn with_ticket_prefix(title: &str, ticket: &str) -> String {
+    if title.contains(ticket) {
+        title.to_string()
+    } else {
+        format!("[{}] {}", ticket, title)
+    }
+}
+
+/// Cut `title` to at most `max_chars` characters, at a word boundary when
+/// there is one, ending in an ellipsis.
+pub fn truncate_title(title: &str, max_chars: usize) -> String {
+    if title.chars().count() <= max_chars {
+        return title.to_string();
+    }
+    let kept: String = title.chars().take(max_chars.saturating_sub(1)).collect();
+    let kept = match kept.rfind(char::is_whitespace) {
+        Some(end) if end > 0 => &kept[..end],
+        _ => &kept,
+    };
+    format!("{}…", kept.trim_end())
+}
+
+/// How a diff changes one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileChangeKind {
+    Added,
+    Deleted,
+    Renamed,
+    Modified,
+}
+
+/// One file of a unified diff, with its path after the change.
+#[derive(Debug)]
+struct FileChange {
+    path: String,
+    kind: FileChangeKind,
+    /// Added plus removed lines
+    changed_lines: usize,
+}
+
+/// The files a `git diff` output touches, in diff order.
+fn diff_file_changes(diff: &str) -> Vec<FileChange> {
+    let mut changes: Vec<FileChange> = Vec::new();
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            in_hunk = false;
+            let path = header.rsplit_once(" b/").map_or(header, |(_, b)| b);
+            changes.push(FileChange {
+                path: path.to_string(),
+                kind: FileChangeKind::Modified,
+                changed_lines: 0,
+            });
+            continue;
+        }
+        let Some(change) = changes.last_mut() else {
+            continue;
+        };
+        if line.starts_with("@@") {
+            in_hunk = true;
+        } else if in_hunk {
+            if line.starts_with('+') || line.starts_with('-') {
+                change.changed_lines += 1;
+            }
+        } else if line.starts_with("new file mode") {
+            change.kind = FileChangeKind::Added;
+        } else if line.starts_with("deleted file mode") {
+            change.kind = FileChangeKind::Deleted;
+        } else if let Some(to) = line.strip_prefix("rename to ") {
+            change.path = to.to_string();
+            change.kind = FileChangeKind::Renamed;
+        } else if let Some(path) = line.strip_prefix("+++ b/") {
+            change.path = path.to_string();
+        }
+    }
+    changes
+}
+
+fn is_docs_path(path: &str) -> bool {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    path.starts_with("docs/")
+        || [".md", ".rst", ".adoc"]
+            .iter()
+            .any(|ext| file.ends_with(ext))
+        || file.starts_with("LICENSE")
+}
+
+fn is_test_path(path: &str) -> bool {
+    let mut parts = path.split('/').rev();
+    let file = parts.next().unwrap_or(path);
+    let stem = file.split('.').next().unwrap_or(file);
+    parts.any(|dir| dir == "tests" || dir == "test")
+        || stem == "tests"
+        || stem.starts_with("test_")
+        || stem.ends_with("_test")
+        || stem.ends_with("_tests")
+        || file.contains(".test.")
+        || file.contains(".spec.")
+}
+
+/// Conventional Commits type for a change, from which kinds of files it
+/// touches and whether it adds, removes or renames them.
+fn heuristic_commit_type(changes: &[FileChange]) -> &'static str {
+    let all = |pred: fn(&FileChange) -> bool| !changes.is_empty() && changes.iter().all(pred);
+    if all(|c| is_docs_path(&c.path)) {
+        "docs"
+    } else if all(|c| is_test_path(&c.path)) {
+        "test"
+    } else if all(|c| c.path.starts_with(".github/")) {
+        "ci"
+    } else if changes.iter().any(|c| c.kind == FileChangeKind::Added) {
+        "feat"
+    } else if all(|c| matches!(c.kind, FileChangeKind::Deleted | FileChangeKind::Renamed)) {
+        "refactor"
+    } else {
+        "chore"
+    }
+}
+
+/// Commit message, branch name and PR body made from the diff alone, for
+/// `--offline` and when no API key is configured. The type comes from the
+/// kinds of files touched, the scope from `infer_scope`, and the summary names
+/// the most-changed file; the body lists the changed files by directory.
+pub fn heuristic_commit_message(diff: &str) -> GeneratedContent {
+    let changes = diff_file_changes(diff);
+    let kind = heuristic_commit_type(&changes);
+    let paths: Vec<String> = changes.iter().map(|c| c.path.clone()).collect();
+    let scope = infer_scope(&paths);
+
+    // The first of the most-changed files, so ties keep diff order
+    let main = changes
+        .iter()
+        .rev()
+        .max_by_key(|c| c.changed_lines)
+        .map(|c| {
+            let verb = match c.kind {
+                FileChangeKind::Added => "add",
+                FileChangeKind::Deleted => "remove",
+                FileChangeKind::Renamed => "rename",
+                FileChangeKind::Modified => "update",
+            };
+            let file = c.path.rsplit('/').next().unwrap_or(&c.path);
+            (verb, file)
+        });
+    let (verb, file) = main.unwrap_or(("update", "files"));
+    let mut summary = format!("{} {}", verb, file);
+    match changes.len() {
+        0 | 1 => {}
+        2 => summary.push_str(" and 1 other file"),
+        n => summary.push_str(&format!(" and {} other files", n - 1)),
+    }
+    let commit_title = match &scope {
+        Some(scope) => format!("{}({}): {}", kind, scope, summary),
+        None => format!("{}: {}", kind, summary),
+    };
+
+    let stem = file
+        .split('.')
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or(file);
+    let branch_name = sanitize_branch_name(&format!("{}-{}-{}", kind, verb, stem).to_lowercase());
+    let branch_name = if is_valid_git_branch_name(&branch_name) {
+        branch_name
+    } else {
+        format!("{}-changes", kind)
+    };
+
+    let mut by_dir: std::collections::BTreeMap<&str, Vec<String>> = Default::default();
+    for change in &changes {
+        let (dir, file) = change.path.rsplit_once('/').unwrap_or((".", &change.path));
+        let note = match change.kind {
+            FileChangeKind::Added => " (added)",
+            FileChangeKind::Deleted => " (deleted)",
+            FileChangeKind::Renamed => " (renamed)",
+            FileChangeKind::Modified => "",
+        };
+        by_dir
+            .entry(dir)
+            .or_default()
+            .push(format!("  - `{}`{}", file, note));
+    }
+    let commit_details = (!by_dir.is_empty()).then(|| {
+        let mut body = String::from("Changed files:\n");
+        for (dir, files) in by_dir {
+            body.push_str(&format!("\n- `{}/`\n{}", dir, files.join("\n")));
+        }
+        body
+    });
+
+    GeneratedContent {
+        branch_name,
+        commit_title,
+        commit_details,
+        labels: vec![],
+        ready_for_review: None,
+        readiness_reason: None,
+    }
+}
+
+/// Keep only the suggested labels that exist in the repo (matched
+/// case-insensitively, returned with the repo's spelling), without duplicates.
+pub fn filter_known_labels(suggested: &[String], available: &[RepoLabel]) -> Vec<String> {
+    let mut known: Vec<String> = Vec::new();
+    for label in suggested {
+        if let Some(repo_label) = available
+            .iter()
+            .find(|l| l.name.eq_ignore_ascii_case(label.trim()))
+        {
+            if !known.contains(&repo_label.name) {
+                known.push(repo_label.name.clone());
+            }
+        }
+    }
+    known
+}
+
+/// One PR of a `--stack` chain, as proposed by the model.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StackGroup {
+    pub branch_name: String,
+    pub commit_title: String,
+    #[serde(default)]
+    pub commit_details: Option<String>,
+    /// Paths or globs (`src/ui/**`, `*.md`) of the files this PR commits
+    #[serde(default)]
+    pub file_globs: Vec<String>,
+}
+
+/// The changed files split over the groups of a stack.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StackAssignment {
+    /// The files each group commits, in group order
+    pub files: Vec<Vec<String>>,
+    /// Files matched by more than one group, with the (0-based) groups that
+    /// matched; each is committed only by the first of them
+    pub shared: Vec<(String, Vec<usize>)>,
+    /// Files no group matched; committed by the last group
+    pub unmatched: Vec<String>,
+}
+
+/// Give every changed file to exactly one group: the first whose globs match
+/// it, since later PRs build on earlier ones and can't take a file back out.
+/// Files nothing matches go to the last group so no change is left behind.
+/// The old path of each of `renames` (`(new, old)`) goes wherever its new path
+/// does, as committing a rename needs both.
+pub fn assign_stack_files(
+    groups: &[StackGroup],
+    paths: &[String],
+    renames: &[(String, String)],
+) -> StackAssignment {
+    let mut assignment = StackAssignment {
+        files: vec![Vec::new(); groups.len()],
+        ..Default::default()
+    };
+    if groups.is_empty() {
+        assignment.unmatched = paths.to_vec();
+        return assignment;
+    }
+    let renames: Vec<&(String, String)> = renames
+        .iter()
+        .filter(|(new, old)| paths.contains(new) && paths.contains(old))
+        .collect();
+    for path in paths {
+        if renames.iter().any(|(_, old)| old == path) {
+            continue;
+        }
+        let matching: Vec<usize> = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.file_globs.iter().any(|g| glob_matches(g, path)))
+            .map(|(i, _)| i)
+            .collect();
+        let group = match matching.first() {
+            Some(&first) => {
+                if matching.len() > 1 {
+                    assignment.shared.push((path.clone(), matching.clone()));
+                }
+                first
+            }
+            None => {
+                assignment.unmatched.push(path.clone());
+                groups.len() - 1
+            }
+        };
+        assignment.files[group].push(path.clone());
+        if let Some((_, old)) = renames.iter().find(|(new, _)| new == path) {
+            assignment.files[group].push(old.clone());
+        }
+    }
+    assignment
+}
+
+/// Whether the gitignore-style `pattern` matches `path` or a directory above
+/// it. `*` and `?` stay within one path component, `**` spans any number, and
+/// a pattern without `/` matches at any depth.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim().trim_start_matches("./").trim_matches('/');
+    if pattern.is_empty() {
+        return false;
+    }
+    let pattern = if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    let matches = |candidate: &str| glob_match(pattern.as_bytes(), candidate.as_bytes());
+    matches(path) || path.match_indices('/').any(|(i, _)| matches(&path[..i]))
+}
+
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => match rest.strip_prefix(b"/") {
+            // `**/`: zero or more whole components
+            Some(rest) => (0..=path.len())
+                .filter(|&i| i == 0 || path[i - 1] == b'/')
+                .any(|i| glob_match(rest, &path[i..])),
+            None => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        },
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != b'/')
+            .any(|i| glob_match(rest, &path[i..])),
+        [b'?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != b'/') && glob_match(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..]),
+    }
+}
+
+/// The groups in a `{"groups": [...]}` stack plan, with branch names made
+/// valid and unique.
+pub fn parse_stack_groups(value: &serde_json::Value) -> Result<Vec<StackGroup>, String> {
+    let groups = value.get("groups").unwrap_or(value).clone();
+    let mut groups: Vec<StackGroup> = serde_json::from_value(groups)
+        .map_err(|e| format!("Stack plan is not a list of groups: {}", e))?;
+    if groups.is_empty() {
+        return Err("Stack plan has no groups".to_string());
+    }
+    let mut seen: Vec<String> = Vec::new();
+    for group in &mut groups {
+        let mut name = sanitize_branch_name(&group.branch_name);
+        if let Some(problem) = branch_name_problem(&name) {
+            return Err(format!(
+                "Stack plan has an invalid branch name '{}' ({})",
+                group.branch_name, problem
+            ));
+        }
+        let base = name.clone();
+        let mut suffix = 2;
+        while seen.contains(&name) {
+            name = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        seen.push(name.clone());
+        group.branch_name = name;
+    }
+    Ok(groups)
+}
+
+// ─── Public API ───────────────────────────────────────────────────────────────
+
+/// User-supplied steering for the model.
+#[derive(Debug, Clone, Default)]
+pub struct PromptHints {
+    pub what: Option<String>,
+    pub why: Option<String>,
+    pub bigger_picture: Option<String>,
+    /// Repo labels the model may pick from (`--auto-label`); empty asks for none
+    pub labels: Vec<RepoLabel>,
+    /// PR template (`--template`) the model fills in as `commit_details`
+    pub pr_template: Option<String>,
+    /// Replace the title's scope with the one inferred from the changed paths
+    pub force_scope: bool,
+    /// Also ask whether the change is ready for review (`--auto-ready`)
+    pub ask_readiness: bool,
+}
+
+/// What the model generated for a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedContent {
+    pub branch_name: String,
+    pub commit_title: String,
+    pub commit_details: Option<String>,
+    /// Suggested labels, already restricted to `PromptHints::labels`
+    pub labels: Vec<String>,
+    /// The model's judgment whether the change is complete enough to leave
+    /// draft (`PromptHints::ask_readiness`); `None` when it gave none
+    pub ready_for_review: Option<bool>,
+    /// Why the model judged the change ready or not
+    pub readiness_reason: Option<String>,
+}
+
+/// What `generate_pr_description` returns.
+pub type PrDescription = GeneratedContent;
+
+/// Settings for `generate_pr_description`.
+#[allow(dead_code)] // library API; the gh-autopr binary doesn't call it
+#[derive(Clone, Default)]
+pub struct GenOptions {
+    /// Provider, model, API key and the rest of the `[ai]` settings
+    pub config: AppConfig,
+    pub hints: PromptHints,
+    /// Gets every log line as `(level, message)`; they are dropped without it
+    pub on_log: Option<LogSink>,
+}
+
+/// Generate a branch name, commit title and PR description for `diff` (a
+/// unified diff) with the configured model, without a TUI or any git or gh
+/// calls. `issues` is an optional JSON array of open issues it may reference.
+#[allow(dead_code)]
+pub async fn generate_pr_description(
+    diff: &str,
+    issues: Option<&str>,
+    opts: GenOptions,
+) -> Result<PrDescription, Box<dyn std::error::Error>> {
+    let mut app = App::new("gh-autopr");
+    app.log_sink = opts.on_log;
+    gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &opts.config,
+        diff.to_string(),
+        issues.map(str::to_string),
+        &opts.hints,
+    )
+    .await
+}
+
+/// Parse a model reply into a `PrDescription` the way `generate_pr_description`
+/// does (code fences stripped, broken JSON repaired), without calling a model.
+/// Branch names and labels are returned unchecked.
+#[allow(dead_code)]
+pub fn parse_pr_description(response: &str) -> Result<PrDescription, Box<dyn std::error::Error>> {
+    let mut app = App::new("gh-autopr");
+    parse_json_response(&mut app, response).map(|parsed| description_from_json(&parsed))
+}
+
+pub async fn gpt_generate_branch_name_and_commit_description(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    diff_context: String,
+    issues_json: Option<String>,
+    hints: &PromptHints,
+) -> Result<GeneratedContent, Box<dyn std::error::Error>> {
+    let result =
+        generate_branch_name_and_commit_description(app, config, diff_context, issues_json, hints)
+            .await;
+
+    // Dump on failure too: that's when a bug report needs it most
+    if let Some(path) = &config.ai.dump_request {
+        match write_exchange_dump(path, config.ai.api_key.as_deref()) {
+            Ok(()) => app.add_log(
+                "INFO",
+                format!("Wrote model exchange to {}", path.display()),
+            ),
+            Err(e) => app.add_log(
+                "WARN",
+                format!("Failed to write --dump-request file: {}", e),
+            ),
+        }
+    }
+
+    result
+}
+
+/// System prompt for `--stack`, which splits one change into a chain of PRs.
+const STACK_SYSTEM_PROMPT: &str = r#"You split one large change into a stack of small, dependent GitHub Pull Requests.
+
+OUTPUT
+Return valid JSON: {"groups": [{"branch_name": "...", "commit_title": "...", "commit_details": "..." or null, "file_globs": ["..."]}]}
+
+GROUPS
+- 2-5 groups, in merge order: each PR is opened on top of the previous one.
+- Put foundations (types, helpers, refactors) before the changes that use them.
+- file_globs: exact paths from the changed files list where possible, else globs like "src/ui/**".
+- Every changed file belongs to exactly one group. A file matching several groups goes to the first.
+
+NAMING
+- branch_name: letters, digits, "-", "_", ".", at most one "/"; unique per group.
+- commit_title: Conventional Commits, "<type>(<scope>): <imperative summary>", ≤ 72 chars.
+- commit_details: ≤ 80 words of Markdown bullets, or null.
+"#;
+
+/// Ask the model how to split the change in `diff_context` (touching
+/// `changed_paths`) into a stack of PRs.
+pub async fn gpt_plan_stack(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    diff_context: String,
+    changed_paths: &[String],
+    hints: &PromptHints,
+) -> Result<Vec<StackGroup>, Box<dyn std::error::Error>> {
+    let mut user_message = format!(
+        "Changed files:\n{}\n\nContext:\n{}",
+        changed_paths.join("\n"),
+        diff_context
+    );
+    for (label, hint) in [
+        ("what", &hints.what),
+        ("why", &hints.why),
+        ("bigger picture", &hints.bigger_picture),
+    ] {
+        if let Some(hint) = hint {
+            user_message.push_str(&format!("\n\nUser provided '{}': {}", label, hint));
+        }
+    }
+    app.add_log(
+        "INFO",
+        format!(
+            "Calling {} ({}) to plan the stack",
+            config.provider(),
+            config.model()
+        ),
+    );
+    let response = request_json(app, config, STACK_SYSTEM_PROMPT, &user_message).await?;
+    parse_stack_groups(&response).map_err(|e| {
+        app.add_error(e.clone());
+        e.into()
+    })
+}
+
+// ─── Large diffs (`--summarize-large`) ────────────────────────────────────────
+
+/// Per-file summary requests in flight at once.
+const FILE_SUMMARY_CONCURRENCY: usize = 4;
+
+/// Share of the diff budget given to the raw head of a summarized diff.
+const SUMMARIZED_DIFF_HEAD_DIVISOR: usize = 4;
+
+/// Files summarized one by one; the rest are only counted, so a huge diff
+/// doesn't turn into hundreds of AI requests.
+const MAX_SUMMARIZED_FILES: usize = 50;
+
+const FILE_SUMMARY_SYSTEM_PROMPT: &str = "You summarize one file's part of a git diff. \
+Reply with a single line of at most 25 words saying what changed in the file. \
+Plain text only: no JSON, no Markdown, no file name.";
+
+/// `--summarize-large`: a diff context over `app.max_diff_bytes` becomes a
+/// one-line model summary per file plus the head of the raw diff, so trailing
+/// files aren't lost to truncation. Smaller contexts are returned as is. A
+/// file whose summary request fails is listed without one. Only the first
+/// `MAX_SUMMARIZED_FILES` files are summarized, and the whole listing is kept
+/// within `app.max_diff_bytes`.
+async fn summarize_large_diff(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    diff_context: String,
+) -> String {
+    let max_bytes = app.max_diff_bytes;
+    if diff_context.len() <= max_bytes {
+        return diff_context;
+    }
+    let mut files = crate::git_ops::split_diff_by_file(&diff_context);
+    let skipped = files.len().saturating_sub(MAX_SUMMARIZED_FILES);
+    files.truncate(MAX_SUMMARIZED_FILES);
+    app.add_log(
+        "INFO",
+        format!(
+            "Summarizing {} file diff(s) with {} ({})",
+            files.len(),
+            config.provider(),
+            config.model()
+        ),
+    );
+
+    let secrets = app.secrets.clone();
+    let summaries: Vec<_> = futures_util::stream::iter(files)
+        .map(|(path, file_diff)| {
+            let secrets = secrets.clone();
+            async move {
+                // Each request gets its own App, as they run concurrently
+                let mut file_app = App::new("gh-autopr");
+                file_app.secrets = secrets;
+                let file_diff = crate::git_ops::truncate_utf8(&file_diff, max_bytes);
+                let summary = call_ai_api(
+                    &mut file_app,
+                    config,
+                    FILE_SUMMARY_SYSTEM_PROMPT,
+                    &file_diff,
+                )
+                .await
+                .map(|reply| reply.lines().next().unwrap_or_default().trim().to_string())
+                .map_err(|e| e.to_string());
+                (path, summary, file_app.logs)
+            }
+        })
+        .buffered(FILE_SUMMARY_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut listing = String::new();
+    for (path, summary, logs) in summaries {
+        // Retries and the like; the per-request INFO lines would only repeat
+        for (level, message, _) in logs.into_iter().filter(|(level, ..)| *level != "INFO") {
+            app.add_log(level, message);
+        }
+        match summary {
+            Ok(summary) => listing.push_str(&format!("- {}: {}\n", path, summary)),
+            Err(e) => {
+                app.add_log("WARN", format!("Could not summarize {}: {}", path, e));
+                listing.push_str(&format!("- {}: (no summary)\n", path));
+            }
+        }
+    }
+    if skipped > 0 {
+        listing.push_str(&format!("- +{} more files\n", skipped));
+    }
+    let head_bytes = max_bytes / SUMMARIZED_DIFF_HEAD_DIVISOR;
+    let listing = crate::git_ops::truncate_utf8(&listing, max_bytes - head_bytes);
+    let head = crate::git_ops::truncate_utf8(&diff_context, head_bytes);
+    format!(
+        "The diff is too large to include whole ({} bytes). Summary of each changed file:\n{}\nStart of the diff:\n{}",
+        diff_context.len(),
+        listing,
+        head
+    )
+}
+
+/// Built-in system prompt; `--prompt-file` / `GH_AUTOPR_PROMPT_FILE` replaces it.
+const DEFAULT_SYSTEM_PROMPT: &str = r#"You prepare concise GitHub Pull Requests.
+
+OUTPUT
+Return valid JSON with EXACTLY these keys: "branch_name", "commit_title", "commit_details".
+IMPORTANT: All values must be strings (or null for commit_details). Do NOT use nested objects or arrays.
+
+BRANCH NAME (strict)
+- Allowed: letters, digits, hyphen (-), underscore (_), dot (.), up to ONE forward slash (/).
+- Not allowed: spaces, parentheses, colons, other symbols.
+- No leading/trailing dot. No consecutive dots. Max one "/".
+Examples OK: feat/worktree-update, fix-memory-leak, release/v1.0.0
+Examples NO: feat(worktree): update, fix memory leak, .hidden, branch., branch..name
+
+COMMIT TITLE (Conventional Commits)
+- Format: <type>(<scope>)!?: <imperative summary>
+- Use "!" if breaking.
+- ≤ 72 chars. Action verbs. No fluff. Only claims supported by the diff.
+
+COMMIT DETAILS (a single Markdown string, NOT a nested object)
+- Value must be a single string containing Markdown, or null. Never an object or array.
+- If the PR is truly tiny AND no issue refs: set "commit_details" to null.
+- Otherwise write ≤ 120 words total AND ≤ 8 lines. Prefer bullets. No code blocks.
+- Include ONLY sections that add high value; Exclude those with low and medium value. Section order:
+  - ### Motivation (≤ 1 bullet in the section)
+  - ### Solution (1-3 bullets)
+  - ### Impact (include ONLY if breaking; note migration in ≤ 1 bullet)
+  - ### Details (0-3 non-obvious bullets; skip routine refactors)
+  - ### Meta — single line if needed: "updated tests accordingly" or "updated comments".
+- Do NOT restate obvious diffs. Do NOT claim perf/security/UX benefits unless explicit in the diff.
+- Focus on MAJOR change(s). Minor changes only if they are the main point.
+
+GITHUB ISSUES (append at end)
+- If relevant: add exactly one line: "Relates to #X[, #Y]" or "Closes #X[, #Y]".
+- Only when truly connected. If multiple, comma-separate numbers.
+- If the PR is tiny BUT has relevant issues, include ONLY this line (do not use null).
+
+STYLE
+- Crisp, professional, fun-but-sparing. No filler ("this PR", "in order to", etc.).
+"#;
+
+/// Keys a custom prompt must still ask for, as the response parser requires them.
+const PROMPT_OUTPUT_KEYS: [&str; 3] = ["branch_name", "commit_title", "commit_details"];
+
+/// The system prompt before labels and the user's what/why/bigger picture are
+/// appended: the `prompt_file` if set and readable, else the built-in one.
+fn system_prompt_template(app: &mut App<'_>, config: &AppConfig) -> String {
+    let Some(path) = &config.ai.prompt_file else {
+        return DEFAULT_SYSTEM_PROMPT.to_string();
+    };
+    match fs_err::read_to_string(path) {
+        Ok(template) if !template.trim().is_empty() => {
+            let missing: Vec<&str> = PROMPT_OUTPUT_KEYS
+                .into_iter()
+                .filter(|key| !template.contains(key))
+                .collect();
+            if !missing.is_empty() {
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "Prompt file {} does not mention {}; the response must still be JSON with these keys",
+                        path.display(),
+                        missing.join(", ")
+                    ),
+                );
+            }
+            app.add_log(
+                "INFO",
+                format!("Using system prompt from {}", path.display()),
+            );
+            template
+        }
+        Ok(_) => {
+            app.add_log(
+                "WARN",
+                format!(
+                    "Prompt file {} is empty; using the built-in prompt",
+                    path.display()
+                ),
+            );
+            DEFAULT_SYSTEM_PROMPT.to_string()
+        }
+        Err(e) => {
+            app.add_log(
+                "WARN",
+                format!("Cannot read prompt file: {}; using the built-in prompt", e),
+            );
+            DEFAULT_SYSTEM_PROMPT.to_string()
+        }
+    }
+}
+
+async fn generate_branch_name_and_commit_description(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    diff_context: String,
+    issues_json: Option<String>,
+    hints: &PromptHints,
+) -> Result<GeneratedContent, Box<dyn std::error::Error>> {
+    // From the full diff, before a large one is reduced to summaries
+    let scope = infer_scope(&diff_changed_paths(&diff_context));
+    let diff_context = if app.summarize_large {
+        summarize_large_diff(app, config, diff_context).await
+    } else {
+        diff_context
+    };
+    let mut system_message = system_prompt_template(app, config);
+
+    if !hints.labels.is_empty() {
+        system_message.push_str(
+            "\nLABELS\n- Also return \"labels\": an array of 0-3 label names, the one key that may be an array.\n- Pick ONLY from these repository labels (name: description):\n",
+        );
+        for label in &hints.labels {
+            system_message.push_str(&format!("  - {}: {}\n", label.name, label.description));
+        }
+    }
+
+    if hints.ask_readiness {
+        system_message.push_str(
+            "\nREADINESS\n- Also return \"ready_for_review\": true if the change looks complete (tests where needed, no TODOs, no debug leftovers), false if it looks like work in progress.\n- And \"readiness_reason\": one short sentence why.\n",
+        );
+    }
+
+    if let Some(what) = &hints.what {
+        system_message.push_str(&format!("\n\nUser provided 'what': {}", what));
+    }
+    if let Some(why) = &hints.why {
+        system_message.push_str(&format!("\n\nUser provided 'why': {}", why));
+    }
+    if let Some(bigger_picture) = &hints.bigger_picture {
+        system_message.push_str(&format!(
+            "\n\nUser provided 'bigger picture': {}",
+            bigger_picture
+        ));
+    }
+
+    let mut user_message = format!(
         "Context:\n{}\n\nOpen GitHub Issues:\n{}",
         diff_context,
         issues_json
-            .map(|j| if j.len() > MAX_ISSUES_LEN {
-                j[..MAX_ISSUES_LEN].to_string()
-            } else {
-                j
+            .map(|j| {
+                let relevant = select_relevant_issues(&diff_context, &j, config.max_issues_bytes());
+                match config.ai.max_issues {
+                    Some(n) => first_issues(&relevant, n),
+                    None => relevant,
+                }
             })
             .unwrap_or_else(|| "No open issues".to_string())
     );
+    if let Some(scope) = &scope {
+        user_message.push_str(&format!(
+            "\n\nSuggested scope (from the changed paths): {}",
+            scope
+        ));
+    }
+    if let Some(template) = &hints.pr_template {
+        user_message.push_str(&format!(
+            "\n\nPull Request Template:\n{}\n\nReturn commit_details as this template filled in as Markdown: \
+             keep every section heading as-is and in order, fill each section from the diff, \
+             and write TODO under any section the diff does not answer.",
+            template
+        ));
+    }
 
     app.add_log(
         "INFO",
         format!("Calling {} ({})", config.provider(), config.model()),
     );
 
-    let chat_response = call_ai_api(config, &system_message, &user_message)
+    let parsed_response = request_json(app, config, &system_message, &user_message).await?;
+
+    let description = description_from_json(&parsed_response);
+    let mut commit_title = description.commit_title;
+    if let Some(scope) = scope.as_deref().filter(|_| hints.force_scope) {
+        commit_title = with_scope(&commit_title, scope);
+    }
+
+    // The model may still drop sections it had nothing for
+    let commit_details = match &hints.pr_template {
+        Some(template) => Some(ensure_template_sections(
+            description.commit_details.as_deref().unwrap_or_default(),
+            template,
+        )),
+        None => description.commit_details,
+    };
+
+    let mut branch_name = description.branch_name;
+    if config.ai.auto_fix_branch && !is_valid_git_branch_name(&branch_name) {
+        let sanitized = sanitize_branch_name(&branch_name);
+        app.add_log(
+            "WARN",
+            format!(
+                "AI returned invalid branch name '{}', using '{}'",
+                branch_name, sanitized
+            ),
+        );
+        if let Some(problem) = branch_name_problem(&sanitized) {
+            let error_msg = format!(
+                "AI returned invalid branch name: '{}', and the sanitized '{}' is still invalid ({})",
+                branch_name, sanitized, problem
+            );
+            app.add_error(error_msg.clone());
+            app.switch_to_tab(1);
+            return Err(error_msg.into());
+        }
+        branch_name = sanitized;
+    }
+
+    // Only the branch name is asked for again; the title and details are kept
+    let mut retries = 0;
+    while let Some(problem) = branch_name_problem(&branch_name) {
+        if retries == MAX_BRANCH_NAME_RETRIES {
+            let error_msg = format!(
+                "AI returned invalid branch name: '{}' ({}) after {} retries. Branch names must only \
+                 contain letters, numbers, hyphens, underscores, and forward slashes.",
+                branch_name, problem, retries
+            );
+            app.add_error(error_msg.clone());
+            app.switch_to_tab(1);
+            return Err(error_msg.into());
+        }
+        retries += 1;
+        app.add_log(
+            "WARN",
+            format!(
+                "AI returned invalid branch name '{}' ({}), asking again ({}/{})",
+                branch_name, problem, retries, MAX_BRANCH_NAME_RETRIES
+            ),
+        );
+        user_message.push_str(&format!(
+            "\n\nYour previous branch_name '{}' was invalid because {}; return a valid one.",
+            branch_name, problem
+        ));
+        let retried = request_json(app, config, &system_message, &user_message).await?;
+        branch_name = retried["branch_name"]
+            .as_str()
+            .unwrap_or("my-pr-branch")
+            .to_string();
+    }
+
+    let suggested = description.labels;
+    let labels = filter_known_labels(&suggested, &hints.labels);
+    if labels.len() < suggested.len() {
+        app.add_log(
+            "WARN",
+            format!(
+                "Dropped label suggestions not defined in the repo: {:?}",
+                suggested
+                    .iter()
+                    .filter(|s| !labels.iter().any(|l| l.eq_ignore_ascii_case(s.trim())))
+                    .collect::<Vec<_>>()
+            ),
+        );
+    }
+
+    Ok(GeneratedContent {
+        branch_name,
+        commit_title,
+        commit_details,
+        labels,
+        ready_for_review: description.ready_for_review,
+        readiness_reason: description.readiness_reason,
+    })
+}
+
+/// The generated fields of a parsed reply, with fallbacks for missing ones.
+/// `commit_details` given as an object of sections is flattened to Markdown;
+/// branch names and labels are returned as the model wrote them.
+fn description_from_json(parsed: &serde_json::Value) -> PrDescription {
+    let text = |key: &str, fallback: &str| parsed[key].as_str().unwrap_or(fallback).to_string();
+    PrDescription {
+        branch_name: text("branch_name", "my-pr-branch"),
+        commit_title: text("commit_title", "Generic commit title"),
+        commit_details: commit_details_markdown(&parsed["commit_details"]),
+        labels: parsed["labels"]
+            .as_array()
+            .map(|labels| {
+                labels
+                    .iter()
+                    .filter_map(|l| l.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ready_for_review: parsed["ready_for_review"].as_bool(),
+        readiness_reason: parsed["readiness_reason"]
+            .as_str()
+            .map(str::trim)
+            .filter(|reason| !reason.is_empty())
+            .map(str::to_string),
+    }
+}
+
+fn commit_details_markdown(details: &serde_json::Value) -> Option<String> {
+    match details {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Object(obj) => {
+            let mut md = String::new();
+            for (key, value) in obj {
+                if key.starts_with("###") || key.starts_with("##") || key.starts_with('#') {
+                    md.push_str(&format!("{}\n", key));
+                } else {
+                    md.push_str(&format!("### {}\n", key));
+                }
+                match value {
+                    serde_json::Value::Array(items) => {
+                        for item in items {
+                            if let Some(s) = item.as_str() {
+                                md.push_str(&format!("- {}\n", s));
+                            }
+                        }
+                    }
+                    serde_json::Value::String(s) => {
+                        md.push_str(&format!("{}\n", s));
+                    }
+                    _ => {}
+                }
+                md.push('\n');
+            }
+            if md.is_empty() {
+                None
+            } else {
+                Some(md.trim().to_string())
+            }
+        }
+        _ => None,
+    }
+}
+
+/// One model call, with the reply parsed as (possibly repaired) JSON.
+async fn request_json(
+    app: &mut App<'_>,
+    config: &AppConfig,
+    system_message: &str,
+    user_message: &str,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let chat_response = call_ai_api(app, config, system_message, user_message)
         .await
         .inspect_err(|e| {
             app.add_error(e.to_string());
             app.switch_to_tab(1);
         })?;
+    parse_json_response(app, &chat_response)
+}
 
+/// A model reply as JSON: code fences stripped, and repaired if it doesn't parse.
+fn parse_json_response(
+    app: &mut App<'_>,
+    chat_response: &str,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
     let chat_response = chat_response
         .trim()
         .trim_start_matches("```json")
@@ -434,25 +2200,29 @@ STYLE
 
     app.add_log("INFO", format!("chat_response: {}", chat_response));
 
-    let parsed_response: serde_json::Value = match serde_json::from_str(&chat_response) {
-        Ok(value) => value,
+    match serde_json::from_str(&chat_response) {
+        Ok(value) => Ok(value),
         Err(err) => {
             app.add_log(
                 "WARN",
                 format!("JSON parse failed: {}, attempting repair", err),
             );
-            match try_repair_json(&chat_response) {
+            let repaired = try_repair_json(&chat_response);
+            record_exchange(serde_json::json!({
+                "repair": { "error": err.to_string(), "input": chat_response, "output": repaired }
+            }));
+            match repaired {
                 Some(repaired) => {
                     app.add_log("INFO", "JSON repair succeeded");
                     match serde_json::from_str(&repaired) {
-                        Ok(value) => value,
+                        Ok(value) => Ok(value),
                         Err(err2) => {
                             app.add_error(format!(
                                 "JSON repair failed: {}\nResponse was:\n{}",
                                 err2, chat_response
                             ));
                             app.switch_to_tab(1);
-                            return Err(err2.into());
+                            Err(err2.into())
                         }
                     }
                 }
@@ -462,68 +2232,70 @@ STYLE
                         err, chat_response
                     ));
                     app.switch_to_tab(1);
-                    return Err(err.into());
+                    Err(err.into())
                 }
             }
         }
-    };
-
-    let branch_name = parsed_response["branch_name"]
-        .as_str()
-        .unwrap_or("my-pr-branch")
-        .to_string();
-    let commit_title = parsed_response["commit_title"]
-        .as_str()
-        .unwrap_or("Generic commit title")
-        .to_string();
+    }
+}
 
-    let commit_details = match &parsed_response["commit_details"] {
-        serde_json::Value::String(s) => Some(s.clone()),
-        serde_json::Value::Null => None,
-        serde_json::Value::Object(obj) => {
-            let mut md = String::new();
-            for (key, value) in obj {
-                if key.starts_with("###") || key.starts_with("##") || key.starts_with('#') {
-                    md.push_str(&format!("{}\n", key));
-                } else {
-                    md.push_str(&format!("### {}\n", key));
-                }
-                match value {
-                    serde_json::Value::Array(items) => {
-                        for item in items {
-                            if let Some(s) = item.as_str() {
-                                md.push_str(&format!("- {}\n", s));
-                            }
-                        }
-                    }
-                    serde_json::Value::String(s) => {
-                        md.push_str(&format!("{}\n", s));
-                    }
-                    _ => {}
-                }
-                md.push('\n');
-            }
-            if md.is_empty() {
-                None
-            } else {
-                Some(md.trim().to_string())
-            }
+/// Query the configured endpoint's `/models` listing and return the model IDs, sorted.
+///
+/// This is a diagnostic for custom `base_url`s (local servers, proxies) where it
+/// is not obvious which model names are valid. Endpoints that do not implement
+/// `/models` produce an explanatory error rather than a raw HTTP status.
+pub async fn list_models(config: &AppConfig) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let api_key = config.ai.api_key.as_deref().unwrap_or_default();
+    let client = reqwest::Client::new();
+
+    let (url, request) = match config.provider() {
+        "anthropic" => {
+            let base_url = config
+                .ai
+                .base_url
+                .as_deref()
+                .unwrap_or("https://api.anthropic.com");
+            let url = format!("{}/v1/models", base_url.trim_end_matches('/'));
+            let request = client
+                .get(&url)
+                .header("x-api-key", api_key)
+                .header("anthropic-version", "2023-06-01");
+            (url, request)
+        }
+        _ => {
+            let endpoint = openai_endpoint(config, api_key, "models")?;
+            let request = client
+                .get(&endpoint.url)
+                .header(endpoint.auth_header, endpoint.auth_value);
+            (endpoint.url, request)
         }
-        _ => None,
     };
 
-    if !is_valid_git_branch_name(&branch_name) {
-        let error_msg = format!(
-            "AI returned invalid branch name: '{}'. Branch names must only contain letters, \
-             numbers, hyphens, underscores, and forward slashes.",
-            branch_name
-        );
-        app.add_error(error_msg.clone());
-        app.switch_to_tab(1);
-        return Err(error_msg.into());
+    let resp = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to query {}: {}", url, e))?;
+    let status = resp.status();
+    let raw = resp.text().await.unwrap_or_default();
+
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+    {
+        return Err(format!(
+            "The endpoint {} does not implement model listing (HTTP {}). \
+             Check your provider's documentation for valid model names.",
+            url, status
+        )
+        .into());
+    }
+    if !status.is_success() {
+        return Err(format!("Model listing failed: HTTP {}: {}", status, raw).into());
     }
 
-    Ok((branch_name, commit_title, commit_details))
+    let parsed: ModelList = serde_json::from_str(&raw)
+        .map_err(|e| format!("Unexpected /models response: {}\nRaw body: {}", e, raw))?;
+    let mut ids: Vec<String> = parsed.data.into_iter().map(|m| m.id).collect();
+    ids.sort();
+    Ok(ids)
 }
 
 #[cfg(test)]
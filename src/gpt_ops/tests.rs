@@ -146,14 +146,16 @@ async fn test_gpt_generate_integration() {
         &config,
         diff_context,
         issues_json,
-        None,
-        None,
-        None,
+        &PromptHints::default(),
     )
     .await;
 
     match result {
-        Ok((branch_name, commit_title, _commit_details)) => {
+        Ok(GeneratedContent {
+            branch_name,
+            commit_title,
+            ..
+        }) => {
             assert!(!branch_name.is_empty());
             assert!(!commit_title.is_empty());
             // commit_details can be None for small changes
@@ -240,6 +242,42 @@ fn test_invalid_git_branch_names() {
     assert!(!is_valid_git_branch_name("branch=name"));
 }
 
+#[test]
+fn test_sanitize_branch_name() {
+    assert_eq!(
+        sanitize_branch_name("feat(worktree): update"),
+        "feat-worktree-update"
+    );
+    assert_eq!(sanitize_branch_name("fix memory leak"), "fix-memory-leak");
+    assert_eq!(sanitize_branch_name("release..v1...2"), "release.v1.2");
+    assert_eq!(sanitize_branch_name(".hidden-branch."), "hidden-branch");
+    assert_eq!(sanitize_branch_name("feat/good-name"), "feat/good-name");
+    for raw in [
+        "feat(worktree): update",
+        "fix: a, b & c!",
+        "..x..",
+        "  spaced  ",
+    ] {
+        assert!(
+            is_valid_git_branch_name(&sanitize_branch_name(raw)),
+            "{}",
+            raw
+        );
+    }
+    // Nothing salvageable: still invalid, so the caller errors out
+    assert!(!is_valid_git_branch_name(&sanitize_branch_name("(): ...")));
+}
+
+#[test]
+fn test_invalid_git_branch_names_for_ref_storage() {
+    assert!(is_valid_git_branch_name(&"a".repeat(244)));
+    assert!(!is_valid_git_branch_name(&"a".repeat(245)));
+    assert!(!is_valid_git_branch_name("feature.lock"));
+    assert!(!is_valid_git_branch_name("feature.lock/more"));
+    assert!(is_valid_git_branch_name("feature.locked"));
+    assert!(!is_valid_git_branch_name("feature@{1}"));
+}
+
 #[test]
 fn test_json_repair_trailing_comma() {
     // Test repair of trailing comma before }
@@ -278,6 +316,47 @@ fn test_json_repair_bare_string_closes() {
     assert!(parsed2["Note"].as_str().unwrap().contains("Closes #456"));
 }
 
+#[test]
+fn test_json_repair_cut_off_inside_commit_details() {
+    // Token limit hit mid-string, inside a nested section list
+    let truncated = r#"{"branch_name": "feat/x", "commit_title": "feat: x", "commit_details": {"Solution": ["Adds \"x\", and y"#;
+    let repaired = try_repair_json(truncated).expect("Should close the string and containers");
+    let parsed: serde_json::Value = serde_json::from_str(&repaired).unwrap();
+    assert_eq!(parsed["commit_title"], "feat: x");
+    assert_eq!(parsed["commit_details"]["Solution"][0], "Adds \"x\", and y");
+
+    // Cut inside an escape sequence
+    let repaired = try_repair_json(r#"{"commit_details": "a\u00"#).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&repaired).unwrap()["commit_details"],
+        "a"
+    );
+}
+
+#[test]
+fn test_json_repair_missing_final_brace() {
+    let repaired = try_repair_json(r#"{"branch_name": "fix-x", "commit_details": null"#).unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(&repaired).unwrap();
+    assert_eq!(parsed["branch_name"], "fix-x");
+    assert!(parsed["commit_details"].is_null());
+
+    // Dangling key and trailing comma
+    let repaired = try_repair_json(r#"{"branch_name": "fix-x", "labels": ["a",  "#).unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&repaired).unwrap()["labels"],
+        json!(["a"])
+    );
+    let repaired = try_repair_json(r#"{"branch_name": "fix-x", "commit_title""#).unwrap();
+    assert!(
+        serde_json::from_str::<serde_json::Value>(&repaired).unwrap()["commit_title"].is_null()
+    );
+
+    // Too broken to mean anything
+    assert_eq!(try_repair_json(r#"{"a": [1}"#), None);
+    assert_eq!(try_repair_json(r#"{"a": tru"#), None);
+    assert_eq!(try_repair_json("Sorry, I can't help with that"), None);
+}
+
 #[test]
 fn test_json_repair_valid_json_unchanged() {
     // Valid JSON should pass through without issues
@@ -344,3 +423,1115 @@ fn test_commit_details_as_object_conversion() {
     assert!(details.contains("### Solution"));
     assert!(details.contains("- What was done"));
 }
+
+#[tokio::test]
+async fn test_list_models_returns_sorted_ids() {
+    let server = httpmock::MockServer::start_async().await;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::GET)
+                .path("/models")
+                .header("Authorization", "Bearer test-key");
+            then.status(200).json_body(json!({
+                "object": "list",
+                "data": [{"id": "gpt-4o-mini"}, {"id": "gpt-4o"}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let models = list_models(&config).await.unwrap();
+
+    mock.assert_async().await;
+    assert_eq!(models, vec!["gpt-4o", "gpt-4o-mini"]);
+}
+
+#[tokio::test]
+async fn test_list_models_unsupported_endpoint() {
+    let server = httpmock::MockServer::start_async().await;
+    server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::GET).path("/models");
+            then.status(404);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.base_url = Some(server.base_url());
+
+    let err = list_models(&config).await.unwrap_err().to_string();
+    assert!(err.contains("does not implement model listing"), "{}", err);
+}
+
+#[test]
+fn test_truncate_issues_json_keeps_whole_issues() {
+    let issues = json!([
+        {"number": 1, "title": "First issue", "body": "a".repeat(100)},
+        {"number": 2, "title": "Second issue", "body": "b".repeat(100)},
+        {"number": 3, "title": "Third issue", "body": "c".repeat(100)},
+    ])
+    .to_string();
+
+    let truncated = truncate_issues_json(&issues, 300);
+
+    assert!(truncated.len() <= 300);
+    let parsed: Vec<serde_json::Value> =
+        serde_json::from_str(&truncated).expect("Truncated issues should remain valid JSON");
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0]["number"], 1);
+    assert_eq!(parsed[1]["number"], 2);
+}
+
+#[test]
+fn test_truncate_issues_json_unchanged_when_within_budget() {
+    let issues = r#"[{"number":1,"title":"Small"}]"#;
+    assert_eq!(truncate_issues_json(issues, 1024), issues);
+}
+
+#[test]
+fn test_truncate_issues_json_non_array_falls_back_to_byte_cut() {
+    let not_json = "ü".repeat(10);
+    let truncated = truncate_issues_json(&not_json, 5);
+    assert_eq!(truncated, "üü");
+}
+
+#[test]
+fn test_select_relevant_issues_keeps_issues_matching_the_diff() {
+    let diff = "diff --git a/src/tui.rs b/src/tui.rs
+--- a/src/tui.rs
++++ b/src/tui.rs
+@@ -1,3 +1,3 @@
+-fn render_logs(frame: &mut Frame) {
++fn render_logs(frame: &mut Frame, scroll_offset: usize) {
+";
+    let issues = json!([
+        {"number": 1, "title": "Add dark mode", "body": "x".repeat(120)},
+        {"number": 2, "title": "Support GitLab", "body": "y".repeat(120)},
+        {"number": 3, "title": "Log pane ignores scroll offset", "body": "render_logs in the tui"},
+        {"number": 4, "title": "Windows paths", "body": "z".repeat(120)},
+    ])
+    .to_string();
+
+    let selected = select_relevant_issues(diff, &issues, 250);
+
+    assert!(selected.len() <= 250);
+    let parsed: Vec<serde_json::Value> =
+        serde_json::from_str(&selected).expect("Selected issues should remain valid JSON");
+    assert_eq!(parsed[0]["number"], 3);
+    assert_eq!(
+        truncate_issues_json(&issues, 250).find("\"number\":3"),
+        None
+    );
+}
+
+#[test]
+fn test_select_relevant_issues_keeps_order_without_matches() {
+    let issues = r#"[{"number":1,"title":"Alpha"},{"number":2,"title":"Beta"}]"#;
+    let selected = select_relevant_issues("+unrelated change", issues, 1024);
+    let parsed: Vec<serde_json::Value> = serde_json::from_str(&selected).unwrap();
+    assert_eq!(parsed[0]["number"], 1);
+    assert_eq!(parsed[1]["number"], 2);
+    assert_eq!(
+        first_issues(&selected, 1),
+        r#"[{"number":1,"title":"Alpha"}]"#
+    );
+}
+
+#[tokio::test]
+async fn test_dump_request_records_exchange_and_redacts_key() {
+    let server = httpmock::MockServer::start_async().await;
+    let content =
+        r#"{"branch_name": "fix/dump", "commit_title": "fix: dump", "commit_details": null,}"#;
+    server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": content}}],
+                "echo": "secret-dump-key"
+            }));
+        })
+        .await;
+
+    let dump_dir = tempfile::tempdir().unwrap();
+    let dump_path = dump_dir.path().join("dump.json");
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("secret-dump-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.dump_request = Some(dump_path.clone());
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/dump-marker.txt".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(generated.branch_name, "fix/dump");
+
+    let dump = std::fs::read_to_string(&dump_path).unwrap();
+    assert!(dump.contains("dump-marker.txt"), "request recorded");
+    assert!(dump.contains("\"status\": 200"), "response recorded");
+    assert!(dump.contains("\"repair\""), "repair attempt recorded");
+    assert!(!dump.contains("secret-dump-key"), "API key redacted");
+}
+
+#[tokio::test]
+async fn test_streamed_response_is_assembled_and_previewed() {
+    let server = httpmock::MockServer::start_async().await;
+    let events = [
+        r#"{"branch_name": "feat/"#,
+        r#"stream", "commit_title": "feat: stream", "#,
+        r#""commit_details": null}"#,
+    ]
+    .iter()
+    .map(|piece| {
+        format!(
+            "data: {}\n\n",
+            json!({"choices": [{"delta": {"content": piece}}]})
+        )
+    })
+    .collect::<String>()
+        + "data: [DONE]\n\n";
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes(r#""stream":true"#);
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(events);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+
+    mock.assert_async().await;
+    assert_eq!(generated.branch_name, "feat/stream");
+    assert_eq!(generated.commit_title, "feat: stream");
+    assert!(app
+        .streaming_preview
+        .lock()
+        .unwrap()
+        .starts_with(r#"{"branch_name": "feat/stream""#));
+}
+
+#[tokio::test]
+async fn test_anthropic_stream_deltas_are_assembled() {
+    let server = httpmock::MockServer::start_async().await;
+    let body = [
+        json!({"type": "message_start", "message": {}}),
+        json!({"type": "content_block_delta", "delta": {"type": "text_delta", "text": r#"{"branch_name": "fix/a", "#}}),
+        json!({"type": "content_block_delta", "delta": {"type": "text_delta", "text": r#""commit_title": "fix: a", "commit_details": null}"#}}),
+        json!({"type": "message_stop"}),
+    ]
+    .iter()
+    .map(|event| format!("event: {}\ndata: {}\n\n", event["type"].as_str().unwrap(), event))
+    .collect::<String>();
+    server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST).path("/v1/messages");
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(body);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.provider = Some("anthropic".to_string());
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(generated.branch_name, "fix/a");
+}
+
+#[tokio::test]
+async fn test_stream_cut_off_before_its_closing_event_is_retried() {
+    let server = httpmock::MockServer::start_async().await;
+    // The connection drops mid-reply: no `[DONE]` after the last delta
+    let truncated = format!(
+        "data: {}\n\n",
+        json!({"choices": [{"delta": {"content": r#"{"branch_name": "feat/cut", "commit_title": "feat: cut", "commit_details": "first half"#}}]})
+    );
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions");
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(truncated);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.max_attempts = Some(2);
+
+    let mut app = App::new("Test App");
+    let result = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await;
+
+    mock.assert_calls_async(2).await;
+    let err = result
+        .expect_err("A cut-off reply must not be used")
+        .to_string();
+    assert!(err.contains("AI stream ended early"), "err: {}", err);
+}
+
+#[tokio::test]
+async fn test_endpoint_rejecting_stream_falls_back_to_blocking_call() {
+    let server = httpmock::MockServer::start_async().await;
+    let streaming = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes(r#""stream":true"#);
+            then.status(400)
+                .json_body(json!({"error": {"message": "stream is not supported"}}));
+        })
+        .await;
+    let blocking = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_excludes(r#""stream""#);
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": r#"{"branch_name": "fix/blocking", "commit_title": "fix: blocking", "commit_details": null}"#}}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+
+    streaming.assert_calls_async(1).await;
+    blocking.assert_calls_async(1).await;
+    assert_eq!(generated.branch_name, "fix/blocking");
+}
+
+#[tokio::test]
+async fn test_invalid_branch_name_is_asked_for_again() {
+    let server = httpmock::MockServer::start_async().await;
+    let first = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_excludes("was invalid");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": r#"{"branch_name": "feat: retry names", "commit_title": "feat: retry invalid branch names", "commit_details": "Keeps the title."}"#}}]
+            }));
+        })
+        .await;
+    let retry = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes("previous branch_name 'feat: retry names' was invalid");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": r#"{"branch_name": "feat/retry-names", "commit_title": "something else", "commit_details": null}"#}}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+
+    first.assert_calls_async(1).await;
+    retry.assert_calls_async(1).await;
+    assert_eq!(generated.branch_name, "feat/retry-names");
+    assert_eq!(generated.commit_title, "feat: retry invalid branch names");
+    assert_eq!(
+        generated.commit_details.as_deref(),
+        Some("Keeps the title.")
+    );
+}
+
+#[tokio::test]
+async fn test_auto_fix_branch_sanitizes_instead_of_retrying() {
+    let server = httpmock::MockServer::start_async().await;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST).path("/chat/completions");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": r#"{"branch_name": "feat(worktree): update", "commit_title": "t", "commit_details": null}"#}}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.auto_fix_branch = true;
+
+    let mut app = App::new("Test App");
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap();
+
+    mock.assert_calls_async(1).await;
+    assert_eq!(generated.branch_name, "feat-worktree-update");
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg.contains("'feat(worktree): update'")
+            && msg.contains("'feat-worktree-update'")));
+}
+
+#[tokio::test]
+async fn test_invalid_branch_name_fails_after_retries() {
+    let server = httpmock::MockServer::start_async().await;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST).path("/chat/completions");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": r#"{"branch_name": "bad name", "commit_title": "t", "commit_details": null}"#}}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let err = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x".to_string(),
+        None,
+        &PromptHints::default(),
+    )
+    .await
+    .unwrap_err()
+    .to_string();
+
+    mock.assert_calls_async(3).await;
+    assert!(err.contains("after 2 retries"), "{}", err);
+}
+
+#[test]
+fn test_parse_conventional_type() {
+    assert_eq!(
+        parse_conventional_type("feat: add x"),
+        Some("feat".to_string())
+    );
+    assert_eq!(
+        parse_conventional_type("fix(parser)!: handle y"),
+        Some("fix".to_string())
+    );
+    assert_eq!(
+        parse_conventional_type("Docs: typo"),
+        Some("docs".to_string())
+    );
+    assert_eq!(parse_conventional_type("Update readme"), None);
+    assert_eq!(parse_conventional_type("v1.2: release"), None);
+}
+
+#[test]
+fn test_type_prefixed_branch() {
+    assert_eq!(
+        type_prefixed_branch("worktree-update", "feat(worktree): update"),
+        "feat/worktree-update"
+    );
+    // Keeps the single-slash rule by replacing an existing prefix
+    assert_eq!(
+        type_prefixed_branch("bugfix/leak", "fix: plug leak"),
+        "fix/leak"
+    );
+    assert_eq!(
+        type_prefixed_branch("worktree-update", "Update worktree"),
+        "worktree-update"
+    );
+    assert!(is_valid_git_branch_name(&type_prefixed_branch(
+        "release/v1.0.0",
+        "chore!: release"
+    )));
+}
+
+#[test]
+fn test_infer_scope() {
+    let scope =
+        |paths: &[&str]| infer_scope(&paths.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+    assert_eq!(
+        scope(&["src/git_ops/tests.rs", "src/git_ops/mod.rs"]).as_deref(),
+        Some("git_ops")
+    );
+    assert_eq!(
+        scope(&["src/git_ops.rs", "src/git_ops/tests.rs"]).as_deref(),
+        Some("git_ops")
+    );
+    assert_eq!(scope(&["src/main.rs"]).as_deref(), Some("main"));
+    assert_eq!(scope(&["docs/a.md", "docs/b.md"]).as_deref(), Some("docs"));
+    assert_eq!(
+        scope(&["crates/cli/src/main.rs", "crates/cli/Cargo.toml"]).as_deref(),
+        Some("cli")
+    );
+    // Only a source root, or nothing at all, in common
+    assert_eq!(scope(&["src/main.rs", "src/config.rs"]), None);
+    assert_eq!(scope(&["README.md", "src/main.rs"]), None);
+    assert_eq!(scope(&[]), None);
+}
+
+#[test]
+fn test_select_relevant_issues_ignores_dev_null_headers() {
+    let diff = "diff --git a/old.md b/old.md\n--- a/old.md\n+++ /dev/null\n@@ -1 +0,0 @@\n-bye\n";
+    let issues = r#"[{"number":1,"title":"Alpha"},{"number":2,"title":"Null pointer in parser"}]"#;
+    let selected = select_relevant_issues(diff, issues, 1024);
+    let parsed: Vec<serde_json::Value> = serde_json::from_str(&selected).unwrap();
+    assert_eq!(parsed[0]["number"], 1);
+}
+
+#[test]
+fn test_diff_changed_paths() {
+    let diff =
+        "diff --git a/src/a.rs b/src/a.rs\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-x\n+y\n\
+                diff --git a/new.md b/new.md\n--- /dev/null\n+++ b/new.md\n@@ -0,0 +1 @@\n+hi\n";
+    assert_eq!(diff_changed_paths(diff), vec!["src/a.rs", "new.md"]);
+}
+
+/// A `git diff` section for one file; `header` is e.g. "new file mode 100644".
+fn file_diff(path: &str, header: &str, added: usize, removed: usize) -> String {
+    let mut diff = format!(
+        "diff --git a/{0} b/{0}\n{1}\n--- a/{0}\n+++ b/{0}\n@@ -1 +1 @@\n",
+        path, header
+    );
+    diff.push_str(&"+line\n".repeat(added));
+    diff.push_str(&"-line\n".repeat(removed));
+    diff
+}
+
+#[test]
+fn test_heuristic_commit_type() {
+    let title = |diff: String| heuristic_commit_message(&diff).commit_title;
+    assert_eq!(
+        title(file_diff("README.md", "index 1..2 100644", 3, 1)),
+        "docs(readme): update README.md"
+    );
+    assert_eq!(
+        title(file_diff(
+            "tests/git_ops_tests.rs",
+            "index 1..2 100644",
+            3,
+            0
+        )),
+        "test(tests): update git_ops_tests.rs"
+    );
+    assert_eq!(
+        title(
+            file_diff("src/main.rs", "index 1..2 100644", 1, 0)
+                + &file_diff("src/ui.rs", "new file mode 100644", 9, 0)
+        ),
+        "feat: add ui.rs and 1 other file"
+    );
+    assert_eq!(
+        title(file_diff("src/old.rs", "deleted file mode 100644", 0, 4)),
+        "refactor(old): remove old.rs"
+    );
+    let renamed = "diff --git a/src/a.rs b/src/b.rs\nsimilarity index 100%\nrename from src/a.rs\nrename to src/b.rs\n";
+    assert_eq!(title(renamed.to_string()), "refactor(b): rename b.rs");
+    assert_eq!(
+        title(file_diff("src/main.rs", "index 1..2 100644", 2, 2)),
+        "chore(main): update main.rs"
+    );
+    assert_eq!(title(String::new()), "chore: update files");
+}
+
+#[test]
+fn test_heuristic_commit_scope_and_summary() {
+    let diff = file_diff("src/git_ops/tests.rs", "index 1..2 100644", 2, 0)
+        + &file_diff("src/git_ops/mod.rs", "index 1..2 100644", 10, 5)
+        + &file_diff("src/git_ops/worktree.rs", "index 1..2 100644", 15, 0);
+    let generated = heuristic_commit_message(&diff);
+    // Ties go to the file listed first
+    assert_eq!(
+        generated.commit_title,
+        "chore(git_ops): update mod.rs and 2 other files"
+    );
+    assert_eq!(generated.branch_name, "chore-update-mod");
+    assert!(generated.labels.is_empty());
+
+    // Diff content that looks like a header is still counted as a change
+    let diff = file_diff("src/a.rs", "index 1..2 100644", 1, 0) + "++++ b/x\n";
+    assert_eq!(
+        heuristic_commit_message(&diff).commit_title,
+        "chore(a): update a.rs"
+    );
+}
+
+#[test]
+fn test_heuristic_commit_body_groups_by_directory() {
+    let diff = file_diff("src/main.rs", "index 1..2 100644", 1, 0)
+        + &file_diff("README.md", "index 1..2 100644", 1, 0)
+        + &file_diff("src/ui.rs", "new file mode 100644", 1, 0);
+    assert_eq!(
+        heuristic_commit_message(&diff).commit_details.as_deref(),
+        Some("Changed files:\n\n- `./`\n  - `README.md`\n- `src/`\n  - `main.rs`\n  - `ui.rs` (added)")
+    );
+}
+
+#[test]
+fn test_with_scope() {
+    assert_eq!(
+        with_scope("fix(ui)!: plug leak", "git_ops"),
+        "fix(git_ops)!: plug leak"
+    );
+    assert_eq!(with_scope("feat: add x", "tui"), "feat(tui): add x");
+    assert_eq!(with_scope("Add x", "tui"), "Add x");
+}
+
+fn stack_group(branch_name: &str, globs: &[&str]) -> StackGroup {
+    StackGroup {
+        branch_name: branch_name.to_string(),
+        commit_title: format!("feat: {}", branch_name),
+        commit_details: None,
+        file_globs: globs.iter().map(|g| g.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_assign_stack_files() {
+    let groups = [
+        stack_group("types", &["src/types.rs", "src/model/**"]),
+        stack_group("ui", &["src/ui/*", "src/model/view.rs"]),
+        stack_group("docs", &["*.md"]),
+    ];
+    let paths: Vec<String> = [
+        "src/types.rs",
+        "src/model/view.rs",
+        "src/ui/list.rs",
+        "docs/guide.md",
+        "Cargo.lock",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect();
+
+    let assignment = assign_stack_files(&groups, &paths, &[]);
+    assert_eq!(
+        assignment.files,
+        vec![
+            vec!["src/types.rs", "src/model/view.rs"],
+            vec!["src/ui/list.rs"],
+            vec!["docs/guide.md", "Cargo.lock"],
+        ]
+    );
+    // Matched by two groups: only the first commits it
+    assert_eq!(
+        assignment.shared,
+        vec![("src/model/view.rs".to_string(), vec![0, 1])]
+    );
+    // Matched by none: the last group commits it
+    assert_eq!(assignment.unmatched, vec!["Cargo.lock"]);
+
+    // Every file lands in exactly one group
+    let mut assigned: Vec<String> = assignment.files.concat();
+    assigned.sort();
+    let mut expected = paths.clone();
+    expected.sort();
+    assert_eq!(assigned, expected);
+
+    assert_eq!(assign_stack_files(&[], &paths, &[]).unmatched, paths);
+}
+
+#[test]
+fn test_assign_stack_files_keeps_renames_whole() {
+    let groups = [
+        stack_group("ui", &["src/ui/**"]),
+        stack_group("rest", &["src/*.rs"]),
+    ];
+    let paths: Vec<String> = ["src/ui/list.rs", "src/list.rs", "src/lib.rs"]
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    let renames = [("src/ui/list.rs".to_string(), "src/list.rs".to_string())];
+
+    let assignment = assign_stack_files(&groups, &paths, &renames);
+    assert_eq!(
+        assignment.files,
+        vec![vec!["src/ui/list.rs", "src/list.rs"], vec!["src/lib.rs"]]
+    );
+    assert!(assignment.shared.is_empty());
+}
+
+#[test]
+fn test_glob_matches() {
+    assert!(glob_matches("src/ui/*", "src/ui/list.rs"));
+    assert!(!glob_matches("src/ui/*.rs", "src/ui/widgets/button.rs"));
+    // Like .gitignore: `src/ui/*` matches the `widgets` directory, so its files too
+    assert!(glob_matches("src/ui/*", "src/ui/widgets/button.rs"));
+    assert!(glob_matches("src/**/*.rs", "src/a/b/c.rs"));
+    assert!(glob_matches("src/**/*.rs", "src/c.rs"));
+    assert!(glob_matches("*.md", "docs/guide.md"));
+    assert!(
+        glob_matches("src/ui", "src/ui/list.rs"),
+        "directories match what is below"
+    );
+    assert!(glob_matches("./src/ui/", "src/ui/list.rs"));
+    assert!(glob_matches("te?t.rs", "test.rs"));
+    assert!(!glob_matches("src/ui", "src/uix.rs"));
+    assert!(!glob_matches("", "src/ui/list.rs"));
+}
+
+#[test]
+fn test_parse_stack_groups() {
+    let plan = json!({"groups": [
+        {"branch_name": "feat/types", "commit_title": "feat: add types", "file_globs": ["src/types.rs"]},
+        {"branch_name": "feat/types", "commit_title": "feat: use types", "commit_details": "- uses them", "file_globs": ["src/ui/**"]},
+        {"branch_name": "bad name", "commit_title": "docs: explain", "commit_details": null}
+    ]});
+    let groups = parse_stack_groups(&plan).unwrap();
+    let names: Vec<&str> = groups.iter().map(|g| g.branch_name.as_str()).collect();
+    assert_eq!(names, vec!["feat/types", "feat/types-2", "bad-name"]);
+    assert_eq!(groups[1].commit_details.as_deref(), Some("- uses them"));
+    assert!(groups[2].file_globs.is_empty());
+
+    assert!(parse_stack_groups(&json!({"groups": []})).is_err());
+    assert!(parse_stack_groups(&json!({"groups": [{"branch_name": "x"}]})).is_err());
+}
+
+#[test]
+fn test_parse_pr_description_readiness() {
+    let description = parse_pr_description(
+        r#"{"branch_name": "feat/x", "commit_title": "feat: x", "commit_details": null,
+            "ready_for_review": true, "readiness_reason": " Tests cover the new path. "}"#,
+    )
+    .unwrap();
+    assert_eq!(description.ready_for_review, Some(true));
+    assert_eq!(
+        description.readiness_reason.as_deref(),
+        Some("Tests cover the new path.")
+    );
+
+    // Left out (or not a bool): no judgment, which --auto-ready treats as draft
+    let description =
+        parse_pr_description(r#"{"branch_name": "feat/x", "commit_title": "feat: x"}"#).unwrap();
+    assert_eq!(description.ready_for_review, None);
+    assert_eq!(description.readiness_reason, None);
+    let description = parse_pr_description(r#"{"ready_for_review": "yes"}"#).unwrap();
+    assert_eq!(description.ready_for_review, None);
+}
+
+#[test]
+fn test_parse_pr_description_repairs_and_flattens() {
+    // Fenced, with a trailing comma, and details as an object of sections
+    let response =
+        "```json\n{\"branch_name\": \"feat/api\", \"commit_title\": \"feat(api): add x\", \
+                    \"commit_details\": {\"Solution\": [\"Adds x\"]}, \"labels\": [\"api\"],}\n```";
+    let description = parse_pr_description(response).expect("Repair should succeed");
+    assert_eq!(
+        description,
+        PrDescription {
+            branch_name: "feat/api".to_string(),
+            commit_title: "feat(api): add x".to_string(),
+            commit_details: Some("### Solution\n- Adds x".to_string()),
+            labels: vec!["api".to_string()],
+            ready_for_review: None,
+            readiness_reason: None,
+        }
+    );
+
+    let description = parse_pr_description(r#"{"commit_details": null}"#).unwrap();
+    assert_eq!(description.branch_name, "my-pr-branch");
+    assert_eq!(description.commit_title, "Generic commit title");
+    assert_eq!(description.commit_details, None);
+
+    assert!(parse_pr_description("not json at all").is_err());
+}
+
+#[test]
+fn test_ticket_from_branch() {
+    let pattern = regex::Regex::new(DEFAULT_TICKET_PATTERN).unwrap();
+    assert_eq!(
+        ticket_from_branch("feature/ABC-123-add-thing", &pattern).as_deref(),
+        Some("ABC-123")
+    );
+    assert_eq!(ticket_from_branch("add-thing", &pattern), None);
+    assert_eq!(ticket_from_branch("main", &pattern), None);
+
+    assert_eq!(
+        with_ticket_prefix("Add thing", "ABC-123"),
+        "[ABC-123] Add thing"
+    );
+    assert_eq!(
+        with_ticket_prefix("ABC-123: Add thing", "ABC-123"),
+        "ABC-123: Add thing"
+    );
+}
+
+#[test]
+fn test_truncate_title() {
+    assert_eq!(truncate_title("Add thing", 20), "Add thing");
+    assert_eq!(
+        truncate_title("[ABC-123] Add the new thing to the parser", 24),
+        "[ABC-123] Add the new…"
+    );
+    assert!(
+        truncate_title("[ABC-123] Add the new thing", 24)
+            .chars()
+            .count()
+            <= 24
+    );
+    // No word boundary to cut at
+    assert_eq!(truncate_title("Supercalifragilistic", 6), "Super…");
+}
+
+#[test]
+fn test_is_retryable_status() {
+    use reqwest::StatusCode;
+    assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+    assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+    assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+    assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+}
+
+#[test]
+fn test_backoff_delay_doubles_with_bounded_jitter() {
+    assert_eq!(backoff_delay(1, 0), Duration::from_millis(1000));
+    assert_eq!(backoff_delay(2, 0), Duration::from_millis(2000));
+    assert_eq!(backoff_delay(3, 0), Duration::from_millis(4000));
+    assert!(backoff_delay(1, u64::MAX) <= Duration::from_millis(1250));
+}
+
+#[test]
+fn test_stream_error_event_retryable_only_when_transient() {
+    let overloaded = serde_json::json!({"type": "overloaded_error", "message": "Overloaded"});
+    let error = ApiError::stream(&overloaded);
+    assert!(error.retryable);
+    assert_eq!(error.message, "AI stream error: Overloaded");
+
+    let invalid = serde_json::json!({"type": "invalid_request_error", "message": "bad"});
+    assert!(!ApiError::stream(&invalid).retryable);
+}
+
+#[tokio::test]
+async fn test_retry_with_backoff_stops_on_fatal_error() {
+    let mut app = App::new("test");
+    let mut calls = 0;
+    let result: Result<(), ApiError> = retry_with_backoff(&mut app, 3, || {
+        calls += 1;
+        Box::pin(async { Err(ApiError::fatal("401 Unauthorized".to_string())) })
+    })
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+    assert!(app.logs.is_empty());
+}
+
+fn repo_label(name: &str) -> RepoLabel {
+    RepoLabel {
+        name: name.to_string(),
+        description: String::new(),
+    }
+}
+
+#[test]
+fn test_filter_known_labels() {
+    let available = vec![repo_label("bug"), repo_label("Documentation")];
+    let suggested = vec![
+        "BUG".to_string(),
+        "documentation".to_string(),
+        "made-up".to_string(),
+        "bug".to_string(),
+    ];
+    assert_eq!(
+        filter_known_labels(&suggested, &available),
+        vec!["bug", "Documentation"]
+    );
+}
+
+#[tokio::test]
+async fn test_generate_offers_repo_labels_and_drops_unknown_ones() {
+    let server = httpmock::MockServer::start_async().await;
+    let content = r#"{"branch_name": "fix/x", "commit_title": "fix: x", "commit_details": null, "labels": ["bug", "invented"]}"#;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes("triage-me");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": content}}]
+            }));
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+
+    let mut app = App::new("Test App");
+    let hints = PromptHints {
+        labels: vec![repo_label("bug"), repo_label("triage-me")],
+        ..Default::default()
+    };
+    let generated = gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x b/x".to_string(),
+        None,
+        &hints,
+    )
+    .await
+    .unwrap();
+
+    mock.assert_async().await;
+    assert_eq!(generated.labels, vec!["bug"]);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(level, msg, _)| *level == "WARN" && msg.contains("invented")));
+}
+
+#[tokio::test]
+async fn test_sampling_overrides_are_sent_and_explained_on_rejection() {
+    let server = httpmock::MockServer::start_async().await;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes("\"temperature\":0.0")
+                .body_includes("\"max_tokens\":256");
+            then.status(400)
+                .body(r#"{"error": "unsupported parameter"}"#);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.temperature = Some(-0.5);
+    config.ai.max_tokens = Some(256);
+
+    let mut app = App::new("Test App");
+    let err = call_ai_api(&mut app, &config, "system", "user")
+        .await
+        .unwrap_err()
+        .to_string();
+
+    mock.assert_async().await;
+    assert!(err.contains("unset --temperature/--max-tokens"), "{}", err);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg == "AI sampling: temperature=0, max_tokens=256"));
+}
+
+#[tokio::test]
+async fn test_prompt_file_replaces_system_prompt_and_keeps_user_hints() {
+    let server = httpmock::MockServer::start_async().await;
+    let content = r#"{"branch_name": "fix/x", "commit_title": "fix: x", "commit_details": null}"#;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions")
+                .body_includes("TEAM STYLE")
+                .body_includes("User provided 'why': flaky CI");
+            then.status(200).json_body(json!({
+                "choices": [{"message": {"content": content}}]
+            }));
+        })
+        .await;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let prompt_file = dir.path().join("prompt.md");
+    std::fs::write(
+        &prompt_file,
+        "TEAM STYLE\nReturn JSON with keys branch_name, commit_title, commit_details.",
+    )
+    .unwrap();
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.prompt_file = Some(prompt_file);
+
+    let mut app = App::new("Test App");
+    let hints = PromptHints {
+        why: Some("flaky CI".to_string()),
+        ..Default::default()
+    };
+    gpt_generate_branch_name_and_commit_description(
+        &mut app,
+        &config,
+        "diff --git a/x b/x".to_string(),
+        None,
+        &hints,
+    )
+    .await
+    .unwrap();
+
+    mock.assert_async().await;
+    assert!(!app.logs.iter().any(|(level, _, _)| *level == "WARN"));
+}
+
+#[test]
+fn test_unreadable_prompt_file_falls_back_to_default() {
+    let mut config = crate::config::AppConfig::default();
+    config.ai.prompt_file = Some("/nonexistent/gh-autopr-prompt.md".into());
+
+    let mut app = App::new("Test App");
+    assert_eq!(
+        system_prompt_template(&mut app, &config),
+        DEFAULT_SYSTEM_PROMPT
+    );
+    assert!(app
+        .logs
+        .iter()
+        .any(|(level, msg, _)| *level == "WARN" && msg.contains("gh-autopr-prompt.md")));
+}
+
+#[test]
+fn test_estimate_tokens_and_cost() {
+    assert_eq!(estimate_tokens(""), 0);
+    assert_eq!(estimate_tokens("abcd"), 1);
+    assert_eq!(estimate_tokens("abcde"), 2);
+    assert_eq!(estimate_input_cost("gpt-4o-mini", 1_000_000), Some(0.15));
+    assert_eq!(
+        estimate_input_cost("gpt-4o-2024-08-06", 2_000_000),
+        Some(5.0)
+    );
+    assert_eq!(estimate_input_cost("my-local-model", 1_000), None);
+}
+
+#[tokio::test]
+async fn test_max_cost_aborts_before_the_request() {
+    let server = httpmock::MockServer::start_async().await;
+    let mock = server
+        .mock_async(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/chat/completions");
+            then.status(500);
+        })
+        .await;
+
+    let mut config = crate::config::AppConfig::default();
+    config.ai.api_key = Some("test-key".to_string());
+    config.ai.base_url = Some(server.base_url());
+    config.ai.model = Some("gpt-4o".to_string());
+    config.ai.max_cost = Some(0.01);
+
+    let mut app = App::new("Test App");
+    // ~10k tokens at $2.50/M is ~$0.025
+    let err = call_ai_api(&mut app, &config, "system", &"x".repeat(40_000))
+        .await
+        .unwrap_err()
+        .to_string();
+
+    mock.assert_calls_async(0).await;
+    assert!(err.contains("exceeds --max-cost"), "{}", err);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg.starts_with("Estimated prompt: ~10002 tokens")));
+}
+
+#[test]
+fn test_openai_endpoint_uses_bearer_token_by_default() {
+    let mut config = AppConfig::default();
+    config.ai.base_url = Some("http://localhost:8080/v1/".to_string());
+    let endpoint = openai_endpoint(&config, "sk-1", "chat/completions").unwrap();
+    assert_eq!(endpoint.url, "http://localhost:8080/v1/chat/completions");
+    assert_eq!(endpoint.auth_header, "Authorization");
+    assert_eq!(endpoint.auth_value, "Bearer sk-1");
+}
+
+#[test]
+fn test_openai_endpoint_builds_azure_deployment_urls() {
+    let mut config = AppConfig::default();
+    config.ai.base_url = Some("https://res.openai.azure.com".to_string());
+    config.ai.model = Some("gpt4o-prod".to_string());
+    assert!(openai_endpoint(&config, "k", "chat/completions")
+        .unwrap_err()
+        .contains("OPENAI_API_VERSION"));
+
+    config.ai.api_version = Some("2024-10-21".to_string());
+    let endpoint = openai_endpoint(&config, "k", "chat/completions").unwrap();
+    assert_eq!(
+        endpoint.url,
+        "https://res.openai.azure.com/openai/deployments/gpt4o-prod/chat/completions?api-version=2024-10-21"
+    );
+    assert_eq!(
+        (endpoint.auth_header, endpoint.auth_value.as_str()),
+        ("api-key", "k")
+    );
+    assert_eq!(
+        openai_endpoint(&config, "k", "models").unwrap().url,
+        "https://res.openai.azure.com/openai/models?api-version=2024-10-21"
+    );
+
+    // A deployment URL wins over the model name; the flavor can be forced for proxies
+    config.ai.base_url = Some("https://proxy.local/openai/deployments/mini/".to_string());
+    config.ai.openai_flavor = Some("azure".to_string());
+    assert_eq!(
+        openai_endpoint(&config, "k", "chat/completions")
+            .unwrap()
+            .url,
+        "https://proxy.local/openai/deployments/mini/chat/completions?api-version=2024-10-21"
+    );
+}
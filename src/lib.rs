@@ -1,16 +1,20 @@
 pub mod config;
+pub mod forge;
 pub mod git_ops;
 pub mod git_temp_worktree;
 pub mod github_ops;
 pub mod gpt_ops;
 pub mod review_ops;
+pub mod template_ops;
 pub mod tui;
 
 // Re-export commonly used items
 pub use config::*;
+pub use forge::*;
 pub use git_ops::*;
 pub use git_temp_worktree::*;
 pub use github_ops::*;
 pub use gpt_ops::*;
 pub use review_ops::*;
+pub use template_ops::*;
 pub use tui::*;
@@ -1,20 +1,24 @@
 mod config;
+mod forge;
 mod git_ops;
 mod git_temp_worktree;
 mod github_ops;
 mod gpt_ops;
 mod review_ops;
+mod template_ops;
 mod tui;
-use crate::config::AppConfig;
+use crate::config::{mask_api_key, parse_max_cost, parse_max_tokens, parse_temperature, AppConfig};
+use crate::forge::*;
 use crate::git_ops::*;
 use crate::git_temp_worktree::*;
 use crate::github_ops::*;
 use crate::gpt_ops::*;
 use crate::review_ops::*;
+use crate::template_ops::*;
 use crate::tui::*;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use ratatui::{
-    backend::{Backend, CrosstermBackend},
+    backend::{Backend, CrosstermBackend, TestBackend},
     crossterm::{
         event::{
             self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind,
@@ -26,6 +30,9 @@ use ratatui::{
     style::Color,
     Terminal,
 };
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use tokio::time::{Duration, Instant};
 
 #[derive(Parser)]
@@ -33,17 +40,101 @@ use tokio::time::{Duration, Instant};
     author,
     version,
     about = "gh-autopr: Automate GitHub Pull Request creation.",
-    long_about = "gh-autopr is a command-line tool that automates the process of creating GitHub Pull Requests. It analyzes your uncommitted changes, generates a branch name, commit message, and PR description using AI, and then pushes the changes and creates the PR."
+    long_about = "gh-autopr is a command-line tool that automates the process of creating GitHub Pull Requests. It analyzes your uncommitted changes, generates a branch name, commit message, and PR description using AI, and then pushes the changes and creates the PR.",
+    args_conflicts_with_subcommands = true
 )]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    /// PR creation flags, when run without a subcommand
+    #[command(flatten)]
+    create: Args,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Create or update a PR from the uncommitted changes (the default)
+    Create(Box<Args>),
+    /// Delete local branches whose PR was merged or whose remote branch is gone
+    Prune(PruneArgs),
+    /// Manage the API key stored in the system keyring
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Store the API key in the system keyring, replacing any stored one
+    #[command(name = "set-key")]
+    Set {
+        /// Read the key from stdin instead of prompting for it
+        #[arg(long)]
+        stdin: bool,
+    },
+    /// Show a masked preview of the stored API key
+    #[command(name = "get-key")]
+    Get,
+    /// Remove the stored API key from the system keyring
+    #[command(name = "delete-key")]
+    Delete,
+}
+
+#[derive(clap::Args)]
+struct PruneArgs {
+    /// List the branches that would be deleted without deleting them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Delete branches even if the config sets `prune_dry_run`
+    #[arg(long, conflicts_with = "dry_run")]
+    no_dry_run: bool,
+
+    /// Ask before deleting; on by default when interactive
+    #[arg(long, conflicts_with = "yes")]
+    confirm_destructive: bool,
+
+    /// Never ask for confirmation before deleting
+    #[arg(long, short = 'y', visible_aliases = ["no-confirm"])]
+    yes: bool,
+
+    /// Also delete branches whose remote is gone but whose commits are not in main
+    #[arg(long)]
+    force_prune: bool,
+
+    /// Remote whose branches are checked; defaults as for `create`
+    #[arg(long, value_name = "NAME")]
+    remote: Option<String>,
+}
+
+impl PruneArgs {
+    fn confirm_destructive(&self) -> bool {
+        self.confirm_destructive || (!self.yes && std::io::stdin().is_terminal())
+    }
+}
+
+#[derive(clap::Args)]
 struct Args {
     /// Update an existing PR instead of creating a new one
     #[arg(long, visible_aliases = ["update-existing", "update"])]
     update_pr: bool,
 
-    /// Create PR as ready for review instead of draft
+    /// On a feature branch, commit onto it instead of creating a new branch (implied by --update-pr)
+    #[arg(long, visible_aliases = ["stay-on-branch"])]
+    no_new_branch: bool,
+
+    /// On a detached HEAD, create this branch at the current commit and continue on it
+    #[arg(long, value_name = "NAME")]
+    branch_from_detached: Option<String>,
+
+    /// Create PR as ready for review instead of draft; with --update-pr, mark the PR ready
     #[arg(long)]
     ready: bool,
 
+    /// Create PR as draft (the default unless the config sets `draft = false`); with --update-pr, convert the PR to draft
+    #[arg(long, conflicts_with = "ready")]
+    draft: bool,
+
     /// What changes are included in this PR?
     #[arg(long)]
     what: Option<String>,
@@ -56,7 +147,8 @@ struct Args {
     #[arg(long, visible_aliases = ["bigger-picture", "biggerpicture", "context", "overview"])]
     bigger_picture: Option<String>,
 
-    /// Prune local branches that have been merged
+    /// Prune local branches that have been merged (same as `gh-autopr prune`; honors
+    /// `prune_dry_run`, which only `prune --no-dry-run` overrides)
     #[arg(long, visible_aliases = ["prune", "cleanup"])]
     prune_branches: bool,
 
@@ -67,18 +159,523 @@ struct Args {
     /// Maximum autonomous review/prep rounds when external reviewer requests additional prep
     #[arg(long, default_value_t = 2)]
     review_max_rounds: u32,
+
+    /// Drop unchanged context lines from the diff sent to the AI (reduces tokens)
+    #[arg(long, visible_aliases = ["strip-diff-context"])]
+    strip_context: bool,
+
+    /// Diff from the branch's fork point (`git merge-base --fork-point`) instead of the merge base
+    #[arg(long, visible_aliases = ["since-branch-point"])]
+    fork_point: bool,
+
+    /// Describe the changes since this commit, tag or branch instead of the detected base branch
+    #[arg(long, value_name = "REV", conflicts_with = "fork_point")]
+    since_commit: Option<String>,
+
+    /// Have the AI fill in this PR template (default: the repo's `.github/pull_request_template.md` or similar)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, conflicts_with = "fill")]
+    template: Option<Option<std::path::PathBuf>>,
+
+    /// Open the PR from this fork (`owner/repo`) instead of detecting whether the remote is one
+    #[arg(long, value_name = "OWNER/REPO")]
+    head_repo: Option<String>,
+
+    /// List the model IDs available at the configured AI endpoint and exit
+    #[arg(long, visible_aliases = ["models"])]
+    list_models: bool,
+
+    /// Model to use for this run, over AUTOPR_MODEL/OPENAI_MODEL/ANTHROPIC_MODEL and the config file
+    #[arg(long, value_name = "NAME")]
+    model: Option<String>,
+
+    /// Byte budget for the open-issues context sent to the AI (default 16 KiB)
+    #[arg(long)]
+    max_issues_bytes: Option<usize>,
+
+    /// Ask gh for open issues even if a recent run cached them in .git
+    #[arg(long)]
+    refresh_issues: bool,
+
+    /// Truncate diffs sent to the AI at this many bytes (default 200 KiB; env GH_AUTOPR_MAX_DIFF_BYTES)
+    #[arg(long, value_parser = parse_max_diff_bytes)]
+    max_diff_bytes: Option<usize>,
+
+    /// Instead of truncating a diff over --max-diff-bytes, have the AI summarize each file and describe the summaries
+    #[arg(long, conflicts_with_all = ["offline", "stack"])]
+    summarize_large: bool,
+
+    /// Describe and commit only the staged changes; never `git add` the rest of the working tree
+    #[arg(long, conflicts_with = "stack")]
+    staged_only: bool,
+
+    /// Replace the built-in AI system prompt with this file's contents; it must keep the JSON output keys (env GH_AUTOPR_PROMPT_FILE)
+    #[arg(long, value_name = "FILE")]
+    prompt_file: Option<std::path::PathBuf>,
+
+    /// Sampling temperature for the AI, clamped to [0.0, 2.0] (env GH_AUTOPR_TEMPERATURE)
+    #[arg(long, value_parser = parse_temperature)]
+    temperature: Option<f32>,
+
+    /// Response token limit for the AI (env GH_AUTOPR_MAX_TOKENS)
+    #[arg(long, value_parser = parse_max_tokens)]
+    max_tokens: Option<u32>,
+
+    /// Abort before an AI request whose estimated input cost exceeds this many USD
+    #[arg(long, value_name = "USD", value_parser = parse_max_cost)]
+    max_cost: Option<f64>,
+
+    /// Build the PR body from the branch's commit messages: list them as-is (default) or have the AI synthesize them
+    #[arg(
+        long,
+        visible_aliases = ["pr-body-from-commits"],
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "list"
+    )]
+    body_from_commits: Option<CommitBodyMode>,
+
+    /// With --update-pr, fold the changes into the branch's last commit (if gh-autopr made it) and force-push
+    #[arg(long, requires = "update_pr", conflicts_with_all = ["squash", "fill"])]
+    amend: bool,
+
+    /// Let --amend rewrite a commit gh-autopr didn't make, or one the PR's reviewers have seen
+    #[arg(long, requires = "amend")]
+    force_amend: bool,
+
+    /// Split the uncommitted changes into a chain of dependent PRs, one per group the AI proposes
+    #[arg(long, conflicts_with_all = ["update_pr", "fill", "no_new_branch", "squash"])]
+    stack: bool,
+
+    /// Take the PR title/body from the branch's commits (like `gh pr create --fill`) without calling the AI
+    #[arg(long, conflicts_with = "body_from_commits")]
+    fill: bool,
+
+    /// Make the branch name, commit message and PR text from the diff alone, without calling the AI
+    #[arg(long, conflicts_with_all = ["fill", "stack"])]
+    offline: bool,
+
+    /// Write every model request and raw response (API key redacted) to this file, for bug reports
+    #[arg(long, hide = true, value_name = "PATH")]
+    dump_request: Option<std::path::PathBuf>,
+
+    /// Prefix the generated branch with the Conventional Commits type of its title (feat/..., fix/...)
+    #[arg(long, visible_aliases = ["typed-branch"])]
+    type_prefixed_branch: bool,
+
+    /// Turn an invalid AI branch name into a valid one (`feat(x): y` -> `feat-x-y`) instead of asking the AI again
+    #[arg(long)]
+    auto_fix_branch: bool,
+
+    /// Append the named `[checklists]` entry from the config file to the PR body as a task list
+    #[arg(long, visible_aliases = ["review-checklist"], value_name = "NAME")]
+    checklist: Option<String>,
+
+    /// Append a collapsed `git diff --stat` summary to the PR body
+    #[arg(long, visible_aliases = ["include-diffstat-in-body", "diffstat"])]
+    diffstat_in_body: bool,
+
+    /// Leave out any single file whose diff exceeds this many bytes from the AI context
+    #[arg(long, value_name = "N")]
+    max_file_diff_bytes: Option<usize>,
+
+    /// Author the commit as this identity instead of git's configured user
+    #[arg(long, value_name = "NAME <EMAIL>", value_parser = parse_author)]
+    author: Option<String>,
+
+    /// Add a `Signed-off-by` trailer for git's configured user.name and user.email
+    #[arg(long, visible_aliases = ["signoff"])]
+    sign_off: bool,
+
+    /// Add a `Co-authored-by` trailer; repeatable
+    #[arg(long, value_name = "NAME <EMAIL>", value_parser = parse_author)]
+    co_author: Vec<String>,
+
+    /// Rebase the feature branch onto the latest base before pushing (force-pushes with lease)
+    #[arg(long, visible_aliases = ["base-auto-update"])]
+    rebase_onto_base: bool,
+
+    /// Describe the branch's unpushed commits together with the uncommitted changes when naming the branch and writing the commit message
+    #[arg(long, conflicts_with_all = ["squash", "stack"])]
+    include_unpushed: bool,
+
+    /// Fold the branch's unpushed local commits and the uncommitted changes into one new AI-described commit
+    #[arg(long, conflicts_with_all = ["update_pr", "paths", "fill"])]
+    squash: bool,
+
+    /// After creating the PR, poll its CI checks until they settle (default timeout 1800s); exit code reflects the result
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "1800"
+    )]
+    wait_for_checks: Option<u64>,
+
+    /// Let the AI judge whether the change is ready for review or a draft; --ready and --draft override it
+    #[arg(long, conflicts_with_all = ["fill", "stack"])]
+    auto_ready: bool,
+
+    /// Open the PR as a draft (even with --ready) when it changes more than N files or more than N lines
+    #[arg(long, value_name = "N")]
+    auto_draft_over: Option<usize>,
+
+    /// Set the commit title's Conventional Commits scope to the one inferred from the changed paths
+    #[arg(long)]
+    force_scope: bool,
+
+    /// Prefix the PR title with the ticket (e.g. `[ABC-123]`) found in the original branch name
+    #[arg(long)]
+    ticket_from_branch: bool,
+
+    /// Regex for --ticket-from-branch (default `[A-Z]+-\d+`)
+    #[arg(long, value_name = "REGEX")]
+    ticket_pattern: Option<String>,
+
+    /// Truncate longer PR titles at a word boundary, with an ellipsis
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(2..))]
+    max_title_length: Option<u64>,
+
+    /// Template variable for `{{key}}` placeholders in --what/--why/--bigger-picture and checklists (repeatable)
+    #[arg(
+        long = "var",
+        visible_aliases = ["template-var"],
+        value_name = "KEY=VALUE",
+        value_parser = parse_template_var
+    )]
+    vars: Vec<(String, String)>,
+
+    /// Fail on `{{...}}` placeholders that no variable resolves, instead of leaving them verbatim
+    #[arg(long)]
+    strict_vars: bool,
+
+    /// Only print an AI-generated branch name for the uncommitted changes; creates no branch, commit or PR
+    #[arg(long)]
+    output_branch_name: bool,
+
+    /// Ask before destructive git operations (reset --hard, branch -D); on by default when interactive
+    #[arg(long, conflicts_with = "yes")]
+    confirm_destructive: bool,
+
+    /// Never ask for confirmation of destructive git operations, nor before pushing and opening the PR
+    #[arg(long, short = 'y', visible_aliases = ["no-confirm", "assume-yes"])]
+    yes: bool,
+
+    /// Never wait for input (implies --yes): no confirmations, no pauses, no keypress to exit
+    #[arg(long, conflicts_with = "confirm_destructive")]
+    non_interactive: bool,
+
+    /// Request a review from this GitHub login (repeatable)
+    #[arg(long = "reviewer", value_name = "LOGIN", value_parser = parse_login)]
+    reviewers: Vec<String>,
+
+    /// Assign the PR to this GitHub login instead of yourself (repeatable)
+    #[arg(long = "assignee", value_name = "LOGIN", value_parser = parse_login)]
+    assignees: Vec<String>,
+
+    /// Apply this label to the PR (repeatable)
+    #[arg(long = "label", value_name = "NAME", value_parser = parse_label)]
+    labels: Vec<String>,
+
+    /// Let the AI pick labels from the repo's existing ones (adds to --label)
+    #[arg(long)]
+    auto_label: bool,
+
+    /// Send diffs to the AI without masking likely secrets (API keys, tokens, passwords)
+    #[arg(long)]
+    no_scrub: bool,
+
+    /// Only include changes under this pathspec in the diff and commit (repeatable)
+    #[arg(long = "path", value_name = "PATHSPEC")]
+    paths: Vec<String>,
+
+    /// After the run, warn about leftover temp worktrees or scratch files
+    #[arg(long, visible_aliases = ["self-check"])]
+    verify_clean_exit: bool,
+
+    /// Log the git/gh commands that would change branches, push or open the PR, without running them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Append every log and error line to this file as JSON lines (timestamp, level, message, stage)
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Plain log lines on stderr instead of the TUI (implied when stdout is not a terminal)
+    #[arg(long)]
+    no_tui: bool,
+
+    /// Open the created or updated PR in the browser once the run is done
+    #[arg(long)]
+    web: bool,
+
+    /// Code host to open the PR/MR on; detected from the remote's URL when omitted
+    #[arg(long, value_enum)]
+    forge: Option<ForgeKind>,
+
+    /// Remote to fetch from and push to; defaults to the one the current branch tracks, else origin
+    #[arg(long, value_name = "NAME")]
+    remote: Option<String>,
+
+    /// When the remote branch has diverged, overwrite it with --force-with-lease instead of failing
+    #[arg(long)]
+    force_push: bool,
+}
+
+fn parse_login(login: &str) -> Result<String, String> {
+    let login = login.trim();
+    if login.is_empty() || login.contains(char::is_whitespace) {
+        Err(format!("expected a GitHub login, got \"{}\"", login))
+    } else {
+        Ok(login.to_string())
+    }
+}
+
+fn parse_label(label: &str) -> Result<String, String> {
+    let label = label.trim();
+    if label.is_empty() {
+        Err("label must not be empty".to_string())
+    } else {
+        Ok(label.to_string())
+    }
+}
+
+fn parse_author(author: &str) -> Result<String, String> {
+    if is_valid_commit_author(author) {
+        Ok(author.trim().to_string())
+    } else {
+        Err(format!("expected \"Name <email>\", got \"{}\"", author))
+    }
+}
+
+impl Args {
+    fn confirm_destructive(&self) -> bool {
+        self.confirm_destructive
+            || (!self.yes && !self.non_interactive && std::io::stdin().is_terminal())
+    }
+
+    /// Preview the PR and wait for `y` before pushing; on when interactive
+    fn confirm_submit(&self) -> bool {
+        !self.yes && !self.non_interactive && std::io::stdin().is_terminal()
+    }
+
+    /// `--max-diff-bytes`, else `GH_AUTOPR_MAX_DIFF_BYTES`, else the config's
+    /// `max_diff_bytes`, else the default.
+    fn max_diff_bytes(&self, app_config: &AppConfig) -> Result<usize, String> {
+        if let Some(bytes) = self.max_diff_bytes {
+            return Ok(bytes);
+        }
+        match std::env::var("GH_AUTOPR_MAX_DIFF_BYTES") {
+            Ok(v) => {
+                parse_max_diff_bytes(&v).map_err(|e| format!("GH_AUTOPR_MAX_DIFF_BYTES {}", e))
+            }
+            Err(_) => match app_config.defaults.max_diff_bytes {
+                Some(bytes) => parse_max_diff_bytes(&bytes.to_string())
+                    .map_err(|e| format!("max_diff_bytes in the config {}", e)),
+                None => Ok(DEFAULT_MAX_DIFF_BYTES),
+            },
+        }
+    }
+
+    /// The compiled ticket regex when `--ticket-from-branch` (or the config's
+    /// `ticket_from_branch`) is on: `--ticket-pattern`, else the config's, else the default.
+    fn ticket_pattern(&self, app_config: &AppConfig) -> Result<Option<regex::Regex>, String> {
+        if !(self.ticket_from_branch || app_config.title.ticket_from_branch == Some(true)) {
+            return Ok(None);
+        }
+        let pattern = self
+            .ticket_pattern
+            .as_deref()
+            .or(app_config.title.ticket_pattern.as_deref())
+            .unwrap_or(DEFAULT_TICKET_PATTERN);
+        regex::Regex::new(pattern)
+            .map(Some)
+            .map_err(|e| format!("invalid ticket pattern {:?}: {}", pattern, e))
+    }
+
+    fn run_config(&self, app_config: &AppConfig) -> RunConfig {
+        RunConfig {
+            update_pr: self.update_pr,
+            amend: self.amend,
+            force_amend: self.force_amend,
+            no_new_branch: self.no_new_branch,
+            stack: self.stack,
+            ready: !app_config.draft_by_default(),
+            auto_ready: self.auto_ready && !(self.ready || self.draft),
+            sync_draft: self.ready || self.draft,
+            what: self.what.clone(),
+            why: self.why.clone(),
+            bigger_picture: self.bigger_picture.clone(),
+            review_command: self.review_command.clone(),
+            review_max_rounds: self.review_max_rounds,
+            strip_context: self.strip_context,
+            fork_point: self.fork_point,
+            since_commit: self.since_commit.clone(),
+            head_repo: self.head_repo.clone(),
+            // Relative to where gh-autopr was started, not the temp worktree
+            template: self
+                .template
+                .clone()
+                .map(|path| path.map(|p| std::path::absolute(&p).unwrap_or(p))),
+            body_from_commits: self.body_from_commits,
+            fill: self.fill,
+            offline: self.offline,
+            type_prefixed_branch: self.type_prefixed_branch,
+            checklist: self.checklist.clone(),
+            diffstat_in_body: self.diffstat_in_body,
+            max_file_diff_bytes: self.max_file_diff_bytes,
+            author: self.author.clone(),
+            sign_off: self.sign_off,
+            co_authors: self.co_author.clone(),
+            rebase_onto_base: self.rebase_onto_base,
+            squash: self.squash,
+            include_unpushed: self.include_unpushed,
+            wait_for_checks: self.wait_for_checks,
+            auto_draft_over: self.auto_draft_over,
+            force_scope: self.force_scope,
+            // Validated in main, before the session starts
+            ticket_pattern: self.ticket_pattern(app_config).ok().flatten(),
+            max_title_length: self
+                .max_title_length
+                .map(|n| n as usize)
+                .or(app_config.title.max_length),
+            vars: self.vars.clone(),
+            strict_vars: self.strict_vars,
+            reviewers: self.reviewers.clone(),
+            assignees: self.assignees.clone(),
+            labels: self.labels.clone(),
+            auto_label: self.auto_label,
+            forge: self.forge,
+            refresh_issues: self.refresh_issues,
+        }
+    }
+
+    /// Apply CLI overrides on top of the loaded config file and environment.
+    fn app_config(&self) -> AppConfig {
+        let mut app_config = AppConfig::load();
+        if let Some(model) = &self.model {
+            app_config.ai.model = Some(model.clone());
+            app_config.ai.model_source = Some("--model".to_string());
+        }
+        if let Some(max_issues_bytes) = self.max_issues_bytes {
+            app_config.ai.max_issues_bytes = Some(max_issues_bytes);
+        }
+        if let Some(prompt_file) = &self.prompt_file {
+            app_config.ai.prompt_file = Some(prompt_file.clone());
+        }
+        if let Some(temperature) = self.temperature {
+            app_config.ai.temperature = Some(temperature);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            app_config.ai.max_tokens = Some(max_tokens);
+        }
+        if let Some(max_cost) = self.max_cost {
+            app_config.ai.max_cost = Some(max_cost);
+        }
+        app_config.ai.dump_request = self.dump_request.clone();
+        app_config.ai.auto_fix_branch = self.auto_fix_branch;
+        if self.ready || self.draft {
+            app_config.defaults.draft = Some(self.draft);
+        }
+        if let Some(remote) = &self.remote {
+            app_config.defaults.remote = Some(remote.clone());
+        }
+        app_config
+    }
+}
+
+/// How `--body-from-commits` turns the branch's commits into a PR body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CommitBodyMode {
+    /// List each commit subject with its body
+    List,
+    /// Feed the commit messages to the AI and let it write the body
+    Synthesize,
 }
 
 /// Configuration passed from CLI args to the run function
 #[derive(Debug, Clone)]
 struct RunConfig {
     update_pr: bool,
+    amend: bool,
+    force_amend: bool,
+    no_new_branch: bool,
+    stack: bool,
     ready: bool,
+    /// `--auto-ready` without an explicit `--ready`/`--draft`
+    auto_ready: bool,
+    /// `--ready`/`--draft` given: `--update-pr` also sets the PR's draft state
+    sync_draft: bool,
     what: Option<String>,
     why: Option<String>,
     bigger_picture: Option<String>,
     review_command: Option<String>,
     review_max_rounds: u32,
+    strip_context: bool,
+    fork_point: bool,
+    since_commit: Option<String>,
+    head_repo: Option<String>,
+    /// `Some(None)`: look for the repo's default PR template
+    template: Option<Option<std::path::PathBuf>>,
+    body_from_commits: Option<CommitBodyMode>,
+    fill: bool,
+    /// Also set when no API key is configured
+    offline: bool,
+    type_prefixed_branch: bool,
+    checklist: Option<String>,
+    diffstat_in_body: bool,
+    max_file_diff_bytes: Option<usize>,
+    author: Option<String>,
+    sign_off: bool,
+    co_authors: Vec<String>,
+    rebase_onto_base: bool,
+    squash: bool,
+    /// Diff uncommitted changes plus unpushed commits for the AI (`--include-unpushed`)
+    include_unpushed: bool,
+    wait_for_checks: Option<u64>,
+    auto_draft_over: Option<usize>,
+    vars: Vec<(String, String)>,
+    force_scope: bool,
+    ticket_pattern: Option<regex::Regex>,
+    max_title_length: Option<usize>,
+    strict_vars: bool,
+    reviewers: Vec<String>,
+    assignees: Vec<String>,
+    labels: Vec<String>,
+    auto_label: bool,
+    forge: Option<ForgeKind>,
+    refresh_issues: bool,
+}
+
+impl RunConfig {
+    fn prompt_hints(&self, labels: Vec<RepoLabel>) -> PromptHints {
+        PromptHints {
+            what: self.what.clone(),
+            why: self.why.clone(),
+            bigger_picture: self.bigger_picture.clone(),
+            labels,
+            pr_template: None,
+            force_scope: self.force_scope,
+            ask_readiness: self.auto_ready,
+        }
+    }
+
+    /// `Co-authored-by` trailers for `--co-author`, then `Signed-off-by` for `--sign-off`.
+    fn commit_trailers(&self) -> Result<Vec<String>, String> {
+        let mut trailers: Vec<String> = self
+            .co_authors
+            .iter()
+            .map(|author| format!("Co-authored-by: {}", author))
+            .collect();
+        if self.sign_off {
+            match (git_user_name(), git_user_email()) {
+                (Some(name), Some(email)) => {
+                    trailers.push(format!("Signed-off-by: {} <{}>", name, email))
+                }
+                _ => return Err("--sign-off needs git user.name and user.email to be set".into()),
+            }
+        }
+        Ok(trailers)
+    }
 }
 
 /// Branch information gathered before entering temp worktree
@@ -87,13 +684,90 @@ struct BranchInfo {
     current_branch: String,
 }
 
+/// Set by the SIGINT handler that `main` installs for the PR session. The TUI
+/// puts the terminal in raw mode, where Ctrl+C arrives as a key instead (see
+/// `refresh_ui`); this covers `--no-tui` and a `kill -INT`, which would
+/// otherwise end the process with the temp worktree and raw mode left behind.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Turn SIGINT into `INTERRUPTED`, so `run` stops at its next `refresh_ui` and
+/// unwinds normally. A second SIGINT exits at once, e.g. if a prompt is waiting.
+fn install_interrupt_handler() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+            eprintln!(
+                "Interrupted. Reverting repository to original state (Ctrl+C again to exit now)..."
+            );
+        }
+    });
+}
+
+/// The error `run` unwinds with once SIGINT arrived.
+fn check_interrupted() -> Result<(), Box<dyn std::error::Error>> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(Failure::Interrupted.error("Interrupted by signal"));
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    let args = match cli.command {
+        Some(CliCommand::Prune(prune)) => {
+            let config = AppConfig::load();
+            return run_prune_branches(
+                prune.confirm_destructive(),
+                config.prune_dry_run(prune.dry_run, prune.no_dry_run),
+                prune
+                    .remote
+                    .as_deref()
+                    .or(config.defaults.remote.as_deref()),
+                prune.force_prune,
+            );
+        }
+        Some(CliCommand::Config(command)) => return run_config_command(command),
+        Some(CliCommand::Create(args)) => *args,
+        None => cli.create,
+    };
+    // Effective settings, once: CLI flags over env vars over config files
+    let app_config = args.app_config();
+    let max_diff_bytes = args
+        .max_diff_bytes(&app_config)
+        .unwrap_or_else(|e| usage_error(e));
+    if let Err(e) = args.ticket_pattern(&app_config) {
+        usage_error(e);
+    }
+    if let Some(name) = args.branch_from_detached.as_deref() {
+        if !is_valid_git_branch_name(name) {
+            usage_error(format!(
+                "--branch-from-detached: \"{}\" is not a valid branch name",
+                name
+            ));
+        }
+    }
+    // Resolved before anything changes into the repo root
+    let paths = resolve_path_restrictions(&args.paths).unwrap_or_else(|e| usage_error(e));
+    let log_file = args.log_file.as_ref().map(|path| {
+        fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(fs_err::File::into_file)
+            .unwrap_or_else(|e| usage_error(format!("--log-file: {}", e)))
+    });
 
     // Handle branch pruning early - no TUI needed
     if args.prune_branches {
-        return run_prune_branches();
+        return run_prune_branches(
+            args.confirm_destructive(),
+            app_config.prune_dry_run(args.dry_run, false),
+            app_config.defaults.remote.as_deref(),
+            false,
+        );
     }
 
     // Create a stub config if none exists, then ask the user to fill it in.
@@ -106,65 +780,237 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    // Initialize the terminal for PR creation mode
-    enable_raw_mode()?;
-    let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-    terminal.clear()?;
+    // Model listing is a diagnostic - no TUI or git state needed
+    if args.list_models {
+        return run_list_models().await;
+    }
 
-    let mut app = App::new("GitHub PR Auto-Submit");
-    let tick_rate = Duration::from_millis(250);
+    // Branch-name scripting mode is read-only - no TUI or temp worktree
+    if args.output_branch_name {
+        return run_output_branch_name(&args, app_config, max_diff_bytes, paths, log_file).await;
+    }
 
-    let config = RunConfig {
-        update_pr: args.update_pr,
-        ready: args.ready,
-        what: args.what,
-        why: args.why,
-        bigger_picture: args.bigger_picture,
-        review_command: args.review_command,
-        review_max_rounds: args.review_max_rounds,
+    install_interrupt_handler();
+    let mut app = App::new("GitHub PR Auto-Submit");
+    app.confirm_destructive = args.confirm_destructive();
+    app.confirm_submit = args.confirm_submit();
+    app.dry_run = args.dry_run;
+    app.max_diff_bytes = max_diff_bytes;
+    app.summarize_large = args.summarize_large;
+    app.staged_only = args.staged_only;
+    app.paths = paths;
+    app.plain = args.no_tui || !std::io::stdout().is_terminal();
+    app.non_interactive = args.non_interactive;
+    app.force_push = args.force_push;
+    app.scrub_secrets = !args.no_scrub;
+    app.log_file = log_file;
+    let mut orig_root = None;
+
+    let app_result = if app.plain {
+        // Same steps, drawn into an off-screen buffer; logs stream to stderr
+        let mut terminal = Terminal::new(TestBackend::new(80, 24))?;
+        run_session(&mut terminal, &mut app, &args, app_config, &mut orig_root).await
+    } else {
+        // Initialize the terminal for PR creation mode
+        enable_raw_mode()?;
+        let mut stdout = std::io::stdout();
+        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+        let backend = CrosstermBackend::new(stdout);
+        let mut terminal = Terminal::new(backend)?;
+        terminal.clear()?;
+
+        let result = run_session(&mut terminal, &mut app, &args, app_config, &mut orig_root).await;
+        restore_terminal(&mut terminal)?;
+        result
     };
 
-    // Do git operations that need original worktree BEFORE entering temp worktree
-    let branch_info = match pre_worktree_setup(&mut terminal, &mut app, tick_rate).await {
-        Ok(info) => info,
-        Err(e) => {
-            restore_terminal(&mut terminal)?;
-            eprintln!("ERROR in pre-worktree setup: {}", e);
-            return Err(e);
+    if let Err((stage, ref e)) = app_result {
+        let message = redact(&format!("ERROR in {}: {}", stage, e), &app.secrets);
+        eprintln!("{}", message);
+        app.write_log_record("ERROR", &message);
+    }
+
+    // Print logs after terminal is restored (plain mode already streamed them)
+    if !app.plain {
+        for (log_level, log_message, _) in &app.logs {
+            println!("{}: {}", log_level, log_message);
+        }
+    }
+
+    // After the TUI is gone, so the browser doesn't draw over it
+    if args.web && app_result.is_ok() && !app.dry_run {
+        if let Some(url) = &app.status.pr_url {
+            open_in_browser(url, |name| std::env::var_os(name).is_some());
+        }
+    }
+
+    if let Some(orig_root) = orig_root.filter(|_| args.verify_clean_exit) {
+        match verify_clean_exit(&orig_root) {
+            Ok(leftovers) if leftovers.is_empty() => {
+                println!("INFO: Self-check passed: nothing left behind")
+            }
+            Ok(leftovers) => {
+                for leftover in leftovers {
+                    println!("WARN: Self-check: {}", leftover);
+                }
+            }
+            Err(e) => println!("WARN: Self-check failed to run: {}", e),
         }
+    }
+
+    // Terminal restored, worktree dropped and logs printed: safe to exit here
+    if let Err((stage, e)) = app_result {
+        std::process::exit(exit_code(stage, e.as_ref()));
+    }
+    Ok(())
+}
+
+/// Open `url` with the platform's opener (`--web`). Does nothing but print the
+/// URL in CI or, off macOS and Windows, without an X11 or Wayland display;
+/// `env_set` says whether an environment variable is set.
+fn open_in_browser(url: &str, env_set: impl Fn(&str) -> bool) {
+    let (program, args): (&str, &[&str]) = if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else if cfg!(windows) {
+        ("cmd", &["/C", "start", ""])
+    } else {
+        ("xdg-open", &[])
     };
+    let has_display =
+        cfg!(any(target_os = "macos", windows)) || env_set("DISPLAY") || env_set("WAYLAND_DISPLAY");
+    if env_set("CI") || !has_display {
+        println!("INFO: --web: no browser available, PR is at {}", url);
+        return;
+    }
+    let status = std::process::Command::new(program)
+        .args(args)
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => println!("WARN: --web: {} exited with {}", program, status),
+        Err(e) => println!("WARN: --web: could not run {}: {}", program, e),
+    }
+}
 
-    // All subsequent Git commands act inside the isolated worktree
-    let temp_worktree = TempWorktree::enter()?;
+/// Report an invalid flag or config value and exit with the usage status (2).
+fn usage_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("Error: {}", message);
+    std::process::exit(2)
+}
 
-    let app_config = AppConfig::load();
+/// What a failed run was doing, which decides its exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// An invalid flag, config value or missing API key (2)
+    Usage,
+    /// A git command or the repository state (3)
+    Git,
+    /// gh/glab, or the PR itself (4)
+    Forge,
+    /// Ctrl+C or SIGINT (130)
+    Interrupted,
+}
 
-    let app_result = run(
-        &mut terminal,
-        &mut app,
-        tick_rate,
-        config,
-        app_config,
-        branch_info,
-        temp_worktree,
-    )
-    .await;
+impl Failure {
+    /// Tag `error` with this failure, unless it already carries one: the step
+    /// that actually failed knows best.
+    fn error(self, error: impl Into<Box<dyn std::error::Error>>) -> Box<dyn std::error::Error> {
+        let error = error.into();
+        if error.is::<FailureError>() {
+            return error;
+        }
+        Box::new(FailureError {
+            failure: self,
+            error,
+        })
+    }
+}
+
+/// An error tagged with the `Failure` it exits with; displays as the error itself.
+#[derive(Debug)]
+struct FailureError {
+    failure: Failure,
+    error: Box<dyn std::error::Error>,
+}
 
-    restore_terminal(&mut terminal)?;
+impl std::fmt::Display for FailureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for FailureError {}
+
+/// `step()?` becomes `step().fail_as(Failure::Git)?`.
+trait FailAs<T> {
+    fn fail_as(self, failure: Failure) -> Result<T, Box<dyn std::error::Error>>;
+}
 
-    if let Err(ref e) = app_result {
-        eprintln!("ERROR in execution: {}", e);
+impl<T, E: Into<Box<dyn std::error::Error>>> FailAs<T> for Result<T, E> {
+    fn fail_as(self, failure: Failure) -> Result<T, Box<dyn std::error::Error>> {
+        self.map_err(|e| failure.error(e))
     }
+}
 
-    // Print logs after terminal is restored
-    for (log_level, log_message) in &app.logs {
-        println!("{}: {}", log_level, log_message);
+/// Exit status for a run that failed in `stage` with `error`: 2 for usage and
+/// config problems, 3 for git, 4 for gh/glab and the PR itself, 130 after an
+/// interrupt, else 1. The setup stages before `execution` only run git.
+fn exit_code(stage: &str, error: &(dyn std::error::Error + 'static)) -> i32 {
+    match error.downcast_ref::<FailureError>().map(|e| e.failure) {
+        Some(Failure::Usage) => 2,
+        Some(Failure::Git) => 3,
+        Some(Failure::Forge) => 4,
+        Some(Failure::Interrupted) => 130,
+        None if stage != "execution" => 3,
+        None => 1,
     }
+}
+
+/// Pre-worktree setup, then the PR flow inside the temp worktree. Errors carry
+/// the stage they happened in; `orig_root` is set once the worktree is entered.
+async fn run_session<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App<'_>,
+    args: &Args,
+    app_config: AppConfig,
+    orig_root: &mut Option<std::path::PathBuf>,
+) -> Result<(), (&'static str, Box<dyn std::error::Error>)>
+where
+    <B as Backend>::Error: 'static,
+{
+    let tick_rate = Duration::from_millis(250);
+    let config = args.run_config(&app_config);
+
+    // Do git operations that need original worktree BEFORE entering temp worktree
+    let remote = app_config.defaults.remote.clone();
+    let branch_info = pre_worktree_setup(
+        terminal,
+        app,
+        tick_rate,
+        remote.as_deref(),
+        args.branch_from_detached.as_deref(),
+    )
+    .await
+    .map_err(|e| ("pre-worktree setup", e))?;
 
-    app_result.map(|_| ())
+    // All subsequent Git commands act inside the isolated worktree
+    let temp_worktree = TempWorktree::enter(&app.remote).map_err(|e| ("temp worktree setup", e))?;
+    *orig_root = Some(temp_worktree.original_root().clone());
+
+    run(
+        terminal,
+        app,
+        tick_rate,
+        config,
+        app_config,
+        branch_info,
+        temp_worktree,
+    )
+    .await
+    .map_err(|e| ("execution", e))
 }
 
 fn restore_terminal<B: Backend + std::io::Write>(
@@ -183,15 +1029,50 @@ where
     Ok(())
 }
 
-fn run_prune_branches() -> Result<(), Box<dyn std::error::Error>> {
+fn run_prune_branches(
+    confirm: bool,
+    dry_run: bool,
+    remote: Option<&str>,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut app = App::new("GitHub PR Auto-Submit");
+    app.confirm_destructive = confirm;
+    app.dry_run = dry_run;
 
+    ensure_git_available(&mut app)?;
+    ensure_gh_available(&mut app)?;
     git_ensure_in_repo(&mut app)?;
     git_cd_to_repo_root(&mut app)?;
+    app.remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => git_default_remote(&mut app)?,
+    };
 
-    let result = prune_merged_branches(&mut app);
+    let result = find_prunable_branches(&mut app, force).and_then(|branches| {
+        if dry_run {
+            // Only list them; delete_local_branch would log a misleading "Deleted"
+            for branch in &branches {
+                app.add_log(
+                    "INFO",
+                    format!("Would delete {} ({})", branch.name, branch.reason),
+                );
+            }
+            app.add_log(
+                "INFO",
+                format!(
+                    "Dry run: {} branch(es) would be deleted, nothing deleted",
+                    branches.len()
+                ),
+            );
+        } else if confirm_prune(&mut app, &branches)? {
+            delete_prunable_branches(&mut app, &branches);
+        } else {
+            app.add_log("INFO", "Branch pruning cancelled, nothing deleted");
+        }
+        Ok(())
+    });
 
-    for (log_level, log_message) in &app.logs {
+    for (log_level, log_message, _) in &app.logs {
         println!("{}: {}", log_level, log_message);
     }
 
@@ -207,34 +1088,289 @@ fn run_prune_branches() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-async fn pre_worktree_setup<B: Backend>(
-    terminal: &mut Terminal<B>,
+/// Pruning has no TUI of its own, so bring one up just for the confirmation.
+fn confirm_prune(
     app: &mut App<'_>,
-    tick_rate: Duration,
-) -> Result<BranchInfo, Box<dyn std::error::Error>>
-where
-    <B as Backend>::Error: 'static,
-{
-    let mut last_tick = Instant::now();
+    branches: &[PrunableBranch],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if !app.confirm_destructive || branches.is_empty() {
+        return Ok(true);
+    }
 
-    app.add_log("INFO", "Checking git repository...");
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
-    git_ensure_in_repo(app)?;
+    let targets: Vec<String> = branches
+        .iter()
+        .map(|b| format!("branch {} ({})", b.name, b.reason))
+        .collect();
 
-    app.add_log("INFO", "Navigating to repository root...");
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
-    git_cd_to_repo_root(app)?;
+    enable_raw_mode()?;
+    execute!(std::io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+    let confirmed = confirm_destructive(
+        &mut terminal,
+        app,
+        "Delete merged local branches (git branch -D)",
+        &targets,
+    );
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    confirmed
+}
 
-    let main_branch = git_main_branch(app).unwrap_or_else(|_| "main".to_string());
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+/// `gh-autopr config ...`: manage the API key in the keyring entry that
+/// `get_api_key` reads, without a TUI or temp worktree.
+fn run_config_command(command: ConfigCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let app_config = AppConfig::load();
+    let entry = keyring::Entry::new(&app_config.keyring_service(), &app_config.keyring_account())?;
+    let result = match command {
+        ConfigCommand::Set { stdin } => {
+            let key = if stdin || !std::io::stdin().is_terminal() {
+                let mut key = String::new();
+                std::io::stdin().read_line(&mut key)?;
+                key
+            } else {
+                read_hidden_line(&format!("{} API key: ", app_config.provider()))?
+            };
+            let key = key.trim();
+            if key.is_empty() {
+                Err("No API key given".to_string())
+            } else {
+                entry
+                    .set_password(key)
+                    .map(|()| {
+                        format!(
+                            "Stored {} in the keyring ({})",
+                            mask_api_key(key),
+                            keyring_location(&app_config)
+                        )
+                    })
+                    .map_err(|e| format!("Could not store the API key: {}", e))
+            }
+        }
+        ConfigCommand::Get => stored_key_preview(&entry, &app_config),
+        ConfigCommand::Delete => match entry.delete_credential() {
+            Ok(()) => Ok(format!(
+                "Deleted the API key from the keyring ({})",
+                keyring_location(&app_config)
+            )),
+            Err(keyring::Error::NoEntry) => Err(no_stored_key(&app_config)),
+            Err(e) => Err(format!("Could not delete the API key: {}", e)),
+        },
+    };
+    match result {
+        Ok(message) => {
+            println!("{}", message);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2)
+        }
+    }
+}
 
-    let current_branch = git_current_branch(app)?;
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+/// The masked key stored in `entry`, or why there is none.
+fn stored_key_preview(entry: &keyring::Entry, app_config: &AppConfig) -> Result<String, String> {
+    match entry.get_password() {
+        Ok(key) => Ok(format!(
+            "{} ({})",
+            mask_api_key(&key),
+            keyring_location(app_config)
+        )),
+        Err(keyring::Error::NoEntry) => Err(no_stored_key(app_config)),
+        Err(e) => Err(format!("Could not read the keyring: {}", e)),
+    }
+}
 
-    git_ensure_not_detached_head(terminal, app, &current_branch)?;
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+fn no_stored_key(app_config: &AppConfig) -> String {
+    format!(
+        "No {} API key in the keyring ({}); store one with `gh-autopr config set-key`",
+        app_config.provider(),
+        keyring_location(app_config)
+    )
+}
 
-    app.add_log("INFO", "Fetching latest changes...");
+fn keyring_location(app_config: &AppConfig) -> String {
+    format!(
+        "service {:?}, account {:?}",
+        app_config.keyring_service(),
+        app_config.keyring_account()
+    )
+}
+
+/// Prompt on stderr and read a line from the terminal without echoing it.
+fn read_hidden_line(prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    eprint!("{}", prompt);
+    enable_raw_mode()?;
+    let mut line = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Esc => break Err("Cancelled".into()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err("Cancelled".into())
+                }
+                KeyCode::Char(c) => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+    disable_raw_mode()?;
+    eprintln!();
+    result.map(|()| line)
+}
+
+async fn run_list_models() -> Result<(), Box<dyn std::error::Error>> {
+    let mut app_config = AppConfig::load();
+    let keyring = keyring_lookup(&app_config);
+    app_config.resolve_api_key(keyring);
+
+    match list_models(&app_config).await {
+        Ok(models) => {
+            println!(
+                "Models available from {} (configured: {}):",
+                app_config.provider(),
+                app_config.model()
+            );
+            for model in models {
+                let marker = if model == app_config.model() {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}", marker, model);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("ERROR listing models: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Print just the generated branch name on stdout, e.g. for `git switch -c $(gh-autopr --output-branch-name)`.
+/// Logs go to stderr so they never end up in the captured name.
+async fn run_output_branch_name(
+    args: &Args,
+    mut app_config: AppConfig,
+    max_diff_bytes: usize,
+    paths: Vec<String>,
+    log_file: Option<std::fs::File>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App::new("GitHub PR Auto-Submit");
+    app.log_file = log_file;
+    app.max_diff_bytes = max_diff_bytes;
+    app.summarize_large = args.summarize_large;
+    app.staged_only = args.staged_only;
+    app.paths = paths;
+    app.scrub_secrets = !args.no_scrub;
+    let config = args.run_config(&app_config);
+    let keyring = keyring_lookup(&app_config);
+    app_config.resolve_api_key(keyring);
+    app.issues_cache_ttl = app_config.issues_cache_ttl();
+    app.refresh_issues = config.refresh_issues;
+
+    let result = async {
+        git_ensure_in_repo(&mut app)?;
+        git_cd_to_repo_root(&mut app)?;
+        git_ensure_has_commits(&mut app)?;
+        let current_branch = git_current_branch(&mut app)?;
+        let diff = git_diff_uncommitted(&mut app, &current_branch)?;
+        if diff.is_empty() {
+            return Err("No uncommitted changes to name a branch after".into());
+        }
+        let diff = if config.include_unpushed {
+            git_diff_uncommitted_and_unpushed(&mut app, &current_branch)?.unwrap_or(diff)
+        } else {
+            diff
+        };
+        // Issues only sharpen the name; don't fail without gh
+        let issues_json = github_list_issues(&mut app).ok();
+        let context = model_input_diff(&mut app, &config, diff);
+        let generated = gpt_generate_branch_name_and_commit_description(
+            &mut app,
+            &app_config,
+            context,
+            issues_json,
+            &config.prompt_hints(vec![]),
+        )
+        .await?;
+        Ok::<_, Box<dyn std::error::Error>>(if config.type_prefixed_branch {
+            type_prefixed_branch(&generated.branch_name, &generated.commit_title)
+        } else {
+            generated.branch_name
+        })
+    }
+    .await;
+
+    match result {
+        Ok(branch) => {
+            println!("{}", branch);
+            Ok(())
+        }
+        Err(e) => {
+            for (log_level, log_message, _) in &app.logs {
+                eprintln!("{}: {}", log_level, log_message);
+            }
+            eprintln!("ERROR generating branch name: {}", e);
+            Err(e)
+        }
+    }
+}
+
+async fn pre_worktree_setup<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App<'_>,
+    tick_rate: Duration,
+    remote: Option<&str>,
+    branch_from_detached: Option<&str>,
+) -> Result<BranchInfo, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    let mut last_tick = Instant::now();
+
+    app.add_log("INFO", "Checking git repository...");
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    ensure_git_available(app)?;
+    git_ensure_in_repo(app)?;
+
+    app.add_log("INFO", "Navigating to repository root...");
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    git_cd_to_repo_root(app)?;
+    git_ensure_has_commits(app)?;
+
+    // Temp worktrees of runs that were killed before they could clean up
+    if let Err(e) = cleanup_stale_worktrees(app) {
+        app.add_log(
+            "WARN",
+            format!("Could not clean up stale temp worktrees: {}", e),
+        );
+    }
+
+    app.remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => git_default_remote(app)?,
+    };
+    let main_branch = git_main_branch(app).unwrap_or_else(|_| "main".to_string());
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+
+    let current_branch = git_current_branch(app)?;
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+
+    let current_branch =
+        git_ensure_not_detached_head(terminal, app, &current_branch, branch_from_detached)?;
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+
+    app.add_log("INFO", "Fetching latest changes...");
+    app.set_stage(Stage::FetchMain);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
     git_fetch_main(app, &current_branch, &main_branch)?;
 
@@ -251,7 +1387,7 @@ async fn run<B: Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App<'_>,
     tick_rate: Duration,
-    config: RunConfig,
+    mut config: RunConfig,
     mut app_config: AppConfig,
     branch_info: BranchInfo,
     temp_worktree: TempWorktree,
@@ -261,102 +1397,436 @@ where
 {
     let mut last_tick = Instant::now();
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    app.issues_cache_ttl = app_config.issues_cache_ttl();
+    app.refresh_issues = config.refresh_issues;
+    let trailers = config.commit_trailers().fail_as(Failure::Usage)?;
 
     // Resolve API key (config file / env var already applied; check keyring as fallback)
-    get_api_key(app, terminal, &mut app_config).await?;
+    if config.fill {
+        app.add_log("INFO", "--fill: PR content comes from commits, AI disabled");
+    } else if config.offline {
+        app.add_log(
+            "INFO",
+            "--offline: PR content comes from the diff, AI disabled",
+        );
+    } else if let Err(missing) = get_api_key(app, &mut app_config) {
+        if config.stack {
+            app.add_error(missing.clone());
+            app.switch_to_tab(1);
+            terminal.draw(|f| ui(f, app))?;
+            linger(app).await;
+            return Err(
+                Failure::Usage.error(format!("{} API key not found", app_config.provider()))
+            );
+        }
+        app.add_log("WARN", format!("{}; continuing as with --offline", missing));
+        config.offline = true;
+    } else {
+        if let Some(key) = app_config.ai.api_key.as_deref() {
+            app.add_secret(key);
+        }
+        app.add_log(
+            "INFO",
+            format!(
+                "Model: {} ({})",
+                app_config.model(),
+                app_config.model_source()
+            ),
+        );
+    }
+
+    // Resolve --checklist up front so a typo fails before any AI call
+    let checklist_items = match &config.checklist {
+        Some(name) => match app_config.checklist(name) {
+            Some(items) => Some(items.to_vec()),
+            None => {
+                let mut known: Vec<&String> = app_config.checklists.keys().collect();
+                known.sort();
+                let msg = format!(
+                    "Unknown checklist '{}'; configured checklists: {:?}",
+                    name, known
+                );
+                app.add_error(msg.clone());
+                return Err(Failure::Usage.error(msg));
+            }
+        },
+        None => None,
+    };
+    // Likewise --template; the temp worktree has the repo's tracked templates
+    let pr_template = match &config.template {
+        Some(path) => {
+            let Some(path) = path
+                .clone()
+                .or_else(|| find_pr_template(std::path::Path::new(".")))
+            else {
+                let msg = format!(
+                    "--template: no PR template found (looked for {})",
+                    PR_TEMPLATE_PATHS.join(", ")
+                );
+                app.add_error(msg.clone());
+                return Err(Failure::Usage.error(msg));
+            };
+            let template = fs_err::read_to_string(&path)
+                .inspect_err(|e| app.add_error(e))
+                .fail_as(Failure::Usage)?;
+            app.add_log("INFO", format!("Filling PR template {}", path.display()));
+            Some(template)
+        }
+        None => None,
+    };
 
     app.add_log("INFO", "Working in temp worktree...");
-    app.update_progress(0.1);
+    app.set_stage(Stage::EnterWorktree);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
     // Use branch info from pre_worktree_setup (no need to re-detect)
     let main_branch = &branch_info.main_branch;
     let mut current_branch = branch_info.current_branch.clone();
     let is_on_main = current_branch == *main_branch;
-    let base_branch = discover_parent_branch(app, main_branch, &current_branch)?;
+    // Uncommitted changes on main always go to a new branch
+    let creates_new_branch = is_on_main || !(config.update_pr || config.no_new_branch);
+    if config.no_new_branch && is_on_main {
+        app.add_log(
+            "WARN",
+            format!(
+                "--no-new-branch ignored: never committing onto {}",
+                main_branch
+            ),
+        );
+    }
+    let forge_kind = config
+        .forge
+        .unwrap_or_else(|| ForgeKind::detect(&app.remote));
+    let forge = forge_kind.forge();
+    forge.ensure_cli_available(app).fail_as(Failure::Forge)?;
+    let mut base_branch = match &config.since_commit {
+        // The PR targets the rev if it is a branch, else main
+        Some(rev) => {
+            if let Err(e) = git_verify_commit(rev) {
+                app.add_error(e.to_string());
+                return Err(Failure::Usage.error(e));
+            }
+            if local_branch_exists(rev).fail_as(Failure::Git)? {
+                rev.clone()
+            } else {
+                main_branch.clone()
+            }
+        }
+        None => discover_parent_branch(app, main_branch, &current_branch).fail_as(Failure::Git)?,
+    };
+    // An updated PR keeps its target, even if the branch was rebased elsewhere since
+    if config.update_pr && !is_on_main && config.since_commit.is_none() {
+        if let Some(pr_base) = forge
+            .list_open_pr_base(app, &current_branch)
+            .ok()
+            .flatten()
+            .filter(|pr_base| *pr_base != base_branch)
+        {
+            app.add_log(
+                "INFO",
+                format!(
+                    "Open {} PR targets {}, using it as the base instead of {}",
+                    forge.name(),
+                    pr_base,
+                    base_branch
+                ),
+            );
+            base_branch = pr_base;
+        }
+    }
 
     app.add_log(
         "INFO",
         format!(
-            "Main: {}, Current: {}, Base: {}",
-            main_branch, current_branch, base_branch
+            "Main: {}, Current: {}, Base: {}, Forge: {}",
+            main_branch,
+            current_branch,
+            base_branch,
+            forge.name()
         ),
     );
-    app.update_progress(0.2);
+    app.status.main_branch = Some(main_branch.clone());
+    app.status.base_branch = Some(base_branch.clone());
+    // Diffs, stats and commit lists all compare against this one ref
+    let mut base_ref = resolve_base_ref(&app.remote, &base_branch);
+    if base_ref != base_branch {
+        app.add_log(
+            "INFO",
+            format!(
+                "Comparing against {} (local {} is missing or behind it)",
+                base_ref, base_branch
+            ),
+        );
+    }
+    update_status(app, &current_branch);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
+    // {{var}} substitution in user-supplied prompt context and checklist items
+    let template_vars = template_vars(&config, &current_branch, &base_branch);
+    let render = |app: &mut App<'_>, text: &str| {
+        render_template(text, &template_vars, config.strict_vars)
+            .inspect_err(|e| app.add_error(e))
+            .fail_as(Failure::Usage)
+    };
+    for field in [
+        &mut config.what,
+        &mut config.why,
+        &mut config.bigger_picture,
+    ] {
+        if let Some(text) = field.as_deref() {
+            *field = Some(render(app, text)?);
+        }
+    }
+    let checklist_items = match checklist_items {
+        Some(items) => Some(
+            items
+                .iter()
+                .map(|item| render(app, item))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        None => None,
+    };
+
+    // --squash: the branch's local commits become pending changes of one new commit
+    if config.squash && is_on_main {
+        app.add_log("WARN", "--squash ignored: not on a feature branch");
+    } else if config.squash
+        && git_squash_onto_merge_base(app, &base_branch).fail_as(Failure::Git)? > 0
+    {
+        // The new branch starts from the detached merge base, not the old tip
+        current_branch = "HEAD".to_string();
+        refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    }
+
     // Get uncommitted changes
-    let diff_uncommitted = git_diff_uncommitted(app, &current_branch)?;
+    app.set_stage(Stage::DiffUncommitted);
+    let diff_uncommitted = git_diff_uncommitted(app, &current_branch).fail_as(Failure::Git)?;
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
-    // Fetch GitHub issues once
-    app.add_log("INFO", "Fetching GitHub issues...");
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
-    let issues_json = github_list_issues(app)?;
+    if config.fill && !diff_uncommitted.is_empty() {
+        app.add_error(
+            "--fill only uses existing commits; commit or stash uncommitted changes first",
+        );
+        return Err(Failure::Usage.error("Uncommitted changes with --fill"));
+    }
+
+    // Nothing to commit and nothing to PR: stop before fetching issues or calling the AI
+    if diff_uncommitted.is_empty()
+        && (is_on_main
+            || branch_diff(app, &config, &base_ref, &current_branch)
+                .fail_as(Failure::Git)?
+                .is_empty())
+    {
+        let message = if is_on_main {
+            "No changes to commit.".to_string()
+        } else {
+            format!(
+                "Nothing to do — working tree clean and {} matches {}",
+                current_branch, base_branch
+            )
+        };
+        app.add_log("INFO", &message);
+        render_message(terminal, "Info", &message, Color::Cyan)?;
+        app.set_stage(Stage::Done);
+        terminal.draw(|f| ui(f, app))?;
+        run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
+        return Ok(());
+    }
+
+    if config.stack {
+        let top_branch = run_stack(
+            terminal,
+            app,
+            tick_rate,
+            &mut last_tick,
+            &config,
+            &app_config,
+            forge_kind,
+            &base_branch,
+            &current_branch,
+            diff_uncommitted,
+        )
+        .await?;
+        switch_original_worktree(
+            terminal,
+            app,
+            tick_rate,
+            &mut last_tick,
+            temp_worktree,
+            &top_branch,
+        )
+        .fail_as(Failure::Git)?;
+        return Ok(());
+    }
+
+    // Fetch issues once (only the AI uses them)
+    let issues_json = if config.fill || config.offline {
+        String::new()
+    } else {
+        app.add_log("INFO", format!("Fetching {} issues...", forge.name()));
+        refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+        forge.list_issues(app).fail_as(Failure::Forge)?
+    };
+
+    // Real repo labels for --auto-label, so the model can't invent any
+    let repo_labels = if config.auto_label && config.fill {
+        app.add_log("WARN", "--auto-label ignored: --fill disables the AI");
+        vec![]
+    } else if config.auto_label && config.offline {
+        app.add_log("WARN", "--auto-label ignored: the AI is off (--offline)");
+        vec![]
+    } else if config.auto_label && forge_kind != ForgeKind::Github {
+        app.add_log("WARN", "--auto-label ignored: only supported on GitHub");
+        vec![]
+    } else if config.auto_label {
+        match github_list_labels(app) {
+            Ok(labels) => labels,
+            Err(e) => {
+                app.add_log("WARN", format!("--auto-label disabled: {}", e));
+                vec![]
+            }
+        }
+    } else {
+        vec![]
+    };
+    let mut hints = config.prompt_hints(repo_labels);
+    hints.pr_template = pr_template;
 
     // Track GPT response for reuse (avoid calling twice for fresh branches)
-    let mut cached_gpt_response: Option<(String, Option<String>)> = None;
+    let mut cached_gpt_response: Option<GeneratedContent> = None;
+
+    let amend = config.amend
+        && !creates_new_branch
+        && !diff_uncommitted.is_empty()
+        && amend_allowed(app, forge.as_ref(), &config, &base_ref, &current_branch)?;
+    // The amended commit describes its old changes plus the new ones
+    let diff_uncommitted = if amend {
+        git_diff_uncommitted(app, "HEAD~1").fail_as(Failure::Git)?
+    } else {
+        diff_uncommitted
+    };
 
     if diff_uncommitted.is_empty() {
-        if is_on_main {
-            app.add_log("INFO", "No changes to commit.");
-            render_message(terminal, "Info", "No changes to commit.", Color::Cyan)?;
-            app.update_progress(1.0);
-            terminal.draw(|f| ui(f, app))?;
-            run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
-            return Ok(());
-        }
+        // Everything is committed already; the PR describes the branch as it is
+    } else if let Some(existing) = creates_new_branch
+        .then(|| find_branch_with_pending_changes(app))
+        .transpose()
+        .fail_as(Failure::Git)?
+        .flatten()
+    {
+        // A previous run committed these exact changes but failed later (e.g. at push)
+        git_switch_to_committed_branch(app, &existing).fail_as(Failure::Git)?;
+        current_branch = existing;
+        update_status(app, &current_branch);
+        refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
     } else {
         app.update_details(diff_uncommitted.clone());
         refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
         app.add_log("INFO", "Generating branch name and commit message...");
+        app.set_stage(Stage::GenerateBranch);
         refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
-        let (generated_branch_name, commit_title, commit_details) =
-            gpt_generate_branch_name_and_commit_description(
-                app,
-                &app_config,
-                diff_uncommitted,
-                Some(issues_json.clone()),
-                config.what.clone(),
-                config.why.clone(),
-                config.bigger_picture.clone(),
+        let diff_uncommitted = if config.include_unpushed {
+            git_diff_uncommitted_and_unpushed(app, &current_branch)
+                .fail_as(Failure::Git)?
+                .unwrap_or(diff_uncommitted)
+        } else {
+            diff_uncommitted
+        };
+        let context = model_input_diff(app, &config, diff_uncommitted);
+        let generated = if config.offline {
+            heuristic_commit_message(&context)
+        } else {
+            let (plain, preview) = (app.plain, app.streaming_preview.clone());
+            with_streaming_preview(
+                terminal,
+                plain,
+                &preview,
+                tick_rate,
+                gpt_generate_branch_name_and_commit_description(
+                    app,
+                    &app_config,
+                    context,
+                    Some(issues_json.clone()),
+                    &hints,
+                ),
             )
-            .await?;
+            .await??
+        };
+        let mut generated_branch_name = generated.branch_name.clone();
+        let commit_title = generated.commit_title.clone();
+        let commit_details = generated.commit_details.clone();
         refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
-        // Create new branch if on main or creating new PR
-        let creating_new_branch = is_on_main || !config.update_pr;
-        if creating_new_branch {
-            git_checkout_new_branch(app, &generated_branch_name, &current_branch, false)?;
+        if config.type_prefixed_branch {
+            generated_branch_name = type_prefixed_branch(&generated_branch_name, &commit_title);
+        }
+
+        // Create new branch if on main or creating new PR (without --no-new-branch)
+        if creates_new_branch {
+            git_checkout_new_branch(app, &generated_branch_name, &current_branch, None)
+                .fail_as(Failure::Git)?;
             app.add_log("INFO", format!("Created branch: {}", generated_branch_name));
             current_branch = generated_branch_name;
             // Cache response - branch diff will be same as uncommitted diff
-            cached_gpt_response = Some((commit_title.clone(), commit_details.clone()));
+            cached_gpt_response = Some(generated);
             terminal.draw(|f| ui(f, app))?;
         }
 
+        app.set_stage(Stage::Commit);
+        refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+        if amend {
+            git_amend_last_commit(app, &commit_title, &commit_details, &trailers)
+                .fail_as(Failure::Git)?;
+        } else {
+            git_stage_and_commit(
+                app,
+                &commit_title,
+                &commit_details,
+                config.author.as_deref(),
+                &trailers,
+            )
+            .fail_as(Failure::Git)?;
+        }
+        update_status(app, &current_branch);
         refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
-        git_stage_and_commit(app, &commit_title, &commit_details)?;
+    }
+
+    let rebased = config.rebase_onto_base && current_branch != *main_branch;
+    if rebased {
+        git_rebase_onto_base(app, &base_branch).fail_as(Failure::Git)?;
+        // The rebase fetched the base, which may have moved past the local one
+        base_ref = resolve_base_ref(&app.remote, &base_branch);
         refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    } else if config.rebase_onto_base {
+        app.add_log(
+            "WARN",
+            "--rebase-onto-base ignored: not on a feature branch",
+        );
     }
 
     // Get diff between current branch and base
-    let diff_between_branches = git_diff_between_branches(app, &base_branch, &current_branch)?;
+    app.set_stage(Stage::DiffBranches);
+    let diff_between_branches =
+        branch_diff(app, &config, &base_ref, &current_branch).fail_as(Failure::Git)?;
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
     if diff_between_branches.is_empty() {
         app.add_log("INFO", "No changes between branches.");
         terminal.draw(|f| ui(f, app))?;
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        linger(app).await;
         return Ok(());
     }
 
     // External diff review + prep gate before any push/PR creation
     // CLI flags override user-level config; config values run automatically when set.
     let review_enabled = app_config.review_enabled();
-    let effective_review_command = if review_enabled {
+    let effective_review_command = if app.dry_run {
+        // The reviewer may amend commits, which --dry-run must not do
+        app.add_log("INFO", "--dry-run: skipping external review.");
+        None
+    } else if review_enabled {
         config
             .review_command
             .clone()
@@ -380,7 +1850,7 @@ where
             review_command: effective_review_command,
             max_rounds: effective_review_max_rounds,
         },
-        &base_branch,
+        &base_ref,
         &current_branch,
         diff_between_branches.clone(),
     )?;
@@ -397,7 +1867,7 @@ where
                 "INFO",
                 "Review blocked PR submission as not worth submitting.",
             );
-            app.update_progress(1.0);
+            app.set_stage(Stage::Done);
             refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
             run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
             return Ok(());
@@ -411,7 +1881,7 @@ where
             for q in &review_result.questions {
                 app.add_log("INFO", format!("Question: {}", q));
             }
-            app.update_progress(1.0);
+            app.set_stage(Stage::Done);
             refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
             run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
             return Ok(());
@@ -430,41 +1900,214 @@ where
 
     // Re-read final diff in case autonomous prep amended the commit.
     let final_diff_between_branches =
-        git_diff_between_branches(app, &base_branch, &current_branch)?;
+        branch_diff(app, &config, &base_ref, &current_branch).fail_as(Failure::Git)?;
+    let final_diff_size = diff_size(&final_diff_between_branches);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
-    // Get PR title/body (reuse cached or generate new)
-    let (pr_title, pr_body) = match cached_gpt_response {
-        Some((title, details)) if final_diff_between_branches == diff_between_branches => {
-            app.add_log("INFO", "Reusing generated content for PR...");
-            app.update_progress(0.5);
-            refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
-            (title, details)
-        }
-        _ => {
-            app.add_log("INFO", "Generating PR details...");
-            app.update_progress(0.5);
-            refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    // Under --dry-run a new branch was never created; its commits would sit on HEAD
+    let head_ref = if app.dry_run && !local_branch_exists(&current_branch).fail_as(Failure::Git)? {
+        "HEAD".to_string()
+    } else {
+        current_branch.clone()
+    };
 
-            let (_, title, details) = gpt_generate_branch_name_and_commit_description(
-                app,
-                &app_config,
-                final_diff_between_branches,
-                Some(issues_json),
-                config.what,
-                config.why,
-                config.bigger_picture,
-            )
-            .await?;
-            (title, details)
+    // Commit messages for --body-from-commits
+    let commits = match config.body_from_commits {
+        Some(_) => git_branch_commits(app, &base_ref, &head_ref).fail_as(Failure::Git)?,
+        None => vec![],
+    };
+    let synthesize_from_commits =
+        config.body_from_commits == Some(CommitBodyMode::Synthesize) && !commits.is_empty();
+
+    // Get PR title/body (from commits with --fill, else reuse cached or generate new)
+    let mut pr_labels = config.labels.clone();
+    let mut readiness = (None, None);
+    let (pr_title, mut pr_body) = if config.fill {
+        let commits = git_branch_commits(app, &base_ref, &head_ref).fail_as(Failure::Git)?;
+        match fill_pr_from_commits(&commits) {
+            Some(filled) => filled,
+            None => {
+                app.add_error(format!(
+                    "No commits on {} to fill the PR from",
+                    current_branch
+                ));
+                return Err(Failure::Usage.error("No commits to fill the PR from"));
+            }
+        }
+    } else {
+        let generated = match cached_gpt_response {
+            Some(generated)
+                if !synthesize_from_commits
+                    && final_diff_between_branches == diff_between_branches =>
+            {
+                app.add_log("INFO", "Reusing generated content for PR...");
+                app.set_stage(Stage::GeneratePr);
+                refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+                generated
+            }
+            _ if config.offline => {
+                app.set_stage(Stage::GeneratePr);
+                let context = model_input_diff(app, &config, final_diff_between_branches);
+                heuristic_commit_message(&context)
+            }
+            _ => {
+                app.add_log("INFO", "Generating PR details...");
+                app.set_stage(Stage::GeneratePr);
+                refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+
+                let mut context = model_input_diff(app, &config, final_diff_between_branches);
+                if synthesize_from_commits {
+                    let instruction =
+                        "Write the PR description by synthesizing these commit messages:";
+                    context = format!(
+                        "{}\n{}\n\nDiff:\n{}",
+                        instruction,
+                        format_commits_as_pr_body(&commits),
+                        context
+                    );
+                }
+                let (plain, preview) = (app.plain, app.streaming_preview.clone());
+                with_streaming_preview(
+                    terminal,
+                    plain,
+                    &preview,
+                    tick_rate,
+                    gpt_generate_branch_name_and_commit_description(
+                        app,
+                        &app_config,
+                        context,
+                        Some(issues_json),
+                        &hints,
+                    ),
+                )
+                .await??
+            }
+        };
+        for label in generated.labels {
+            if !pr_labels.contains(&label) {
+                pr_labels.push(label);
+            }
         }
+        readiness = (generated.ready_for_review, generated.readiness_reason);
+        (generated.commit_title, generated.commit_details)
     };
 
+    if config.body_from_commits == Some(CommitBodyMode::List) && !commits.is_empty() {
+        app.add_log("INFO", "Using branch commit messages as PR body");
+        pr_body = Some(format_commits_as_pr_body(&commits));
+    }
+
+    if let Some(items) = &checklist_items {
+        pr_body = Some(append_checklist(
+            pr_body.as_deref().unwrap_or_default(),
+            items,
+        ));
+    }
+
+    if config.diffstat_in_body {
+        let stat = git_diff_stat(app, &base_ref, &head_ref).fail_as(Failure::Git)?;
+        pr_body = Some(with_diffstat_block(
+            pr_body.as_deref().unwrap_or_default(),
+            &stat,
+        ));
+    }
+
+    let mut pr_title = pr_title;
+    if let Some(pattern) = &config.ticket_pattern {
+        match ticket_from_branch(&branch_info.current_branch, pattern) {
+            Some(ticket) => pr_title = with_ticket_prefix(&pr_title, &ticket),
+            None => app.add_log(
+                "INFO",
+                format!(
+                    "--ticket-from-branch: no ticket in branch {}",
+                    branch_info.current_branch
+                ),
+            ),
+        }
+    }
+    if let Some(max) = config.max_title_length {
+        pr_title = truncate_title(&pr_title, max);
+    }
+
     app.add_log("INFO", format!("PR title: {}", pr_title));
+    if !pr_labels.is_empty() {
+        app.add_log("INFO", format!("PR labels: {}", pr_labels.join(", ")));
+    }
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
+    let mut ready = config.ready;
+    if config.auto_ready {
+        let (ready_for_review, reason) = readiness;
+        ready = ready_for_review.unwrap_or(false);
+        let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+        app.add_log(
+            "INFO",
+            match ready_for_review {
+                Some(true) => format!(
+                    "--auto-ready: AI judged the PR ready for review ({})",
+                    reason
+                ),
+                Some(false) => format!("--auto-ready: AI judged the PR a draft ({})", reason),
+                None => {
+                    "--auto-ready: AI gave no readiness judgment, creating as draft".to_string()
+                }
+            },
+        );
+    }
+    if let Some(limit) = config.auto_draft_over {
+        let size = final_diff_size;
+        if size.files > limit || size.changed_lines > limit {
+            app.add_log(
+                "INFO",
+                format!(
+                    "PR changes {} lines in {} files (over --auto-draft-over {}), creating as draft",
+                    size.changed_lines, size.files, limit
+                ),
+            );
+            ready = false;
+        } else {
+            app.add_log(
+                "INFO",
+                format!(
+                    "PR changes {} lines in {} files (within --auto-draft-over {})",
+                    size.changed_lines, size.files, limit
+                ),
+            );
+        }
+    }
+
+    let changed_files = git_diff_stat(app, &base_ref, &head_ref).fail_as(Failure::Git)?;
+    let confirmed = confirm_submit(
+        terminal,
+        app,
+        &SubmitPreview {
+            branch: &current_branch,
+            base: &base_branch,
+            title: &pr_title,
+            body: pr_body.as_deref().unwrap_or_default(),
+            changed_files: &changed_files,
+        },
+    )?;
+    if !confirmed {
+        // The commit stays on its branch; dropping `temp_worktree` removes the worktree
+        app.add_log(
+            "WARN",
+            format!(
+                "Stopped before pushing: the commit is on local branch {}, nothing was pushed",
+                current_branch
+            ),
+        );
+        app.set_stage(Stage::Done);
+        refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+        return Ok(());
+    }
+
     // Push branch (may rename if remote conflict)
-    current_branch = git_push_branch(app, &current_branch)?;
+    app.set_stage(Stage::Push);
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    current_branch =
+        git_push_branch(app, &current_branch, rebased || amend).fail_as(Failure::Git)?;
+    update_status(app, &current_branch);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
     // Create or update PR
@@ -476,103 +2119,604 @@ where
             "Creating PR..."
         },
     );
-    app.update_progress(0.8);
+    app.set_stage(Stage::CreatePr);
+    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+
+    let (head_repo, base_repo) = resolve_fork_target(app, &config, forge_kind);
+    let pr_body = pr_body.unwrap_or_default();
+    let pr = forge
+        .create_or_update_pr(
+            app,
+            &pr_title,
+            &pr_body,
+            config.update_pr,
+            &base_branch,
+            &current_branch,
+            &PullRequestOptions {
+                ready,
+                sync_draft: config.sync_draft,
+                reviewers: config.reviewers.clone(),
+                assignees: config.assignees.clone(),
+                labels: pr_labels,
+                head_repo,
+                base_repo,
+            },
+        )
+        .fail_as(Failure::Forge)?;
+    app.status.pr_url = Some(pr.url.clone());
+    app.pr_body = Some(pr_body);
+
+    if app.dry_run {
+        app.add_log(
+            "SUCCESS",
+            "Dry run complete: no branch was changed, pushed or opened as a PR",
+        );
+    } else {
+        app.add_log(
+            "SUCCESS",
+            format!("Pull request #{} created/updated successfully!", pr.number),
+        );
+    }
+    app.set_stage(Stage::Done);
     refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
 
-    create_or_update_pull_request(
+    // Hand the user's worktree back before a CI wait that can take many minutes
+    if !switch_original_worktree(
+        terminal,
         app,
-        &pr_title,
-        &pr_body.unwrap_or_default(),
-        config.update_pr,
-        config.ready,
-        &base_branch,
+        tick_rate,
+        &mut last_tick,
+        temp_worktree,
         &current_branch,
-    )?;
+    )
+    .fail_as(Failure::Git)?
+    {
+        return Ok(());
+    }
 
-    app.add_log("SUCCESS", "Pull request created/updated successfully!");
-    app.update_progress(1.0);
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    let checks_outcome = match config.wait_for_checks {
+        Some(_) if app.dry_run => {
+            app.add_log("INFO", "--dry-run: not waiting for PR checks");
+            None
+        }
+        Some(_) if forge_kind != ForgeKind::Github => {
+            app.add_log(
+                "WARN",
+                "--wait-for-checks ignored: only supported on GitHub",
+            );
+            None
+        }
+        Some(timeout_secs) => {
+            // `q` was pressed to finish the cleanup; it now stops the wait
+            app.should_quit = false;
+            let outcome = wait_for_checks(
+                terminal,
+                app,
+                tick_rate,
+                &mut last_tick,
+                pr.number,
+                Duration::from_secs(timeout_secs),
+            )
+            .fail_as(Failure::Forge)?;
+            run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
+            Some(outcome)
+        }
+        None => None,
+    };
+
+    match checks_outcome {
+        Some(ChecksOutcome::Failed) => {
+            Err(Failure::Forge.error(format!("CI checks failed on PR #{}", pr.number)))
+        }
+        Some(ChecksOutcome::Pending) => {
+            Err(Failure::Forge.error(format!("CI checks on PR #{} did not settle", pr.number)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `--stack`: commit the uncommitted changes as a chain of branches, one per
+/// group the AI proposes, and open each as a PR into the one before it (the
+/// first into `base_branch`). Returns the top branch of the stack.
+#[allow(clippy::too_many_arguments)]
+async fn run_stack<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App<'_>,
+    tick_rate: Duration,
+    last_tick: &mut Instant,
+    config: &RunConfig,
+    app_config: &AppConfig,
+    forge_kind: ForgeKind,
+    base_branch: &str,
+    current_branch: &str,
+    diff_uncommitted: String,
+) -> Result<String, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    if diff_uncommitted.is_empty() {
+        let msg = "--stack splits uncommitted changes, and there are none";
+        app.add_error(msg);
+        return Err(Failure::Usage.error(msg));
+    }
+    let trailers = config.commit_trailers().fail_as(Failure::Usage)?;
+    let forge = forge_kind.forge();
+    let paths = git_uncommitted_paths(app).fail_as(Failure::Git)?;
+    let renames = git_uncommitted_renames(app).fail_as(Failure::Git)?;
+    app.update_details(diff_uncommitted.clone());
+    app.add_log("INFO", "Planning the stack of PRs...");
+    app.set_stage(Stage::GenerateBranch);
+    refresh_ui(terminal, app, tick_rate, last_tick)?;
+
+    let context = model_input_diff(app, config, diff_uncommitted);
+    let (plain, preview) = (app.plain, app.streaming_preview.clone());
+    let groups = with_streaming_preview(
+        terminal,
+        plain,
+        &preview,
+        tick_rate,
+        gpt_plan_stack(
+            app,
+            app_config,
+            context,
+            &paths,
+            &config.prompt_hints(vec![]),
+        ),
+    )
+    .await??;
+
+    let assignment = assign_stack_files(&groups, &paths, &renames);
+    for (path, matching) in &assignment.shared {
+        let numbers: Vec<String> = matching.iter().map(|i| (i + 1).to_string()).collect();
+        app.add_log(
+            "WARN",
+            format!(
+                "{} matches stack groups {}; committing it with group {}",
+                path,
+                numbers.join(", "),
+                matching[0] + 1
+            ),
+        );
+    }
+    for path in &assignment.unmatched {
+        app.add_log(
+            "WARN",
+            format!(
+                "{} matches no stack group; committing it with the last one",
+                path
+            ),
+        );
+    }
+
+    if app_config.review_enabled()
+        && (config.review_command.is_some() || app_config.review_command().is_some())
+    {
+        app.add_log("WARN", "External review is not run for --stack");
+    }
+    let (head_repo, base_repo) = resolve_fork_target(app, config, forge_kind);
+    let options = PullRequestOptions {
+        ready: config.ready,
+        sync_draft: false,
+        reviewers: config.reviewers.clone(),
+        assignees: config.assignees.clone(),
+        labels: config.labels.clone(),
+        head_repo,
+        base_repo,
+    };
+
+    let planned: Vec<(&StackGroup, &Vec<String>)> = groups
+        .iter()
+        .zip(&assignment.files)
+        .filter(|(group, files)| {
+            if files.is_empty() {
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "Stack group {} has no files; skipping it",
+                        group.branch_name
+                    ),
+                );
+            }
+            !files.is_empty()
+        })
+        .collect();
+    let mut head = current_branch.to_string();
+    let mut pr_base = base_branch.to_string();
+    // One commit, push and PR per group; the log says which group is at which step
+    app.set_stage(Stage::Commit);
+    for (i, (group, files)) in planned.iter().enumerate() {
+        let position = format!("{}/{}", i + 1, planned.len());
+        let branch_name = if config.type_prefixed_branch {
+            type_prefixed_branch(&group.branch_name, &group.commit_title)
+        } else {
+            group.branch_name.clone()
+        };
+        let branch =
+            git_checkout_new_branch(app, &branch_name, &head, None).fail_as(Failure::Git)?;
+        app.add_log(
+            "INFO",
+            format!("Stack {}: {} ({} files)", position, branch, files.len()),
+        );
+        git_commit_paths(
+            app,
+            files,
+            &group.commit_title,
+            &group.commit_details,
+            config.author.as_deref(),
+            &trailers,
+        )
+        .fail_as(Failure::Git)?;
+        update_status(app, &branch);
+        refresh_ui(terminal, app, tick_rate, last_tick)?;
+
+        let branch = git_push_branch(app, &branch, false).fail_as(Failure::Git)?;
+        let pr = forge
+            .create_or_update_pr(
+                app,
+                &group.commit_title,
+                group.commit_details.as_deref().unwrap_or_default(),
+                false,
+                &pr_base,
+                &branch,
+                &options,
+            )
+            .fail_as(Failure::Forge)?;
+        app.add_log(
+            "SUCCESS",
+            format!(
+                "Stack {}: PR #{} {} into {}",
+                position, pr.number, branch, pr_base
+            ),
+        );
+        app.status.pr_url = Some(pr.url);
+        app.pr_body = group.commit_details.clone();
+        refresh_ui(terminal, app, tick_rate, last_tick)?;
+        pr_base = branch.clone();
+        head = branch;
+    }
+    app.set_stage(Stage::Done);
+    Ok(head)
+}
 
-    // Cleanup: update original worktree to PR branch
-    let pr_branch = current_branch.clone();
+/// Cleanup: drop the temp worktree and, once confirmed, move the original
+/// worktree to `pr_branch`. Returns false if the user kept it untouched.
+fn switch_original_worktree<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    tick_rate: Duration,
+    last_tick: &mut Instant,
+    temp_worktree: TempWorktree,
+    pr_branch: &str,
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
     let orig_root = temp_worktree.original_root().clone();
     let had_staged = temp_worktree.had_staged_changes();
 
     app.add_log("INFO", "Switching original worktree to PR branch...");
-    refresh_ui(terminal, app, tick_rate, &mut last_tick)?;
+    refresh_ui(terminal, app, tick_rate, last_tick)?;
 
     // Wait for user before cleanup
-    run_event_loop(terminal, app, tick_rate, &mut last_tick)?;
+    run_event_loop(terminal, app, tick_rate, last_tick)?;
 
     // Drop temp worktree, then update original
     std::mem::drop(temp_worktree);
-    update_original_worktree_to_pr_branch(app, &pr_branch, &orig_root, had_staged)?;
 
-    Ok(())
+    let discards = {
+        let _cwd = CwdGuard::change_to(&orig_root)?;
+        pending_worktree_discards(had_staged, &app.paths)?
+    };
+    let action = if had_staged {
+        "Discard staged changes in the original worktree (git reset --hard) and switch to the PR branch"
+    } else {
+        "Discard all local changes in the original worktree (git reset --hard, git clean -fd) and switch to the PR branch"
+    };
+    if !confirm_destructive(terminal, app, action, &discards)? {
+        app.add_log(
+            "WARN",
+            format!(
+                "Left original worktree untouched; the changes are on PR branch {}",
+                pr_branch
+            ),
+        );
+        return Ok(false);
+    }
+    update_original_worktree_to_pr_branch(app, pr_branch, &orig_root, had_staged)?;
+    Ok(true)
+}
+
+/// Refresh the branch and changed-file counts on the Status tab.
+fn update_status(app: &mut App, current_branch: &str) {
+    app.status.current_branch = Some(current_branch.to_string());
+    app.status.changed_files = git_changed_file_counts().ok();
+}
+
+/// The `(head_repo, base_repo)` for a PR from a fork: `--head-repo` wins, else
+/// whether `app.remote` is a fork on GitHub. When that can't be told, both stay
+/// unset and gh opens the PR in its default repository.
+fn resolve_fork_target(
+    app: &mut App,
+    config: &RunConfig,
+    forge_kind: ForgeKind,
+) -> (Option<String>, Option<String>) {
+    if forge_kind != ForgeKind::Github {
+        if config.head_repo.is_some() {
+            app.add_log("WARN", "--head-repo ignored: only supported on GitHub");
+        }
+        return (None, None);
+    }
+    match (config.head_repo.clone(), github_fork_target(app)) {
+        (Some(head_repo), Ok(Some(target))) => (Some(head_repo), Some(target.base_repo)),
+        (Some(head_repo), _) => (Some(head_repo), None),
+        (None, Ok(Some(target))) => (Some(target.head_repo), Some(target.base_repo)),
+        (None, Ok(None)) => (None, None),
+        (None, Err(e)) => {
+            app.add_log(
+                "WARN",
+                format!(
+                    "Could not tell whether {} is a fork ({}); opening the PR in gh's default repository",
+                    app.remote, e
+                ),
+            );
+            (None, None)
+        }
+    }
 }
 
-/// Resolve the API key into `app_config.ai.api_key`, using keyring as a fallback.
-/// The config has already applied env var overrides at load time; this only adds keyring lookup.
-async fn get_api_key<B: Backend>(
+/// Poll the PR's CI checks until they settle, `timeout` passes, or the user
+/// presses q / Ctrl+C (which stops waiting but still lets cleanup run).
+fn wait_for_checks<B: Backend>(
+    terminal: &mut Terminal<B>,
     app: &mut App<'_>,
+    tick_rate: Duration,
+    last_tick: &mut Instant,
+    pr_number: u64,
+    timeout: Duration,
+) -> Result<ChecksOutcome, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+    app.add_log(
+        "INFO",
+        format!(
+            "Waiting up to {}s for CI checks on PR #{} (q to stop waiting)...",
+            timeout.as_secs(),
+            pr_number
+        ),
+    );
+    let started = Instant::now();
+    let mut outcome = ChecksOutcome::Pending;
+
+    loop {
+        let checks = github_pr_checks(app, pr_number)?;
+        outcome = match ChecksOutcome::from_checks(&checks) {
+            // Checks may not be registered yet right after the PR is opened
+            ChecksOutcome::NoChecks if started.elapsed() < POLL_INTERVAL => outcome,
+            o => o,
+        };
+
+        let table: Vec<String> = checks
+            .iter()
+            .map(|c| format!("{:<8} {}", c.bucket, c.name))
+            .collect();
+        app.update_details(format!(
+            "CI checks for PR #{}: {:?}\n\n{}",
+            pr_number,
+            outcome,
+            table.join("\n")
+        ));
+
+        match outcome {
+            ChecksOutcome::NoChecks => {
+                app.add_log("INFO", "No CI checks reported for this PR");
+                return Ok(outcome);
+            }
+            ChecksOutcome::Passed => {
+                app.add_log("SUCCESS", format!("All {} CI checks passed", checks.len()));
+                return Ok(outcome);
+            }
+            ChecksOutcome::Failed => {
+                app.add_error(format!("CI checks failed on PR #{}", pr_number));
+                return Ok(outcome);
+            }
+            ChecksOutcome::Pending => {}
+        }
+
+        if started.elapsed() >= timeout {
+            app.add_log("WARN", "Timed out waiting for CI checks");
+            return Ok(outcome);
+        }
+
+        // Keep the UI responsive between polls
+        if !idle_until(
+            terminal,
+            app,
+            tick_rate,
+            last_tick,
+            Instant::now() + POLL_INTERVAL,
+        ) {
+            app.add_log("WARN", "Stopped waiting for CI checks");
+            return Ok(outcome);
+        }
+    }
+}
+
+/// Refresh the UI every tick until `deadline`; `false` when the user quit or
+/// was interrupted first. Plain mode has no `event::poll` to block on, so it
+/// sleeps out each tick instead.
+fn idle_until<B: Backend>(
     terminal: &mut Terminal<B>,
-    app_config: &mut AppConfig,
-) -> Result<(), Box<dyn std::error::Error>>
+    app: &mut App<'_>,
+    tick_rate: Duration,
+    last_tick: &mut Instant,
+    deadline: Instant,
+) -> bool
 where
     <B as Backend>::Error: 'static,
 {
-    if app_config.ai.api_key.is_some() {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        let interrupted = refresh_ui(terminal, app, tick_rate, last_tick).is_err();
+        if interrupted || app.should_quit {
+            return false;
+        }
+        if app.plain {
+            std::thread::sleep(tick_rate.min(deadline.saturating_duration_since(now)));
+        }
+    }
+}
+
+/// Built-in template variables (`branch`, `base`, `author`, `date`), then `--var`
+/// values, which take precedence.
+fn template_vars(
+    config: &RunConfig,
+    branch: &str,
+    base: &str,
+) -> std::collections::HashMap<String, String> {
+    let author = config
+        .author
+        .clone()
+        .unwrap_or_else(|| git_user_name().unwrap_or_default());
+    let mut vars = std::collections::HashMap::from([
+        ("branch".to_string(), branch.to_string()),
+        ("base".to_string(), base.to_string()),
+        ("author".to_string(), author),
+        ("date".to_string(), today_utc()),
+    ]);
+    vars.extend(config.vars.iter().cloned());
+    vars
+}
+
+/// Diff of the current branch against its base, honoring `--fork-point` and `--since-commit`.
+fn branch_diff(
+    app: &mut App<'_>,
+    config: &RunConfig,
+    base_branch: &str,
+    current_branch: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if app.dry_run && !local_branch_exists(current_branch)? {
+        // The branch would have been created from the uncommitted changes
+        return git_diff_uncommitted(app, base_branch);
+    }
+    if let Some(rev) = &config.since_commit {
+        git_diff_between_branches(app, rev, current_branch)
+    } else if config.fork_point {
+        git_diff_since_fork_point(app, base_branch, current_branch)
+    } else {
+        git_diff_between_branches(app, base_branch, current_branch)
+    }
+}
+
+/// Diff as sent to the AI. The full diff stays in the Details tab; only the model
+/// input drops oversized files (`--max-file-diff-bytes`) and context lines
+/// (`--strip-context`).
+fn model_input_diff(app: &mut App<'_>, config: &RunConfig, diff: String) -> String {
+    let diff = match config.max_file_diff_bytes {
+        Some(max_bytes) => {
+            let (kept, omitted) = drop_oversized_file_diffs(&diff, max_bytes);
+            if !omitted.is_empty() {
+                app.add_log(
+                    "WARN",
+                    format!(
+                        "Omitted {} oversized file diff(s) from AI context: {}",
+                        omitted.len(),
+                        omitted.join(", ")
+                    ),
+                );
+            }
+            kept
+        }
+        None => diff,
+    };
+    if config.strip_context {
+        strip_diff_context(&diff)
+    } else {
+        diff
+    }
+}
+
+/// Resolve the API key into `app_config.ai.api_key` (see `AppConfig::resolve_api_key`
+/// for the precedence). Without a key, the error says where one can be set.
+fn get_api_key(app: &mut App<'_>, app_config: &mut AppConfig) -> Result<(), String> {
+    let keyring = keyring_lookup(app_config);
+    if let Some(source) = app_config.resolve_api_key(keyring) {
         app.add_log(
             "INFO",
-            format!(
-                "Found {} API key in config/environment",
-                app_config.provider()
-            ),
+            format!("Found {} API key in {}", app_config.provider(), source),
         );
         return Ok(());
     }
 
-    let keyring_account = match app_config.provider() {
-        "anthropic" => "anthropic_key",
-        _ => "openai_key",
-    };
+    let provider = app_config.provider();
+    Err(format!(
+        "{} API key not found. Set it via `api_key` in ~/.config/gh-autopr/config.toml, \
+         the system keyring (service {:?}, account {:?}), or the {} env var",
+        provider,
+        app_config.keyring_service(),
+        app_config.keyring_account(),
+        app_config.api_key_env_vars().join(" or ")
+    ))
+}
 
-    if let Ok(entry) = keyring::Entry::new("gh-autopr", keyring_account) {
-        if let Ok(key) = entry.get_password() {
-            app.add_log("INFO", "Found API key in keyring");
-            app_config.ai.api_key = Some(key);
-            return Ok(());
-        }
+/// Reads the API key from the system keyring, for `AppConfig::resolve_api_key`.
+fn keyring_lookup(app_config: &AppConfig) -> impl FnOnce() -> Option<String> {
+    let (service, account) = (app_config.keyring_service(), app_config.keyring_account());
+    move || {
+        keyring::Entry::new(&service, &account)
+            .ok()?
+            .get_password()
+            .ok()
+    }
+}
 
-        // Cache env var into keyring for next run
-        let env_key = match app_config.provider() {
-            "anthropic" => std::env::var("ANTHROPIC_API_KEY").ok(),
-            _ => std::env::var("OPENAI_KEY")
-                .or_else(|_| std::env::var("OPENAI_API_KEY"))
-                .ok(),
-        };
-        if let Some(key) = env_key {
-            app.add_log("INFO", "Found API key in environment, storing in keyring");
-            let _ = entry.set_password(&key);
-            app_config.ai.api_key = Some(key);
-            return Ok(());
+/// Whether `--amend` may rewrite the last commit of `branch`; logs why not, in
+/// which case the changes get a commit of their own.
+fn amend_allowed(
+    app: &mut App<'_>,
+    forge: &dyn Forge,
+    config: &RunConfig,
+    base_branch: &str,
+    branch: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut blocker = git_amend_blocker(base_branch, config.force_amend).fail_as(Failure::Git)?;
+    if blocker.is_none()
+        && !config.force_amend
+        && forge
+            .open_pr_is_reviewed(app, branch)
+            .fail_as(Failure::Forge)?
+    {
+        blocker = Some(format!(
+            "the {} PR already has reviews (--force-amend amends anyway)",
+            forge.name()
+        ));
+    }
+    match blocker {
+        Some(reason) => {
+            app.add_log(
+                "WARN",
+                format!("--amend skipped: {}; committing on top instead", reason),
+            );
+            Ok(false)
+        }
+        None => {
+            app.add_log("INFO", "--amend: folding the changes into the last commit");
+            Ok(true)
         }
     }
+}
 
-    let provider = app_config.provider().to_string();
-    app.add_error(format!(
-        "{} API key not found. Set it via `api_key` in ~/.config/gh-autopr/config.toml, \
-         the {} env var, or the system keyring.",
-        provider,
-        match provider.as_str() {
-            "anthropic" => "ANTHROPIC_API_KEY",
-            _ => "OPENAI_KEY",
-        }
-    ));
-    app.switch_to_tab(1);
-    terminal.draw(|f| ui(f, app))?;
-    tokio::time::sleep(Duration::from_secs(2)).await;
-    Err(format!("{} API key not found", provider).into())
+/// Leave the last message on screen for a moment before moving on; skipped
+/// when nobody is watching.
+async fn linger(app: &App<'_>) {
+    if !(app.plain || app.non_interactive) {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
 }
 
 fn run_event_loop<B: Backend>(
@@ -584,6 +2728,11 @@ fn run_event_loop<B: Backend>(
 where
     <B as Backend>::Error: 'static,
 {
+    // Nobody is watching to press 'q' in plain mode
+    if app.plain || app.non_interactive {
+        app.should_quit = true;
+        return Ok(());
+    }
     loop {
         refresh_ui(terminal, app, tick_rate, last_tick)?;
         if app.should_quit {
@@ -592,6 +2741,38 @@ where
     }
 }
 
+/// Await an AI `request`, redrawing the model output it streams into `preview`
+/// every tick. The request borrows the `App`, so the usual UI is redrawn by the
+/// next `refresh_ui` once it completes.
+async fn with_streaming_preview<B: Backend, T>(
+    terminal: &mut Terminal<B>,
+    plain: bool,
+    preview: &Mutex<String>,
+    tick_rate: Duration,
+    request: impl std::future::Future<Output = T>,
+) -> Result<T, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    tokio::pin!(request);
+    let mut ticks = tokio::time::interval(tick_rate);
+    loop {
+        tokio::select! {
+            result = &mut request => return Ok(result),
+            _ = ticks.tick() => {
+                check_interrupted()?;
+                if plain {
+                    continue;
+                }
+                let text = preview.lock().map(|p| p.clone()).unwrap_or_default();
+                if !text.is_empty() {
+                    render_streaming_preview(terminal, &text)?;
+                }
+            }
+        }
+    }
+}
+
 /// Draws the UI and checks for user input events.
 /// This is the main UI refresh function that should be called after state changes.
 fn refresh_ui<B: Backend>(
@@ -603,24 +2784,39 @@ fn refresh_ui<B: Backend>(
 where
     <B as Backend>::Error: 'static,
 {
+    if let Err(e) = check_interrupted() {
+        app.should_quit = true;
+        return Err(e);
+    }
+    if app.plain {
+        return Ok(());
+    }
     terminal.draw(|f| ui(f, app))?;
 
     let timeout = tick_rate.saturating_sub(last_tick.elapsed());
     if event::poll(timeout)? {
-        if let Event::Key(key) = event::read()? {
-            if key.kind == KeyEventKind::Press {
-                match key.code {
-                    KeyCode::Left => app.on_left(),
-                    KeyCode::Right => app.on_right(),
-                    KeyCode::Char('q') => app.should_quit = true,
-                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        eprintln!("Ctrl+C detected. Reverting repository to original state...");
-                        app.should_quit = true;
-                        return Err("Interrupted by user".into());
-                    }
-                    _ => {}
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Left => app.on_left(),
+                KeyCode::Right => app.on_right(),
+                KeyCode::Up => app.scroll_up(1),
+                KeyCode::Down => app.scroll_down(1),
+                KeyCode::PageUp => app.scroll_up(app.viewport_lines),
+                KeyCode::PageDown => app.scroll_down(app.viewport_lines),
+                KeyCode::Home => app.scroll_up(usize::MAX),
+                KeyCode::End => app.scroll_to_bottom(),
+                KeyCode::Char('q') => app.should_quit = true,
+                KeyCode::Char('y') => app.copy_pr_url(),
+                KeyCode::Char('Y') => app.copy_pr_body(),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    eprintln!("Ctrl+C detected. Reverting repository to original state...");
+                    app.should_quit = true;
+                    return Err(Failure::Interrupted.error("Interrupted by user"));
                 }
-            }
+                _ => {}
+            },
+            Event::Mouse(mouse) => app.on_mouse(mouse),
+            _ => {}
         }
     }
 
@@ -630,3 +2826,154 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn non_interactive_never_waits_for_input() {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut app = App::new("test");
+        app.non_interactive = true;
+        let started = Instant::now();
+
+        let tick_rate = Duration::from_secs(5);
+        run_event_loop(&mut terminal, &mut app, tick_rate, &mut Instant::now()).unwrap();
+        assert!(app.should_quit);
+        linger(&app).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn plain_idle_sleeps_between_ticks() {
+        // Nanoseconds this thread has spent on a CPU
+        let cpu_time = || -> u64 {
+            let stat = fs_err::read_to_string("/proc/thread-self/schedstat").unwrap();
+            stat.split_whitespace().next().unwrap().parse().unwrap()
+        };
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut app = App::new("test");
+        app.plain = true;
+        let tick_rate = Duration::from_millis(50);
+
+        let (started, cpu_before) = (Instant::now(), cpu_time());
+        let deadline = started + Duration::from_millis(500);
+        assert!(idle_until(
+            &mut terminal,
+            &mut app,
+            tick_rate,
+            &mut Instant::now(),
+            deadline
+        ));
+        let busy = Duration::from_nanos(cpu_time() - cpu_before);
+
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        assert!(busy < Duration::from_millis(100), "spun for {:?}", busy);
+    }
+
+    #[test]
+    fn get_key_without_a_stored_key_says_how_to_set_one() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let app_config = AppConfig::default();
+        let entry = keyring::Entry::new("gh-autopr-test", "openai_key").unwrap();
+
+        let err = stored_key_preview(&entry, &app_config).unwrap_err();
+        assert!(
+            err.starts_with("No openai API key in the keyring"),
+            "{}",
+            err
+        );
+        assert!(err.contains("gh-autopr config set-key"), "{}", err);
+
+        entry.set_password("sk-proj-abcdefghijklmnop1234").unwrap();
+        let preview = stored_key_preview(&entry, &app_config).unwrap();
+        assert!(preview.starts_with("sk-…1234 ("), "{}", preview);
+    }
+
+    #[test]
+    fn co_authors_become_trailers_and_are_validated() {
+        let cli = Cli::try_parse_from([
+            "gh-autopr",
+            "--co-author",
+            "Pat Doe <pat@example.com>",
+            "--co-author",
+            "Sam Roe <sam@example.com>",
+        ])
+        .unwrap();
+        let config = cli.create.run_config(&AppConfig::default());
+        assert_eq!(
+            config.commit_trailers().unwrap(),
+            vec![
+                "Co-authored-by: Pat Doe <pat@example.com>",
+                "Co-authored-by: Sam Roe <sam@example.com>",
+            ]
+        );
+
+        assert!(Cli::try_parse_from(["gh-autopr", "--co-author", "Pat Doe"]).is_err());
+        assert!(Cli::try_parse_from(["gh-autopr", "--co-author", "<pat@example.com>"]).is_err());
+    }
+
+    #[test]
+    fn ready_and_draft_conflict() {
+        let err = Cli::try_parse_from(["gh-autopr", "--ready", "--draft"])
+            .err()
+            .expect("--ready with --draft must be rejected");
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+
+        // Neither given: draft, and an updated PR keeps its draft state
+        let cli = Cli::try_parse_from(["gh-autopr", "--update-pr"]).unwrap();
+        let config = cli.create.run_config(&AppConfig::default());
+        assert!(!config.ready);
+        assert!(!config.sync_draft);
+
+        let cli = Cli::try_parse_from(["gh-autopr", "--update-pr", "--ready"]).unwrap();
+        let config = cli.create.run_config(&cli.create.app_config());
+        assert!(config.ready);
+        assert!(config.sync_draft);
+    }
+
+    #[test]
+    fn exit_code_maps_error_categories() {
+        let code = |stage, error: Box<dyn std::error::Error>| exit_code(stage, error.as_ref());
+        assert_eq!(
+            code("execution", Failure::Usage.error("API key not found")),
+            2
+        );
+        assert_eq!(code("execution", Failure::Git.error("push rejected")), 3);
+        assert_eq!(code("execution", Failure::Forge.error("HTTP 422")), 4);
+        assert_eq!(
+            code("execution", Failure::Interrupted.error("Interrupted")),
+            130
+        );
+        // Untagged: the setup stages only run git; anything else is generic
+        assert_eq!(
+            code("pre-worktree setup", "Not in a git repository".into()),
+            3
+        );
+        assert_eq!(
+            code("temp worktree setup", "failed to apply patch".into()),
+            3
+        );
+        assert_eq!(
+            code("execution", "OpenAI API error HTTP 500: oops".into()),
+            1
+        );
+        // Only the tag counts, not what the message mentions
+        assert_eq!(
+            code("execution", "Failed to push branch to the PR".into()),
+            1
+        );
+
+        // The innermost tag wins
+        let interrupted: Result<(), _> = Err(Failure::Interrupted.error("Interrupted"));
+        let error = interrupted.fail_as(Failure::Git).unwrap_err();
+        assert_eq!(code("execution", error), 130);
+        let error = Err::<(), _>("no such ref")
+            .fail_as(Failure::Git)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "no such ref");
+        assert_eq!(code("execution", error), 3);
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Substitute `{{name}}` placeholders in `template` from `vars`.
+///
+/// Unknown placeholders are left verbatim, unless `strict` is set, in which case
+/// every unresolved name is reported in the error.
+pub fn render_template(
+    template: &str,
+    vars: &HashMap<String, String>,
+    strict: bool,
+) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut unresolved = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            // Unterminated: keep the remainder as-is
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let name = after[..end].trim();
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => {
+                unresolved.push(name.to_string());
+                out.push_str(&rest[start..start + 2 + end + 2]);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+
+    if strict && !unresolved.is_empty() {
+        return Err(format!(
+            "Unresolved template variable(s): {}",
+            unresolved.join(", ")
+        ));
+    }
+    Ok(out)
+}
+
+/// Parse a `--var key=value` argument.
+pub fn parse_template_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected key=value, got \"{}\"", arg)),
+    }
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`, for the `{{date}}` built-in.
+pub fn today_utc() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `time` in UTC as RFC 3339 with milliseconds, e.g. `2026-10-15T09:04:05.123Z`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Where GitHub looks for a repository's default pull request template, in order.
+pub const PR_TEMPLATE_PATHS: [&str; 6] = [
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "pull_request_template.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+    "docs/PULL_REQUEST_TEMPLATE.md",
+];
+
+/// The default pull request template under `root`, if the repo has one.
+pub fn find_pr_template(root: &Path) -> Option<PathBuf> {
+    PR_TEMPLATE_PATHS
+        .iter()
+        .map(|path| root.join(path))
+        .find(|path| path.is_file())
+}
+
+/// The Markdown heading lines of `template`, skipping fenced code blocks.
+pub fn template_section_headings(template: &str) -> Vec<&str> {
+    let mut in_fence = false;
+    let mut headings = Vec::new();
+    for line in template.lines().map(str::trim) {
+        if line.starts_with("```") {
+            in_fence = !in_fence;
+        } else if !in_fence && line.starts_with('#') {
+            headings.push(line);
+        }
+    }
+    headings
+}
+
+/// `details` with every section heading of `template` it dropped appended,
+/// each marked TODO, so a filled template keeps all its required sections.
+pub fn ensure_template_sections(details: &str, template: &str) -> String {
+    let present: Vec<&str> = details.lines().map(str::trim).collect();
+    let mut out = details.trim().to_string();
+    for heading in template_section_headings(template) {
+        if !present.contains(&heading) {
+            if !out.is_empty() {
+                out.push_str("\n\n");
+            }
+            out.push_str(heading);
+            out.push_str("\n\nTODO");
+        }
+    }
+    out
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([
+            ("branch".to_string(), "feat/x".to_string()),
+            ("team".to_string(), "core".to_string()),
+        ])
+    }
+
+    #[test]
+    fn render_substitutes_known_vars() {
+        assert_eq!(
+            render_template("{{branch}} by {{ team }}", &vars(), true).unwrap(),
+            "feat/x by core"
+        );
+    }
+
+    #[test]
+    fn render_keeps_unknown_vars_unless_strict() {
+        assert_eq!(
+            render_template("{{branch}} {{ticket}}", &vars(), false).unwrap(),
+            "feat/x {{ticket}}"
+        );
+        let err = render_template("{{ticket}} {{owner}}", &vars(), true).unwrap_err();
+        assert!(err.contains("ticket, owner"));
+    }
+
+    #[test]
+    fn render_leaves_unterminated_placeholder() {
+        assert_eq!(
+            render_template("a {{branch", &vars(), true).unwrap(),
+            "a {{branch"
+        );
+    }
+
+    #[test]
+    fn parse_template_var_splits_on_first_equals() {
+        assert_eq!(
+            parse_template_var("url=https://x?a=b").unwrap(),
+            ("url".to_string(), "https://x?a=b".to_string())
+        );
+        assert!(parse_template_var("novalue").is_err());
+        assert!(parse_template_var("=x").is_err());
+    }
+
+    #[test]
+    fn ensure_template_sections_keeps_every_heading() {
+        let template = "## Summary\n<!-- what and why -->\n\n```\n# not a heading\n```\n## Testing\n\n## Risks\n";
+        let filled =
+            ensure_template_sections("## Summary\nAdds X.\n\n## Testing\nUnit tests.", template);
+        assert_eq!(
+            filled,
+            "## Summary\nAdds X.\n\n## Testing\nUnit tests.\n\n## Risks\n\nTODO"
+        );
+        assert_eq!(
+            template_section_headings(&filled),
+            template_section_headings(template)
+        );
+        assert_eq!(
+            ensure_template_sections("", "## Summary"),
+            "## Summary\n\nTODO"
+        );
+    }
+
+    #[test]
+    fn utc_timestamp_is_rfc3339() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1_792_055_045_123);
+        assert_eq!(utc_timestamp(time), "2026-10-15T09:04:05.123Z");
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn civil_from_days_known_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(20_740), (2026, 10, 14));
+    }
+}
@@ -1,11 +1,16 @@
 use ratatui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    crossterm::event::{
+        self, Event, KeyCode, KeyEventKind, MouseButton, MouseEvent, MouseEventKind,
+    },
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Span, Text},
-    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Tabs},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Tabs, Wrap},
     Terminal,
 };
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub struct TabsState<'a> {
     pub titles: Vec<&'a str>,
@@ -28,16 +33,175 @@ impl<'a> TabsState<'a> {
     }
 }
 
+/// Repository and PR state shown on the Status tab; `None` until `run` knows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStatus {
+    pub main_branch: Option<String>,
+    pub current_branch: Option<String>,
+    pub base_branch: Option<String>,
+    /// `(staged, unstaged)` file counts, untracked files counting as unstaged
+    pub changed_files: Option<(usize, usize)>,
+    pub pr_url: Option<String>,
+}
+
+impl RunStatus {
+    /// `(label, value)` rows in display order.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let or_pending = |value: &Option<String>| value.clone().unwrap_or_else(|| "…".to_string());
+        let (staged, unstaged) = match self.changed_files {
+            Some((staged, unstaged)) => (staged.to_string(), unstaged.to_string()),
+            None => ("…".to_string(), "…".to_string()),
+        };
+        vec![
+            ("Main branch", or_pending(&self.main_branch)),
+            ("Current branch", or_pending(&self.current_branch)),
+            ("Base branch", or_pending(&self.base_branch)),
+            ("Staged files", staged),
+            ("Unstaged files", unstaged),
+            (
+                "Pull request",
+                self.pr_url
+                    .clone()
+                    .unwrap_or_else(|| "not created yet".to_string()),
+            ),
+        ]
+    }
+}
+
+/// Steps of a run, in the order `run` goes through them. Runs skip some (no
+/// uncommitted changes, a reused PR description), which only makes the
+/// Progress gauge jump ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    FetchMain,
+    EnterWorktree,
+    DiffUncommitted,
+    GenerateBranch,
+    Commit,
+    DiffBranches,
+    GeneratePr,
+    Push,
+    CreatePr,
+    Done,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 10] = [
+        Stage::FetchMain,
+        Stage::EnterWorktree,
+        Stage::DiffUncommitted,
+        Stage::GenerateBranch,
+        Stage::Commit,
+        Stage::DiffBranches,
+        Stage::GeneratePr,
+        Stage::Push,
+        Stage::CreatePr,
+        Stage::Done,
+    ];
+
+    /// Position in `ALL`.
+    pub fn index(self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|&stage| stage == self)
+            .unwrap_or(0)
+    }
+
+    /// Share of the run finished once this stage is reached: 0.0 for the
+    /// first, 1.0 for `Done`.
+    pub fn progress(self) -> f64 {
+        self.index() as f64 / (Self::ALL.len() - 1) as f64
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stage::FetchMain => "Fetching the main branch",
+            Stage::EnterWorktree => "Entering the temp worktree",
+            Stage::DiffUncommitted => "Reading uncommitted changes",
+            Stage::GenerateBranch => "Generating branch name and commit message",
+            Stage::Commit => "Committing",
+            Stage::DiffBranches => "Diffing against the base branch",
+            Stage::GeneratePr => "Generating PR details",
+            Stage::Push => "Pushing",
+            Stage::CreatePr => "Creating the PR",
+            Stage::Done => "Done",
+        }
+    }
+}
+
+/// How long a flash message replaces the key help.
+const FLASH_DURATION: Duration = Duration::from_secs(2);
+
+/// Receives `(level, message)` for every log and error line, already redacted.
+pub type LogSink = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
 pub struct App<'a> {
     pub title: &'a str,
     pub should_quit: bool,
     pub tabs: TabsState<'a>,
-    pub logs: Vec<(&'a str, String)>,
+    /// `(level, message, time since App::new)`
+    pub logs: Vec<(&'a str, String, Duration)>,
     pub errors: Vec<String>,
     pub progress: f64,
+    /// Last stage passed to `set_stage`, shown on the Progress gauge
+    pub stage: Option<Stage>,
     pub details: String,
     pub error_tab_blink: bool,
     pub blink_timer: u8,
+    /// Ask before running destructive git operations (see `confirm_destructive`)
+    pub confirm_destructive: bool,
+    /// Show the PR and wait for `y` before pushing (see `confirm_submit`)
+    pub confirm_submit: bool,
+    /// Log mutating git/gh commands instead of running them (see `run_command`)
+    pub dry_run: bool,
+    /// Byte cap for diffs sent to the model (`--max-diff-bytes`)
+    pub max_diff_bytes: usize,
+    /// Keep diffs over `max_diff_bytes` whole and have the model summarize them
+    /// per file instead of truncating them (`--summarize-large`)
+    pub summarize_large: bool,
+    /// Describe and commit only what is staged; never `git add` (`--staged-only`)
+    pub staged_only: bool,
+    /// Mask likely secrets in diffs before they reach the model (off with `--no-scrub`)
+    pub scrub_secrets: bool,
+    /// Repo-relative pathspecs the diff and commit are restricted to (`--path`)
+    pub paths: Vec<String>,
+    /// No TUI: stream logs to stderr instead of drawing (`--no-tui`, non-TTY stdout)
+    pub plain: bool,
+    /// Never wait for a keypress or pause for the user (`--non-interactive`)
+    pub non_interactive: bool,
+    /// Lines the Logs tab is scrolled up from the bottom; 0 follows new lines
+    pub log_scroll: usize,
+    /// Same as `log_scroll`, for the Errors tab
+    pub error_scroll: usize,
+    /// List rows visible at the last draw, the PageUp/PageDown step
+    pub viewport_lines: usize,
+    /// Screen area of each tab title at the last draw, for mouse clicks
+    pub tab_rects: Vec<Rect>,
+    /// Start of the run, for log timestamps
+    pub started: Instant,
+    /// Values masked in every log and error line (see `redact`)
+    pub secrets: Vec<String>,
+    /// Remote to fetch from and push to (`--remote`, else `git_default_remote`)
+    pub remote: String,
+    /// Retry a push rejected as non-fast-forward with `--force-with-lease` (`--force-push`)
+    pub force_push: bool,
+    /// Contents of the Status tab, updated as `run` progresses
+    pub status: RunStatus,
+    /// Body of the PR once it is created or updated, for copying with `Y`
+    pub pr_body: Option<String>,
+    /// Short notice shown in place of the key help until it expires, e.g. "Copied PR URL!"
+    pub flash: Option<(String, Instant)>,
+    /// Model output streamed so far by the current AI call; shared so the
+    /// caller can redraw it while the call holds `&mut App`
+    pub streaming_preview: Arc<Mutex<String>>,
+    /// How long `github_list_issues` reuses issues saved on disk (zero disables it)
+    pub issues_cache_ttl: Duration,
+    /// Skip the on-disk issues cache and ask `gh` (`--refresh-issues`)
+    pub refresh_issues: bool,
+    /// Where library callers without a TUI get the log lines (see `generate_pr_description`)
+    pub log_sink: Option<LogSink>,
+    /// `--log-file`: every log and error line is appended here as a JSON record
+    pub log_file: Option<std::fs::File>,
 }
 
 impl<'a> App<'a> {
@@ -45,13 +209,40 @@ impl<'a> App<'a> {
         App {
             title,
             should_quit: false,
-            tabs: TabsState::new(vec!["Logs", "Errors", "Details"]),
+            tabs: TabsState::new(vec!["Logs", "Errors", "Details", "Status"]),
             logs: vec![],
             errors: vec![],
             progress: 0.0,
+            stage: None,
             details: String::new(),
             error_tab_blink: false,
             blink_timer: 0,
+            confirm_destructive: false,
+            confirm_submit: false,
+            dry_run: false,
+            max_diff_bytes: crate::git_ops::DEFAULT_MAX_DIFF_BYTES,
+            summarize_large: false,
+            staged_only: false,
+            scrub_secrets: true,
+            paths: Vec::new(),
+            plain: false,
+            non_interactive: false,
+            log_scroll: 0,
+            error_scroll: 0,
+            viewport_lines: 10,
+            tab_rects: Vec::new(),
+            started: Instant::now(),
+            secrets: Vec::new(),
+            remote: "origin".to_string(),
+            force_push: false,
+            status: RunStatus::default(),
+            pr_body: None,
+            flash: None,
+            streaming_preview: Arc::default(),
+            issues_cache_ttl: crate::github_ops::DEFAULT_ISSUES_CACHE_TTL,
+            refresh_issues: false,
+            log_sink: None,
+            log_file: None,
         }
     }
 
@@ -67,26 +258,170 @@ impl<'a> App<'a> {
         self.tabs.index = index;
     }
 
-    pub fn update_progress(&mut self, value: f64) {
-        self.progress = value;
+    /// Move the Progress gauge to `stage`.
+    pub fn set_stage(&mut self, stage: Stage) {
+        self.stage = Some(stage);
+        self.progress = stage.progress();
     }
 
     pub fn add_log<S: ToString>(&mut self, level: &'a str, message: S) {
-        self.logs.push((level, message.to_string()));
+        let message = redact(&message.to_string(), &self.secrets);
+        let elapsed = self.started.elapsed();
+        if self.plain {
+            eprintln!("[{}] {}: {}", format_elapsed(elapsed), level, message);
+        }
+        if let Some(sink) = &self.log_sink {
+            sink(level, &message);
+        }
+        self.write_log_record(level, &message);
+        self.logs.push((level, message, elapsed));
+        // Keep a scrolled-up view on the same lines; at 0 it follows the tail
+        if self.log_scroll > 0 {
+            self.log_scroll = self.log_scroll.saturating_add(1);
+        }
     }
 
     pub fn add_error<S: ToString>(&mut self, error: S) {
-        self.errors
-            .extend(error.to_string().split('\n').map(|s| s.to_string()));
-        self.logs.extend(
-            error
-                .to_string()
-                .split('\n')
-                .map(|s| ("ERROR", s.to_string())),
-        );
+        let error = redact(&error.to_string(), &self.secrets);
+        let elapsed = self.started.elapsed();
+        for line in error.split('\n') {
+            if self.plain {
+                eprintln!("[{}] ERROR: {}", format_elapsed(elapsed), line);
+            }
+            if let Some(sink) = &self.log_sink {
+                sink("ERROR", line);
+            }
+            self.write_log_record("ERROR", line);
+            self.errors.push(line.to_string());
+            self.logs.push(("ERROR", line.to_string(), elapsed));
+            if self.log_scroll > 0 {
+                self.log_scroll = self.log_scroll.saturating_add(1);
+            }
+            if self.error_scroll > 0 {
+                self.error_scroll = self.error_scroll.saturating_add(1);
+            }
+        }
         self.start_error_blink();
     }
 
+    /// Append `{timestamp, level, message, stage}` as one JSON line to the
+    /// `--log-file`, if any. `message` must already be redacted. Written
+    /// unbuffered, so the file has the whole trail even if the run crashes.
+    pub fn write_log_record(&mut self, level: &str, message: &str) {
+        use std::io::Write;
+        let Some(file) = self.log_file.as_mut() else {
+            return;
+        };
+        let record = serde_json::json!({
+            "timestamp": crate::template_ops::utc_timestamp(std::time::SystemTime::now()),
+            "level": level,
+            "message": message,
+            "stage": self.stage.map(Stage::label),
+        });
+        // A failing log file must not fail the run; the TUI still has the line
+        let _ = writeln!(file, "{}", record);
+    }
+
+    /// Scroll offset of the active tab, if it is a scrollable list.
+    fn active_scroll(&mut self) -> Option<&mut usize> {
+        match self.tabs.index {
+            0 => Some(&mut self.log_scroll),
+            1 => Some(&mut self.error_scroll),
+            _ => None,
+        }
+    }
+
+    /// Scroll the active tab towards older lines.
+    pub fn scroll_up(&mut self, lines: usize) {
+        if let Some(scroll) = self.active_scroll() {
+            *scroll = scroll.saturating_add(lines);
+        }
+    }
+
+    /// Scroll the active tab towards newer lines; reaching the bottom follows the tail again.
+    pub fn scroll_down(&mut self, lines: usize) {
+        if let Some(scroll) = self.active_scroll() {
+            *scroll = scroll.saturating_sub(lines);
+        }
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        if let Some(scroll) = self.active_scroll() {
+            *scroll = 0;
+        }
+    }
+
+    /// Index of the tab whose title was drawn at `(column, row)`, if any.
+    pub fn tab_at(&self, column: u16, row: u16) -> Option<usize> {
+        self.tab_rects
+            .iter()
+            .position(|rect| rect.contains(ratatui::layout::Position::new(column, row)))
+    }
+
+    /// A left click on a tab title switches to it; the wheel scrolls the active tab.
+    pub fn on_mouse(&mut self, mouse: MouseEvent) {
+        const WHEEL_LINES: usize = 3;
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(index) = self.tab_at(mouse.column, mouse.row) {
+                    self.switch_to_tab(index);
+                }
+            }
+            MouseEventKind::ScrollUp => self.scroll_up(WHEEL_LINES),
+            MouseEventKind::ScrollDown => self.scroll_down(WHEEL_LINES),
+            _ => {}
+        }
+    }
+
+    /// Show `message` on the help line for `FLASH_DURATION`.
+    pub fn flash<S: ToString>(&mut self, message: S) {
+        self.flash = Some((message.to_string(), Instant::now()));
+    }
+
+    /// The flash message, while it hasn't expired.
+    fn active_flash(&self) -> Option<&str> {
+        self.flash
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < FLASH_DURATION)
+            .map(|(message, _)| message.as_str())
+    }
+
+    /// Copy the PR URL to the system clipboard (`y`).
+    pub fn copy_pr_url(&mut self) {
+        let url = self.status.pr_url.clone();
+        self.copy_to_clipboard("PR URL", url);
+    }
+
+    /// Copy the PR body to the system clipboard (`Y`).
+    pub fn copy_pr_body(&mut self) {
+        let body = self.pr_body.clone();
+        self.copy_to_clipboard("PR body", body);
+    }
+
+    /// Copy `text` and flash the outcome; without a clipboard (e.g. on a
+    /// headless server) a WARN says why.
+    fn copy_to_clipboard(&mut self, what: &str, text: Option<String>) {
+        let Some(text) = text else {
+            self.flash(format!("No {} yet", what));
+            return;
+        };
+        let commands = clipboard_commands(|name| std::env::var_os(name).is_some());
+        match write_to_clipboard(&text, &commands) {
+            Ok(()) => self.flash(format!("Copied {}!", what)),
+            Err(e) => {
+                self.add_log("WARN", format!("Could not copy the {}: {}", what, e));
+                self.flash(format!("Could not copy the {} (see Logs)", what));
+            }
+        }
+    }
+
+    /// Mask `secret` (e.g. the API key) in all later log and error lines.
+    pub fn add_secret(&mut self, secret: &str) {
+        if !secret.is_empty() && !self.secrets.iter().any(|s| s == secret) {
+            self.secrets.push(secret.to_string());
+        }
+    }
+
     pub fn update_details(&mut self, details: String) {
         self.details = details;
     }
@@ -139,9 +474,19 @@ pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
             .add_modifier(Modifier::BOLD),
     );
     f.render_widget(tabs, chunks[0]);
+    app.tab_rects = tab_title_rects(chunks[0], &app.tabs.titles);
 
-    let help = Paragraph::new("Press 'q' to quit or Ctrl+C to interrupt and revert")
-        .style(Style::default().fg(Color::White));
+    let help = match app.active_flash() {
+        Some(message) => Paragraph::new(message.to_string()).style(
+            Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD),
+        ),
+        None => Paragraph::new(
+            "←/→ or click tabs, ↑/↓/PgUp/PgDn/wheel scroll, End follow, 'y'/'Y' copy PR URL/body, 'q' quit, Ctrl+C interrupt and revert",
+        )
+        .style(Style::default().fg(Color::White)),
+    };
     f.render_widget(help, chunks[1]);
 
     app.update_blink();
@@ -150,10 +495,11 @@ pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
         0 => render_logs(f, app, chunks[2]),
         1 => render_errors(f, app, chunks[2]),
         2 => render_details(f, app, chunks[2]),
+        3 => render_status(f, app, chunks[2]),
         _ => {}
     }
 
-    let gauge = Gauge::default()
+    let mut gauge = Gauge::default()
         .block(Block::default().borders(Borders::ALL).title("Progress"))
         .gauge_style(
             Style::default()
@@ -162,20 +508,72 @@ pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
                 .add_modifier(Modifier::ITALIC),
         )
         .ratio(app.progress);
+    if let Some(stage) = app.stage {
+        gauge = gauge.label(match stage {
+            Stage::Done => stage.label().to_string(),
+            _ => format!(
+                "Step {}/{}: {}",
+                stage.index() + 1,
+                Stage::ALL.len() - 1,
+                stage.label()
+            ),
+        });
+    }
     f.render_widget(gauge, chunks[3]);
 }
 
+/// Where `Tabs` draws each title, padding included, inside the bordered
+/// `area`: a space, the title, a space, then a one-column divider. Titles
+/// cut off at the right edge get what is left of their area, or none.
+fn tab_title_rects(area: Rect, titles: &[&str]) -> Vec<Rect> {
+    let inner = Block::default().borders(Borders::ALL).inner(area);
+    let mut rects = Vec::new();
+    let mut x = inner.left();
+    for title in titles {
+        if x >= inner.right() {
+            break;
+        }
+        let width = (Span::raw(*title).width() as u16 + 2).min(inner.right() - x);
+        rects.push(Rect::new(x, inner.top(), width, 1));
+        x = x.saturating_add(width + 1);
+    }
+    rects
+}
+
 fn render_details(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
     let paragraph = Paragraph::new(Text::from(app.details.clone()))
-        .block(Block::default().borders(Borders::ALL).title("Details"));
+        .block(Block::default().borders(Borders::ALL).title("Details"))
+        .wrap(Wrap { trim: false });
     f.render_widget(paragraph, area);
 }
 
-fn render_logs(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
+fn render_status(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
+    let rows: Vec<Line> = app
+        .status
+        .rows()
+        .into_iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:<16}", format!("{}:", label)),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(value),
+            ])
+        })
+        .collect();
+    let paragraph = Paragraph::new(rows)
+        .block(Block::default().borders(Borders::ALL).title("Status"))
+        .wrap(Wrap { trim: false });
+    f.render_widget(paragraph, area);
+}
+
+fn render_logs(f: &mut ratatui::Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let width = content_width(area);
     let logs: Vec<ListItem> = app
         .logs
         .iter()
-        .map(|(level, message)| {
+        .flat_map(|(level, message, elapsed)| {
             let style = match *level {
                 "INFO" => Style::default().fg(Color::Blue),
                 "ERROR" => Style::default().fg(Color::Red),
@@ -184,30 +582,234 @@ fn render_logs(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
                 "WARNING" => Style::default().fg(Color::Yellow),
                 _ => Style::default().fg(Color::Gray),
             };
-            ListItem::new(Span::styled(message.clone(), style))
+            let timestamp = Span::styled(
+                format!("{} ", format_elapsed(*elapsed)),
+                Style::default().fg(Color::DarkGray),
+            );
+            wrapped_rows(timestamp, message, style, width)
         })
         .collect();
-    let logs_widget = List::new(logs).block(Block::default().borders(Borders::ALL).title("Logs"));
+    let (logs, title) = scrolled_window(logs, "Logs", &mut app.log_scroll, area);
+    app.viewport_lines = visible_lines(area);
+    let logs_widget = List::new(logs).block(Block::default().borders(Borders::ALL).title(title));
     f.render_widget(logs_widget, area);
 }
 
-fn render_errors(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
+fn render_errors(f: &mut ratatui::Frame, app: &mut App, area: ratatui::layout::Rect) {
+    let width = content_width(area);
     let errors: Vec<ListItem> = app
         .errors
         .iter()
-        .map(|message| {
-            ListItem::new(Span::styled(
-                message.clone(),
+        .flat_map(|message| {
+            wrapped_rows(
+                Span::raw(""),
+                message,
                 Style::default().fg(Color::Red),
-            ))
+                width,
+            )
         })
         .collect();
+    let (errors, title) = scrolled_window(errors, "Errors", &mut app.error_scroll, area);
+    app.viewport_lines = visible_lines(area);
     let errors_widget = List::new(errors)
-        .block(Block::default().borders(Borders::ALL).title("Errors"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .style(Style::default().fg(Color::Red));
     f.render_widget(errors_widget, area);
 }
 
+/// Mask every `secret` and every token `scrub_diff` masks in diffs (API keys,
+/// GitHub tokens, AWS access key IDs; see `SECRET_TOKEN`) in `text`.
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        text = text.replace(secret.as_str(), "<redacted>");
+    }
+    crate::git_ops::SECRET_TOKEN
+        .replace_all(&text, "<redacted>")
+        .into_owned()
+}
+
+/// Time since the start of the run as `MM:SS.m`, e.g. `01:07.3`.
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let tenths = elapsed.as_millis() / 100;
+    format!(
+        "{:02}:{:02}.{}",
+        tenths / 600,
+        tenths / 10 % 60,
+        tenths % 10
+    )
+}
+
+/// List rows inside a bordered block.
+fn visible_lines(area: ratatui::layout::Rect) -> usize {
+    usize::from(area.height.saturating_sub(2)).max(1)
+}
+
+/// Columns inside a bordered block.
+fn content_width(area: ratatui::layout::Rect) -> usize {
+    usize::from(area.width.saturating_sub(2)).max(1)
+}
+
+/// `text` as list rows at most `width` columns wide: the first after `prefix`,
+/// continuations indented to line up under the text. Widths are display
+/// columns, so wide characters (CJK, emoji) take two. `List` cannot wrap, so
+/// this runs on every draw, which also re-flows the rows when the terminal is
+/// resized. Scroll offsets count these rows, not log entries.
+fn wrapped_rows<'t>(prefix: Span<'t>, text: &str, style: Style, width: usize) -> Vec<ListItem<'t>> {
+    use unicode_width::UnicodeWidthChar;
+
+    let indent = prefix.width();
+    let room = width.saturating_sub(indent).max(1);
+    let mut prefix = Some(prefix);
+    let mut rows = Vec::new();
+    for line in text.split('\n') {
+        let mut chunks = vec![String::new()];
+        let mut used = 0;
+        for c in line.chars() {
+            let c_width = c.width().unwrap_or(0);
+            if used + c_width > room && used > 0 {
+                chunks.push(String::new());
+                used = 0;
+            }
+            used += c_width;
+            chunks.last_mut().unwrap().push(c);
+        }
+        for chunk in chunks {
+            let lead = prefix
+                .take()
+                .unwrap_or_else(|| Span::raw(" ".repeat(indent)));
+            rows.push(ListItem::new(Line::from(vec![
+                lead,
+                Span::styled(chunk, style),
+            ])));
+        }
+    }
+    rows
+}
+
+/// The rows of `items` visible `scroll` lines up from the bottom, and the block
+/// title. `scroll` is clamped so scrolling back down never needs extra presses.
+fn scrolled_window<'i>(
+    items: Vec<ListItem<'i>>,
+    title: &str,
+    scroll: &mut usize,
+    area: ratatui::layout::Rect,
+) -> (Vec<ListItem<'i>>, String) {
+    let visible = visible_lines(area);
+    let (start, end) = window_bounds(items.len(), visible, scroll);
+    let title = if *scroll > 0 {
+        format!(
+            "{} ({}-{} of {}, End to follow)",
+            title,
+            start + 1,
+            end,
+            items.len()
+        )
+    } else {
+        title.to_string()
+    };
+    let window = items.into_iter().skip(start).take(end - start).collect();
+    (window, title)
+}
+
+/// `[start, end)` of the rows shown for `total` rows, `visible` at a time,
+/// `scroll` rows up from the bottom (clamped in place).
+fn window_bounds(total: usize, visible: usize, scroll: &mut usize) -> (usize, usize) {
+    let max_scroll = total.saturating_sub(visible);
+    *scroll = (*scroll).min(max_scroll);
+    let end = total - *scroll;
+    (end.saturating_sub(visible), end)
+}
+
+/// Full-screen Details view of a model response as it streams in, showing
+/// its newest lines. Drawn by the caller while an AI call owns the `App`.
+pub fn render_streaming_preview<B: Backend>(
+    terminal: &mut Terminal<B>,
+    preview: &str,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    terminal.draw(|f| {
+        let area = f.area();
+        let rows = wrapped_rows(
+            Span::raw(""),
+            preview,
+            Style::default().fg(Color::Cyan),
+            content_width(area),
+        );
+        let skip = rows.len().saturating_sub(visible_lines(area));
+        let list = List::new(rows.into_iter().skip(skip).collect::<Vec<_>>()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Details (AI response, streaming)"),
+        );
+        f.render_widget(list, area);
+    })?;
+    Ok(())
+}
+
+/// Clipboard programs to try in order, with their arguments. Off macOS and
+/// Windows they need an X11 or Wayland display, so a headless session gets
+/// none; `env_set` says whether an environment variable is set.
+pub fn clipboard_commands(
+    env_set: impl Fn(&str) -> bool,
+) -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        return vec![("pbcopy", &[])];
+    }
+    if cfg!(windows) {
+        return vec![("clip", &[])];
+    }
+    let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+    if env_set("WAYLAND_DISPLAY") {
+        commands.push(("wl-copy", &[]));
+    }
+    if env_set("DISPLAY") {
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+    }
+    commands
+}
+
+/// Pipe `text` into the first of `commands` that runs and succeeds.
+fn write_to_clipboard(
+    text: &str,
+    commands: &[(&'static str, &'static [&'static str])],
+) -> Result<(), String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    if commands.is_empty() {
+        return Err("no clipboard available (no X11 or Wayland display)".to_string());
+    }
+    let mut failures = Vec::new();
+    for (program, args) in commands {
+        let child = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                failures.push(format!("{}: {}", program, e));
+                continue;
+            }
+        };
+        let written = child
+            .stdin
+            .take()
+            .map(|mut stdin| stdin.write_all(text.as_bytes()));
+        match (written, child.wait()) {
+            (Some(Ok(())), Ok(status)) if status.success() => return Ok(()),
+            (_, Ok(status)) => failures.push(format!("{} exited with {}", program, status)),
+            (_, Err(e)) => failures.push(format!("{}: {}", program, e)),
+        }
+    }
+    Err(failures.join("; "))
+}
+
 pub fn render_message<B: Backend>(
     terminal: &mut Terminal<B>,
     title: &str,
@@ -229,6 +831,175 @@ where
     Ok(())
 }
 
+/// Ask the user to confirm a destructive operation before it runs.
+/// Lists every target that will be destroyed; `y` proceeds, `n`/`Esc`/`q` cancels.
+/// Returns immediately with `true` when `app.confirm_destructive` is off or in
+/// `--dry-run`, where nothing is actually discarded.
+pub fn confirm_destructive<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    action: &str,
+    targets: &[String],
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    if !app.confirm_destructive || app.dry_run || targets.is_empty() {
+        return Ok(true);
+    }
+
+    let message = destructive_prompt(action, targets);
+    if app.plain {
+        return confirm_on_stdin(app, action, &message);
+    }
+    loop {
+        render_message(
+            terminal,
+            "Confirm destructive operation",
+            &message,
+            Color::Yellow,
+        )?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => {
+                    app.add_log("INFO", format!("Confirmed: {}", action));
+                    return Ok(true);
+                }
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Char('q') | KeyCode::Esc => {
+                    app.add_log("WARN", format!("Cancelled: {}", action));
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// What is about to be pushed and opened as a PR, for `confirm_submit`.
+pub struct SubmitPreview<'a> {
+    pub branch: &'a str,
+    pub base: &'a str,
+    pub title: &'a str,
+    pub body: &'a str,
+    /// `git diff --stat` of the branch against `base`
+    pub changed_files: &'a str,
+}
+
+impl SubmitPreview<'_> {
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "Branch: {} -> {}\nTitle:  {}\n\n{}\n\nChanged files:\n",
+            self.branch,
+            self.base,
+            self.title,
+            self.body.trim_end()
+        );
+        for line in self.changed_files.lines() {
+            message.push_str(&format!("  {}\n", line.trim()));
+        }
+        message
+    }
+}
+
+/// Show the PR about to be submitted and wait for `y` to push and open it, or
+/// `q`/`n`/`Esc` to stop with the commit left unpushed. `Up`/`Down` scroll.
+/// Returns immediately with `true` when `app.confirm_submit` is off or in
+/// `--dry-run`, where nothing is pushed.
+pub fn confirm_submit<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    preview: &SubmitPreview,
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    <B as Backend>::Error: 'static,
+{
+    if !app.confirm_submit || app.dry_run {
+        return Ok(true);
+    }
+
+    let action = format!("Push {} and open the PR", preview.branch);
+    let message = preview.message();
+    if app.plain {
+        let prompt = format!(
+            "{}\nPush and open the PR? [y/N] (re-run with --yes to skip this prompt)",
+            message
+        );
+        return confirm_on_stdin(app, &action, &prompt);
+    }
+    let mut scroll: u16 = 0;
+    loop {
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(1)])
+                .split(f.area());
+            let block = Block::default().borders(Borders::ALL).title(Span::styled(
+                "Review before submitting",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ));
+            let paragraph = Paragraph::new(Text::from(message.as_str()))
+                .block(block)
+                .wrap(Wrap { trim: false })
+                .scroll((scroll, 0));
+            f.render_widget(paragraph, chunks[0]);
+            f.render_widget(
+                Paragraph::new("y: push and open the PR | q: stop without pushing | ↑/↓: scroll"),
+                chunks[1],
+            );
+        })?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => {
+                    app.add_log("INFO", format!("Confirmed: {}", action));
+                    return Ok(true);
+                }
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Char('q') | KeyCode::Esc => {
+                    app.add_log("WARN", format!("Cancelled: {}", action));
+                    return Ok(false);
+                }
+                KeyCode::Down | KeyCode::Char('j') => scroll = scroll.saturating_add(1),
+                KeyCode::Up | KeyCode::Char('k') => scroll = scroll.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Plain-mode confirmation: the prompt goes to stderr, the answer is a line on stdin.
+fn confirm_on_stdin(
+    app: &mut App,
+    action: &str,
+    message: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    eprintln!("{}", message);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        app.add_log("INFO", format!("Confirmed: {}", action));
+        Ok(true)
+    } else {
+        app.add_log("WARN", format!("Cancelled: {}", action));
+        Ok(false)
+    }
+}
+
+fn destructive_prompt(action: &str, targets: &[String]) -> String {
+    let mut message = format!("{}\n\nThe following will be destroyed:\n", action);
+    for target in targets {
+        message.push_str(&format!("  {}\n", target));
+    }
+    message.push_str("\nProceed? [y/N] (re-run with --yes to skip this prompt)");
+    message
+}
+
 #[cfg(test)]
 #[path = "tui/tests.rs"]
 mod tests;
@@ -7,6 +7,7 @@ use tempfile::TempDir;
 use serial_test::serial;
 
 // Import from the local crate
+use gh_autopr::forge::*;
 use gh_autopr::git_ops::*;
 use gh_autopr::github_ops::*;
 use gh_autopr::tui::App;
@@ -53,6 +54,48 @@ fn create_test_repo() -> (TempDir, String) {
     (temp_dir, repo_path)
 }
 
+/// Run git in the current directory and return its stdout. Only trailing
+/// whitespace is trimmed, as porcelain lines can start with a space.
+fn git(args: &[&str]) -> String {
+    let output = Command::new("git").args(args).output().unwrap();
+    assert!(output.status.success(), "git {:?}: {:?}", args, output);
+    String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string()
+}
+
+/// A stub `gh` running the shell `script`, first on PATH until dropped.
+#[cfg(unix)]
+struct StubGh {
+    _bin_dir: TempDir,
+    original_path: std::ffi::OsString,
+}
+
+#[cfg(unix)]
+impl Drop for StubGh {
+    fn drop(&mut self) {
+        env::set_var("PATH", &self.original_path);
+    }
+}
+
+#[cfg(unix)]
+fn with_stub_gh(script: &str) -> StubGh {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin_dir = TempDir::new().expect("Failed to create temp directory");
+    let gh = bin_dir.path().join("gh");
+    fs::write(&gh, format!("#!/bin/sh\n{}", script)).unwrap();
+    fs::set_permissions(&gh, fs::Permissions::from_mode(0o755)).unwrap();
+    let original_path = env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![bin_dir.path().to_path_buf()];
+    paths.extend(env::split_paths(&original_path));
+    env::set_var("PATH", env::join_paths(paths).unwrap());
+    StubGh {
+        _bin_dir: bin_dir,
+        original_path,
+    }
+}
+
 #[test]
 #[serial]
 fn test_git_ensure_in_repo_success() {
@@ -69,6 +112,33 @@ fn test_git_ensure_in_repo_success() {
     assert!(result.is_ok());
 }
 
+#[test]
+#[serial]
+fn test_git_ensure_has_commits() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let empty_dir = TempDir::new().expect("Failed to create temp directory");
+    Command::new("git")
+        .args(["init", "-b", "main"])
+        .current_dir(&empty_dir)
+        .output()
+        .expect("Failed to initialize git repo");
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    let mut app = App::new("Test App");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    let with_commits = git_ensure_has_commits(&mut app);
+    env::set_current_dir(empty_dir.path()).expect("Failed to change directory");
+    let unborn = git_ensure_has_commits(&mut app);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(with_commits.is_ok());
+    assert_eq!(
+        unborn.unwrap_err().to_string(),
+        "repository has no commits yet; make an initial commit first"
+    );
+}
+
 #[test]
 #[serial]
 fn test_git_cd_to_repo_root() {
@@ -83,7 +153,7 @@ fn test_git_cd_to_repo_root() {
     let _ = env::set_current_dir(&original_dir);
 
     assert!(result.is_ok());
-    assert!(app.logs.iter().any(|(level, msg)| {
+    assert!(app.logs.iter().any(|(level, msg, _)| {
         *level == "INFO" && msg.contains("Changed directory to repo root")
     }));
 }
@@ -126,7 +196,55 @@ fn test_git_current_branch() {
     assert!(app
         .logs
         .iter()
-        .any(|(level, msg)| { *level == "INFO" && msg.contains("Current branch:") }));
+        .any(|(level, msg, _)| { *level == "INFO" && msg.contains("Current branch:") }));
+}
+
+#[test]
+#[serial]
+fn test_branch_from_detached_head() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    Command::new("git")
+        .args(["checkout", "--detach", "HEAD"])
+        .output()
+        .unwrap();
+    let head = || {
+        let out = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&out.stdout).trim().to_string()
+    };
+    let detached_at = head();
+
+    let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+    let mut app = App::new("Test App");
+    let no_name = git_ensure_not_detached_head(&mut terminal, &mut app, &"HEAD".to_string(), None);
+    let invalid = git_ensure_not_detached_head(
+        &mut terminal,
+        &mut app,
+        &"HEAD".to_string(),
+        Some("bad..name"),
+    );
+    let still_detached = git_current_branch(&mut app).unwrap();
+    let created = git_ensure_not_detached_head(
+        &mut terminal,
+        &mut app,
+        &"HEAD".to_string(),
+        Some("feature/from-detached"),
+    );
+    let current = git_current_branch(&mut app).unwrap();
+    let created_at = head();
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(no_name.is_err());
+    assert!(invalid.is_err());
+    assert_eq!(still_detached, "HEAD");
+    assert_eq!(created.unwrap(), "feature/from-detached");
+    assert_eq!(current, "feature/from-detached");
+    assert_eq!(created_at, detached_at);
 }
 
 #[test]
@@ -162,6 +280,7 @@ fn test_git_main_branch() {
         .output()
         .ok(); // This might fail, that's okay
 
+    reset_main_branch_cache();
     let mut app = App::new("Test App");
     let result = git_main_branch(&mut app);
 
@@ -171,7 +290,7 @@ fn test_git_main_branch() {
     match result {
         Ok(branch) => {
             assert!(!branch.is_empty());
-            assert!(app.logs.iter().any(|(level, msg)| {
+            assert!(app.logs.iter().any(|(level, msg, _)| {
                 *level == "INFO"
                     && (msg.contains("Determined main branch:")
                         || msg.contains("Setting origin HEAD"))
@@ -237,12 +356,14 @@ fn test_git_commit_staged_changes() {
         &mut app,
         "Test commit",
         &Some("Test commit details".to_string()),
+        None,
+        &[],
     );
 
     let _ = env::set_current_dir(&original_dir);
 
     assert!(result.is_ok());
-    assert!(app.logs.iter().any(|(level, msg)| {
+    assert!(app.logs.iter().any(|(level, msg, _)| {
         *level == "INFO" && msg.contains("Committed changes successfully")
     }));
 }
@@ -256,7 +377,7 @@ fn test_git_checkout_new_branch() {
     env::set_current_dir(&repo_path).expect("Failed to change directory");
 
     let mut app = App::new("Test App");
-    let result = git_checkout_new_branch(&mut app, "test-branch", "HEAD", false);
+    let result = git_checkout_new_branch(&mut app, "test-branch", "HEAD", None);
 
     let _ = env::set_current_dir(&original_dir);
 
@@ -267,7 +388,7 @@ fn test_git_checkout_new_branch() {
             assert!(app
                 .logs
                 .iter()
-                .any(|(level, msg)| { *level == "INFO" && msg.contains("Created branch") }));
+                .any(|(level, msg, _)| { *level == "INFO" && msg.contains("Created branch") }));
         }
         Err(_) => {
             // Expected in some CI environments
@@ -293,6 +414,8 @@ fn test_git_stage_and_commit() {
         &mut app,
         "Test commit",
         &Some("Test commit details".to_string()),
+        None,
+        &[],
     );
 
     let _ = env::set_current_dir(&original_dir);
@@ -301,12 +424,103 @@ fn test_git_stage_and_commit() {
     assert!(app
         .logs
         .iter()
-        .any(|(level, msg)| { *level == "INFO" && msg.contains("Staged all changes") }));
-    assert!(app.logs.iter().any(|(level, msg)| {
+        .any(|(level, msg, _)| { *level == "INFO" && msg.contains("Staged all changes") }));
+    assert!(app.logs.iter().any(|(level, msg, _)| {
         *level == "INFO" && msg.contains("Committed changes successfully")
     }));
 }
 
+#[test]
+#[serial]
+fn test_git_stage_and_commit_with_trailers() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    fs::write("test.txt", "test content").unwrap();
+
+    let mut app = App::new("Test App");
+    let result = git_stage_and_commit(
+        &mut app,
+        "feat: pair on x",
+        &Some("- Adds x\n\n\n- Tests x\n\n".to_string()),
+        None,
+        &[
+            "Co-authored-by: Pat Doe <pat@example.com>".to_string(),
+            "Signed-off-by: Test User <test@example.com>".to_string(),
+        ],
+    );
+    let message = Command::new("git")
+        .args(["log", "-1", "--format=%B"])
+        .output()
+        .expect("Failed to read log");
+    let trailers = Command::new("git")
+        .args(["log", "-1", "--format=%(trailers:only)"])
+        .output()
+        .expect("Failed to read trailers");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_ok());
+    let message = String::from_utf8_lossy(&message.stdout);
+    assert_eq!(
+        message.trim_end(),
+        "feat: pair on x\n\n- Adds x\n\n- Tests x\n\n\
+         Co-authored-by: Pat Doe <pat@example.com>\n\
+         Signed-off-by: Test User <test@example.com>\n\
+         Generated-by: gh-autopr"
+    );
+    assert!(!message.trim_end().contains("\n\n\n"));
+    // git itself parses all three as trailers
+    assert_eq!(
+        String::from_utf8_lossy(&trailers.stdout)
+            .trim_end()
+            .lines()
+            .count(),
+        3
+    );
+}
+
+#[test]
+#[serial]
+fn test_git_stage_and_commit_staged_only() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write("staged.txt", "staged\n").unwrap();
+    fs::write("unstaged.txt", "unstaged\n").unwrap();
+    fs::write("README.md", "# Edited but not staged\n").unwrap();
+    git(&["add", "staged.txt"]);
+
+    let mut app = App::new("Test App");
+    app.staged_only = true;
+    let diff = git_diff_uncommitted(&mut app, "HEAD").unwrap();
+    let commit = git_stage_and_commit(&mut app, "feat: staged only", &None, None, &[]);
+    let committed = git(&["show", "--name-only", "--format=", "HEAD"]);
+    let status = git(&["status", "--porcelain"]);
+
+    // Nothing staged now: no fallback to the working tree, and no commit
+    let empty_diff = git_diff_uncommitted(&mut app, "HEAD").unwrap();
+    let empty_commit = git_stage_and_commit(&mut app, "feat: nothing", &None, None, &[]);
+    let head_subject = git(&["log", "-1", "--format=%s"]);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(diff.contains("staged.txt"));
+    assert!(!diff.contains("unstaged.txt"));
+    assert!(!diff.contains("README.md"));
+    assert!(commit.is_ok());
+    assert_eq!(committed, "staged.txt");
+    assert!(status.contains(" M README.md"));
+    assert!(status.contains("?? unstaged.txt"));
+    assert_eq!(empty_diff, "");
+    assert!(empty_commit
+        .unwrap_err()
+        .to_string()
+        .contains("nothing is staged"));
+    assert_eq!(head_subject, "feat: staged only");
+}
+
 #[test]
 fn test_discover_parent_branch_main_branch() {
     let mut app = App::new("Test App");
@@ -449,6 +663,149 @@ fn test_git_diff_uncommitted_with_changes() {
     assert!(diff.contains("test.txt"));
 }
 
+#[test]
+#[serial]
+fn test_git_diff_uncommitted_and_unpushed_combines_both() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let mut app = App::new("Test App");
+    git(&["checkout", "-q", "-b", "feature"]);
+    fs::write("unpushed.txt", "committed but not pushed").unwrap();
+    git(&["add", "unpushed.txt"]);
+    git(&["commit", "-q", "-m", "Add unpushed"]);
+    fs::write("README.md", "# Edited in the working tree").unwrap();
+
+    // Without an upstream only the uncommitted changes count
+    let without_upstream = git_diff_uncommitted_and_unpushed(&mut app, "feature").unwrap();
+
+    let remote_dir = TempDir::new().unwrap();
+    git(&["init", "-q", "--bare", remote_dir.path().to_str().unwrap()]);
+    git(&[
+        "remote",
+        "add",
+        "origin",
+        remote_dir.path().to_str().unwrap(),
+    ]);
+    git(&["push", "-q", "origin", "main:feature"]);
+    git(&["branch", "-q", "--set-upstream-to", "origin/feature"]);
+    let combined = git_diff_uncommitted_and_unpushed(&mut app, "feature").unwrap();
+    let uncommitted_only = git_diff_uncommitted(&mut app, "feature").unwrap();
+
+    git(&["push", "-q", "origin", "feature"]);
+    let all_pushed = git_diff_uncommitted_and_unpushed(&mut app, "feature").unwrap();
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(without_upstream, None);
+    let combined = combined.expect("the unpushed commit should be included");
+    assert!(
+        combined.contains("+committed but not pushed"),
+        "{}",
+        combined
+    );
+    assert!(
+        combined.contains("+# Edited in the working tree"),
+        "{}",
+        combined
+    );
+    assert!(!uncommitted_only.contains("unpushed.txt"));
+    assert_eq!(all_pushed, None);
+}
+
+#[test]
+#[serial]
+fn test_git_verify_commit_resolves_revs_and_rejects_unknown_ones() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    Command::new("git")
+        .args(["tag", "v1.0"])
+        .output()
+        .expect("Failed to tag");
+    let head = git_verify_commit("HEAD").unwrap();
+    let tagged = git_verify_commit("v1.0").unwrap();
+    let branch = git_verify_commit("main").unwrap();
+    let unknown = git_verify_commit("no-such-rev");
+
+    let _ = env::set_current_dir(&original_dir);
+    assert_eq!(head.len(), 40);
+    assert_eq!(tagged, head);
+    assert_eq!(branch, head);
+    let err = unknown.unwrap_err().to_string();
+    assert!(err.contains("--since-commit no-such-rev"), "{}", err);
+}
+
+#[test]
+#[serial]
+fn test_git_diff_uncommitted_skips_ignore_file_patterns_and_binaries() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let app = App::new("Test App");
+    assert_eq!(build_diff_pathspec(&app), vec!["--", ".", ":!*.lock"]);
+
+    fs::write(DIFF_IGNORE_FILE, "dist/**\n*.min.js\n").unwrap();
+    let pathspec = build_diff_pathspec(&app);
+    assert!(pathspec.contains(&":(exclude,glob)dist/**".to_string()));
+    assert!(pathspec.contains(&":(exclude,glob)**/*.min.js".to_string()));
+
+    fs::create_dir_all("dist").unwrap();
+    fs::create_dir_all("web").unwrap();
+    fs::write("dist/bundle.js", "bundled();").unwrap();
+    fs::write("web/app.min.js", "minified();").unwrap();
+    fs::write("logo.png", [0u8, 159, 146, 150, 0, 1]).unwrap();
+    fs::write("main.rs", "fn main() {}").unwrap();
+    Command::new("git").args(["add", "-A"]).output().unwrap();
+
+    let mut app = App::new("Test App");
+    let diff = git_diff_uncommitted(&mut app, "HEAD").unwrap();
+
+    let _ = env::set_current_dir(&original_dir);
+    assert!(diff.contains("main.rs"));
+    assert!(diff.contains(DIFF_IGNORE_FILE));
+    for skipped in ["dist/bundle.js", "app.min.js", "logo.png"] {
+        assert!(!diff.contains(skipped), "{} should be excluded", skipped);
+    }
+}
+
+#[test]
+#[serial]
+fn test_git_diff_uncommitted_truncates_to_max_diff_bytes() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write(
+        Path::new(&repo_path).join("big.txt"),
+        "line of content\n".repeat(1024),
+    )
+    .expect("Failed to write test file");
+
+    Command::new("git")
+        .args(["add", "big.txt"])
+        .current_dir(&repo_path)
+        .output()
+        .expect("Failed to stage file");
+
+    let mut app = App::new("Test App");
+    app.max_diff_bytes = MIN_MAX_DIFF_BYTES;
+    let result = git_diff_uncommitted(&mut app, "HEAD");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let diff = result.unwrap();
+    assert!(diff.len() <= MIN_MAX_DIFF_BYTES);
+    assert!(diff.contains("big.txt"));
+    assert!(app
+        .logs
+        .iter()
+        .any(|(level, msg, _)| *level == "WARN" && msg.contains("truncated")));
+}
+
 #[test]
 #[serial]
 fn test_update_original_worktree_to_pr_branch() {
@@ -557,7 +914,7 @@ fn test_update_original_worktree_to_pr_branch() {
     );
 
     // Verify that the logs show the correct operations
-    assert!(app.logs.iter().any(|(level, msg)| {
+    assert!(app.logs.iter().any(|(level, msg, _)| {
         *level == "SUCCESS" && msg.contains(&format!("Switched to branch '{}'", pr_branch))
     }));
 
@@ -842,6 +1199,188 @@ fn test_update_original_worktree_to_pr_branch_with_remote_tracking() {
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[test]
+fn test_cached_issues_expire_after_ttl() {
+    let ttl = std::time::Duration::from_secs(600);
+    let saved = with_cached_issues("", "git@github.com:o/a.git", "[1]", 1_000);
+    let saved = with_cached_issues(&saved, "git@github.com:o/b.git", "[2]", 1_500);
+
+    assert_eq!(
+        cached_issues(&saved, "git@github.com:o/a.git", ttl, 1_599).as_deref(),
+        Some("[1]")
+    );
+    assert_eq!(
+        cached_issues(&saved, "git@github.com:o/a.git", ttl, 1_600),
+        None
+    );
+    assert_eq!(
+        cached_issues(&saved, "git@github.com:o/b.git", ttl, 1_600).as_deref(),
+        Some("[2]")
+    );
+    assert_eq!(
+        cached_issues(&saved, "git@github.com:o/c.git", ttl, 1_600),
+        None
+    );
+    assert_eq!(
+        cached_issues("not json", "git@github.com:o/a.git", ttl, 1_000),
+        None
+    );
+}
+
+#[test]
+fn test_missing_cli_gives_install_hint() {
+    let mut app = App::new("Test App");
+    let err = ensure_command_available(&mut app, "gh-autopr-no-such-tool", GH_INSTALL_HINT)
+        .expect_err("A missing binary must fail the check");
+
+    let msg = err.to_string();
+    assert!(
+        msg.starts_with("gh-autopr-no-such-tool is not installed"),
+        "{}",
+        msg
+    );
+    assert!(msg.contains("https://cli.github.com"));
+    assert!(msg.contains("gh auth login"));
+    assert_eq!(app.errors, vec![msg]);
+
+    ensure_git_available(&mut app).expect("git is installed where the tests run");
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_unauthenticated_gh_is_reported() {
+    // A gh that is installed but logged out, first on PATH
+    let stub_gh = with_stub_gh(
+        "if [ \"$1\" = auth ]; then\n  echo 'You are not logged into any GitHub hosts.' >&2\n  exit 1\nfi\necho 'gh version 2.0.0'\n",
+    );
+
+    let mut app = App::new("Test App");
+    let available = ensure_gh_available(&mut app);
+    let result = GithubForge.ensure_cli_available(&mut app);
+
+    drop(stub_gh);
+
+    assert!(available.is_ok(), "The stub gh is installed");
+    let msg = result
+        .expect_err("A logged-out gh must fail the check")
+        .to_string();
+    assert!(
+        msg.starts_with("GitHub CLI is not authenticated; run `gh auth login`"),
+        "{}",
+        msg
+    );
+    assert!(msg.contains("not logged into any GitHub hosts"));
+}
+
+#[test]
+#[serial]
+fn test_git_commit_paths_commits_only_those_files() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write("README.md", "# Changed\n").unwrap();
+    fs::write("new.txt", "new\n").unwrap();
+    fs::write("later.txt", "later\n").unwrap();
+    Command::new("git")
+        .args(["add", "later.txt"])
+        .output()
+        .unwrap();
+
+    let mut app = App::new("Test App");
+    let paths = git_uncommitted_paths(&mut app).expect("Failed to list changes");
+    let result = git_commit_paths(
+        &mut app,
+        &["README.md".to_string(), "new.txt".to_string()],
+        "docs: first group",
+        &None,
+        None,
+        &[],
+    );
+    let committed = Command::new("git")
+        .args(["show", "--name-only", "--format=", "HEAD"])
+        .output()
+        .unwrap();
+    let remaining = git_uncommitted_paths(&mut app).expect("Failed to list changes");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let mut paths = paths;
+    paths.sort();
+    assert_eq!(paths, vec!["README.md", "later.txt", "new.txt"]);
+    assert!(result.is_ok(), "{:?}", app.errors);
+    assert_eq!(
+        String::from_utf8_lossy(&committed.stdout).trim(),
+        "README.md\nnew.txt"
+    );
+    // Left for a later group, staged state dropped
+    assert_eq!(remaining, vec!["later.txt"]);
+}
+
+#[test]
+fn test_paths_from_porcelain_z_keeps_both_rename_paths() {
+    assert_eq!(
+        paths_from_porcelain_z("R  new name.rs\0old.rs\0 M src/a.rs\0?? notes.md\0"),
+        vec!["new name.rs", "old.rs", "src/a.rs", "notes.md"]
+    );
+    assert_eq!(
+        renames_from_porcelain_z("R  new name.rs\0old.rs\0C  copy.rs\0src.rs\0 M src/a.rs\0"),
+        vec![("new name.rs".to_string(), "old.rs".to_string())]
+    );
+}
+
+#[test]
+fn test_fork_target_from_repo_view() {
+    let fork = r#"{"nameWithOwner":"me/tool","parent":{"id":"R_1","name":"tool","owner":{"id":"U_1","login":"up"}}}"#;
+    assert_eq!(
+        fork_target_from_repo_view(fork),
+        Some(ForkTarget {
+            head_repo: "me/tool".to_string(),
+            base_repo: "up/tool".to_string(),
+        })
+    );
+    assert_eq!(
+        fork_target_from_repo_view(r#"{"nameWithOwner":"up/tool","parent":null}"#),
+        None
+    );
+    assert_eq!(fork_target_from_repo_view("not json"), None);
+}
+
+#[test]
+#[serial]
+fn test_github_list_issues_uses_fresh_disk_cache_without_gh() {
+    let (temp_dir, _repo_path) = create_test_repo();
+    let original_dir = env::current_dir().unwrap();
+    env::set_current_dir(&temp_dir).unwrap();
+
+    let url = "https://github.com/example/cached.git";
+    Command::new("git")
+        .args(["remote", "add", "origin", url])
+        .output()
+        .unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let issues = r#"[{"number":7,"title":"Cached issue"}]"#;
+    fs::write(
+        temp_dir.path().join(".git/gh-autopr-issues-cache.json"),
+        with_cached_issues("", url, issues, now),
+    )
+    .unwrap();
+
+    let mut app = App::new("Test App");
+    let result = github_list_issues(&mut app);
+
+    env::set_current_dir(original_dir).unwrap();
+    assert_eq!(result.unwrap(), issues);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg.contains("cached by a recent run")));
+}
+
 #[test]
 #[ignore = "requires gh CLI tool"]
 fn test_github_list_issues_integration() {
@@ -874,16 +1413,19 @@ fn test_create_or_update_pull_request_integration() {
         "Test PR",
         "Test PR body",
         false,
-        true,
         "main",
         "test-branch",
+        &PullRequestOptions {
+            ready: true,
+            ..Default::default()
+        },
     );
 
     // This will likely fail without proper GitHub setup
     // We just test that it handles errors gracefully
     match result {
         Ok(_) => {
-            assert!(app.logs.iter().any(|(level, _)| *level == "SUCCESS"));
+            assert!(app.logs.iter().any(|(level, _, _)| *level == "SUCCESS"));
         }
         Err(_) => {
             assert!(!app.errors.is_empty());
@@ -895,7 +1437,10 @@ fn test_create_or_update_pull_request_integration() {
 // TempWorktree Integration Tests
 // ============================================================================
 
-use gh_autopr::git_temp_worktree::TempWorktree;
+use gh_autopr::git_temp_worktree::{
+    cleanup_stale_worktrees, is_in_temp_worktree, verify_clean_exit, CwdGuard, TempWorktree,
+    STALE_WORKTREE_AGE,
+};
 
 #[test]
 #[serial]
@@ -913,7 +1458,7 @@ fn test_temp_worktree_captures_and_replays_staged_changes() {
         .expect("Failed to stage file");
 
     // Enter temp worktree
-    let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
 
     // Verify we're in the temp worktree
     let current_dir = env::current_dir().expect("Failed to get current dir");
@@ -977,7 +1522,7 @@ fn test_temp_worktree_captures_unstaged_changes() {
     fs::write("README.md", "# Modified content").expect("Failed to modify file");
 
     // Enter temp worktree
-    let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
 
     // Verify unstaged changes were replayed
     let diff = Command::new("git")
@@ -1000,30 +1545,144 @@ fn test_temp_worktree_captures_unstaged_changes() {
 
 #[test]
 #[serial]
-fn test_temp_worktree_captures_untracked_files() {
+fn test_temp_worktree_keeps_staged_and_unstaged_hunks_of_one_file_apart() {
     let (_temp_dir, repo_path) = create_test_repo();
     let original_dir = env::current_dir().expect("Failed to get current directory");
-
     env::set_current_dir(&repo_path).expect("Failed to change directory");
 
-    // Create an untracked file
-    fs::write("untracked.txt", "untracked content").expect("Failed to write file");
+    let lines: Vec<String> = (1..=20).map(|n| format!("line {}", n)).collect();
+    fs::write("split.txt", lines.join("\n") + "\n").unwrap();
+    Command::new("git")
+        .args(["add", "split.txt"])
+        .output()
+        .unwrap();
+    Command::new("git")
+        .args(["commit", "-m", "Add split.txt"])
+        .output()
+        .unwrap();
 
-    // Enter temp worktree
-    let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+    // One hunk staged near the top, another left unstaged near the bottom
+    let mut staged = lines.clone();
+    staged[1] = "line 2 staged".to_string();
+    fs::write("split.txt", staged.join("\n") + "\n").unwrap();
+    Command::new("git")
+        .args(["add", "split.txt"])
+        .output()
+        .unwrap();
+    let mut worktree = staged.clone();
+    worktree[18] = "line 19 unstaged".to_string();
+    fs::write("split.txt", worktree.join("\n") + "\n").unwrap();
+
+    let snapshot = || {
+        (
+            git(&["status", "--porcelain"]),
+            git(&["diff", "--cached"]),
+            git(&["diff"]),
+            fs::read_to_string("split.txt").unwrap(),
+        )
+    };
+    let original = snapshot();
 
-    // Verify untracked file was copied
-    assert!(
-        Path::new("untracked.txt").exists(),
-        "Untracked file should exist in temp worktree"
-    );
-    let content = fs::read_to_string("untracked.txt").expect("Failed to read file");
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
+    let replayed = snapshot();
+    drop(temp_worktree);
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(original.0, "MM split.txt");
+    assert_eq!(replayed, original);
+}
+
+#[test]
+#[serial]
+fn test_cleanup_stale_worktrees_removes_only_old_temp_worktrees() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let git_dir = Path::new(&repo_path).join(".git");
+    let registered = git_dir.join("autopr-wt-1000");
+    Command::new("git")
+        .args(["worktree", "add", "--detach", registered.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let orphaned = git_dir.join("autopr-wt-2000");
+    let fresh = git_dir.join("autopr-wt-3000");
+    let unrelated = git_dir.join("other-wt-4000");
+    for dir in [&orphaned, &fresh, &unrelated] {
+        fs::create_dir_all(dir).unwrap();
+    }
+    let old = std::time::SystemTime::now() - STALE_WORKTREE_AGE * 2;
+    for dir in [&registered, &orphaned, &unrelated] {
+        fs::File::open(dir).unwrap().set_modified(old).unwrap();
+    }
+
+    let mut app = App::new("Test App");
+    let removed = cleanup_stale_worktrees(&mut app).unwrap();
+    let worktrees = Command::new("git")
+        .args(["worktree", "list", "--porcelain"])
+        .output()
+        .unwrap();
+
+    let _ = env::set_current_dir(&original_dir);
+    assert_eq!(removed.len(), 2, "{:?}", removed);
+    assert!(!registered.exists() && !orphaned.exists());
+    assert!(fresh.exists() && unrelated.exists());
+    assert!(!String::from_utf8_lossy(&worktrees.stdout).contains("autopr-wt-1000"));
+}
+
+#[test]
+#[serial]
+fn test_temp_worktree_captures_untracked_files() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // Create an untracked file
+    fs::write("untracked.txt", "untracked content").expect("Failed to write file");
+
+    // Enter temp worktree
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
+
+    // Verify untracked file was copied
+    assert!(
+        Path::new("untracked.txt").exists(),
+        "Untracked file should exist in temp worktree"
+    );
+    let content = fs::read_to_string("untracked.txt").expect("Failed to read file");
     assert_eq!(content, "untracked content");
 
     drop(temp_worktree);
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_temp_worktree_keeps_executable_bit_and_symlinks_of_untracked_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write("run.sh", "#!/bin/sh\necho hi\n").unwrap();
+    fs::set_permissions("run.sh", fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("run.sh", "link.sh").unwrap();
+    std::os::unix::fs::symlink("missing-target", "dangling").unwrap();
+
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
+    let mode = fs::metadata("run.sh").unwrap().permissions().mode();
+    let link = fs::read_link("link.sh");
+    let dangling = fs::read_link("dangling");
+    drop(temp_worktree);
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(mode & 0o777, 0o755);
+    assert_eq!(link.unwrap(), Path::new("run.sh"));
+    assert_eq!(dangling.unwrap(), Path::new("missing-target"));
+}
+
 #[test]
 #[serial]
 fn test_temp_worktree_preserves_branch() {
@@ -1039,7 +1698,7 @@ fn test_temp_worktree_preserves_branch() {
         .expect("Failed to create branch");
 
     // Enter temp worktree
-    let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
 
     // Verify we're on the same branch in temp worktree
     let branch_output = Command::new("git")
@@ -1096,7 +1755,7 @@ fn test_temp_worktree_full_workflow() {
         .expect("Failed to stage file");
 
     // Enter temp worktree
-    let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
     let orig_root = temp_worktree.original_root().clone();
 
     // Create a new branch (simulating what gh-autopr does)
@@ -1179,7 +1838,7 @@ fn test_temp_worktree_cleanup_on_early_drop() {
     fs::write("error_test.txt", "content").expect("Failed to write file");
 
     let orig_root = {
-        let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+        let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
         let root = temp_worktree.original_root().clone();
 
         // Simulate an error scenario - drop without completing workflow
@@ -1205,7 +1864,38 @@ fn test_temp_worktree_cleanup_on_early_drop() {
         !worktrees.contains("autopr-wt-"),
         "Temp worktree should be cleaned up even after early drop"
     );
+    assert_eq!(
+        verify_clean_exit(&orig_root).expect("Self-check failed"),
+        Vec::<String>::new()
+    );
+
+    let _ = env::set_current_dir(&original_dir);
+}
+
+#[test]
+#[serial]
+fn test_verify_clean_exit_reports_live_temp_worktree() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
 
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    assert!(!is_in_temp_worktree());
+
+    let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
+    assert!(is_in_temp_worktree());
+
+    let leftovers = verify_clean_exit(temp_worktree.original_root()).expect("Self-check failed");
+    assert!(leftovers
+        .iter()
+        .any(|l| l.starts_with("Working directory is")));
+    assert!(leftovers
+        .iter()
+        .any(|l| l.contains("still inside a temp worktree")));
+    assert!(leftovers
+        .iter()
+        .any(|l| l.starts_with("Temp worktree still registered")));
+
+    drop(temp_worktree);
     let _ = env::set_current_dir(&original_dir);
 }
 
@@ -1225,10 +1915,10 @@ fn test_create_pr_error_no_existing_pr_to_update() {
         &mut app,
         "Test Title",
         "Test Body",
-        true,  // update_pr = true
-        false, // ready = false
+        true, // update_pr = true
         "main",
         "nonexistent-branch",
+        &PullRequestOptions::default(), // ready = false
     );
 
     // Should fail with appropriate error
@@ -1279,20 +1969,74 @@ fn test_git_checkout_new_branch_auto_renames_when_exists() {
 
     // Try to create the same branch again without force_reset
     // Should auto-rename to existing-branch-iter-2
-    let result = git_checkout_new_branch(&mut app, "existing-branch", &current_branch, false);
+    let result = git_checkout_new_branch(&mut app, "existing-branch", &current_branch, None);
 
     assert!(result.is_ok(), "Should succeed with auto-renamed branch");
     assert_eq!(result.unwrap(), "existing-branch-iter-2");
     assert!(
         app.logs
             .iter()
-            .any(|(level, msg)| { *level == "WARN" && msg.contains("already exists") }),
+            .any(|(level, msg, _)| { *level == "WARN" && msg.contains("already exists") }),
         "Should warn about branch already existing"
     );
 
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[test]
+#[serial]
+fn test_resolve_base_ref_uses_remote_when_local_missing_or_stale() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    let origin_dir = temp_dir.path().join("origin.git");
+    git(&["init", "--bare", "-b", "main", origin_dir.to_str().unwrap()]);
+    git(&["remote", "add", "origin", origin_dir.to_str().unwrap()]);
+    git(&["push", "origin", "main", "main:develop"]);
+    git(&["fetch", "origin"]);
+    git(&["checkout", "-b", "feature"]);
+    fs::write("feature.txt", "feature\n").unwrap();
+    git(&["add", "feature.txt"]);
+    git(&["commit", "-m", "feature"]);
+
+    let up_to_date = resolve_base_ref("origin", "main");
+    let missing = resolve_base_ref("origin", "develop");
+    let not_on_remote = resolve_base_ref("origin", "HEAD~1");
+
+    // origin/main moves on; local main stays behind
+    git(&["checkout", "main"]);
+    fs::write("upstream.txt", "upstream\n").unwrap();
+    git(&["add", "upstream.txt"]);
+    git(&["commit", "-m", "upstream"]);
+    git(&["push", "origin", "main"]);
+    git(&["fetch", "origin"]);
+    git(&["reset", "--hard", "HEAD~1"]);
+    git(&["checkout", "feature"]);
+    let stale = resolve_base_ref("origin", "main");
+
+    let mut app = App::new("Test App");
+    app.remote = "origin".to_string();
+    let diff = git_diff_between_branches(&mut app, "develop", "feature").unwrap();
+
+    // Local commits of its own make main the user's, not stale
+    git(&["checkout", "main"]);
+    fs::write("local.txt", "local\n").unwrap();
+    git(&["add", "local.txt"]);
+    git(&["commit", "-m", "local"]);
+    let diverged = resolve_base_ref("origin", "main");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(up_to_date, "main");
+    assert_eq!(missing, "origin/develop");
+    assert_eq!(not_on_remote, "HEAD~1");
+    assert_eq!(stale, "origin/main");
+    assert_eq!(diverged, "main");
+    assert!(diff.contains("feature.txt"));
+    assert!(app.logs.iter().any(|(_, msg, _)| msg
+        == "Comparing feature against base branch: origin/develop (local develop is missing or behind it)"));
+}
+
 #[test]
 #[serial]
 fn test_git_diff_between_branches_error_invalid_branch() {
@@ -1326,7 +2070,7 @@ fn test_git_push_branch_error_no_remote() {
     let mut app = App::new("Test App");
 
     // Try to push - should fail because there's no remote
-    let result = git_push_branch(&mut app, "main");
+    let result = git_push_branch(&mut app, "main", false);
 
     assert!(result.is_err(), "Should fail when no remote configured");
     assert!(!app.errors.is_empty(), "Should log an error");
@@ -1334,6 +2078,58 @@ fn test_git_push_branch_error_no_remote() {
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[test]
+#[serial]
+fn test_git_push_branch_uses_remote_named_upstream() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    let remote_dir = temp_dir.path().join("upstream.git");
+    Command::new("git")
+        .args(["init", "--bare", remote_dir.to_str().unwrap()])
+        .output()
+        .expect("Failed to init bare remote");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    Command::new("git")
+        .args(["remote", "add", "upstream", remote_dir.to_str().unwrap()])
+        .output()
+        .expect("Failed to add upstream remote");
+    Command::new("git")
+        .args(["switch", "-c", "feature"])
+        .output()
+        .expect("Failed to create feature branch");
+
+    let mut app = App::new("Test App");
+    app.remote = git_default_remote(&mut app).expect("Should pick a remote");
+    let result = git_push_branch(&mut app, "feature", false);
+
+    let upstream = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "feature@{upstream}"])
+        .output()
+        .expect("Failed to read upstream");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(app.remote, "upstream", "The only remote is the default");
+    assert_eq!(result.expect("Push should succeed"), "feature");
+    assert_eq!(
+        String::from_utf8_lossy(&upstream.stdout).trim(),
+        "upstream/feature"
+    );
+    let pushed = Command::new("git")
+        .args([
+            "--git-dir",
+            remote_dir.to_str().unwrap(),
+            "rev-parse",
+            "--verify",
+            "refs/heads/feature",
+        ])
+        .output()
+        .expect("Failed to inspect remote");
+    assert!(pushed.status.success(), "feature should exist on upstream");
+}
+
 #[test]
 #[serial]
 fn test_update_original_worktree_error_invalid_branch() {
@@ -1397,7 +2193,7 @@ fn test_temp_worktree_multiple_sequential_creates() {
         fs::write(format!("file_{}.txt", i), format!("content {}", i))
             .expect("Failed to write file");
 
-        let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+        let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
 
         // Verify file exists in temp worktree
         assert!(
@@ -1461,7 +2257,7 @@ fn test_git_commit_staged_changes_error_nothing_staged() {
     let mut app = App::new("Test App");
 
     // Try to commit with nothing staged - should fail
-    let result = git_commit_staged_changes(&mut app, "Empty commit", &None);
+    let result = git_commit_staged_changes(&mut app, "Empty commit", &None, None, &[]);
 
     assert!(result.is_err(), "Should fail when nothing to commit");
     assert!(!app.errors.is_empty(), "Should log an error");
@@ -1481,7 +2277,7 @@ fn test_git_stage_and_commit_error_nothing_to_stage() {
     let mut app = App::new("Test App");
 
     // Try to stage and commit with no changes - should fail
-    let result = git_stage_and_commit(&mut app, "No changes commit", &None);
+    let result = git_stage_and_commit(&mut app, "No changes commit", &None, None, &[]);
 
     assert!(result.is_err(), "Should fail when nothing to commit");
 
@@ -1519,7 +2315,7 @@ fn test_git_checkout_new_branch_error_invalid_base() {
     let mut app = App::new("Test App");
 
     // Try to create branch from non-existent base
-    let result = git_checkout_new_branch(&mut app, "new-branch", "nonexistent-base", false);
+    let result = git_checkout_new_branch(&mut app, "new-branch", "nonexistent-base", None);
 
     assert!(
         result.is_err(),
@@ -1546,9 +2342,9 @@ fn test_create_pr_error_create_fails_no_github() {
         "Test PR",
         "Test body",
         false, // create new (not update)
-        false, // not ready
         "main",
         "feature",
+        &PullRequestOptions::default(), // not ready
     );
 
     assert!(result.is_err(), "Should fail when gh isn't configured");
@@ -1606,13 +2402,48 @@ fn test_temp_worktree_error_not_in_git_repo() {
     // NOT a git repo - just an empty directory
     env::set_current_dir(temp_dir.path()).expect("Failed to change directory");
 
-    let result = TempWorktree::enter();
+    let result = TempWorktree::enter("origin");
 
     assert!(result.is_err(), "Should fail when not in git repo");
 
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[test]
+#[serial]
+fn test_git_main_branch_runs_set_head_at_most_once() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    let origin_dir = temp_dir.path().join("origin.git");
+    git(&["init", "--bare", "-b", "main", origin_dir.to_str().unwrap()]);
+    git(&["remote", "add", "origin", origin_dir.to_str().unwrap()]);
+    git(&["push", "origin", "main"]);
+
+    reset_main_branch_cache();
+    let mut app = App::new("Test App");
+    let first = git_main_branch(&mut app);
+    // Would make an uncached lookup run set-head again
+    git(&["remote", "set-head", "origin", "--delete"]);
+    let second = git_main_branch(&mut app);
+    reset_main_branch_cache();
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(first.unwrap(), "main");
+    assert_eq!(second.unwrap(), "main");
+    let set_head_runs = app
+        .logs
+        .iter()
+        .filter(|(_, msg, _)| msg.contains("Setting origin HEAD automatically"))
+        .count();
+    assert_eq!(set_head_runs, 1);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg == "Using cached main branch: main"));
+}
+
 #[test]
 #[serial]
 fn test_git_main_branch_error_no_origin() {
@@ -1622,6 +2453,7 @@ fn test_git_main_branch_error_no_origin() {
 
     env::set_current_dir(&repo_path).expect("Failed to change directory");
 
+    reset_main_branch_cache();
     let mut app = App::new("Test App");
 
     // This should fail since there's no origin configured
@@ -1710,6 +2542,156 @@ fn test_get_merged_prs_error_no_github() {
     let _ = env::set_current_dir(&original_dir);
 }
 
+#[test]
+#[serial]
+fn test_branch_has_commits_beyond_merged_head() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    fs::write("feature.txt", "merged").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "feature.txt"])
+        .output()
+        .expect("Failed to stage");
+    Command::new("git")
+        .args(["commit", "-m", "feat: merged part"])
+        .output()
+        .expect("Failed to commit");
+    let merged_head = String::from_utf8(
+        Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .expect("Failed to rev-parse")
+            .stdout,
+    )
+    .unwrap()
+    .trim()
+    .to_string();
+
+    let at_merged_head = branch_has_commits_beyond("feature", &merged_head);
+    fs::write("feature.txt", "added after merge").expect("Failed to write file");
+    Command::new("git")
+        .args(["commit", "-am", "feat: follow-up"])
+        .output()
+        .expect("Failed to commit");
+    let with_follow_up = branch_has_commits_beyond("feature", &merged_head);
+    let empty_head = branch_has_commits_beyond("feature", "");
+    let unfetched_head =
+        branch_has_commits_beyond("feature", "0123456789abcdef0123456789abcdef01234567");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(at_merged_head, Ok(false));
+    assert_eq!(
+        with_follow_up,
+        Ok(true),
+        "Commits after the merge keep the branch"
+    );
+    assert!(empty_head.is_err());
+    assert!(
+        unfetched_head.is_err(),
+        "A head that isn't in the local repo can't prove the branch is merged"
+    );
+}
+
+#[test]
+#[serial]
+fn test_git_squash_range_counts_local_and_pushed_commits() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let main_tip = String::from_utf8(
+        Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .expect("Failed to rev-parse")
+            .stdout,
+    )
+    .unwrap()
+    .trim()
+    .to_string();
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    for (i, content) in ["one", "two"].iter().enumerate() {
+        fs::write(format!("wip{}.txt", i), content).expect("Failed to write file");
+        Command::new("git")
+            .args(["add", "-A"])
+            .output()
+            .expect("Failed to stage");
+        Command::new("git")
+            .args(["commit", "-m", &format!("wip {}", i)])
+            .output()
+            .expect("Failed to commit");
+    }
+    let local_only = git_squash_range("main", "origin");
+
+    // Push the first WIP commit, as a previous push of the branch would have
+    let remote_dir = temp_dir.path().join("origin.git");
+    Command::new("git")
+        .args(["init", "--bare"])
+        .arg(&remote_dir)
+        .output()
+        .expect("Failed to init origin");
+    Command::new("git")
+        .args(["remote", "add", "origin"])
+        .arg(&remote_dir)
+        .output()
+        .expect("Failed to add origin");
+    Command::new("git")
+        .args(["push", "origin", "HEAD~1:refs/heads/feature"])
+        .output()
+        .expect("Failed to push");
+    let partly_pushed = git_squash_range("main", "origin");
+
+    // The whole branch is on a remote that isn't called origin
+    let upstream_dir = temp_dir.path().join("upstream.git");
+    Command::new("git")
+        .args(["init", "--bare"])
+        .arg(&upstream_dir)
+        .output()
+        .expect("Failed to init upstream");
+    Command::new("git")
+        .args(["remote", "add", "upstream"])
+        .arg(&upstream_dir)
+        .output()
+        .expect("Failed to add upstream");
+    Command::new("git")
+        .args(["push", "upstream", "HEAD:refs/heads/feature"])
+        .output()
+        .expect("Failed to push");
+    let fully_pushed = git_squash_range("main", "upstream");
+
+    let mut app = App::new("Test App");
+    let outside_temp_worktree = git_squash_onto_merge_base(&mut app, "main");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let local_only = local_only.expect("Should compute range");
+    assert_eq!(local_only.merge_base, main_tip);
+    assert_eq!(local_only.commits, 2);
+    assert_eq!(local_only.on_remote, 0);
+    let partly_pushed = partly_pushed.expect("Should compute range");
+    assert_eq!(partly_pushed.commits, 2);
+    assert_eq!(partly_pushed.on_remote, 1);
+    let fully_pushed = fully_pushed.expect("Should compute range");
+    assert_eq!(fully_pushed.commits, 2);
+    assert_eq!(fully_pushed.on_remote, 2);
+    assert!(
+        outside_temp_worktree.is_err(),
+        "--squash must not rewrite the user's worktree"
+    );
+}
+
 #[test]
 #[serial]
 fn test_truncate_utf8_edge_cases() {
@@ -2209,7 +3191,7 @@ fn test_temp_worktree_tracks_staged_changes_flag() {
         fs::write("unstaged.txt", "unstaged").expect("Failed to write");
         // Don't stage it
 
-        let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+        let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
         assert!(
             !temp_worktree.had_staged_changes(),
             "Should report no staged changes"
@@ -2228,7 +3210,7 @@ fn test_temp_worktree_tracks_staged_changes_flag() {
             .output()
             .expect("Failed to stage");
 
-        let temp_worktree = TempWorktree::enter().expect("Failed to enter temp worktree");
+        let temp_worktree = TempWorktree::enter("origin").expect("Failed to enter temp worktree");
         assert!(
             temp_worktree.had_staged_changes(),
             "Should report staged changes exist"
@@ -2238,3 +3220,1094 @@ fn test_temp_worktree_tracks_staged_changes_flag() {
 
     let _ = env::set_current_dir(&original_dir);
 }
+
+#[test]
+#[serial]
+fn test_git_main_branch_recovers_from_stale_origin_head() {
+    // origin/HEAD left pointing at a default branch that was renamed/deleted upstream
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let remote_dir = _temp_dir.path().join("origin.git");
+    fs::create_dir(&remote_dir).expect("Failed to create remote dir");
+    Command::new("git")
+        .args(["init", "--bare", "-b", "main"])
+        .current_dir(&remote_dir)
+        .output()
+        .expect("Failed to init bare repo");
+    Command::new("git")
+        .args(["remote", "add", "origin", remote_dir.to_str().unwrap()])
+        .output()
+        .expect("Failed to add remote");
+    Command::new("git")
+        .args(["push", "origin", "main"])
+        .output()
+        .expect("Failed to push main");
+    Command::new("git")
+        .args([
+            "symbolic-ref",
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/old-default",
+        ])
+        .output()
+        .expect("Failed to point origin/HEAD at a missing branch");
+
+    reset_main_branch_cache();
+    let mut app = App::new("Test App");
+    let result = git_main_branch(&mut app);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(result.unwrap(), "main");
+    assert!(app.logs.iter().any(|(level, msg, _)| {
+        *level == "WARN" && msg.contains("origin/old-default, which no longer exists")
+    }));
+}
+
+#[test]
+#[serial]
+fn test_git_diff_since_fork_point_ignores_rewritten_base_commits() {
+    // main: A -> B, feature forks at B, then main is rewritten to A -> C.
+    // The merge base is A (so B's changes leak into a three-dot diff), while the
+    // fork point from main's reflog is still B.
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let commit_file = |name: &str| {
+        fs::write(name, name).expect("Failed to write file");
+        Command::new("git")
+            .args(["add", name])
+            .output()
+            .expect("Failed to stage");
+        Command::new("git")
+            .args(["commit", "-m", name])
+            .output()
+            .expect("Failed to commit");
+    };
+
+    commit_file("b.txt");
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    commit_file("feature.txt");
+    Command::new("git")
+        .args(["checkout", "main"])
+        .output()
+        .expect("Failed to checkout main");
+    Command::new("git")
+        .args(["reset", "--hard", "HEAD~1"])
+        .output()
+        .expect("Failed to rewind main");
+    commit_file("c.txt");
+
+    let mut app = App::new("Test App");
+    let three_dot = git_diff_between_branches(&mut app, "main", "feature").unwrap();
+    let fork_point = git_diff_since_fork_point(&mut app, "main", "feature").unwrap();
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(three_dot.contains("b.txt"));
+    assert!(fork_point.contains("feature.txt"));
+    assert!(
+        !fork_point.contains("b.txt"),
+        "Fork-point diff should only contain commits introduced on the branch"
+    );
+}
+
+#[test]
+#[serial]
+fn test_git_branch_commits_lists_branch_commits_oldest_first() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    for (file, message) in [
+        (
+            "one.txt",
+            "feat: first change\n\nWhy the first change matters.",
+        ),
+        ("two.txt", "fix: second change"),
+    ] {
+        fs::write(file, file).expect("Failed to write file");
+        Command::new("git")
+            .args(["add", file])
+            .output()
+            .expect("Failed to stage");
+        Command::new("git")
+            .args(["commit", "-m", message])
+            .output()
+            .expect("Failed to commit");
+    }
+
+    let mut app = App::new("Test App");
+    let result = git_branch_commits(&mut app, "main", "feature");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let commits = result.expect("Should list commits");
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0].subject, "feat: first change");
+    assert_eq!(commits[0].body, "Why the first change matters.");
+    assert_eq!(commits[1].subject, "fix: second change");
+    assert!(commits[1].body.is_empty());
+}
+
+#[test]
+#[serial]
+fn test_pending_worktree_discards_lists_what_reset_destroys() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write("staged.txt", "staged").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "staged.txt"])
+        .output()
+        .expect("Failed to stage");
+    fs::write("untracked.txt", "untracked").expect("Failed to write file");
+
+    let staged_only = pending_worktree_discards(true, &[]);
+    let everything = pending_worktree_discards(false, &[]);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(staged_only.expect("Should list staged"), vec!["staged.txt"]);
+    let everything = everything.expect("Should list changes");
+    assert_eq!(everything.len(), 2);
+    assert!(everything.iter().any(|l| l.ends_with("staged.txt")));
+    assert!(everything.iter().any(|l| l.ends_with("untracked.txt")));
+}
+
+#[test]
+#[serial]
+fn test_git_diff_stat_between_branches() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    fs::write("stat.txt", "one\ntwo\n").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "stat.txt"])
+        .output()
+        .expect("Failed to stage");
+    Command::new("git")
+        .args(["commit", "-m", "add stat.txt"])
+        .output()
+        .expect("Failed to commit");
+    // main moves on after the fork; its commits aren't part of the branch
+    Command::new("git")
+        .args(["checkout", "main"])
+        .output()
+        .expect("Failed to checkout main");
+    fs::write("main-only.txt", "main\n").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "main-only.txt"])
+        .output()
+        .expect("Failed to stage");
+    Command::new("git")
+        .args(["commit", "-m", "add main-only.txt"])
+        .output()
+        .expect("Failed to commit");
+
+    let mut app = App::new("Test App");
+    let result = git_diff_stat(&mut app, "main", "feature");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let stat = result.expect("Should compute diffstat");
+    assert!(stat.contains("stat.txt"));
+    assert!(!stat.contains("main-only.txt"));
+    assert!(stat.contains("1 file changed, 2 insertions(+)"));
+}
+
+#[test]
+#[serial]
+fn test_git_commit_staged_changes_with_author_override() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::write("authored.txt", "content").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "authored.txt"])
+        .output()
+        .expect("Failed to stage");
+
+    let mut app = App::new("Test App");
+    let result = git_commit_staged_changes(
+        &mut app,
+        "Authored commit",
+        &None,
+        Some("Release Bot <bot@example.com>"),
+        &[],
+    );
+    let author = Command::new("git")
+        .args(["log", "-1", "--format=%an <%ae>"])
+        .output()
+        .expect("Failed to read log");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_ok(), "commit should succeed: {:?}", result);
+    assert_eq!(
+        String::from_utf8_lossy(&author.stdout).trim(),
+        "Release Bot <bot@example.com>"
+    );
+}
+
+#[test]
+#[serial]
+fn test_git_rebase_onto_base_aborts_cleanly_on_conflict() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let commit = |file: &str, content: &str, message: &str| {
+        fs::write(file, content).expect("Failed to write file");
+        Command::new("git")
+            .args(["add", file])
+            .output()
+            .expect("Failed to stage");
+        Command::new("git")
+            .args(["commit", "-m", message])
+            .output()
+            .expect("Failed to commit");
+    };
+    let head = || {
+        let out = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .expect("Failed to rev-parse");
+        String::from_utf8_lossy(&out.stdout).trim().to_string()
+    };
+
+    Command::new("git")
+        .args(["checkout", "-b", "feature"])
+        .output()
+        .expect("Failed to create branch");
+    commit("shared.txt", "feature\n", "feature edit");
+    let feature_head = head();
+
+    Command::new("git")
+        .args(["checkout", "main"])
+        .output()
+        .expect("Failed to checkout main");
+    commit("shared.txt", "main\n", "main edit");
+    Command::new("git")
+        .args(["checkout", "feature"])
+        .output()
+        .expect("Failed to checkout feature");
+
+    let mut app = App::new("Test App");
+    let result = git_rebase_onto_base(&mut app, "main");
+    let after = head();
+    let rebase_dir_exists = Path::new(".git/rebase-merge").exists();
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_err());
+    assert_eq!(after, feature_head, "branch must be left untouched");
+    assert!(!rebase_dir_exists, "rebase must be aborted");
+    assert!(app.errors.iter().any(|e| e.contains("aborted")));
+}
+
+#[test]
+fn test_checks_outcome_from_checks() {
+    let check = |name: &str, bucket: &str| PrCheck {
+        name: name.to_string(),
+        bucket: bucket.to_string(),
+    };
+
+    assert_eq!(ChecksOutcome::from_checks(&[]), ChecksOutcome::NoChecks);
+    assert_eq!(
+        ChecksOutcome::from_checks(&[check("build", "pass"), check("lint", "skipping")]),
+        ChecksOutcome::Passed
+    );
+    assert_eq!(
+        ChecksOutcome::from_checks(&[check("build", "pass"), check("test", "pending")]),
+        ChecksOutcome::Pending
+    );
+    // A failure is final even while other checks are still running
+    assert_eq!(
+        ChecksOutcome::from_checks(&[check("test", "pending"), check("lint", "fail")]),
+        ChecksOutcome::Failed
+    );
+}
+
+#[test]
+#[serial]
+fn test_find_branch_with_pending_changes_detects_earlier_commit() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // An earlier run committed new.txt on its own branch, then failed to push
+    Command::new("git")
+        .args(["checkout", "-b", "feat/earlier-run"])
+        .output()
+        .expect("Failed to create branch");
+    fs::write("new.txt", "change").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "new.txt"])
+        .output()
+        .expect("Failed to stage");
+    Command::new("git")
+        .args(["commit", "-m", "feat: earlier run"])
+        .output()
+        .expect("Failed to commit");
+    Command::new("git")
+        .args(["checkout", "main"])
+        .output()
+        .expect("Failed to checkout main");
+
+    let mut app = App::new("Test App");
+    // Same change still uncommitted (untracked) on main
+    fs::write("new.txt", "change").expect("Failed to write file");
+    let same = find_branch_with_pending_changes(&mut app);
+    // A different change must not match
+    fs::write("new.txt", "other change").expect("Failed to write file");
+    let different = find_branch_with_pending_changes(&mut app);
+    let index_clean = !git_has_staged_changes().expect("Failed to check index");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(
+        same.expect("Should inspect branches"),
+        Some("feat/earlier-run".to_string())
+    );
+    assert_eq!(different.expect("Should inspect branches"), None);
+    assert!(index_clean, "real index must be untouched");
+}
+
+#[test]
+#[serial]
+fn test_path_restriction_limits_diff_and_commit() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    fs::create_dir("sub").expect("Failed to create dir");
+    fs::write("sub/a.txt", "in scope").expect("Failed to write file");
+    fs::write("other.txt", "out of scope").expect("Failed to write file");
+    fs::write("README.md", "# Changed").expect("Failed to write file");
+
+    let mut app = App::new("Test App");
+    assert!(resolve_path_restrictions(&["missing".to_string()]).is_err());
+    app.paths = resolve_path_restrictions(&["sub".to_string()]).expect("Should resolve");
+    assert_eq!(app.paths, vec!["sub"]);
+
+    let diff = git_diff_uncommitted(&mut app, "HEAD");
+    let commit = git_stage_and_commit(&mut app, "feat: sub only", &None, None, &[]);
+    let committed = Command::new("git")
+        .args(["show", "--name-only", "--format=", "HEAD"])
+        .output()
+        .expect("Failed to show commit");
+    let status = Command::new("git")
+        .args(["status", "--porcelain"])
+        .output()
+        .expect("Failed to get status");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    // Untracked files only show up in the diff once staged
+    let diff = diff.expect("Should diff");
+    assert!(!diff.contains("other.txt"));
+    assert!(!diff.contains("README.md"));
+    commit.expect("Should commit");
+    assert_eq!(
+        String::from_utf8_lossy(&committed.stdout).trim(),
+        "sub/a.txt"
+    );
+    let status = String::from_utf8_lossy(&status.stdout).to_string();
+    assert!(status.contains(" M README.md"));
+    assert!(status.contains("?? other.txt"));
+}
+
+#[test]
+#[serial]
+fn test_update_original_worktree_keeps_changes_outside_paths() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // The PR branch holds only the sub/ change, as a --path sub run commits it
+    let wt_path = temp_dir.path().join("pr-wt");
+    Command::new("git")
+        .args(["worktree", "add", "-b", "pr-branch"])
+        .arg(&wt_path)
+        .output()
+        .expect("Failed to add worktree");
+    fs::create_dir(wt_path.join("sub")).expect("Failed to create dir");
+    fs::write(wt_path.join("sub/a.txt"), "in scope").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "sub"])
+        .current_dir(&wt_path)
+        .output()
+        .expect("Failed to stage");
+    Command::new("git")
+        .args(["commit", "-m", "feat: sub"])
+        .current_dir(&wt_path)
+        .output()
+        .expect("Failed to commit");
+    Command::new("git")
+        .args(["worktree", "remove", "--force"])
+        .arg(&wt_path)
+        .output()
+        .expect("Failed to remove worktree");
+
+    fs::create_dir("sub").expect("Failed to create dir");
+    fs::write("sub/a.txt", "in scope").expect("Failed to write file");
+    fs::write("other.txt", "out of scope").expect("Failed to write file");
+    fs::write("README.md", "# Changed").expect("Failed to write file");
+
+    let mut app = App::new("Test App");
+    app.paths = vec!["sub".to_string()];
+    let root = std::path::PathBuf::from(&repo_path);
+    let result = update_original_worktree_to_pr_branch(&mut app, "pr-branch", &root, false);
+    let branch = git_current_branch(&mut app);
+
+    let readme = fs::read_to_string("README.md").expect("Failed to read README");
+    let other_exists = Path::new("other.txt").exists();
+    let _ = env::set_current_dir(&original_dir);
+
+    result.expect("Should update original worktree");
+    assert_eq!(branch.expect("Should get branch"), "pr-branch");
+    assert_eq!(readme, "# Changed");
+    assert!(other_exists, "Untracked files outside --path are kept");
+}
+
+#[test]
+#[serial]
+fn test_update_original_worktree_reports_reapply_conflicts() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // The PR branch changes the same line the local unstaged edit touches
+    let wt_path = temp_dir.path().join("pr-wt");
+    Command::new("git")
+        .args(["worktree", "add", "-b", "pr-branch"])
+        .arg(&wt_path)
+        .output()
+        .expect("Failed to add worktree");
+    fs::write(wt_path.join("README.md"), "# Upstream").expect("Failed to write file");
+    Command::new("git")
+        .args(["commit", "-am", "docs: upstream"])
+        .current_dir(&wt_path)
+        .output()
+        .expect("Failed to commit");
+    Command::new("git")
+        .args(["worktree", "remove", "--force"])
+        .arg(&wt_path)
+        .output()
+        .expect("Failed to remove worktree");
+
+    fs::write("staged.txt", "went to the PR").expect("Failed to write file");
+    Command::new("git")
+        .args(["add", "staged.txt"])
+        .output()
+        .expect("Failed to stage");
+    fs::write("README.md", "# Local edit").expect("Failed to write file");
+
+    let mut app = App::new("Test App");
+    let root = std::path::PathBuf::from(&repo_path);
+    let result = update_original_worktree_to_pr_branch(&mut app, "pr-branch", &root, true);
+
+    let saved = Path::new(".git/gh-autopr-unapplied-unstaged-changes.patch").exists();
+    let _ = env::set_current_dir(&original_dir);
+
+    let err = result.expect_err("A conflicting reapply must fail the cleanup");
+    assert!(err
+        .to_string()
+        .contains("Conflicts restoring unstaged changes"));
+    let errors = app.errors.join("\n");
+    assert!(errors.contains("README.md"), "errors: {}", errors);
+    assert!(errors.contains("Resolve the conflict markers"));
+    assert!(saved, "The unapplied patch is kept for manual recovery");
+}
+
+#[test]
+#[serial]
+#[ignore = "requires gh CLI tool"]
+fn test_create_pr_passes_reviewers_and_assignees() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // --dry-run logs the gh command instead of running it
+    let mut app = App::new("Test App");
+    app.dry_run = true;
+    let result = create_or_update_pull_request(
+        &mut app,
+        "Test PR",
+        "Test body",
+        false,
+        "main",
+        "feature",
+        &PullRequestOptions {
+            ready: true,
+            reviewers: vec!["alice".to_string(), "bob".to_string()],
+            assignees: vec!["carol".to_string()],
+            labels: vec!["bug".to_string()],
+            ..Default::default()
+        },
+    );
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_ok());
+    let command = app
+        .logs
+        .iter()
+        .find_map(|(_, msg, _)| msg.strip_prefix("WOULD RUN: gh pr create"))
+        .expect("Should log the create command");
+    assert!(command.contains("--reviewer alice --reviewer bob"));
+    assert!(command.contains("--assignee carol"));
+    assert!(command.contains("--label bug"));
+    assert!(!command.contains("@me"));
+    assert!(!command.contains("--draft"));
+}
+
+#[test]
+#[serial]
+#[ignore = "requires gh CLI tool"]
+fn test_create_pr_from_fork_targets_base_repo() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let mut app = App::new("Test App");
+    app.dry_run = true;
+    let result = create_or_update_pull_request(
+        &mut app,
+        "Test PR",
+        "Test body",
+        false,
+        "main",
+        "feature",
+        &PullRequestOptions {
+            head_repo: Some("me/tool".to_string()),
+            base_repo: Some("up/tool".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_ok());
+    let command = app
+        .logs
+        .iter()
+        .find_map(|(_, msg, _)| msg.strip_prefix("WOULD RUN: gh pr create"))
+        .expect("Should log the create command");
+    assert!(command.contains("--head me:feature --base main"));
+    assert!(command.contains("--repo up/tool"));
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg == "Cross-repository PR: head me:feature into up/tool"));
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_update_pr_from_fork_ignores_other_forks_prs() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // Another fork has an open PR from a branch of the same name
+    let stub_gh =
+        with_stub_gh("echo '[{\"number\":7,\"headRepositoryOwner\":{\"login\":\"other\"}}]'\n");
+
+    let options = |owner: &str| PullRequestOptions {
+        head_repo: Some(format!("{}/tool", owner)),
+        base_repo: Some("up/tool".to_string()),
+        ..Default::default()
+    };
+    let mut app = App::new("Test App");
+    app.dry_run = true;
+    let mine = create_or_update_pull_request(
+        &mut app,
+        "Test PR",
+        "Test body",
+        true,
+        "main",
+        "feature",
+        &options("me"),
+    );
+    let theirs = create_or_update_pull_request(
+        &mut app,
+        "Test PR",
+        "Test body",
+        true,
+        "main",
+        "feature",
+        &options("other"),
+    );
+
+    drop(stub_gh);
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(
+        mine.expect_err("Another fork's PR must not be updated")
+            .to_string(),
+        "No existing PR found to update"
+    );
+    assert!(theirs.is_ok());
+    assert!(app
+        .logs
+        .iter()
+        .any(|(_, msg, _)| msg.starts_with("WOULD RUN: gh pr edit other:feature")));
+}
+
+#[test]
+#[serial]
+fn test_gitlab_forge_creates_mr_with_glab() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let mut app = App::new("Test App");
+    app.dry_run = true;
+    let result = ForgeKind::Gitlab.forge().create_or_update_pr(
+        &mut app,
+        "Test MR",
+        "Test body",
+        false,
+        "main",
+        "feature",
+        &PullRequestOptions {
+            ready: false,
+            reviewers: vec!["alice".to_string()],
+            assignees: vec![],
+            labels: vec!["bug".to_string()],
+            ..Default::default()
+        },
+    );
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(result.is_ok());
+    let command = app
+        .logs
+        .iter()
+        .find_map(|(_, msg, _)| msg.strip_prefix("WOULD RUN: glab mr create"))
+        .expect("Should log the glab create command");
+    assert!(command.contains("--source-branch feature --target-branch main"));
+    assert!(command.contains("--reviewer alice"));
+    assert!(command.contains("--label bug"));
+    assert!(command.contains("--draft"));
+    assert!(!command.contains("--assignee"), "GitLab has no @me default");
+}
+
+#[test]
+#[serial]
+fn test_discover_parent_branch_spawns_bounded_git_processes() {
+    let (temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // 50 stacked branches, each one commit further along, plus side branches
+    // that are not ancestors of the child
+    for i in 0..50 {
+        git(&["commit", "--allow-empty", "-m", &format!("commit {}", i)]);
+        git(&["branch", &format!("stack-{:02}", i)]);
+        if i % 10 == 0 {
+            git(&["branch", &format!("side-{:02}", i)]);
+            git(&["checkout", "-q", &format!("side-{:02}", i)]);
+            git(&["commit", "--allow-empty", "-m", "side work"]);
+            git(&["checkout", "-q", "main"]);
+        }
+    }
+    git(&["checkout", "-q", "-b", "feature", "stack-37"]);
+    git(&["commit", "--allow-empty", "-m", "feature work"]);
+
+    // Every git process appends a "version" event to this trace
+    let trace = temp_dir.path().join("trace2.json");
+    env::set_var("GIT_TRACE2_EVENT", &trace);
+    let mut app = App::new("Test App");
+    let result = discover_parent_branch(&mut app, "main", "feature");
+    env::remove_var("GIT_TRACE2_EVENT");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(result.expect("Should find parent"), "stack-37");
+    let spawned = fs::read_to_string(&trace)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| line.contains("\"event\":\"version\""))
+        .count();
+    assert!(
+        (1..=5).contains(&spawned),
+        "spawned {} git processes",
+        spawned
+    );
+}
+
+#[test]
+#[serial]
+fn test_discover_parent_branch_picks_nearest_of_merged_branches() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // feature merges branch-a (two commits) and branch-b (one commit), so it
+    // is 3 commits ahead of branch-a and 4 ahead of branch-b
+    git(&["checkout", "-q", "-b", "branch-a"]);
+    git(&["commit", "--allow-empty", "-m", "a1"]);
+    git(&["commit", "--allow-empty", "-m", "a2"]);
+    git(&["checkout", "-q", "-b", "branch-b", "main"]);
+    git(&["commit", "--allow-empty", "-m", "b1"]);
+    git(&["checkout", "-q", "-b", "feature", "branch-a"]);
+    git(&["merge", "--no-edit", "branch-b"]);
+    git(&["commit", "--allow-empty", "-m", "feature work"]);
+
+    let mut app = App::new("Test App");
+    let result = discover_parent_branch(&mut app, "main", "feature");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(result.expect("Should find parent"), "branch-a");
+}
+
+#[test]
+#[serial]
+fn test_checkout_new_branch_reports_ref_directory_collision() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+    Command::new("git")
+        .args(["branch", "feat"])
+        .output()
+        .expect("Failed to create feat branch");
+    Command::new("git")
+        .args(["branch", "fix/crash"])
+        .output()
+        .expect("Failed to create fix/crash branch");
+
+    let mut app = App::new("Test App");
+    let under_branch = git_checkout_new_branch(&mut app, "feat/foo", "main", None);
+    let over_branches = git_checkout_new_branch(&mut app, "fix", "main", None);
+    let unrelated = git_checkout_new_branch(&mut app, "feature/foo", "main", None);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let err = under_branch
+        .expect_err("feat/foo collides with feat")
+        .to_string();
+    assert!(err.contains("existing branch \"feat\""), "err: {}", err);
+    let err = over_branches
+        .expect_err("fix collides with fix/crash")
+        .to_string();
+    assert!(
+        err.contains("existing branch \"fix/crash\""),
+        "err: {}",
+        err
+    );
+    assert_eq!(unrelated.expect("No collision"), "feature/foo");
+}
+
+#[test]
+#[serial]
+fn test_git_checkout_new_branch_force_reset_keeps_unique_commits() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    git(&["checkout", "-q", "-b", "behind"]);
+    git(&["checkout", "-q", "-b", "diverged"]);
+    fs::write("unique.txt", "only on this branch").unwrap();
+    git(&["add", "unique.txt"]);
+    git(&["commit", "-q", "-m", "Unique commit"]);
+    let diverged_tip = git(&["rev-parse", "diverged"]);
+    git(&["checkout", "-q", "main"]);
+    fs::write("main.txt", "newer main").unwrap();
+    git(&["add", "main.txt"]);
+    git(&["commit", "-q", "-m", "Advance main"]);
+
+    let mut app = App::new("Test App");
+    let reset = ForceReset {
+        reason: "test",
+        discard_commits: false,
+    };
+
+    let refused = git_checkout_new_branch(&mut app, "diverged", "main", Some(reset));
+    let refused_tip = git(&["rev-parse", "diverged"]);
+    let refused_head = git(&["branch", "--show-current"]);
+
+    // A branch main already contains is fast-forwarded without fuss
+    let fast_forwarded = git_checkout_new_branch(&mut app, "behind", "main", Some(reset));
+    let behind_tip = git(&["rev-parse", "behind"]);
+    let main_tip = git(&["rev-parse", "main"]);
+
+    let reset = ForceReset {
+        discard_commits: true,
+        ..reset
+    };
+    let discarded = git_checkout_new_branch(&mut app, "diverged", "main", Some(reset));
+    let discarded_tip = git(&["rev-parse", "diverged"]);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let err = refused.expect_err("Resetting a branch with unique commits must be refused");
+    assert!(err.to_string().contains("Refusing to reset"), "{}", err);
+    assert_eq!(refused_tip, diverged_tip);
+    assert_eq!(refused_head, "main");
+    assert_eq!(fast_forwarded.unwrap(), "behind");
+    assert_eq!(behind_tip, main_tip);
+    assert_eq!(discarded.unwrap(), "diverged");
+    assert_eq!(discarded_tip, main_tip);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(level, msg, _)| *level == "WARN" && msg.contains("dropping its own commits")));
+}
+
+#[test]
+fn test_parse_branch_upstreams() {
+    let branches = parse_branch_upstreams("main origin/main\nfeat/x origin/feat/x\nlocal-only \n");
+    assert_eq!(branches.len(), 3);
+    assert_eq!(branches["main"].as_deref(), Some("origin/main"));
+    assert_eq!(branches["feat/x"].as_deref(), Some("origin/feat/x"));
+    assert_eq!(branches["local-only"], None);
+}
+
+#[test]
+#[serial]
+fn test_get_local_branches_with_remotes_worktree_detached_and_diverged() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    // `git branch -vv` shows this one with a `+` and the worktree's path
+    let worktree_dir = TempDir::new().unwrap();
+    let worktree_path = worktree_dir.path().join("wt");
+    git(&[
+        "worktree",
+        "add",
+        "-q",
+        "-b",
+        "in-worktree",
+        worktree_path.to_str().unwrap(),
+    ]);
+    // ... and this one as `[main: ahead 1, behind 1]`
+    git(&["checkout", "-q", "-b", "diverged"]);
+    git(&["branch", "-q", "--set-upstream-to=main"]);
+    fs::write("diverged.txt", "x").unwrap();
+    git(&["add", "diverged.txt"]);
+    git(&["commit", "-q", "-m", "Diverged commit"]);
+    git(&["checkout", "-q", "main"]);
+    fs::write("main.txt", "x").unwrap();
+    git(&["add", "main.txt"]);
+    git(&["commit", "-q", "-m", "Main commit"]);
+    // ... and `(HEAD detached at ...)` comes first
+    git(&["checkout", "-q", "--detach", "HEAD~1"]);
+
+    let mut app = App::new("Test App");
+    let branches = get_local_branches_with_remotes(&mut app);
+
+    let _ = env::set_current_dir(&original_dir);
+
+    let branches = branches.unwrap();
+    let mut names: Vec<&str> = branches.keys().map(String::as_str).collect();
+    names.sort();
+    assert_eq!(names, ["diverged", "in-worktree", "main"]);
+    assert_eq!(branches["diverged"].as_deref(), Some("main"));
+    assert_eq!(branches["in-worktree"], None);
+    assert_eq!(branches["main"], None);
+}
+
+#[cfg(unix)]
+#[test]
+#[serial]
+fn test_find_prunable_branches_keeps_unmerged_branch_whose_remote_is_gone() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    // Both guards restore on drop, so a failed git step can't leak into other tests
+    let cwd = CwdGuard::change_to(&repo_path).expect("Failed to change directory");
+
+    // A gh that knows no merged PRs, so only the remote-gone check applies
+    let stub_gh = with_stub_gh("echo '[]'\n");
+
+    let remote_dir = TempDir::new().unwrap();
+    git(&["init", "-q", "--bare", remote_dir.path().to_str().unwrap()]);
+    git(&[
+        "remote",
+        "add",
+        "origin",
+        remote_dir.path().to_str().unwrap(),
+    ]);
+    for (branch, file) in [("merged", "merged.txt"), ("abandoned", "abandoned.txt")] {
+        git(&["checkout", "-q", "-b", branch, "main"]);
+        fs::write(file, branch).unwrap();
+        git(&["add", file]);
+        git(&["commit", "-q", "-m", branch]);
+        git(&["push", "-q", "-u", "origin", branch]);
+    }
+    git(&["checkout", "-q", "main"]);
+    git(&["merge", "-q", "--no-edit", "merged"]);
+    git(&["push", "-q", "origin", "main"]);
+    git(&["push", "-q", "origin", "--delete", "merged", "abandoned"]);
+
+    let mut app = App::new("Test App");
+    let prunable = find_prunable_branches(&mut app, false);
+    let mut forced_app = App::new("Test App");
+    let forced = find_prunable_branches(&mut forced_app, true);
+
+    drop(stub_gh);
+    drop(cwd);
+
+    let names = |branches: Vec<PrunableBranch>| {
+        let mut names: Vec<String> = branches.into_iter().map(|b| b.name).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(prunable.unwrap()), ["merged"]);
+    assert!(app
+        .logs
+        .iter()
+        .any(|(level, msg, _)| *level == "WARN" && msg.starts_with("Keeping abandoned")));
+    assert_eq!(names(forced.unwrap()), ["abandoned", "merged"]);
+}
+
+#[test]
+#[serial]
+fn test_git_amend_last_commit_only_rewrites_own_commits() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let mut app = App::new("Test App");
+    git(&["checkout", "-q", "-b", "feature"]);
+    let no_own_commits = git_amend_blocker("main", true);
+
+    fs::write("a.txt", "a").unwrap();
+    git(&["add", "a.txt"]);
+    git(&["commit", "-q", "-m", "Hand-written commit"]);
+    let hand_written = git_amend_blocker("main", false);
+    let hand_written_forced = git_amend_blocker("main", true);
+
+    fs::write("b.txt", "b").unwrap();
+    let committed = git_stage_and_commit(&mut app, "feat: add b", &None, None, &[]);
+    let own = git_amend_blocker("main", false);
+
+    fs::write("c.txt", "c").unwrap();
+    let amended = git_amend_last_commit(
+        &mut app,
+        "feat: add b and c",
+        &Some("- Adds c".to_string()),
+        &[],
+    );
+    let count = git(&["rev-list", "--count", "main..HEAD"]);
+    let message = git(&["log", "-1", "--format=%B"]);
+    let files = git(&["show", "--format=", "--name-only", "HEAD"]);
+    let commits = git_branch_commits(&mut app, "main", "feature");
+
+    let _ = env::set_current_dir(&original_dir);
+
+    assert!(no_own_commits
+        .unwrap()
+        .unwrap()
+        .contains("no commits of its own"));
+    assert!(hand_written
+        .unwrap()
+        .unwrap()
+        .contains("may not be gh-autopr's"));
+    assert_eq!(hand_written_forced.unwrap(), None);
+    committed.unwrap();
+    assert_eq!(own.unwrap(), None);
+    amended.unwrap();
+    assert_eq!(count, "2");
+    assert_eq!(
+        message,
+        "feat: add b and c\n\n- Adds c\n\nGenerated-by: gh-autopr"
+    );
+    assert_eq!(files, "b.txt\nc.txt");
+    // PR bodies built from the commits leave the trailer out
+    assert_eq!(commits.unwrap()[1].body, "- Adds c");
+}
+
+#[test]
+#[serial]
+fn test_git_push_branch_non_fast_forward() {
+    let (_temp_dir, repo_path) = create_test_repo();
+    let original_dir = env::current_dir().expect("Failed to get current directory");
+    env::set_current_dir(&repo_path).expect("Failed to change directory");
+
+    let remote_dir = TempDir::new().unwrap();
+    git(&["init", "-q", "--bare", remote_dir.path().to_str().unwrap()]);
+    git(&[
+        "remote",
+        "add",
+        "origin",
+        remote_dir.path().to_str().unwrap(),
+    ]);
+    git(&["checkout", "-q", "-b", "feature"]);
+    fs::write("a.txt", "a").unwrap();
+    git(&["add", "a.txt"]);
+    git(&["commit", "-q", "-m", "Add a"]);
+    git(&["push", "-q", "-u", "origin", "feature"]);
+    // Rewritten after the push, so the remote branch is no longer an ancestor
+    git(&["commit", "-q", "--amend", "-m", "Add a, reworded"]);
+
+    let mut app = App::new("Test App");
+    let err = git_push_branch(&mut app, "feature", false)
+        .expect_err("A diverged push must fail without --force-push")
+        .to_string();
+    assert_eq!(err, non_fast_forward_error("feature", "origin"));
+    assert!(err.contains("origin/feature has diverged"), "{}", err);
+    assert!(err.contains("git pull --rebase origin feature"), "{}", err);
+    assert!(err.contains("--force-push"), "{}", err);
+
+    app.force_push = true;
+    let pushed = git_push_branch(&mut app, "feature", false);
+    let _ = env::set_current_dir(&original_dir);
+
+    assert_eq!(pushed.unwrap(), "feature");
+    assert!(app.logs.iter().any(
+        |(level, msg, _)| *level == "WARN" && msg.contains("retrying with --force-with-lease")
+    ));
+}
+
+#[test]
+fn test_is_non_fast_forward() {
+    let rejected =
+        "To github.com:o/r.git\n ! [rejected]        feature -> feature (non-fast-forward)\n\
+                    error: failed to push some refs to 'github.com:o/r.git'\n\
+                    hint: Updates were rejected because the tip of your current branch is behind\n";
+    assert!(is_non_fast_forward(rejected));
+    assert!(is_non_fast_forward(
+        " ! [rejected]        feature -> feature (fetch first)\n"
+    ));
+    assert!(!is_non_fast_forward(
+        " ! [remote rejected] feature -> feature (pre-receive hook declined)\n"
+    ));
+}